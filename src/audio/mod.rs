@@ -0,0 +1,304 @@
+use std::sync::{
+    Arc, Mutex,
+    atomic::{AtomicI64, Ordering},
+};
+
+use crate::{engine::physics::vec2::Vec2, error::TetronError, fs::SimpleFs, system_log};
+
+#[cfg(feature = "rodio")]
+pub mod rodio;
+pub mod sdl_mixer;
+
+/// The most mixer channels a game can ask for.
+pub const MAX_CHANNELS: i32 = 256;
+
+/// SDL_mixer's own default channel count.
+pub const DEFAULT_CHANNELS: i32 = 8;
+
+/// The audio file formats the loaders know how to tell apart.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum AudioFormat {
+    Wav,
+    Ogg,
+    Mp3,
+}
+
+impl AudioFormat {
+    /// Recognise a file from its first bytes, if it has a known header.
+    pub fn sniff(bytes: &[u8]) -> Option<Self> {
+        match bytes {
+            [
+                b'R',
+                b'I',
+                b'F',
+                b'F',
+                _,
+                _,
+                _,
+                _,
+                b'W',
+                b'A',
+                b'V',
+                b'E',
+                ..,
+            ] => Some(Self::Wav),
+            [b'O', b'g', b'g', b'S', ..] => Some(Self::Ogg),
+            [b'I', b'D', b'3', ..] => Some(Self::Mp3),
+            // An MPEG audio frame sync. A layer of 0 is reserved, which rules
+            // out AAC streams that share the sync bits.
+            [0xFF, b, ..] if b & 0xE0 == 0xE0 && b & 0x06 != 0 => Some(Self::Mp3),
+            _ => None,
+        }
+    }
+
+    /// Guess the format from `path`'s extension.
+    pub fn from_path(path: &str) -> Option<Self> {
+        let (_, ext) = path.rsplit_once('.')?;
+        match ext.to_ascii_lowercase().as_str() {
+            "wav" => Some(Self::Wav),
+            "ogg" | "oga" => Some(Self::Ogg),
+            "mp3" => Some(Self::Mp3),
+            _ => None,
+        }
+    }
+
+    /// Sniff the format from `bytes`, trusting the extension only if that's
+    /// inconclusive.
+    pub fn detect(bytes: &[u8], path: &str) -> Option<Self> {
+        Self::sniff(bytes).or_else(|| Self::from_path(path))
+    }
+}
+
+/// Work out what `bytes` loaded from `path` are, warning if the extension
+/// says otherwise. Both backends pick their decoder from the file's contents
+/// too, so a misnamed file still loads; this is for clearer errors.
+pub(crate) fn detect_format(bytes: &[u8], path: &str) -> Option<AudioFormat> {
+    let format = AudioFormat::detect(bytes, path);
+    if let (Some(format), Some(named)) = (format, AudioFormat::from_path(path))
+        && format != named
+    {
+        system_log!("{path} is named as {named:?} but contains {format:?} audio");
+    }
+    format
+}
+
+pub(crate) fn load_error(
+    path: &str,
+    format: Option<AudioFormat>,
+    e: impl std::fmt::Display,
+) -> TetronError {
+    match format {
+        Some(format) => {
+            TetronError::Runtime(format!("Could not load {format:?} audio {path}: {e}"))
+        }
+        None => TetronError::Runtime(format!(
+            "Could not load {path}, unrecognised audio format: {e}"
+        )),
+    }
+}
+
+/// Convert a script-supplied fade length in milliseconds to what SDL_mixer
+/// takes. Negative lengths mean no fade.
+pub fn clamp_fade_ms(fade_ms: i64) -> i32 {
+    fade_ms.clamp(0, i32::MAX as i64) as i32
+}
+
+/// Volume and pan for a sound at `source` heard from `listener`. Volume falls
+/// off linearly to silence at `max_dist`, and pan follows the horizontal
+/// offset, reaching hard left or right at `max_dist`.
+pub fn positional(source: Vec2, listener: Vec2, max_dist: f64) -> (f64, f64) {
+    if max_dist <= 0.0 {
+        return (0.0, 0.0);
+    }
+
+    let volume = (1.0 - source.distance(listener) / max_dist).max(0.0);
+    let pan = ((source.x - listener.x) / max_dist).clamp(-1.0, 1.0);
+    (volume, pan)
+}
+
+/// Clamp a requested channel count to `1..=MAX_CHANNELS`.
+pub fn clamp_channels(channels: i64) -> i32 {
+    channels.clamp(1, MAX_CHANNELS as i64) as i32
+}
+
+/// A request from scripts to the audio backend. Scripts can run on any
+/// thread as far as Rune is concerned, so they queue commands and the game
+/// loop hands them to the backend on the main thread.
+#[derive(Clone, Debug, PartialEq)]
+pub enum AudioCommand {
+    PlaySound(String),
+    /// Play a sound effect once at `volume` (0.0 to 1.0, on top of the sound
+    /// volume) and `pan` (-1.0 for hard left to 1.0 for hard right).
+    PlaySoundEx {
+        path: String,
+        volume: f64,
+        pan: f64,
+    },
+    /// Play a music track, replacing whatever is playing. `loops` of -1
+    /// repeats forever.
+    PlayMusic {
+        path: String,
+        loops: i32,
+    },
+    /// Like `PlayMusic`, but raise the volume from silence over `fade_ms`.
+    FadeInMusic {
+        path: String,
+        loops: i32,
+        fade_ms: i32,
+    },
+    /// Fade the current track to silence over `fade_ms`, then stop it.
+    FadeOutMusic(i32),
+    StopMusic,
+    /// Volumes go from 0.0 to 1.0.
+    SetSoundVolume(f64),
+    SetMusicVolume(f64),
+    /// Change the number of mixer channels, and so how many sounds can play
+    /// at once. Already clamped with [`clamp_channels`].
+    AllocateChannels(i32),
+}
+
+#[derive(Clone, Debug, Default)]
+pub struct AudioQueue {
+    commands: Arc<Mutex<Vec<AudioCommand>>>,
+    /// How many channels were playing when the queue was last drained.
+    playing: Arc<AtomicI64>,
+}
+
+impl AudioQueue {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn push(&self, command: AudioCommand) -> Result<(), TetronError> {
+        self.commands
+            .lock()
+            .map_err(|e| TetronError::Other(format!("audio queue lock poisoned: {e}")))?
+            .push(command);
+        Ok(())
+    }
+
+    pub fn drain(&self) -> Result<Vec<AudioCommand>, TetronError> {
+        Ok(std::mem::take(&mut *self.commands.lock().map_err(|e| {
+            TetronError::Other(format!("audio queue lock poisoned: {e}"))
+        })?))
+    }
+
+    pub fn set_playing(&self, channels: i64) {
+        self.playing.store(channels, Ordering::Relaxed);
+    }
+
+    pub fn playing(&self) -> i64 {
+        self.playing.load(Ordering::Relaxed)
+    }
+}
+
+/// Something that can actually make noise. Files are read through the game's
+/// `SimpleFs`, so backends never touch the disk directly.
+pub trait AudioBackend {
+    fn execute(&mut self, fs: &dyn SimpleFs, command: AudioCommand) -> Result<(), TetronError>;
+
+    /// Decode the sound effect at `path` from `bytes` ahead of its first
+    /// play. Backends without a sound cache ignore it.
+    fn preload_sound(&mut self, _path: &str, _bytes: Vec<u8>) -> Result<(), TetronError> {
+        Ok(())
+    }
+
+    /// How many channels are playing a sound right now.
+    fn playing_channels(&self) -> i64 {
+        0
+    }
+
+    /// Called once a frame after the queued commands have run, for backends
+    /// that have to drive fades themselves.
+    fn update(&mut self) {}
+}
+
+/// Discards every command. Used when running headless.
+pub struct NullAudioBackend;
+
+impl AudioBackend for NullAudioBackend {
+    fn execute(&mut self, _: &dyn SimpleFs, _: AudioCommand) -> Result<(), TetronError> {
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_sniff_audio_format() {
+        assert_eq!(
+            AudioFormat::sniff(b"OggS\0\x02rest"),
+            Some(AudioFormat::Ogg)
+        );
+        assert_eq!(AudioFormat::sniff(b"ID3\x04\0"), Some(AudioFormat::Mp3));
+        // MPEG-1 layer III frame with no ID3 tag.
+        assert_eq!(
+            AudioFormat::sniff(&[0xFF, 0xFB, 0x90, 0x64]),
+            Some(AudioFormat::Mp3)
+        );
+        // AAC's ADTS header has the same sync bits but layer 0.
+        assert_eq!(AudioFormat::sniff(&[0xFF, 0xF1, 0x50, 0x80]), None);
+        assert_eq!(
+            AudioFormat::sniff(b"RIFF\x24\0\0\0WAVEfmt "),
+            Some(AudioFormat::Wav)
+        );
+        assert_eq!(AudioFormat::sniff(b"Og"), None);
+        assert_eq!(AudioFormat::sniff(b""), None);
+
+        // Content wins over a wrong extension, and the extension is the
+        // fallback when the header is unknown.
+        assert_eq!(
+            AudioFormat::detect(b"OggS\0", "music/theme.mp3"),
+            Some(AudioFormat::Ogg)
+        );
+        assert_eq!(
+            AudioFormat::detect(b"????", "sfx/jump.MP3"),
+            Some(AudioFormat::Mp3)
+        );
+        assert_eq!(AudioFormat::detect(b"????", "sfx/jump"), None);
+    }
+
+    #[test]
+    fn test_clamp_channels() {
+        assert_eq!(clamp_channels(32), 32);
+        assert_eq!(clamp_channels(0), 1);
+        assert_eq!(clamp_channels(-4), 1);
+        assert_eq!(clamp_channels(100_000), MAX_CHANNELS);
+    }
+
+    #[test]
+    fn test_positional() {
+        let listener = Vec2::new(100.0, 100.0);
+
+        assert_eq!(positional(listener, listener, 200.0), (1.0, 0.0));
+        assert_eq!(
+            positional(Vec2::new(200.0, 100.0), listener, 200.0),
+            (0.5, 0.5)
+        );
+        assert_eq!(
+            positional(Vec2::new(0.0, 100.0), listener, 200.0),
+            (0.5, -0.5)
+        );
+        // straight above: quieter, but centred
+        assert_eq!(
+            positional(Vec2::new(100.0, 250.0), listener, 200.0),
+            (0.25, 0.0)
+        );
+        // out of range
+        assert_eq!(
+            positional(Vec2::new(500.0, 100.0), listener, 200.0),
+            (0.0, 1.0)
+        );
+        assert_eq!(positional(listener, listener, 0.0), (0.0, 0.0));
+    }
+
+    #[test]
+    fn test_clamp_fade_ms() {
+        assert_eq!(clamp_fade_ms(500), 500);
+        assert_eq!(clamp_fade_ms(0), 0);
+        assert_eq!(clamp_fade_ms(-100), 0);
+        assert_eq!(clamp_fade_ms(i64::MAX), i32::MAX);
+    }
+}
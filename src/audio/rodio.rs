@@ -0,0 +1,279 @@
+use std::{
+    collections::HashMap,
+    io::Cursor,
+    sync::Arc,
+    time::{Duration, Instant},
+};
+
+use rodio::{
+    Decoder, OutputStream, OutputStreamHandle, Sink, Source,
+    source::{Buffered, ChannelVolume},
+};
+
+use super::{AudioBackend, AudioCommand, detect_format, load_error};
+use crate::{error::TetronError, fs::SimpleFs};
+
+type Sound = Buffered<Decoder<Cursor<Arc<[u8]>>>>;
+type Track = Box<dyn Source<Item = i16> + Send>;
+
+/// Left and right gains for a pan from -1.0 (left) to 1.0 (right), matching
+/// the SDL_mixer backend's panning.
+fn pan_gains(pan: f64) -> (f32, f32) {
+    let pan = pan.clamp(-1.0, 1.0) as f32;
+    ((1.0 - pan).min(1.0), (1.0 + pan).min(1.0))
+}
+
+/// Decode a whole sound effect up front so each play is a cheap clone.
+fn decode(bytes: Vec<u8>, path: &str) -> Result<Sound, TetronError> {
+    let format = detect_format(&bytes, path);
+    let bytes: Arc<[u8]> = bytes.into();
+    Decoder::new(Cursor::new(bytes))
+        .map(Source::buffered)
+        .map_err(|e| load_error(path, format, e))
+}
+
+/// A music track played `loops` times, or forever if `loops` is -1. Like
+/// SDL_mixer, 0 plays it once.
+fn track(bytes: &Arc<[u8]>, path: &str, loops: i32) -> Result<Track, TetronError> {
+    let format = detect_format(bytes, path);
+    let decoder =
+        Decoder::new(Cursor::new(bytes.clone())).map_err(|e| load_error(path, format, e))?;
+    if loops < 0 {
+        return Ok(Box::new(decoder.repeat_infinite()));
+    }
+    let once = decoder.buffered();
+    Ok(Box::new(rodio::source::from_iter(
+        (0..loops.max(1)).map(move |_| once.clone()),
+    )))
+}
+
+fn sink_error(e: impl std::fmt::Display) -> TetronError {
+    TetronError::Runtime(format!("Could not play audio: {e}"))
+}
+
+struct FadeOut {
+    started: Instant,
+    length: Duration,
+}
+
+/// Audio through rodio, for builds without SDL2_mixer. Each sound effect
+/// plays on its own sink, and at most `channels` of them play at once, like
+/// the mixer's channels.
+pub struct RodioBackend {
+    _stream: OutputStream,
+    handle: OutputStreamHandle,
+    sounds: HashMap<String, Sound>,
+    /// Tracks are decoded as they play, so only their bytes are cached.
+    music: HashMap<String, Arc<[u8]>>,
+    playing: Vec<Sink>,
+    channels: usize,
+    music_sink: Option<Sink>,
+    fade_out: Option<FadeOut>,
+    sound_volume: f64,
+    music_volume: f64,
+}
+
+impl RodioBackend {
+    /// Open the default output device with `channels` channels for sound
+    /// effects.
+    pub fn new(channels: i32) -> Result<Self, TetronError> {
+        let (stream, handle) = OutputStream::try_default()
+            .map_err(|e| TetronError::Runtime(format!("Could not open an audio device: {e}")))?;
+        Ok(Self {
+            _stream: stream,
+            handle,
+            sounds: HashMap::new(),
+            music: HashMap::new(),
+            playing: Vec::new(),
+            channels: channels.max(1) as usize,
+            music_sink: None,
+            fade_out: None,
+            sound_volume: 1.0,
+            music_volume: 1.0,
+        })
+    }
+
+    fn play_sound(
+        &mut self,
+        fs: &dyn SimpleFs,
+        path: &str,
+        volume: f64,
+        pan: f64,
+    ) -> Result<(), TetronError> {
+        self.playing.retain(|sink| !sink.empty());
+        if self.playing.len() >= self.channels {
+            return Err(TetronError::Runtime("No free channels available".into()));
+        }
+
+        if !self.sounds.contains_key(path) {
+            self.preload_sound(path, fs.open_file(path)?)?;
+        }
+        let (left, right) = pan_gains(pan);
+        let sink = Sink::try_new(&self.handle).map_err(sink_error)?;
+        sink.set_volume((volume * self.sound_volume) as f32);
+        sink.append(ChannelVolume::new(
+            self.sounds[path].clone(),
+            vec![left, right],
+        ));
+        self.playing.push(sink);
+        Ok(())
+    }
+
+    fn play_music(
+        &mut self,
+        fs: &dyn SimpleFs,
+        path: &str,
+        loops: i32,
+        fade_in: Option<Duration>,
+    ) -> Result<(), TetronError> {
+        if !self.music.contains_key(path) {
+            self.music
+                .insert(path.to_owned(), fs.open_file(path)?.into());
+        }
+        let track = track(&self.music[path], path, loops)?;
+        let sink = Sink::try_new(&self.handle).map_err(sink_error)?;
+        sink.set_volume(self.music_volume as f32);
+        match fade_in {
+            Some(length) => sink.append(track.fade_in(length)),
+            None => sink.append(track),
+        }
+        // Dropping the old sink stops whatever was playing.
+        self.music_sink = Some(sink);
+        self.fade_out = None;
+        Ok(())
+    }
+}
+
+impl AudioBackend for RodioBackend {
+    fn execute(&mut self, fs: &dyn SimpleFs, command: AudioCommand) -> Result<(), TetronError> {
+        match command {
+            AudioCommand::PlaySound(path) => self.play_sound(fs, &path, 1.0, 0.0)?,
+            AudioCommand::PlaySoundEx { path, volume, pan } => {
+                self.play_sound(fs, &path, volume, pan)?
+            }
+            AudioCommand::PlayMusic { path, loops } => self.play_music(fs, &path, loops, None)?,
+            AudioCommand::FadeInMusic {
+                path,
+                loops,
+                fade_ms,
+            } => self.play_music(
+                fs,
+                &path,
+                loops,
+                Some(Duration::from_millis(fade_ms as u64)),
+            )?,
+            AudioCommand::FadeOutMusic(fade_ms) => {
+                if self.music_sink.is_some() {
+                    self.fade_out = Some(FadeOut {
+                        started: Instant::now(),
+                        length: Duration::from_millis(fade_ms as u64),
+                    });
+                }
+            }
+            AudioCommand::StopMusic => {
+                self.music_sink = None;
+                self.fade_out = None;
+            }
+            AudioCommand::SetSoundVolume(volume) => {
+                self.sound_volume = volume.clamp(0.0, 1.0);
+                for sink in &self.playing {
+                    sink.set_volume(self.sound_volume as f32);
+                }
+            }
+            AudioCommand::SetMusicVolume(volume) => {
+                self.music_volume = volume.clamp(0.0, 1.0);
+                if let Some(sink) = &self.music_sink {
+                    sink.set_volume(self.music_volume as f32);
+                }
+            }
+            AudioCommand::AllocateChannels(channels) => {
+                self.channels = channels.max(1) as usize;
+                // Dropping a sink stops its sound.
+                self.playing.truncate(self.channels);
+            }
+        }
+        Ok(())
+    }
+
+    fn preload_sound(&mut self, path: &str, bytes: Vec<u8>) -> Result<(), TetronError> {
+        let sound = decode(bytes, path)?;
+        self.sounds.insert(path.to_owned(), sound);
+        Ok(())
+    }
+
+    fn playing_channels(&self) -> i64 {
+        self.playing.iter().filter(|sink| !sink.empty()).count() as i64
+    }
+
+    fn update(&mut self) {
+        let (Some(fade), Some(sink)) = (&self.fade_out, &self.music_sink) else {
+            return;
+        };
+        let progress = if fade.length.is_zero() {
+            1.0
+        } else {
+            fade.started.elapsed().as_secs_f64() / fade.length.as_secs_f64()
+        };
+        if progress >= 1.0 {
+            self.music_sink = None;
+            self.fade_out = None;
+        } else {
+            sink.set_volume((self.music_volume * (1.0 - progress)) as f32);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A mono 16-bit PCM WAV file holding `samples`.
+    fn wav(samples: &[i16]) -> Vec<u8> {
+        let data_len = (samples.len() * 2) as u32;
+        let mut bytes = Vec::new();
+        bytes.extend_from_slice(b"RIFF");
+        bytes.extend_from_slice(&(36 + data_len).to_le_bytes());
+        bytes.extend_from_slice(b"WAVEfmt ");
+        bytes.extend_from_slice(&16u32.to_le_bytes());
+        bytes.extend_from_slice(&1u16.to_le_bytes());
+        bytes.extend_from_slice(&1u16.to_le_bytes());
+        bytes.extend_from_slice(&8_000u32.to_le_bytes());
+        bytes.extend_from_slice(&16_000u32.to_le_bytes());
+        bytes.extend_from_slice(&2u16.to_le_bytes());
+        bytes.extend_from_slice(&16u16.to_le_bytes());
+        bytes.extend_from_slice(b"data");
+        bytes.extend_from_slice(&data_len.to_le_bytes());
+        for sample in samples {
+            bytes.extend_from_slice(&sample.to_le_bytes());
+        }
+        bytes
+    }
+
+    #[test]
+    fn test_decode_and_loop() -> Result<(), TetronError> {
+        let samples = [0, 1000, -1000, 2000];
+        let sound = decode(wav(&samples), "sfx/beep.wav")?;
+        assert_eq!(sound.channels(), 1);
+        assert_eq!(sound.sample_rate(), 8_000);
+        assert_eq!(sound.clone().collect::<Vec<_>>(), samples);
+
+        let bytes: Arc<[u8]> = wav(&samples).into();
+        assert_eq!(track(&bytes, "music.wav", 3)?.count(), 12);
+        assert_eq!(track(&bytes, "music.wav", 0)?.count(), 4);
+        assert_eq!(track(&bytes, "music.wav", -1)?.take(100).count(), 100);
+
+        let Err(e) = decode(b"not audio".to_vec(), "sfx/beep") else {
+            panic!("garbage decoded as audio");
+        };
+        assert!(e.to_string().contains("unrecognised audio format"));
+        Ok(())
+    }
+
+    #[test]
+    fn test_pan_gains() {
+        assert_eq!(pan_gains(0.0), (1.0, 1.0));
+        assert_eq!(pan_gains(-1.0), (1.0, 0.0));
+        assert_eq!(pan_gains(0.5), (0.5, 1.0));
+        assert_eq!(pan_gains(4.0), (0.0, 1.0));
+    }
+}
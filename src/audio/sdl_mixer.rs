@@ -0,0 +1,128 @@
+use std::collections::HashMap;
+
+use sdl2::mixer::{
+    self, AUDIO_S16LSB, Channel, Chunk, DEFAULT_CHANNELS, InitFlag, LoaderRWops, MAX_VOLUME, Music,
+    Sdl2MixerContext,
+};
+use sdl2::rwops::RWops;
+
+use super::{AudioBackend, AudioCommand, detect_format, load_error};
+use crate::{error::TetronError, fs::SimpleFs};
+
+fn to_mixer_volume(volume: f64) -> i32 {
+    (volume.clamp(0.0, 1.0) * MAX_VOLUME as f64).round() as i32
+}
+
+/// Left and right channel gains for a pan from -1.0 (left) to 1.0 (right).
+fn to_mixer_panning(pan: f64) -> (u8, u8) {
+    let pan = pan.clamp(-1.0, 1.0);
+    let gain = |g: f64| (g.min(1.0) * 255.0).round() as u8;
+    (gain(1.0 - pan), gain(1.0 + pan))
+}
+
+/// Audio through SDL2_mixer. Sounds are decoded up front and cached by path.
+pub struct SdlMixerBackend {
+    _context: Sdl2MixerContext,
+    sounds: HashMap<String, Chunk>,
+    /// SDL_mixer streams music from its source buffer while it plays, so
+    /// each track's bytes are leaked and kept for the rest of the run. The
+    /// cache means that only happens once per track.
+    music: HashMap<String, Music<'static>>,
+    /// Last volume set for sound effects. Channel volumes are overwritten
+    /// per sound by `PlaySoundEx`, so this is what they're scaled against.
+    sound_volume: f64,
+}
+
+impl SdlMixerBackend {
+    /// Open the mixer with `channels` channels for sound effects.
+    pub fn new(channels: i32) -> Result<Self, TetronError> {
+        mixer::open_audio(44_100, AUDIO_S16LSB, DEFAULT_CHANNELS, 1_024)?;
+        let context = mixer::init(InitFlag::OGG | InitFlag::MP3)?;
+        mixer::allocate_channels(channels);
+        Ok(Self {
+            _context: context,
+            sounds: HashMap::new(),
+            music: HashMap::new(),
+            sound_volume: 1.0,
+        })
+    }
+
+    fn play_sound(
+        &mut self,
+        fs: &dyn SimpleFs,
+        path: &str,
+        volume: f64,
+        pan: f64,
+    ) -> Result<(), TetronError> {
+        let sound_volume = self.sound_volume;
+        let channel = Channel::all().play(self.sound(fs, path)?, 0)?;
+        // Channels are reused, so reset what a previous sound may have set.
+        channel.set_volume(to_mixer_volume(volume * sound_volume));
+        let (left, right) = to_mixer_panning(pan);
+        channel.set_panning(left, right)?;
+        Ok(())
+    }
+
+    fn sound(&mut self, fs: &dyn SimpleFs, path: &str) -> Result<&Chunk, TetronError> {
+        if !self.sounds.contains_key(path) {
+            self.preload_sound(path, fs.open_file(path)?)?;
+        }
+        Ok(&self.sounds[path])
+    }
+
+    fn music(&mut self, fs: &dyn SimpleFs, path: &str) -> Result<&Music<'static>, TetronError> {
+        if !self.music.contains_key(path) {
+            let bytes: &'static [u8] = Box::leak(fs.open_file(path)?.into_boxed_slice());
+            let format = detect_format(bytes, path);
+            let music = Music::from_static_bytes(bytes).map_err(|e| load_error(path, format, e))?;
+            self.music.insert(path.to_owned(), music);
+        }
+        Ok(&self.music[path])
+    }
+}
+
+impl AudioBackend for SdlMixerBackend {
+    fn execute(&mut self, fs: &dyn SimpleFs, command: AudioCommand) -> Result<(), TetronError> {
+        match command {
+            AudioCommand::PlaySound(path) => self.play_sound(fs, &path, 1.0, 0.0)?,
+            AudioCommand::PlaySoundEx { path, volume, pan } => {
+                self.play_sound(fs, &path, volume, pan)?
+            }
+            AudioCommand::PlayMusic { path, loops } => {
+                self.music(fs, &path)?.play(loops)?;
+            }
+            AudioCommand::FadeInMusic {
+                path,
+                loops,
+                fade_ms,
+            } => {
+                // Tracks stay cached, so the old one lives until its fade is over.
+                self.music(fs, &path)?.fade_in(loops, fade_ms)?;
+            }
+            AudioCommand::FadeOutMusic(fade_ms) => Music::fade_out(fade_ms)?,
+            AudioCommand::StopMusic => Music::halt(),
+            AudioCommand::SetSoundVolume(volume) => {
+                self.sound_volume = volume.clamp(0.0, 1.0);
+                Channel::all().set_volume(to_mixer_volume(volume));
+            }
+            AudioCommand::SetMusicVolume(volume) => Music::set_volume(to_mixer_volume(volume)),
+            AudioCommand::AllocateChannels(channels) => {
+                mixer::allocate_channels(channels);
+            }
+        }
+        Ok(())
+    }
+
+    fn preload_sound(&mut self, path: &str, bytes: Vec<u8>) -> Result<(), TetronError> {
+        let format = detect_format(&bytes, path);
+        let chunk = RWops::from_bytes(&bytes)?
+            .load_wav()
+            .map_err(|e| load_error(path, format, e))?;
+        self.sounds.insert(path.to_owned(), chunk);
+        Ok(())
+    }
+
+    fn playing_channels(&self) -> i64 {
+        mixer::get_playing_channels_number() as i64
+    }
+}
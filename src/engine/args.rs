@@ -1,4 +1,4 @@
-use clap::Parser;
+use clap::{Parser, Subcommand};
 use std::path::PathBuf;
 
 #[derive(Parser, Debug)]
@@ -13,14 +13,90 @@ by {author-with-newline}
 {all-args}{after-help}
 ")]
 pub struct TetronArgs {
+    #[command(subcommand)]
+    pub command: Option<Command>,
+
     /// Base game path (zip or directory)
-    #[arg(long, value_name = "PATH")]
+    #[arg(long, value_name = "PATH", global = true)]
     pub game: Option<PathBuf>,
 
     /// Additional mods to layer. Multiple can be specified and the mods
     /// are layered in the reverse of the order they are specified.
     /// For example `tetron --game foo --layer mod1 --layer mod2` will first
     /// try to find assets from `mod2`, then `mod1`, then `foo`.
-    #[arg(long = "layer", value_name = "PATH")]
+    #[arg(long = "layer", value_name = "PATH", global = true)]
     pub layers: Vec<PathBuf>,
+
+    /// Override a config value from game.json. Can be specified multiple
+    /// times. Keys use the same `a:b` form as game.json, and values are
+    /// parsed as JSON, falling back to a plain string. For example
+    /// `tetron --game foo --set start_level=3 --set sdl:title=Debug`.
+    #[arg(long = "set", value_name = "KEY=VALUE", value_parser = parse_key_value, global = true)]
+    pub overrides: Vec<(String, String)>,
+
+    /// Disable error telemetry, even if the game would otherwise report
+    /// fatal errors.
+    #[arg(long, global = true)]
+    pub no_telemetry: bool,
+
+    /// Where to keep flags and saves: `sqlite` (the default) stores them in
+    /// the user's data directory, `memory` discards them on exit. Same as
+    /// `--set flags:backend=...`.
+    #[arg(long, value_name = "BACKEND", value_parser = ["sqlite", "memory"], global = true)]
+    pub flags_backend: Option<String>,
+
+    /// Keep flags and saves under this directory instead of the user's data
+    /// directory. Overrides the TETRON_DATA_DIR environment variable.
+    #[arg(long, value_name = "PATH", global = true)]
+    pub data_dir: Option<PathBuf>,
+
+    /// Advance every frame by exactly 1/60s and seed the game's RNG with 0,
+    /// so runs with the same input play out identically.
+    #[arg(long, global = true)]
+    pub deterministic: bool,
+
+    /// Load the game and compile its scripts without running them or
+    /// opening a window, then exit. Exits with an error if anything is
+    /// wrong, for checking games and mods in CI.
+    #[arg(long)]
+    pub check: bool,
+
+    /// Load the game, run its `begin`, then print the behaviours and scenes
+    /// it defined as JSON and exit without opening a window.
+    #[arg(long)]
+    pub dump_registry: bool,
+
+    /// Print the config the game would run with, after layers and `--set`
+    /// overrides, as JSON and exit without running the game.
+    #[arg(long)]
+    pub dump_config: bool,
+}
+
+#[derive(Subcommand, Debug)]
+pub enum Command {
+    /// Play the game in deterministic mode, with flags kept in memory, and
+    /// record every frame's input to a file for `replay`.
+    Record {
+        /// Where to write the recording.
+        #[arg(long, value_name = "PATH")]
+        out: PathBuf,
+    },
+    /// Play a recording made by `record` back and print the position of
+    /// every entity after each frame, as one line of JSON per frame. Runs
+    /// like `record`, deterministic with flags kept in memory.
+    Replay {
+        /// The recording to play back.
+        #[arg(long = "in", value_name = "PATH")]
+        input: PathBuf,
+
+        /// Replay without opening a window.
+        #[arg(long)]
+        headless: bool,
+    },
+}
+
+fn parse_key_value(arg: &str) -> Result<(String, String), String> {
+    arg.split_once('=')
+        .map(|(key, value)| (key.to_owned(), value.to_owned()))
+        .ok_or(format!("expected KEY=VALUE, got \"{arg}\""))
 }
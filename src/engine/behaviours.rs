@@ -1,11 +1,19 @@
 use crate::{
+    error::{EngineBug, TetronError},
     log_and_die,
     utils::{
         Registrable,
-        typed_value::{TypedValue, schema::Schema},
+        typed_value::{
+            TypedValue,
+            schema::{Schema, SchemaError},
+        },
     },
 };
-use rune::{ContextError, Module, Value, runtime::Object};
+use rune::{
+    ContextError, FromValue, Module, Value,
+    runtime::{Object, VmResult},
+    vm_try,
+};
 use std::{cell::RefCell, collections::HashMap, rc::Rc, sync::Arc};
 
 #[derive(rune::Any, Debug)]
@@ -13,6 +21,8 @@ pub struct Behaviour {
     pub(crate) name: String,
     pub(crate) config: HashMap<String, TypedValue>,
     pub(crate) schema: Arc<Schema>,
+    /// Set by every `set`, cleared by systems once they've handled the change.
+    pub(crate) dirty: bool,
 }
 
 #[derive(rune::Any, Clone, Debug)]
@@ -20,6 +30,9 @@ pub struct BehaviourFactory {
     name: String,
     schema: Arc<Schema>,
     internal: bool,
+    /// Reject fields the schema doesn't name instead of dropping them. Set
+    /// for factories made by `extend`.
+    strict: bool,
 }
 
 impl BehaviourFactory {
@@ -28,52 +41,130 @@ impl BehaviourFactory {
             name: name.to_owned(),
             schema: Arc::new(schema),
             internal,
+            strict: false,
         }
     }
 
+    /// The factory for one of the engine's own `tetron:` behaviours.
+    pub fn builtin(name: &str) -> Option<Self> {
+        use super::{button, drawable, physics, shape, transform};
+
+        let (short, schema) = match name {
+            "tetron:button" => ("button", button::schema()),
+            "tetron:drawable" => ("drawable", drawable::schema()),
+            "tetron:physics" => ("physics", physics::schema()),
+            "tetron:shape" => ("shape", shape::schema()),
+            "tetron:transform" => ("transform", transform::schema()),
+            _ => return None,
+        };
+        Some(Self::new(short, schema, true))
+    }
+
     pub fn with_map(&self, map: HashMap<String, TypedValue>) -> BehaviourRef {
-        if let Ok(validated) = self.schema.validate(&TypedValue::Object(map.clone())) {
-            let name = if self.internal {
-                String::from("tetron:") + &self.name
-            } else {
-                self.name.clone()
-            };
-            let config = match validated {
-                TypedValue::Object(obj) => obj,
-                _ => unreachable!(),
-            };
-            BehaviourRef::new(Behaviour {
-                name,
-                config,
-                schema: self.schema.clone(),
-            })
-        } else {
-            log_and_die!(
+        match self.try_with_map(map.clone()) {
+            Ok(behaviour) => behaviour,
+            Err(_) => log_and_die!(
                 1,
                 "Could not validate {map:?} against schema {:?}",
                 self.schema
-            )
+            ),
+        }
+    }
+
+    /// Like `with_map`, but returns validation errors instead of exiting.
+    pub fn try_with_map(
+        &self,
+        map: HashMap<String, TypedValue>,
+    ) -> Result<BehaviourRef, SchemaError> {
+        if self.strict
+            && let Schema::Object { fields } = &*self.schema
+            && let Some(unknown) = map.keys().find(|key| !fields.contains_key(*key))
+        {
+            return Err(SchemaError::Validation(format!(
+                "{unknown} is not a field of {}",
+                self.name
+            )));
+        }
+        let validated = self.schema.validate(&TypedValue::Object(map))?;
+        let name = self.behaviour_name();
+        let config = match validated {
+            TypedValue::Object(obj) => obj,
+            _ => unreachable!(),
+        };
+        Ok(BehaviourRef::new(Behaviour {
+            name,
+            config,
+            schema: self.schema.clone(),
+            dirty: true,
+        }))
+    }
+
+    /// The name behaviours made by this factory are attached under, with
+    /// the `tetron:` prefix for builtins.
+    pub fn behaviour_name(&self) -> String {
+        if self.internal {
+            String::from("tetron:") + &self.name
+        } else {
+            self.name.clone()
         }
     }
 
     #[rune::function(keep)]
-    pub fn create(&self, config: &Object) -> BehaviourRef {
+    pub fn create(&self, config: &Object) -> VmResult<BehaviourRef> {
         let mut map = HashMap::<String, TypedValue>::new();
-        for key in config.keys() {
-            if let Some(val) = config.get(key) {
-                map.insert(
-                    key.as_str().to_string(),
-                    val.try_into()
-                        .expect("Engine bug: failed to convert rune value to typed value"),
-                );
-            }
+        for (key, val) in config {
+            let val = vm_try!(
+                TypedValue::try_from(val)
+                    .throw_engine_bug("failed to convert rune value to typed value")
+            );
+            map.insert(key.as_str().to_string(), val);
         }
-        self.with_map(map)
+        VmResult::Ok(self.with_map(map))
+    }
+
+    /// A behaviour built entirely from the schema's defaults. Fails if a
+    /// required field has no default.
+    pub fn default_instance(&self) -> Result<BehaviourRef, SchemaError> {
+        self.try_with_map(HashMap::new())
+    }
+
+    /// Rebuild a behaviour from an object produced by `BehaviourRef::to_object`.
+    /// The object is validated against the schema just like in `create`.
+    #[rune::function(keep)]
+    pub fn from_object(&self, object: &Object) -> VmResult<BehaviourRef> {
+        self.create(object)
     }
 
     pub fn schema(&self) -> Arc<Schema> {
         self.schema.clone()
     }
+
+    /// A new factory whose schema has this one's fields plus the fields of
+    /// `additional`, merged with `Schema::merge`. Both schemas must be object
+    /// schemas. Unlike other factories, it rejects fields in neither schema
+    /// rather than dropping them. The new factory is called
+    /// `{name}_extended` until renamed with `with_name`.
+    #[rune::function(keep)]
+    pub fn extend(&self, additional: Schema) -> Result<BehaviourFactory, SchemaError> {
+        let schema = self.schema.merge(&additional).map_err(|e| {
+            SchemaError::Validation(format!("Cannot extend behaviour {}: {e}", self.name))
+        })?;
+        Ok(Self {
+            name: format!("{}_extended", self.name),
+            schema: Arc::new(schema),
+            internal: self.internal,
+            strict: true,
+        })
+    }
+
+    /// A copy of this factory that creates behaviours called `name`.
+    #[rune::function(keep)]
+    pub fn with_name(&self, name: &str) -> BehaviourFactory {
+        Self {
+            name: name.to_owned(),
+            ..self.clone()
+        }
+    }
 }
 
 impl Behaviour {
@@ -91,35 +182,56 @@ impl Behaviour {
         }
     }
 
-    fn set(&mut self, field: &str, value: Value) {
+    fn set(&mut self, field: &str, value: Value) -> VmResult<()> {
         self.check_field(field);
-        self.config.insert(
-            field.into(),
-            TryInto::try_into(&value)
-                .expect("engine bug: could not convert rune Value into TypedValue"),
+        let value = vm_try!(
+            TypedValue::try_from(&value)
+                .throw_engine_bug("could not convert rune Value into TypedValue")
         );
+        self.config.insert(field.into(), value);
+        self.dirty = true;
+        VmResult::Ok(())
     }
 
-    fn get(&self, field: &str) -> Option<Value> {
-        self.config.get(field).map(|val| {
-            val.try_into().unwrap_or_else(|_| {
-                panic!(
-                    "Could not convert value of {field} on behaviour {} ",
-                    self.name
-                )
-            })
-        })
+    fn get(&self, field: &str) -> VmResult<Option<Value>> {
+        let Some(val) = self.config.get(field) else {
+            return VmResult::Ok(None);
+        };
+        let value = vm_try!(Value::try_from(val).throw_engine_bug(&format!(
+            "could not convert value of {field} on behaviour {}",
+            self.name
+        )));
+        VmResult::Ok(Some(value))
     }
 
-    fn get_typed(&self, field: &str) -> Option<TypedValue> {
+    /// The value of `field`, or `None` if it's optional and unset. Fields
+    /// that aren't in the schema are an error.
+    fn get_typed(&self, field: &str) -> Result<Option<TypedValue>, TetronError> {
+        match &*self.schema {
+            Schema::Object { fields } if fields.contains_key(field) => {
+                Ok(self.config.get(field).cloned())
+            }
+            _ => Err(TetronError::Runtime(format!(
+                "Invalid field {field} accessed on behaviour {}",
+                self.name
+            ))),
+        }
+    }
+
+    fn is_default(&self, field: &str) -> bool {
         self.check_field(field);
-        self.config.get(field).cloned()
+        self.config.get(field) == self.schema.field_default(field).as_ref()
     }
 
     fn name(&self) -> String {
         self.name.clone()
     }
 
+    fn to_object(&self) -> Result<Object, TetronError> {
+        let value = Value::try_from(&TypedValue::Object(self.config.clone()))?;
+        Ok(Object::from_value(value)?)
+    }
+
     pub fn schema(&self) -> Arc<Schema> {
         self.schema.clone()
     }
@@ -134,6 +246,11 @@ impl Registrable for BehaviourRef {
         module.function_meta(BehaviourRef::name__meta)?;
         module.function_meta(BehaviourRef::set__meta)?;
         module.function_meta(BehaviourRef::get__meta)?;
+        module.function_meta(BehaviourRef::to_object__meta)?;
+        module.function_meta(BehaviourRef::is_dirty__meta)?;
+        module.function_meta(BehaviourRef::clear_dirty__meta)?;
+        module.function_meta(BehaviourRef::is_default__meta)?;
+        module.function_meta(BehaviourRef::to_debug_string__meta)?;
         Ok(())
     }
 }
@@ -142,6 +259,9 @@ impl Registrable for BehaviourFactory {
     fn register(module: &mut Module) -> Result<(), ContextError> {
         module.ty::<BehaviourFactory>()?;
         module.function_meta(BehaviourFactory::create__meta)?;
+        module.function_meta(BehaviourFactory::from_object__meta)?;
+        module.function_meta(BehaviourFactory::extend__meta)?;
+        module.function_meta(BehaviourFactory::with_name__meta)?;
         Ok(())
     }
 }
@@ -157,12 +277,12 @@ impl BehaviourRef {
     }
 
     #[rune::function(instance, keep, protocol = SET)]
-    pub fn set(&mut self, field: &str, value: Value) {
-        self.0.borrow_mut().set(field, value);
+    pub fn set(&mut self, field: &str, value: Value) -> VmResult<()> {
+        self.0.borrow_mut().set(field, value)
     }
 
     #[rune::function(instance, keep, protocol = GET)]
-    pub fn get(&self, field: &str) -> Option<Value> {
+    pub fn get(&self, field: &str) -> VmResult<Option<Value>> {
         self.0.borrow().get(field)
     }
 
@@ -170,7 +290,213 @@ impl BehaviourRef {
         self.0.borrow().config.contains_key(field)
     }
 
-    pub fn get_typed(&self, field: &str) -> Option<TypedValue> {
+    pub fn get_typed(&self, field: &str) -> Result<Option<TypedValue>, TetronError> {
         self.0.borrow().get_typed(field)
     }
+
+    /// The behaviour's name followed by one indented `field: value` line per
+    /// field, in alphabetical order.
+    #[rune::function(keep)]
+    pub fn to_debug_string(&self) -> String {
+        let behaviour = self.0.borrow();
+        let mut fields: Vec<(&String, &TypedValue)> = behaviour.config.iter().collect();
+        fields.sort_by_key(|(name, _)| *name);
+        let mut out = behaviour.name.clone();
+        for (name, value) in fields {
+            out.push_str(&format!("\n  {name}: {value}"));
+        }
+        out
+    }
+
+    /// A copy of every field's current value.
+    pub fn config(&self) -> HashMap<String, TypedValue> {
+        self.0.borrow().config.clone()
+    }
+
+    /// Copy the behaviour's whole config into an object. Fields that were
+    /// left out at creation hold their schema defaults, since those are
+    /// filled in when the behaviour is created.
+    #[rune::function(instance, keep)]
+    pub fn to_object(&self) -> VmResult<Object> {
+        self.0
+            .borrow()
+            .to_object()
+            .throw_engine_bug("could not convert behaviour config to an object")
+    }
+
+    /// Whether any field has been `set` since the last `clear_dirty`. This
+    /// flips on every `set`, even if the new value equals the old one. New
+    /// behaviours start out dirty.
+    #[rune::function(instance, keep)]
+    pub fn is_dirty(&self) -> bool {
+        self.0.borrow().dirty
+    }
+
+    /// Mark the behaviour as unchanged, usually once a system has processed it.
+    #[rune::function(instance, keep)]
+    pub fn clear_dirty(&self) {
+        self.0.borrow_mut().dirty = false;
+    }
+
+    /// Whether `field` holds its schema default. An optional field with no
+    /// default counts as default while it's unset; a required field with no
+    /// default never does.
+    #[rune::function(instance, keep)]
+    pub fn is_default(&self, field: &str) -> bool {
+        self.0.borrow().is_default(field)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_object_round_trip() {
+        let schema = Schema::object()
+            .field("hp", Schema::number())
+            .optional_field("label", Schema::string(), Some("enemy".into()))
+            .build();
+        let factory = BehaviourFactory::new("health", schema, false);
+        let original =
+            factory.with_map(HashMap::from([("hp".to_string(), TypedValue::Number(5.0))]));
+
+        let object = original.to_object().unwrap();
+        assert_eq!(object.len(), 2);
+
+        let copy = factory.from_object(&object).unwrap();
+        assert_eq!(copy.get_typed("hp").unwrap(), Some(TypedValue::Number(5.0)));
+        assert_eq!(
+            copy.get_typed("label").unwrap(),
+            Some(TypedValue::String("enemy".into()))
+        );
+        assert_eq!(copy.0.borrow().config, original.0.borrow().config);
+    }
+
+    #[test]
+    fn test_dirty_tracking() -> Result<(), anyhow::Error> {
+        use rune::ToValue;
+
+        let schema = Schema::object().field("hp", Schema::number()).build();
+        let factory = BehaviourFactory::new("health", schema, false);
+        let mut health =
+            factory.with_map(HashMap::from([("hp".to_string(), TypedValue::Number(5.0))]));
+        assert!(health.is_dirty());
+
+        health.clear_dirty();
+        assert!(!health.is_dirty());
+        health.get("hp").into_result()?;
+        assert!(!health.is_dirty());
+
+        health.set("hp", 5.0.to_value()?).into_result()?;
+        assert!(health.is_dirty());
+        health.clear_dirty();
+        assert!(!health.is_dirty());
+        Ok(())
+    }
+
+    #[test]
+    fn test_extend() -> Result<(), SchemaError> {
+        let character = BehaviourFactory::new(
+            "character",
+            Schema::object()
+                .field("hp", Schema::number())
+                .optional_field("name", Schema::string(), None)
+                .build(),
+            false,
+        );
+        let enemy = character.extend(Schema::object().field("damage", Schema::number()).build())?;
+        assert_eq!(enemy.name, "character_extended");
+        assert_eq!(enemy.with_name("enemy").name, "enemy");
+
+        let mut fields = HashMap::from([
+            ("hp".to_string(), TypedValue::Number(3.0)),
+            ("damage".to_string(), TypedValue::Number(1.0)),
+        ]);
+        let behaviour = enemy.try_with_map(fields.clone()).unwrap();
+        assert_eq!(
+            behaviour.get_typed("hp").unwrap(),
+            Some(TypedValue::Number(3.0))
+        );
+        assert_eq!(
+            behaviour.get_typed("damage").unwrap(),
+            Some(TypedValue::Number(1.0))
+        );
+        // Fields from neither schema are rejected.
+        fields.insert("speed".to_string(), TypedValue::Number(2.0));
+        assert_eq!(
+            enemy.try_with_map(fields).err(),
+            Some(SchemaError::Validation(
+                "speed is not a field of character_extended".into()
+            ))
+        );
+
+        // Both the base's and the extension's required fields are enforced.
+        let only_base = HashMap::from([("hp".to_string(), TypedValue::Number(3.0))]);
+        assert!(enemy.try_with_map(only_base.clone()).is_err());
+        assert!(character.try_with_map(only_base).is_ok());
+        let only_extra = HashMap::from([("damage".to_string(), TypedValue::Number(1.0))]);
+        assert!(enemy.try_with_map(only_extra).is_err());
+
+        // Only object schemas can be merged.
+        assert!(character.extend(Schema::number()).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_is_default() -> Result<(), anyhow::Error> {
+        use rune::ToValue;
+
+        let schema = Schema::object()
+            .field("hp", Schema::number().default(TypedValue::Number(10.0)))
+            .field("damage", Schema::number())
+            .optional_field("label", Schema::string(), Some("enemy".into()))
+            .optional_field("tag", Schema::string(), None)
+            .build();
+        let factory = BehaviourFactory::new("health", schema, false);
+        let mut behaviour = factory
+            .try_with_map(HashMap::from([(
+                "damage".to_string(),
+                TypedValue::Number(1.0),
+            )]))
+            .unwrap();
+
+        assert!(behaviour.is_default("hp"));
+        assert!(behaviour.is_default("label"));
+        assert!(behaviour.is_default("tag"));
+        assert!(!behaviour.is_default("damage"));
+
+        behaviour.set("hp", 4.0.to_value()?).into_result()?;
+        behaviour.set("tag", "boss".to_value()?).into_result()?;
+        assert!(!behaviour.is_default("hp"));
+        assert!(!behaviour.is_default("tag"));
+
+        behaviour.set("hp", 10.0.to_value()?).into_result()?;
+        assert!(behaviour.is_default("hp"));
+        Ok(())
+    }
+
+    #[test]
+    fn test_get_typed_distinguishes_unknown_fields() {
+        let schema = Schema::object()
+            .field("hp", Schema::number())
+            .optional_field("label", Schema::string(), None)
+            .build();
+        let factory = BehaviourFactory::new("health", schema, false);
+        let behaviour =
+            factory.with_map(HashMap::from([("hp".to_string(), TypedValue::Number(2.0))]));
+
+        assert_eq!(
+            behaviour.get_typed("hp").unwrap(),
+            Some(TypedValue::Number(2.0))
+        );
+        assert_eq!(behaviour.get_typed("label").unwrap(), None);
+        assert!(behaviour.get_typed("mana").is_err());
+
+        let labelled = factory.with_map(HashMap::from([
+            ("hp".to_string(), TypedValue::Number(2.0)),
+            ("label".to_string(), "boss".into()),
+        ]));
+        assert_eq!(labelled.get_typed("label").unwrap(), Some("boss".into()));
+    }
 }
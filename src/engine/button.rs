@@ -0,0 +1,214 @@
+use super::{
+    behaviours::{BehaviourFactory, BehaviourRef},
+    input::KeyState,
+    physics::vec2::Vec2,
+    systems::Ctx,
+};
+use crate::{
+    error::TetronError,
+    utils::typed_value::{TypedValue, schema::Schema},
+};
+use rune::{
+    ContextError, Module, ToValue, docstring,
+    runtime::{Object, VmResult},
+};
+use std::collections::HashSet;
+
+pub(crate) fn schema() -> Schema {
+    let color = |default: &str| Some(TypedValue::from(default));
+    Schema::object()
+        .optional_field("label", Schema::string(), None)
+        .field("w", Schema::number())
+        .field("h", Schema::number())
+        .optional_field("normal", Schema::string(), color("#444444"))
+        .optional_field("hover", Schema::string(), color("#666666"))
+        .optional_field("pressed", Schema::string(), color("#222222"))
+        .optional_field("text_color", Schema::string(), color("#ffffff"))
+        .optional_field("state", Schema::string(), color("normal"))
+        .optional_field("clicked", Schema::bool(), Some(TypedValue::Bool(false)))
+        .build()
+}
+
+/// The state a button moves to given the mouse this frame, and whether it
+/// was clicked. A click is a release over the button after a press that
+/// also started on it.
+fn next_state(prev: &str, inside: bool, input: &KeyState) -> (&'static str, bool) {
+    if !inside {
+        return ("normal", false);
+    }
+    let held = prev == "pressed" && input.mouse_down();
+    if input.mouse_just_pressed() || held {
+        ("pressed", false)
+    } else {
+        ("hover", prev == "pressed" && input.mouse_just_released())
+    }
+}
+
+fn number(button: &BehaviourRef, field: &str) -> f64 {
+    match button.get_typed(field) {
+        Ok(Some(TypedValue::Number(n))) => n,
+        _ => 0.0,
+    }
+}
+
+/// Hit-test the mouse against every button in the current scene, updating
+/// each one's `state` and `clicked` fields. Buttons are positioned by their
+/// transform, extending right and down from it like a rect shape.
+pub(crate) fn update_buttons(ctx: &Ctx, input: &KeyState) -> Result<(), TetronError> {
+    let behaviours = HashSet::from(["tetron:button".to_string(), "tetron:transform".to_string()]);
+    let mouse = input.mouse_pos();
+    for entity in ctx.query_with_sets(HashSet::new(), behaviours, None, 0, None)? {
+        let (Some(mut button), Some(transform)) = (
+            entity.behaviour("tetron:button"),
+            entity.behaviour("tetron:transform"),
+        ) else {
+            continue;
+        };
+        let pos = match transform.get_typed("pos")? {
+            Some(TypedValue::Vector(pos)) => pos,
+            _ => Vec2::ZERO,
+        };
+        let local = mouse - pos;
+        let inside = (0.0..=number(&button, "w")).contains(&local.x)
+            && (0.0..=number(&button, "h")).contains(&local.y);
+        let prev = match button.get_typed("state")? {
+            Some(TypedValue::String(state)) => state,
+            _ => "normal".to_string(),
+        };
+        let (state, clicked) = next_state(&prev, inside, input);
+        if prev != state {
+            button.set("state", state.to_value()?).into_result()?;
+        }
+        if clicked || button.get_typed("clicked")? == Some(TypedValue::Bool(true)) {
+            button.set("clicked", clicked.to_value()?).into_result()?;
+        }
+    }
+    Ok(())
+}
+
+fn register_factory(module: &mut Module) -> Result<(), ContextError> {
+    let button = BehaviourFactory::new("button", schema(), true);
+
+    let func = move |obj: &Object| -> VmResult<BehaviourRef> { button.create(obj) };
+
+    module.function("create", func).build()?.docs(docstring! {
+        /// Create a new button behaviour. Buttons also need a transform,
+        /// which places their top-left corner.
+        ///
+        /// Fields:
+        /// * label: string (optional)
+        /// * w, h: number
+        /// * normal, hover, pressed: colors for each state (optional)
+        /// * text_color: label color (optional, default white)
+        ///
+        /// The engine keeps these up to date every frame, before systems run:
+        /// * state: "normal", "hover" or "pressed"
+        /// * clicked: true only on the frame the button was clicked
+    })?;
+    Ok(())
+}
+
+pub fn module() -> Result<Module, ContextError> {
+    let mut module = Module::with_crate_item("tetron", ["game", "button"])?;
+    register_factory(&mut module)?;
+    Ok(module)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use sdl2::{event::Event, mouse::MouseButton};
+
+    fn motion(x: i32, y: i32) -> Event {
+        Event::MouseMotion {
+            timestamp: 0,
+            window_id: 0,
+            which: 0,
+            mousestate: sdl2::mouse::MouseState::from_sdl_state(0),
+            x,
+            y,
+            xrel: 0,
+            yrel: 0,
+        }
+    }
+
+    fn click(down: bool, x: i32, y: i32) -> Event {
+        if down {
+            Event::MouseButtonDown {
+                timestamp: 0,
+                window_id: 0,
+                which: 0,
+                mouse_btn: MouseButton::Left,
+                clicks: 1,
+                x,
+                y,
+            }
+        } else {
+            Event::MouseButtonUp {
+                timestamp: 0,
+                window_id: 0,
+                which: 0,
+                mouse_btn: MouseButton::Left,
+                clicks: 1,
+                x,
+                y,
+            }
+        }
+    }
+
+    /// Feed `events` in as one frame and step a button from `prev`.
+    fn step(input: &mut KeyState, prev: &str, events: &[Event]) -> (&'static str, bool) {
+        input.next_frame(1.0 / 60.0);
+        for event in events {
+            input.update(event);
+        }
+        let pos = input.mouse_pos();
+        let inside = (0.0..=10.0).contains(&pos.x) && (0.0..=10.0).contains(&pos.y);
+        next_state(prev, inside, input)
+    }
+
+    #[test]
+    fn test_button_states() {
+        let mut input = KeyState::new();
+        assert_eq!(
+            step(&mut input, "normal", &[motion(20, 20)]),
+            ("normal", false)
+        );
+        assert_eq!(
+            step(&mut input, "normal", &[motion(5, 5)]),
+            ("hover", false)
+        );
+        assert_eq!(
+            step(&mut input, "hover", &[click(true, 5, 5)]),
+            ("pressed", false)
+        );
+        assert_eq!(step(&mut input, "pressed", &[]), ("pressed", false));
+        assert_eq!(
+            step(&mut input, "pressed", &[click(false, 5, 5)]),
+            ("hover", true)
+        );
+        assert_eq!(step(&mut input, "hover", &[]), ("hover", false));
+
+        // Dragging off before releasing cancels the click.
+        step(&mut input, "hover", &[click(true, 5, 5)]);
+        assert_eq!(
+            step(&mut input, "pressed", &[motion(50, 5)]),
+            ("normal", false)
+        );
+        assert_eq!(
+            step(&mut input, "normal", &[click(false, 5, 5)]),
+            ("hover", false)
+        );
+
+        // A press that starts outside doesn't click on release inside.
+        step(&mut input, "normal", &[click(true, 50, 5)]);
+        assert_eq!(
+            step(&mut input, "normal", &[motion(5, 5)]),
+            ("hover", false)
+        );
+        assert_eq!(
+            step(&mut input, "hover", &[click(false, 5, 5)]),
+            ("hover", false)
+        );
+    }
+}
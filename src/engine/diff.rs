@@ -0,0 +1,259 @@
+use super::entity::EntityId;
+use crate::{error::TetronError, utils::typed_value::TypedValue};
+use std::collections::{BTreeMap, BTreeSet, HashMap};
+
+/// One difference between two scene snapshots from `SceneRef::serialize`.
+/// `None` stands for a value that's missing on that side.
+#[derive(Clone, Debug, PartialEq)]
+pub enum SceneChange {
+    ConfigChanged {
+        key: String,
+        old: Option<TypedValue>,
+        new: Option<TypedValue>,
+    },
+    EntityAdded(EntityId),
+    EntityRemoved(EntityId),
+    BehaviourAdded {
+        entity: EntityId,
+        behaviour: String,
+    },
+    BehaviourRemoved {
+        entity: EntityId,
+        behaviour: String,
+    },
+    FieldChanged {
+        entity: EntityId,
+        behaviour: String,
+        field: String,
+        old: Option<TypedValue>,
+        new: Option<TypedValue>,
+    },
+    TagsChanged {
+        entity: EntityId,
+        old: Vec<TypedValue>,
+        new: Vec<TypedValue>,
+    },
+    MetaChanged {
+        entity: EntityId,
+        key: String,
+        old: Option<TypedValue>,
+        new: Option<TypedValue>,
+    },
+}
+
+type Fields = HashMap<String, TypedValue>;
+
+fn invalid(msg: &str) -> TetronError {
+    TetronError::Conversion(format!("Invalid scene snapshot: {msg}"))
+}
+
+fn as_object<'a>(
+    value: Option<&'a TypedValue>,
+    what: &str,
+) -> Result<Option<&'a Fields>, TetronError> {
+    match value {
+        Some(TypedValue::Object(map)) => Ok(Some(map)),
+        Some(_) => Err(invalid(&format!("{what} must be an object"))),
+        None => Ok(None),
+    }
+}
+
+fn config(snapshot: &TypedValue) -> Result<Option<&Fields>, TetronError> {
+    match snapshot {
+        TypedValue::Object(map) => as_object(map.get("config"), "config"),
+        _ => Err(invalid("expected an object")),
+    }
+}
+
+fn entities_by_id(snapshot: &TypedValue) -> Result<BTreeMap<EntityId, &Fields>, TetronError> {
+    let TypedValue::Object(snapshot) = snapshot else {
+        return Err(invalid("expected an object"));
+    };
+    let Some(TypedValue::Array(entities)) = snapshot.get("entities") else {
+        return Err(invalid("expected an entities array"));
+    };
+    entities
+        .iter()
+        .map(|entity| {
+            let TypedValue::Object(entity) = entity else {
+                return Err(invalid("entities must be objects"));
+            };
+            match entity.get("id") {
+                Some(TypedValue::Number(id)) => Ok((*id as EntityId, entity)),
+                _ => Err(invalid("entities need a numeric id")),
+            }
+        })
+        .collect()
+}
+
+/// Keys of both maps, in order, with each side's value.
+fn paired<'a>(
+    old: Option<&'a Fields>,
+    new: Option<&'a Fields>,
+) -> impl Iterator<Item = (&'a String, Option<&'a TypedValue>, Option<&'a TypedValue>)> {
+    let keys: BTreeSet<&String> = old.into_iter().chain(new).flat_map(|m| m.keys()).collect();
+    keys.into_iter().map(move |key| {
+        (
+            key,
+            old.and_then(|m| m.get(key)),
+            new.and_then(|m| m.get(key)),
+        )
+    })
+}
+
+/// Compare two scene snapshots. Entities are matched by id, and changes are
+/// listed config first, then by entity id, behaviour name and field name, so
+/// the same pair of snapshots always gives the same list.
+pub fn diff_scenes(old: &TypedValue, new: &TypedValue) -> Result<Vec<SceneChange>, TetronError> {
+    let mut changes = Vec::new();
+
+    for (key, a, b) in paired(config(old)?, config(new)?) {
+        if a != b {
+            changes.push(SceneChange::ConfigChanged {
+                key: key.clone(),
+                old: a.cloned(),
+                new: b.cloned(),
+            });
+        }
+    }
+
+    let old_entities = entities_by_id(old)?;
+    let new_entities = entities_by_id(new)?;
+    let ids: BTreeSet<EntityId> = old_entities
+        .keys()
+        .chain(new_entities.keys())
+        .copied()
+        .collect();
+    for id in ids {
+        let (a, b) = match (old_entities.get(&id), new_entities.get(&id)) {
+            (Some(a), Some(b)) => (a, b),
+            (Some(_), None) => {
+                changes.push(SceneChange::EntityRemoved(id));
+                continue;
+            }
+            (None, _) => {
+                changes.push(SceneChange::EntityAdded(id));
+                continue;
+            }
+        };
+
+        let behaviours = (
+            as_object(a.get("behaviours"), "behaviours")?,
+            as_object(b.get("behaviours"), "behaviours")?,
+        );
+        for (behaviour, a_config, b_config) in paired(behaviours.0, behaviours.1) {
+            let (a_config, b_config) = match (a_config, b_config) {
+                (Some(a_config), Some(b_config)) => (
+                    as_object(Some(a_config), "behaviour config")?,
+                    as_object(Some(b_config), "behaviour config")?,
+                ),
+                (Some(_), None) => {
+                    changes.push(SceneChange::BehaviourRemoved {
+                        entity: id,
+                        behaviour: behaviour.clone(),
+                    });
+                    continue;
+                }
+                (None, _) => {
+                    changes.push(SceneChange::BehaviourAdded {
+                        entity: id,
+                        behaviour: behaviour.clone(),
+                    });
+                    continue;
+                }
+            };
+            for (field, old, new) in paired(a_config, b_config) {
+                if old != new {
+                    changes.push(SceneChange::FieldChanged {
+                        entity: id,
+                        behaviour: behaviour.clone(),
+                        field: field.clone(),
+                        old: old.cloned(),
+                        new: new.cloned(),
+                    });
+                }
+            }
+        }
+
+        if a.get("tags") != b.get("tags") {
+            let tags = |entity: &Fields| match entity.get("tags") {
+                Some(TypedValue::Array(tags)) => tags.clone(),
+                _ => Vec::new(),
+            };
+            changes.push(SceneChange::TagsChanged {
+                entity: id,
+                old: tags(a),
+                new: tags(b),
+            });
+        }
+
+        let meta = (
+            as_object(a.get("meta"), "meta")?,
+            as_object(b.get("meta"), "meta")?,
+        );
+        for (key, old, new) in paired(meta.0, meta.1) {
+            if old != new {
+                changes.push(SceneChange::MetaChanged {
+                    entity: id,
+                    key: key.clone(),
+                    old: old.cloned(),
+                    new: new.cloned(),
+                });
+            }
+        }
+    }
+
+    Ok(changes)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        engine::{behaviours::BehaviourFactory, world::WorldRef},
+        utils::typed_value::schema::Schema,
+    };
+    use rune::{ToValue, runtime::Object};
+
+    #[test]
+    fn test_single_field_change() -> Result<(), TetronError> {
+        let world = WorldRef::new();
+        let mut scene = world.scene("level", Object::new());
+        let health = BehaviourFactory::new(
+            "health",
+            Schema::object().field("hp", Schema::number()).build(),
+            false,
+        );
+        for hp in [3.0, 5.0] {
+            scene.spawn().attach(
+                health.with_map(HashMap::from([("hp".to_string(), TypedValue::Number(hp))])),
+            );
+        }
+
+        let before = scene.serialize()?;
+        assert!(diff_scenes(&before, &before)?.is_empty());
+
+        let mut hp = scene.entities()[1].behaviour("health").unwrap();
+        hp.set("hp", 4.0.to_value()?).into_result()?;
+        let after = scene.serialize()?;
+        assert_eq!(
+            diff_scenes(&before, &after)?,
+            vec![SceneChange::FieldChanged {
+                entity: 1,
+                behaviour: "health".into(),
+                field: "hp".into(),
+                old: Some(TypedValue::Number(5.0)),
+                new: Some(TypedValue::Number(4.0)),
+            }]
+        );
+
+        let removed = scene.entities()[0].clone();
+        scene.despawn(removed);
+        scene.spawn().tag("new");
+        assert_eq!(
+            diff_scenes(&after, &scene.serialize()?)?,
+            vec![SceneChange::EntityRemoved(0), SceneChange::EntityAdded(2)]
+        );
+        Ok(())
+    }
+}
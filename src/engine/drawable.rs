@@ -1,25 +1,38 @@
 use super::behaviours::{BehaviourFactory, BehaviourRef};
 use crate::utils::typed_value::schema::Schema;
-use rune::{ContextError, Module, docstring, runtime::Object};
+use rune::{
+    ContextError, Module, docstring,
+    runtime::{Object, VmResult},
+};
 
-fn register_factory(module: &mut Module) -> Result<(), ContextError> {
-    // font must be an Object with required size (number), optional face (string)
+pub(crate) fn schema() -> Schema {
+    let outline_schema = Schema::object()
+        .field("width", Schema::number())
+        .optional_field("color", Schema::string(), None)
+        .build();
+
+    // font must be an Object with required size (number), optional face (string),
+    // outline (object) and anchor (string)
     let font_schema = Schema::object()
         .field("size", Schema::number())
         .optional_field("face", Schema::string(), None)
+        .optional_field("outline", outline_schema, None)
+        .optional_field("anchor", Schema::string(), None)
         .build();
 
-    let schema = Schema::object()
+    Schema::object()
         .optional_field("color", Schema::string(), None)
         .optional_field("text", Schema::string(), None)
         .optional_field("font", font_schema, None)
         .optional_field("sprite", Schema::string(), None)
         .optional_field("anim", Schema::string(), None)
-        .build();
+        .build()
+}
 
-    let drawable = BehaviourFactory::new("drawable", schema, true);
+fn register_factory(module: &mut Module) -> Result<(), ContextError> {
+    let drawable = BehaviourFactory::new("drawable", schema(), true);
 
-    let func = move |obj: &Object| -> BehaviourRef { drawable.create(obj) };
+    let func = move |obj: &Object| -> VmResult<BehaviourRef> { drawable.create(obj) };
 
     module.function("create", func).build()?.docs(docstring! {
         /// Create a new drawable behaviour.
@@ -27,7 +40,12 @@ fn register_factory(module: &mut Module) -> Result<(), ContextError> {
         /// Fields:
         /// * color: string
         /// * text: string
-        /// * font: object with size (number) and optional face (string)
+        /// * font: object with size (number), optional face (string) and
+        ///   optional outline (object with width (number) and optional
+        ///   color (string, default black)) and optional anchor (string):
+        ///   which point of the text sits at the entity's position. One of
+        ///   topleft (the default), topcenter, topright, centerleft, center,
+        ///   centerright, bottomleft, bottomcenter or bottomright.
     })?;
     Ok(())
 }
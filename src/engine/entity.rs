@@ -1,16 +1,50 @@
-use super::behaviours::BehaviourRef;
-use crate::{log_and_die, utils::Registrable};
-use rune::{ContextError, Module};
+use super::{
+    behaviours::{BehaviourFactory, BehaviourRef},
+    tween::TweenList,
+};
+use crate::{
+    error::{EngineBug, IntoVmResult, TetronError},
+    log_and_die,
+    utils::{Registrable, typed_value::TypedValue},
+};
+use rune::{ContextError, Module, Value, runtime::VmResult, vm_try};
 use std::{
     cell::RefCell,
     collections::{HashMap, HashSet, hash_map::Entry},
     rc::Rc,
 };
 
+/// Identifies an entity within its scene. Ids are handed out in spawn order.
+pub type EntityId = u64;
+
+/// A scene's map of tag -> entities carrying that tag, shared with the
+/// entities so tagging one updates it.
+pub(crate) type TagIndex = Rc<RefCell<HashMap<String, HashSet<EntityId>>>>;
+
+fn remove_from_index(index: &mut HashMap<String, HashSet<EntityId>>, tag: &str, id: EntityId) {
+    if let Some(ids) = index.get_mut(tag) {
+        ids.remove(&id);
+        if ids.is_empty() {
+            index.remove(tag);
+        }
+    }
+}
+
 #[derive(Default, Debug)]
 pub struct Entity {
+    pub id: EntityId,
+    /// How many times this entity's storage had been reused by a scene's
+    /// entity pool when it was spawned.
+    pub generation: u64,
+    /// Unset for entities that don't belong to a scene.
+    pub(crate) tag_index: Option<TagIndex>,
+    /// The running tweens of the entity's scene, which new tweens on it join.
+    /// Unset for entities that don't belong to a scene.
+    pub(crate) tweens: Option<TweenList>,
     pub behaviours: HashMap<String, BehaviourRef>,
     pub tags: HashSet<String>,
+    /// Free-form per-entity data with no schema, for scripts.
+    pub metadata: HashMap<String, TypedValue>,
 }
 
 #[derive(Clone, Debug, Default, rune::Any)]
@@ -20,11 +54,21 @@ pub struct EntityRef(Rc<RefCell<Entity>>);
 impl Registrable for EntityRef {
     fn register(module: &mut Module) -> Result<(), ContextError> {
         module.ty::<EntityRef>()?;
-        module.function_meta(EntityRef::tag)?;
+        module.function_meta(EntityRef::id__meta)?;
+        module.function_meta(EntityRef::generation__meta)?;
+        module.function_meta(EntityRef::is_alive__meta)?;
+        module.function_meta(EntityRef::tag__meta)?;
+        module.function_meta(EntityRef::untag__meta)?;
         module.function_meta(EntityRef::has_tag__meta)?;
         module.function_meta(EntityRef::attach__meta)?;
         module.function_meta(EntityRef::has_behaviour__meta)?;
         module.function_meta(EntityRef::behaviour__meta)?;
+        module.function_meta(EntityRef::get_or_attach_rune__meta)?;
+        module.function_meta(EntityRef::behaviour_names__meta)?;
+        module.function_meta(EntityRef::behaviour_count__meta)?;
+        module.function_meta(EntityRef::set_meta__meta)?;
+        module.function_meta(EntityRef::get_meta__meta)?;
+        module.function_meta(EntityRef::delete_meta__meta)?;
         Ok(())
     }
 }
@@ -34,9 +78,109 @@ impl EntityRef {
         EntityRef(Rc::new(RefCell::new(Entity::default())))
     }
 
-    #[rune::function]
+    pub(crate) fn with_index(id: EntityId, tag_index: TagIndex, tweens: TweenList) -> Self {
+        EntityRef(Rc::new(RefCell::new(Entity {
+            id,
+            tag_index: Some(tag_index),
+            tweens: Some(tweens),
+            ..Default::default()
+        })))
+    }
+
+    #[rune::function(keep)]
+    pub fn id(&self) -> EntityId {
+        self.0.borrow().id
+    }
+
+    /// Number of times this entity's storage had been recycled when it was
+    /// spawned. Always 0 unless the scene pools entities.
+    #[rune::function(keep)]
+    pub fn generation(&self) -> u64 {
+        self.0.borrow().generation
+    }
+
+    /// Whether the entity is in a scene. False once it has been despawned,
+    /// even if a pooling scene has since reused its storage for another.
+    #[rune::function(keep)]
+    pub fn is_alive(&self) -> bool {
+        self.in_any_index()
+    }
+
+    #[rune::function(keep)]
     pub fn tag(&mut self, tag: &str) {
-        self.0.borrow_mut().tags.insert(tag.into());
+        let mut entity = self.0.borrow_mut();
+        entity.tags.insert(tag.into());
+        if let Some(index) = &entity.tag_index {
+            index
+                .borrow_mut()
+                .entry(tag.into())
+                .or_default()
+                .insert(entity.id);
+        }
+    }
+
+    #[rune::function(keep)]
+    pub fn untag(&mut self, tag: &str) {
+        let mut entity = self.0.borrow_mut();
+        entity.tags.remove(tag);
+        if let Some(index) = &entity.tag_index {
+            remove_from_index(&mut index.borrow_mut(), tag, entity.id);
+        }
+    }
+
+    /// Remove this entity from its scene's tag index, e.g. when it despawns.
+    pub(crate) fn detach_from_index(&self) {
+        let mut entity = self.0.borrow_mut();
+        entity.tweens = None;
+        if let Some(index) = entity.tag_index.take() {
+            let mut index = index.borrow_mut();
+            for tag in &entity.tags {
+                remove_from_index(&mut index, tag, entity.id);
+            }
+        }
+    }
+
+    /// The running tweens of the entity's scene, or `None` if it isn't in one.
+    pub(crate) fn scene_tweens(&self) -> Option<TweenList> {
+        self.0.borrow().tweens.clone()
+    }
+
+    /// Returns true if the entity belongs to any scene.
+    pub(crate) fn in_any_index(&self) -> bool {
+        self.0.borrow().tag_index.is_some()
+    }
+
+    /// Empty a despawned entity and move its storage out for a scene's pool,
+    /// keeping the allocations. Handles to this entity stay pointing at the
+    /// empty husk, so they never see whatever the storage is reused for.
+    pub(crate) fn take_storage(&self) -> Entity {
+        let mut entity = self.0.borrow_mut();
+        entity.behaviours.clear();
+        entity.tags.clear();
+        entity.metadata.clear();
+        Entity {
+            id: entity.id,
+            generation: entity.generation,
+            tag_index: None,
+            tweens: None,
+            behaviours: std::mem::take(&mut entity.behaviours),
+            tags: std::mem::take(&mut entity.tags),
+            metadata: std::mem::take(&mut entity.metadata),
+        }
+    }
+
+    /// Spawn a new entity into a scene under `id`, reusing pooled storage.
+    pub(crate) fn from_storage(
+        mut storage: Entity,
+        id: EntityId,
+        tag_index: TagIndex,
+        tweens: TweenList,
+    ) -> Self {
+        storage.id = id;
+        storage.generation += 1;
+        storage.tag_index = Some(tag_index);
+        storage.tweens = Some(tweens);
+        EntityRef(Rc::new(RefCell::new(storage)))
     }
 
     #[rune::function(keep)]
@@ -72,4 +216,183 @@ impl EntityRef {
     pub fn behaviour(&self, name: &str) -> Option<BehaviourRef> {
         self.0.borrow().behaviours.get(name).cloned()
     }
+
+    /// The behaviour `factory` makes, attaching one built from the schema's
+    /// defaults first if the entity doesn't have it yet. Fails if the schema
+    /// has a required field without a default.
+    pub fn get_or_attach(
+        &mut self,
+        factory: &BehaviourFactory,
+    ) -> Result<BehaviourRef, TetronError> {
+        let behaviours = &mut self.0.try_borrow_mut()?.behaviours;
+        let name = factory.behaviour_name();
+
+        match behaviours.entry(name.clone()) {
+            Entry::Occupied(entry) => Ok(entry.get().clone()),
+            Entry::Vacant(entry) => match factory.default_instance() {
+                Ok(behaviour) => Ok(entry.insert(behaviour).clone()),
+                Err(e) => Err(TetronError::Runtime(format!(
+                    "Cannot attach default {name}: {e}"
+                ))),
+            },
+        }
+    }
+
+    #[rune::function(instance, keep, path = Self::get_or_attach)]
+    fn get_or_attach_rune(&mut self, factory: &BehaviourFactory) -> VmResult<BehaviourRef> {
+        self.get_or_attach(factory).into_vm_result()
+    }
+
+    /// Names of all behaviours attached to this entity, in alphabetical order.
+    #[rune::function(keep)]
+    pub fn behaviour_names(&self) -> Vec<String> {
+        let mut names: Vec<String> = self.0.borrow().behaviours.keys().cloned().collect();
+        names.sort();
+        names
+    }
+
+    #[rune::function(keep)]
+    pub fn behaviour_count(&self) -> i64 {
+        self.0.borrow().behaviours.len() as i64
+    }
+
+    #[rune::function(keep)]
+    pub fn set_meta(&mut self, key: &str, value: Value) -> VmResult<()> {
+        let value = vm_try!(
+            TypedValue::try_from(&value).throw_engine_bug("could not convert metadata value")
+        );
+        self.0.borrow_mut().metadata.insert(key.to_owned(), value);
+        VmResult::Ok(())
+    }
+
+    /// The value stored under `key`. Metadata keeps every number as a float,
+    /// so integers come back as floats.
+    #[rune::function(keep)]
+    pub fn get_meta(&self, key: &str) -> VmResult<Option<Value>> {
+        let Some(value) = self.0.borrow().metadata.get(key).cloned() else {
+            return VmResult::Ok(None);
+        };
+        let value = vm_try!(
+            Value::try_from(&value).throw_engine_bug(&format!("could not convert metadata {key}"))
+        );
+        VmResult::Ok(Some(value))
+    }
+
+    /// Remove `key` from the entity's metadata. Returns false if it wasn't set.
+    #[rune::function(keep)]
+    pub fn delete_meta(&mut self, key: &str) -> bool {
+        self.0.borrow_mut().metadata.remove(key).is_some()
+    }
+
+    /// The entity's tags, in alphabetical order.
+    pub fn tags(&self) -> Vec<String> {
+        let mut tags: Vec<String> = self.0.borrow().tags.iter().cloned().collect();
+        tags.sort();
+        tags
+    }
+
+    /// A copy of the entity's metadata.
+    pub fn metadata(&self) -> HashMap<String, TypedValue> {
+        self.0.borrow().metadata.clone()
+    }
+
+    pub(crate) fn insert_meta(&mut self, key: &str, value: TypedValue) {
+        self.0.borrow_mut().metadata.insert(key.to_owned(), value);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::utils::{
+        RuneVec,
+        typed_value::schema::{Schema, SchemaField},
+    };
+    use rune::ToValue;
+
+    fn behaviour(name: &str) -> BehaviourRef {
+        let schema = Schema::Object {
+            fields: HashMap::new(),
+        };
+        BehaviourFactory::new(name, schema, false).with_map(HashMap::new())
+    }
+
+    #[test]
+    fn test_behaviour_names() {
+        let mut entity = EntityRef::new();
+        assert!(entity.behaviour_names().is_empty());
+        assert_eq!(entity.behaviour_count(), 0);
+
+        entity.attach(behaviour("velocity"));
+        entity.attach(behaviour("health"));
+        assert_eq!(entity.behaviour_names(), vec!["health", "velocity"]);
+        assert_eq!(entity.behaviour_count(), 2);
+    }
+
+    #[test]
+    fn test_get_or_attach() -> Result<(), anyhow::Error> {
+        let mut fields = HashMap::new();
+        fields.insert(
+            "hp".to_string(),
+            SchemaField {
+                schema: Schema::Number.optional_with_default(TypedValue::Number(10.0)),
+            },
+        );
+        let factory = BehaviourFactory::new("health", Schema::Object { fields }, false);
+        let mut entity = EntityRef::new();
+
+        let mut attached = entity.get_or_attach(&factory)?;
+        assert_eq!(entity.behaviour_count(), 1);
+        assert_eq!(attached.get_typed("hp")?, Some(TypedValue::Number(10.0)));
+
+        attached.set("hp", 4.0.to_value()?).into_result()?;
+        let existing = entity.get_or_attach(&factory)?;
+        assert_eq!(entity.behaviour_count(), 1);
+        assert_eq!(existing.get_typed("hp")?, Some(TypedValue::Number(4.0)));
+
+        let builtin = BehaviourFactory::builtin("tetron:transform").unwrap();
+        entity.get_or_attach(&builtin)?;
+        assert!(entity.has_behaviour("tetron:transform"));
+
+        let mut fields = HashMap::new();
+        fields.insert(
+            "name".to_string(),
+            SchemaField {
+                schema: Schema::String,
+            },
+        );
+        let required = BehaviourFactory::new("named", Schema::Object { fields }, false);
+        assert!(entity.get_or_attach(&required).is_err());
+        assert!(!entity.has_behaviour("named"));
+        Ok(())
+    }
+
+    #[test]
+    fn test_metadata() -> Result<(), anyhow::Error> {
+        let mut a = EntityRef::new();
+        let b = EntityRef::new();
+        let get = |entity: &EntityRef, key| entity.get_meta(key).into_result();
+
+        a.set_meta("from_level", true.to_value()?).into_result()?;
+        a.set_meta("spawn_order", 3i64.to_value()?).into_result()?;
+        assert!(get(&a, "from_level")?.unwrap().as_bool()?);
+        assert_eq!(get(&a, "spawn_order")?.unwrap().as_float()?, 3.0);
+        a.set_meta("speed", 2.5.to_value()?).into_result()?;
+        assert_eq!(get(&a, "speed")?.unwrap().as_float()?, 2.5);
+        a.insert_meta(
+            "spawn",
+            TypedValue::Array(vec![TypedValue::Number(4.0), TypedValue::Number(0.5)]),
+        );
+        let spawn = get(&a, "spawn")?.unwrap();
+        let spawn = spawn.borrow_ref::<RuneVec>()?;
+        assert_eq!(spawn[0].as_float()?, 4.0);
+        assert_eq!(spawn[1].as_float()?, 0.5);
+        assert!(get(&b, "from_level")?.is_none());
+
+        assert!(a.delete_meta("from_level"));
+        assert!(get(&a, "from_level")?.is_none());
+        assert!(!a.delete_meta("from_level"));
+        assert!(!a.delete_meta("missing"));
+        Ok(())
+    }
 }
@@ -1,18 +1,140 @@
+use super::physics::vec2::Vec2;
+use crate::{
+    error::{EngineBug, ErrorMode},
+    system_log,
+};
 use rune::{ContextError, Module, docstring};
 use sdl2::{
     event::{Event, WindowEvent},
     keyboard::Scancode,
+    mouse::MouseButton,
 };
+use serde_json::{Value, json};
 use std::{
-    collections::HashSet,
-    sync::{Arc, RwLock},
+    collections::{HashMap, HashSet},
+    sync::{Arc, Mutex, RwLock},
 };
 
+/// Input simulated by a script, applied at the start of the next frame as if
+/// it had come from the hardware. Also what hardware events are recorded as.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum SyntheticEvent {
+    KeyDown(Scancode),
+    KeyUp(Scancode),
+    MouseMove(Vec2),
+    /// The left mouse button, the only one tracked.
+    MouseButton {
+        pressed: bool,
+    },
+    /// Release every key and the mouse button, as when the window loses
+    /// focus.
+    ReleaseAll,
+}
+
+impl SyntheticEvent {
+    /// The input changes an SDL event makes, in order. Events that don't
+    /// affect input, like key repeats, give none.
+    pub fn from_sdl(event: &Event) -> Vec<SyntheticEvent> {
+        match event {
+            Event::KeyDown {
+                scancode: Some(sc),
+                repeat: false,
+                ..
+            } => vec![SyntheticEvent::KeyDown(*sc)],
+            Event::KeyUp {
+                scancode: Some(sc), ..
+            } => vec![SyntheticEvent::KeyUp(*sc)],
+            Event::MouseMotion { x, y, .. } => {
+                vec![SyntheticEvent::MouseMove(Vec2::new(*x as f64, *y as f64))]
+            }
+            Event::MouseButtonDown {
+                mouse_btn: MouseButton::Left,
+                x,
+                y,
+                ..
+            } => vec![
+                SyntheticEvent::MouseMove(Vec2::new(*x as f64, *y as f64)),
+                SyntheticEvent::MouseButton { pressed: true },
+            ],
+            Event::MouseButtonUp {
+                mouse_btn: MouseButton::Left,
+                x,
+                y,
+                ..
+            } => vec![
+                SyntheticEvent::MouseMove(Vec2::new(*x as f64, *y as f64)),
+                SyntheticEvent::MouseButton { pressed: false },
+            ],
+            Event::Window {
+                win_event: WindowEvent::FocusLost,
+                ..
+            } => vec![SyntheticEvent::ReleaseAll],
+            _ => Vec::new(),
+        }
+    }
+
+    /// The event as JSON, with keys by their SDL name: `{"key_down": "A"}`,
+    /// `{"mouse_move": [x, y]}`, `{"mouse_button": true}` or
+    /// `"release_all"`.
+    pub fn to_json(&self) -> Value {
+        match self {
+            SyntheticEvent::KeyDown(sc) => json!({ "key_down": sc.name() }),
+            SyntheticEvent::KeyUp(sc) => json!({ "key_up": sc.name() }),
+            SyntheticEvent::MouseMove(pos) => json!({ "mouse_move": [pos.x, pos.y] }),
+            SyntheticEvent::MouseButton { pressed } => json!({ "mouse_button": pressed }),
+            SyntheticEvent::ReleaseAll => json!("release_all"),
+        }
+    }
+
+    /// Read an event written by [`SyntheticEvent::to_json`].
+    pub fn from_json(value: &Value) -> Option<SyntheticEvent> {
+        if value.as_str() == Some("release_all") {
+            return Some(SyntheticEvent::ReleaseAll);
+        }
+        let (kind, arg) = value.as_object()?.iter().next()?;
+        let scancode = || arg.as_str().and_then(Scancode::from_name);
+        match kind.as_str() {
+            "key_down" => scancode().map(SyntheticEvent::KeyDown),
+            "key_up" => scancode().map(SyntheticEvent::KeyUp),
+            "mouse_move" => match arg.as_array()?.as_slice() {
+                [x, y] => Some(SyntheticEvent::MouseMove(Vec2::new(
+                    x.as_f64()?,
+                    y.as_f64()?,
+                ))),
+                _ => None,
+            },
+            "mouse_button" => Some(SyntheticEvent::MouseButton {
+                pressed: arg.as_bool()?,
+            }),
+            _ => None,
+        }
+    }
+}
+
 #[derive(Default, Debug)]
 pub struct KeyState {
     down: HashSet<Scancode>,
     pressed: HashSet<Scancode>,
     released: HashSet<Scancode>,
+    mouse_pos: Vec2,
+    /// Left mouse button state, tracked like a key.
+    mouse_down: bool,
+    mouse_pressed: bool,
+    mouse_released: bool,
+    /// Seconds each down key has been held for, as of the current frame.
+    held_for: HashMap<Scancode, f64>,
+    /// How long a key must be down before `is_held` reports it.
+    hold_delay: f64,
+    /// Unknown key names that have already been warned about.
+    warned_keys: Mutex<HashSet<String>>,
+    injected_events: Vec<SyntheticEvent>,
+    /// The game's error mode, for engine bugs hit while reading input.
+    error_mode: ErrorMode,
+}
+
+/// Returns true if `name` is a key name SDL recognizes, like "Return" or "A".
+pub fn is_valid_key(name: &str) -> bool {
+    Scancode::from_name(name).is_some()
 }
 
 impl KeyState {
@@ -20,43 +142,85 @@ impl KeyState {
         Self::default()
     }
 
+    pub fn set_error_mode(&mut self, mode: ErrorMode) {
+        self.error_mode = mode;
+    }
+
     pub fn update(&mut self, event: &Event) {
+        for event in SyntheticEvent::from_sdl(event) {
+            self.apply(event);
+        }
+    }
+
+    /// Apply `event` right away.
+    pub fn apply(&mut self, event: SyntheticEvent) {
         match event {
-            Event::KeyDown {
-                scancode: Some(sc),
-                repeat: false,
-                ..
-            } => {
-                if !self.down.contains(sc) {
-                    self.pressed.insert(*sc);
-                }
-                self.down.insert(*sc);
-            }
-            Event::KeyUp {
-                scancode: Some(sc), ..
-            } => {
-                self.down.remove(sc);
-                self.released.insert(*sc);
-            }
-            Event::Window {
-                win_event: WindowEvent::FocusLost,
-                ..
-            } => {
-                self.clear_all();
+            SyntheticEvent::KeyDown(sc) => self.key_down(sc),
+            SyntheticEvent::KeyUp(sc) => self.key_up(sc),
+            SyntheticEvent::MouseMove(pos) => self.mouse_pos = pos,
+            SyntheticEvent::MouseButton { pressed } => self.mouse_button(pressed),
+            SyntheticEvent::ReleaseAll => self.clear_all(),
+        }
+    }
+
+    fn key_down(&mut self, sc: Scancode) {
+        if !self.down.contains(&sc) {
+            self.pressed.insert(sc);
+            self.held_for.insert(sc, 0.0);
+        }
+        self.down.insert(sc);
+    }
+
+    fn key_up(&mut self, sc: Scancode) {
+        self.down.remove(&sc);
+        self.held_for.remove(&sc);
+        self.released.insert(sc);
+    }
+
+    fn mouse_button(&mut self, pressed: bool) {
+        if pressed {
+            if !self.mouse_down {
+                self.mouse_pressed = true;
             }
-            _ => {}
+            self.mouse_down = true;
+        } else {
+            self.mouse_down = false;
+            self.mouse_released = true;
+        }
+    }
+
+    /// Queue `event` to be applied by the next [`KeyState::apply_injected`].
+    pub fn inject(&mut self, event: SyntheticEvent) {
+        self.injected_events.push(event);
+    }
+
+    /// Apply injected events, in the order they were injected. Run at the
+    /// start of each frame, after the real events.
+    pub fn apply_injected(&mut self) {
+        for event in std::mem::take(&mut self.injected_events) {
+            self.apply(event);
         }
     }
 
-    pub fn next_frame(&mut self) {
+    /// Finish a frame that lasted `delta` seconds.
+    pub fn next_frame(&mut self, delta: f64) {
+        for held in self.held_for.values_mut() {
+            *held += delta;
+        }
         self.pressed.clear();
         self.released.clear();
+        self.mouse_pressed = false;
+        self.mouse_released = false;
     }
 
     fn clear_all(&mut self) {
         self.down.clear();
+        self.held_for.clear();
         self.pressed.clear();
         self.released.clear();
+        self.mouse_down = false;
+        self.mouse_pressed = false;
+        self.mouse_released = false;
     }
 
     pub fn is_down(&self, name: &str) -> bool {
@@ -71,29 +235,105 @@ impl KeyState {
         self.check_set(name, &self.released)
     }
 
+    /// Unlike [`KeyState::is_down`], false on the frame a key is pressed,
+    /// and until it has been down for the hold delay.
     pub fn is_held(&self, name: &str) -> bool {
-        self.check_set(name, &self.down) // `down` reflects held keys
+        self.check_set(name, &self.down)
+            && !self.check_set(name, &self.pressed)
+            && self.hold_time(name) >= self.hold_delay
     }
 
+    /// Seconds `name` has been down for, not counting the current frame, or
+    /// 0 if it's up.
+    pub fn hold_time(&self, name: &str) -> f64 {
+        Scancode::from_name(name)
+            .and_then(|sc| self.held_for.get(&sc))
+            .copied()
+            .unwrap_or(0.0)
+    }
+
+    /// Set how many seconds a key must be down before `is_held` is true.
+    /// Negative delays count as 0.
+    pub fn set_hold_delay(&mut self, seconds: f64) {
+        self.hold_delay = seconds.max(0.0);
+    }
+
+    /// The last known mouse position, in window coordinates.
+    pub fn mouse_pos(&self) -> Vec2 {
+        self.mouse_pos
+    }
+
+    pub fn mouse_down(&self) -> bool {
+        self.mouse_down
+    }
+
+    pub fn mouse_just_pressed(&self) -> bool {
+        self.mouse_pressed
+    }
+
+    pub fn mouse_just_released(&self) -> bool {
+        self.mouse_released
+    }
+
+    /// Unknown key names are never down, but warn once so typos don't go
+    /// unnoticed.
     fn check_set(&self, name: &str, set: &HashSet<Scancode>) -> bool {
-        Scancode::from_name(name).is_some_and(|v| set.contains(&v))
+        let Some(scancode) = Scancode::from_name(name) else {
+            if let Some(mut warned) = self
+                .warned_keys
+                .lock()
+                .engine_bug("input lock poisoned", self.error_mode)
+                && warned.insert(name.to_owned())
+            {
+                system_log!("Unknown key name \"{name}\"; see tetron::input::is_valid_key");
+            }
+            return false;
+        };
+        set.contains(&scancode)
+    }
+}
+
+/// Queue a synthetic key event for `name`, warning about unknown names.
+fn inject_key(
+    input: &RwLock<KeyState>,
+    name: &str,
+    event: fn(Scancode) -> SyntheticEvent,
+    mode: ErrorMode,
+) {
+    let Some(scancode) = Scancode::from_name(name) else {
+        system_log!("Can't inject unknown key \"{name}\"; see tetron::input::is_valid_key");
+        return;
+    };
+    if let Some(mut guard) = input.write().engine_bug("input lock poisoned", mode) {
+        guard.inject(event(scancode));
     }
 }
 
-pub fn module(input: Arc<RwLock<KeyState>>) -> Result<Module, ContextError> {
+pub fn module(input: Arc<RwLock<KeyState>>, mode: ErrorMode) -> Result<Module, ContextError> {
     let mut module = Module::with_crate_item("tetron", ["input"])?;
 
+    module
+        .function("is_valid_key", is_valid_key)
+        .build()?
+        .docs(docstring! {
+            /// Returns true if `key` is a key name the input functions
+            /// recognize. Unknown names are never reported as pressed.
+        })?;
+
     module
         .function("is_down", {
             let input = input.clone();
             move |k: &str| -> bool {
-                let guard = input.read().expect("Engine bug: input lock poisoned");
-                guard.is_down(k)
+                input
+                    .read()
+                    .engine_bug("input lock poisoned", mode)
+                    .is_some_and(|guard| guard.is_down(k))
             }
         })
         .build()?
         .docs(docstring! {
-            /// Returns true if the specified key is currently down (pressed).
+            /// Returns true if the specified key is currently down (pressed),
+            /// including on the frame it was pressed.
             /// # Arguments
             /// * `key` - The name of the key to check, as string.
         })?;
@@ -102,8 +342,10 @@ pub fn module(input: Arc<RwLock<KeyState>>) -> Result<Module, ContextError> {
         .function("just_pressed", {
             let input = input.clone();
             move |k: &str| -> bool {
-                let guard = input.read().expect("Engine bug: input lock poisoned");
-                guard.just_pressed(k)
+                input
+                    .read()
+                    .engine_bug("input lock poisoned", mode)
+                    .is_some_and(|guard| guard.just_pressed(k))
             }
         })
         .build()?
@@ -117,8 +359,10 @@ pub fn module(input: Arc<RwLock<KeyState>>) -> Result<Module, ContextError> {
         .function("just_released", {
             let input = input.clone();
             move |k: &str| -> bool {
-                let guard = input.read().expect("Engine bug: input lock poisoned");
-                guard.just_released(k)
+                input
+                    .read()
+                    .engine_bug("input lock poisoned", mode)
+                    .is_some_and(|guard| guard.just_released(k))
             }
         })
         .build()?
@@ -132,16 +376,210 @@ pub fn module(input: Arc<RwLock<KeyState>>) -> Result<Module, ContextError> {
         .function("is_held", {
             let input = input.clone();
             move |k: &str| -> bool {
-                let guard = input.read().expect("Engine bug: input lock poisoned");
-                guard.is_held(k)
+                input
+                    .read()
+                    .engine_bug("input lock poisoned", mode)
+                    .is_some_and(|guard| guard.is_held(k))
             }
         })
         .build()?
         .docs(docstring! {
-            /// Returns true if the specified key is held down.
+            /// Returns true if the specified key was already down before this
+            /// frame and has been down for at least the hold delay (0 seconds
+            /// unless changed with `set_hold_delay` or `input.hold_delay` in
+            /// game.json). Use `is_down` to react on the very first frame.
             /// # Arguments
             /// * `key` - The name of the key to check, as string.
         })?;
 
+    module
+        .function("hold_time", {
+            let input = input.clone();
+            move |k: &str| -> f64 {
+                input
+                    .read()
+                    .engine_bug("input lock poisoned", mode)
+                    .map(|guard| guard.hold_time(k))
+                    .unwrap_or(0.0)
+            }
+        })
+        .build()?
+        .docs(docstring! {
+            /// Seconds the specified key has been down for before this frame,
+            /// or 0 if it's up or was pressed this frame.
+        })?;
+
+    module
+        .function("set_hold_delay", {
+            let input = input.clone();
+            move |seconds: f64| {
+                if let Some(mut guard) = input.write().engine_bug("input lock poisoned", mode) {
+                    guard.set_hold_delay(seconds);
+                }
+            }
+        })
+        .build()?
+        .docs(docstring! {
+            /// Set how many seconds a key must be down before `is_held` returns
+            /// true, e.g. a longer delay in menus so a tap moves the cursor once.
+        })?;
+
+    module
+        .function("mouse_pos", {
+            let input = input.clone();
+            move || -> Vec2 {
+                input
+                    .read()
+                    .engine_bug("input lock poisoned", mode)
+                    .map(|guard| guard.mouse_pos())
+                    .unwrap_or(Vec2::ZERO)
+            }
+        })
+        .build()?
+        .docs(docstring! {
+            /// The mouse position, in window coordinates.
+        })?;
+
+    module
+        .function("mouse_down", {
+            let input = input.clone();
+            move || -> bool {
+                input
+                    .read()
+                    .engine_bug("input lock poisoned", mode)
+                    .is_some_and(|guard| guard.mouse_down())
+            }
+        })
+        .build()?
+        .docs(docstring! {
+            /// Returns true if the left mouse button is down.
+        })?;
+
+    module
+        .function("mouse_just_pressed", {
+            let input = input.clone();
+            move || -> bool {
+                input
+                    .read()
+                    .engine_bug("input lock poisoned", mode)
+                    .is_some_and(|guard| guard.mouse_just_pressed())
+            }
+        })
+        .build()?
+        .docs(docstring! {
+            /// Returns true if the left mouse button was pressed this frame.
+        })?;
+
+    module
+        .function("mouse_just_released", {
+            let input = input.clone();
+            move || -> bool {
+                input
+                    .read()
+                    .engine_bug("input lock poisoned", mode)
+                    .is_some_and(|guard| guard.mouse_just_released())
+            }
+        })
+        .build()?
+        .docs(docstring! {
+            /// Returns true if the left mouse button was released this frame.
+        })?;
+
+    module
+        .function("inject_key_down", {
+            let input = input.clone();
+            move |k: &str| inject_key(&input, k, SyntheticEvent::KeyDown, mode)
+        })
+        .build()?
+        .docs(docstring! {
+            /// Press `key` at the start of the next frame, as if the player had.
+            /// For automated tests and replays.
+        })?;
+
+    module
+        .function("inject_key_up", {
+            let input = input.clone();
+            move |k: &str| inject_key(&input, k, SyntheticEvent::KeyUp, mode)
+        })
+        .build()?
+        .docs(docstring! {
+            /// Release `key` at the start of the next frame.
+        })?;
+
+    module
+        .function("inject_mouse_move", {
+            let input = input.clone();
+            move |pos: Vec2| {
+                if let Some(mut guard) = input.write().engine_bug("input lock poisoned", mode) {
+                    guard.inject(SyntheticEvent::MouseMove(pos));
+                }
+            }
+        })
+        .build()?
+        .docs(docstring! {
+            /// Move the mouse to `pos`, in window coordinates, at the start of
+            /// the next frame.
+        })?;
+
+    module
+        .function("inject_mouse_button", {
+            let input = input.clone();
+            move |button: &str, pressed: bool| {
+                if !button.eq_ignore_ascii_case("left") {
+                    system_log!("Can't inject mouse button \"{button}\"; only \"left\" is tracked");
+                    return;
+                }
+                if let Some(mut guard) = input.write().engine_bug("input lock poisoned", mode) {
+                    guard.inject(SyntheticEvent::MouseButton { pressed });
+                }
+            }
+        })
+        .build()?
+        .docs(docstring! {
+            /// Press or release a mouse button at the start of the next frame.
+            /// Only `"left"` is tracked.
+        })?;
+
     Ok(module)
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_key_names() {
+        assert!(is_valid_key("Return"));
+        assert!(is_valid_key("A"));
+        assert!(!is_valid_key("Retrun"));
+
+        let input = KeyState::new();
+        assert!(!input.is_down("Retrun"));
+        assert!(!input.just_pressed("Retrun"));
+        assert_eq!(input.warned_keys.lock().unwrap().len(), 1);
+    }
+
+    #[test]
+    fn test_is_held_waits_for_hold_delay() {
+        let mut input = KeyState::new();
+        input.inject(SyntheticEvent::KeyDown(Scancode::A));
+        input.apply_injected();
+        assert!(input.is_down("A"));
+        assert!(!input.is_held("A"));
+
+        input.next_frame(0.1);
+        assert!(input.is_held("A"));
+        assert_eq!(input.hold_time("A"), 0.1);
+
+        input.set_hold_delay(0.25);
+        input.next_frame(0.1);
+        assert!(!input.is_held("A"));
+        input.next_frame(0.1);
+        assert!(input.is_held("A"));
+
+        input.inject(SyntheticEvent::KeyUp(Scancode::A));
+        input.apply_injected();
+        assert!(!input.is_held("A"));
+        assert_eq!(input.hold_time("A"), 0.0);
+    }
+}
@@ -1,113 +1,475 @@
 use crate::{
+    audio::{self, AudioBackend, AudioQueue, NullAudioBackend},
     engine::physics::vec2::Vec2,
-    error::TetronError,
-    fs::{SimpleFs, overlay_fs::OverlayFs, to_vfs_layer},
-    scripting::{self, TetronScripting},
-    sdl::TetronSdlHandle,
+    error::{ErrorMode, TetronError},
+    fs::{SimpleFs, overlay_fs::OverlayFs, read_files_parallel, to_vfs_layer},
+    kv::{
+        backend::{self, BackendFactory},
+        cache::{CachedBackend, FlushHandle},
+        config::ConfigExt,
+    },
+    scripting::{self, Flags, FrameState, GameHandles, GameRng, TetronScripting},
+    sdl::{DrawCommand, TetronSdlHandle, TextAnchor, TextOutline, TextStyle, to_rect},
+    system_log,
     utils::{parse_hex_color, resolve_physical_fs_path, typed_value::TypedValue},
 };
-use input::KeyState;
+use entity::EntityId;
+use input::{KeyState, SyntheticEvent};
+use replay::Recording;
+use rune::FromValue;
 use sdl2::{event::Event, keyboard::Keycode, pixels::Color};
 use std::{
     collections::HashSet,
+    io::Write,
+    path::PathBuf,
     process,
-    rc::Rc,
     sync::{Arc, RwLock},
-    time::Instant,
+    time::{Duration, Instant},
+};
+use stupid_simple_kv::{
+    IntoKey, Kv, KvBackend, KvValue, MemoryBackend, display::parse_display_string_to_key,
 };
-use stupid_simple_kv::{IntoKey, Kv, KvBackend, KvValue, MemoryBackend, SqliteBackend};
 use systems::Ctx;
 use world::WorldRef;
 
 mod args;
 pub mod behaviours;
+pub mod button;
+pub mod diff;
 pub mod drawable;
 pub mod entity;
 pub mod input;
 pub mod physics;
+pub mod replay;
 pub mod scene;
 pub mod shape;
 pub mod systems;
 pub mod transform;
+pub mod tween;
 pub mod world;
-pub use args::TetronArgs;
+pub use args::{Command, TetronArgs};
+
+/// How often cached flag changes are written out, with `flags.cache` on.
+const FLAGS_FLUSH_INTERVAL: Duration = Duration::from_secs(1);
+
+/// Whether the game wants to keep running after [`Game::run_frame`].
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum FrameResult {
+    Continue,
+    Quit,
+}
 
 pub struct Game {
-    fs: Rc<dyn SimpleFs>,
+    fs: Arc<dyn SimpleFs>,
     pub(crate) config: Arc<Kv>,
-    sdl: TetronSdlHandle,
+    flags: Flags,
+    flags_cache: FlushHandle,
+    /// `None` when running headless.
+    sdl: Option<TetronSdlHandle>,
     pub identifier: String,
     scripting: TetronScripting,
     world: Option<WorldRef>,
     input: Arc<RwLock<KeyState>>,
+    audio: Box<dyn AudioBackend>,
+    audio_queue: AudioQueue,
+    frame_state: FrameState,
+    /// The game's RNG behind `tetron::random`.
+    rng: GameRng,
+    /// Frame time to use instead of the measured one, in deterministic mode.
+    fixed_dt: Option<f64>,
+    /// Entities already reported for having a drawable that renders nothing.
+    warned_empty_drawables: HashSet<EntityId>,
+    /// Unknown text anchors already reported.
+    warned_anchors: HashSet<String>,
+    /// With the debug overlay on, this key logs every entity in the scene.
+    inspect_key: Option<String>,
+    /// Frames run since the last `begin`.
+    frames: u64,
+    /// The player's input so far, while [`Game::record`] is running.
+    recording: Option<Recording>,
+    /// How engine bugs and frame errors are handled, from `errors:mode`.
+    error_mode: ErrorMode,
+    /// Separation passes per frame, from `physics:iterations`.
+    physics_iterations: u32,
 }
 
 fn parse_fonts_from_config(config: &Arc<Kv>) -> Vec<(String, String)> {
     let mut fonts = Vec::new();
     if let Ok(Some(KvValue::Array(list))) = config.get(&("fonts",)) {
         for font in list {
-            if let KvValue::Object(cfg) = font {
-                if let (Some(KvValue::String(name)), Some(KvValue::String(path))) =
+            if let KvValue::Object(cfg) = font
+                && let (Some(KvValue::String(name)), Some(KvValue::String(path))) =
                     (cfg.get("name"), cfg.get("path"))
-                {
-                    fonts.push((name.clone(), path.clone()));
-                }
+            {
+                fonts.push((name.clone(), path.clone()));
             }
         }
     }
     fonts
 }
 
+fn load_config(
+    fs: &dyn SimpleFs,
+    overrides: &[(String, String)],
+) -> Result<Arc<Kv>, anyhow::Error> {
+    let json = fs.read_text_file("game.json")?;
+    let mut config = Kv::from_json_string(Box::new(MemoryBackend::new()), json)?;
+    apply_config_overrides(&mut config, overrides)?;
+    expand_config_env(&mut config, |name| std::env::var(name).ok())?;
+    Ok(Arc::new(config))
+}
+
+/// Replace each `${VAR}` in `s` with `lookup(VAR)`. Unset variables are left
+/// as written if `keep_missing`, otherwise they're an error.
+fn expand_env(
+    s: &str,
+    lookup: &impl Fn(&str) -> Option<String>,
+    keep_missing: bool,
+) -> Result<String, TetronError> {
+    let mut out = String::with_capacity(s.len());
+    let mut rest = s;
+    while let Some(start) = rest.find("${") {
+        let Some(len) = rest[start + 2..].find('}') else {
+            break;
+        };
+        let name = &rest[start + 2..start + 2 + len];
+        let end = start + 3 + len;
+        out.push_str(&rest[..start]);
+        match lookup(name) {
+            Some(value) => out.push_str(&value),
+            None if keep_missing => out.push_str(&rest[start..end]),
+            None => {
+                return Err(TetronError::Other(format!(
+                    "Config refers to unset environment variable {name}"
+                )));
+            }
+        }
+        rest = &rest[end..];
+    }
+    out.push_str(rest);
+    Ok(out)
+}
+
+fn expand_value_env(
+    value: KvValue,
+    lookup: &impl Fn(&str) -> Option<String>,
+    keep_missing: bool,
+) -> Result<KvValue, TetronError> {
+    Ok(match value {
+        KvValue::String(s) => KvValue::String(expand_env(&s, lookup, keep_missing)?),
+        KvValue::Array(items) => KvValue::Array(
+            items
+                .into_iter()
+                .map(|item| expand_value_env(item, lookup, keep_missing))
+                .collect::<Result<_, _>>()?,
+        ),
+        KvValue::Object(map) => KvValue::Object(
+            map.into_iter()
+                .map(|(k, v)| Ok((k, expand_value_env(v, lookup, keep_missing)?)))
+                .collect::<Result<_, TetronError>>()?,
+        ),
+        other => other,
+    })
+}
+
+/// Substitute environment variables into string config values, e.g.
+/// `"data_path": "${GAME_DATA}"`. `config:env_missing` decides what happens
+/// to unset variables: `keep` (the default) leaves `${VAR}` as written and
+/// `error` refuses to start.
+fn expand_config_env(
+    config: &mut Kv,
+    lookup: impl Fn(&str) -> Option<String>,
+) -> Result<(), TetronError> {
+    let missing = config
+        .get_string("config:env_missing")?
+        .unwrap_or_else(|| "keep".into());
+    let keep_missing = match missing.as_str() {
+        "keep" => true,
+        "error" => false,
+        _ => {
+            return Err(TetronError::Other(format!(
+                "Invalid config:env_missing \"{missing}\". Valid values: keep, error"
+            )));
+        }
+    };
+    for (key, value) in config.entries()? {
+        let expanded = expand_value_env(value.clone(), &lookup, keep_missing)?;
+        if expanded != value {
+            config.set(&key, expanded)?;
+        }
+    }
+    Ok(())
+}
+
+/// The flags backend named by `flags.backend` in the config, SQLite if unset.
+/// SQLite databases are kept under `data_dir`; see [`backend::data_dir`].
+fn configured_backend(
+    config: &Kv,
+    data_dir: Option<PathBuf>,
+) -> Result<BackendFactory, TetronError> {
+    let name = config
+        .get_string("flags:backend")?
+        .unwrap_or_else(|| "sqlite".into());
+    backend::by_name(&name, data_dir)
+}
+
+/// Apply `--set key=value` overrides on top of the config. Values that
+/// aren't valid JSON are stored as strings.
+fn apply_config_overrides(
+    config: &mut Kv,
+    overrides: &[(String, String)],
+) -> Result<(), TetronError> {
+    for (key, value) in overrides {
+        let kv_key = parse_display_string_to_key(key)
+            .ok_or(TetronError::Other(format!("Invalid config key \"{key}\"")))?;
+        let json = serde_json::from_str(value)
+            .unwrap_or_else(|_| serde_json::Value::String(value.clone()));
+        config.set(&kv_key, KvValue::from(&json))?;
+    }
+    Ok(())
+}
+
 impl Game {
-    fn new<F>(fs: Rc<dyn SimpleFs>, backend_factory: F) -> Result<Self, anyhow::Error>
+    fn new<F>(
+        layers: Arc<RwLock<OverlayFs>>,
+        config: Arc<Kv>,
+        backend_factory: F,
+        headless: bool,
+    ) -> Result<Self, anyhow::Error>
     where
         F: FnOnce(&str) -> Result<Box<dyn KvBackend>, anyhow::Error>,
     {
-        let json = fs.read_text_file("game.json")?;
-        let config = Arc::new(Kv::from_json_string(Box::new(MemoryBackend::new()), json)?);
+        let fs: Arc<dyn SimpleFs> = layers.clone();
+        let identifier = config
+            .get_string("identifier")?
+            .ok_or(TetronError::RequiredConfigNotFound("identifier".into()))?;
 
-        let identifier: String = config
-            .get(&("identifier",))?
-            .ok_or(TetronError::RequiredConfigNotFound("identifier".into()))?
-            .try_into()?;
+        let use_cache = config.get_bool("flags:cache")?.unwrap_or(false);
+        let (backend, flags_cache) = if use_cache {
+            let (cached, handle) = CachedBackend::new(backend_factory(&identifier)?)?;
+            (Box::new(cached) as Box<dyn KvBackend>, handle)
+        } else {
+            (backend_factory(&identifier)?, FlushHandle::default())
+        };
+        let flags = Flags::new(Arc::new(RwLock::new(Kv::new(backend))));
 
-        let flags = Arc::new(RwLock::new(Kv::new(backend_factory(&identifier)?)));
+        let width = config.get_i64("sdl:width")?.unwrap_or(800);
+        let height = config.get_i64("sdl:height")?.unwrap_or(600);
+        let title = config
+            .get_string("sdl:title")?
+            .unwrap_or_else(|| identifier.clone());
 
-        let width: i64 = config
-            .get(&("sdl", "width").to_key())?
-            .unwrap_or(800i64.into())
-            .try_into()?;
-        let height: i64 = config
-            .get(&("sdl", "height").to_key())?
-            .unwrap_or(600i64.into())
-            .try_into()?;
-        let title: String = config
-            .get(&("sdl", "title").to_key())?
-            .unwrap_or(identifier.clone().into())
-            .try_into()?;
+        let error_mode = config
+            .get_string("errors:mode")?
+            .unwrap_or_else(|| "panic".into());
+        let error_mode = ErrorMode::parse(&error_mode).ok_or(TetronError::Other(format!(
+            "Invalid error mode \"{error_mode}\". Valid modes: panic, recover"
+        )))?;
 
-        let fonts_to_load = parse_fonts_from_config(&config);
-        let mut sdl = TetronSdlHandle::new(&title, width.try_into()?, height.try_into()?)?;
-        sdl.load_fonts(&fonts_to_load, fs.clone())?;
-        let input = Arc::new(RwLock::new(KeyState::new()));
-        let scripting =
-            TetronScripting::new(fs.clone(), flags, config.clone(), Arc::clone(&input))?;
+        let physics_iterations = config.get_i64("physics:iterations")?.unwrap_or(1);
+        let physics_iterations = u32::try_from(physics_iterations)
+            .ok()
+            .filter(|n| *n >= 1)
+            .ok_or(TetronError::Other(format!(
+                "Invalid physics:iterations {physics_iterations}: must be at least 1"
+            )))?;
+
+        let (sdl, audio): (_, Box<dyn AudioBackend>) = if headless {
+            (None, Box::new(NullAudioBackend))
+        } else {
+            let fonts_to_load = parse_fonts_from_config(&config);
+            let mut sdl = TetronSdlHandle::new(&title, width.try_into()?, height.try_into()?)?;
+            sdl.load_fonts(&fonts_to_load, fs.clone())?;
+            let images = config.get_strings("preload:images")?.unwrap_or_default();
+            let images = images.iter().map(String::as_str).collect::<Vec<_>>();
+            sdl.preload_images(&images, fs.as_ref())?;
+            let channels = audio::clamp_channels(
+                config
+                    .get_i64("audio:channels")?
+                    .unwrap_or(audio::DEFAULT_CHANNELS as i64),
+            );
+            // Built with the `rodio` feature, audio skips SDL entirely.
+            #[cfg(feature = "rodio")]
+            let backend = audio::rodio::RodioBackend::new(channels)
+                .map(|backend| Box::new(backend) as Box<dyn AudioBackend>);
+            #[cfg(not(feature = "rodio"))]
+            let backend = match &sdl.audio {
+                Some(_) => audio::sdl_mixer::SdlMixerBackend::new(channels)
+                    .map(|backend| Box::new(backend) as Box<dyn AudioBackend>),
+                None => Ok(Box::new(NullAudioBackend) as Box<dyn AudioBackend>),
+            };
+            let mut backend = backend.unwrap_or_else(|e| {
+                system_log!("Could not open audio, running without sound: {e}");
+                Box::new(NullAudioBackend)
+            });
+            let sounds = config.get_strings("preload:sounds")?.unwrap_or_default();
+            let sounds = sounds.iter().map(String::as_str).collect::<Vec<_>>();
+            for (path, bytes) in sounds.iter().zip(read_files_parallel(fs.as_ref(), &sounds)) {
+                backend.preload_sound(path, bytes?)?;
+            }
+            (Some(sdl), backend)
+        };
+        let mut key_state = KeyState::new();
+        key_state.set_error_mode(error_mode);
+        key_state.set_hold_delay(config.get_f64("input:hold_delay")?.unwrap_or(0.0));
+        let input = Arc::new(RwLock::new(key_state));
+        let audio_queue = AudioQueue::new();
+        let frame_state = FrameState::new();
+        let rng = GameRng::new();
+        let scripting = TetronScripting::new(GameHandles {
+            flags: flags.clone(),
+            flags_cache: flags_cache.clone(),
+            config: config.clone(),
+            input: Arc::clone(&input),
+            fs: layers,
+            audio: audio_queue.clone(),
+            frame: frame_state.clone(),
+            rng: rng.clone(),
+            mode: error_mode,
+        })?;
         Ok(Self {
             fs,
             config,
+            flags,
+            flags_cache,
             sdl,
             identifier,
             scripting,
             world: None,
             input,
+            audio,
+            audio_queue,
+            frame_state,
+            rng,
+            fixed_dt: None,
+            warned_empty_drawables: HashSet::new(),
+            warned_anchors: HashSet::new(),
+            inspect_key: None,
+            frames: 0,
+            recording: None,
+            error_mode,
+            physics_iterations,
         })
     }
 }
 
-impl TryFrom<TetronArgs> for Game {
-    type Error = anyhow::Error;
+impl Game {
+    /// Start building a game programmatically, e.g. to embed the engine or
+    /// to test a game without the CLI.
+    pub fn builder() -> GameBuilder {
+        GameBuilder::default()
+    }
 
-    fn try_from(args: TetronArgs) -> Result<Self, Self::Error> {
+    /// Start building a game that stores its flags in the backend returned by
+    /// `factory`, instead of the default SQLite database. The factory is
+    /// passed the game's identifier.
+    pub fn with_backend_factory(
+        factory: impl FnOnce(&str) -> Box<dyn KvBackend> + 'static,
+    ) -> GameBuilder {
+        GameBuilder::default().backend(factory)
+    }
+}
+
+/// Builds a [`Game`] with parts of the engine swapped out.
+#[derive(Default)]
+pub struct GameBuilder {
+    fs: Option<Arc<dyn SimpleFs>>,
+    backend_factory: Option<BackendFactory>,
+    overrides: Vec<(String, String)>,
+    headless: bool,
+    deterministic: bool,
+    data_dir: Option<PathBuf>,
+}
+
+impl GameBuilder {
+    /// Load the game from `fs`. Required by [`GameBuilder::build`].
+    pub fn fs(mut self, fs: Arc<dyn SimpleFs>) -> Self {
+        self.fs = Some(fs);
+        self
+    }
+
+    /// Override a game.json value, like `--set key=value` on the command line.
+    pub fn set(mut self, key: &str, value: &str) -> Self {
+        self.overrides.push((key.to_owned(), value.to_owned()));
+        self
+    }
+
+    /// Run without opening a window. Nothing is drawn, no input arrives and
+    /// audio commands are discarded; the game runs until a script quits.
+    pub fn headless(mut self, headless: bool) -> Self {
+        self.headless = headless;
+        self
+    }
+
+    /// Override the error mode set in game.json.
+    pub fn error_mode(self, mode: ErrorMode) -> Self {
+        self.set("errors:mode", mode.as_str())
+    }
+
+    /// Advance every frame by exactly 1/60s instead of the measured frame
+    /// time, and seed the game's RNG with 0. Scene RNGs are already derived
+    /// from the world seed, so together this makes runs reproducible.
+    pub fn deterministic(mut self, deterministic: bool) -> Self {
+        self.deterministic = deterministic;
+        self
+    }
+
+    fn finish(deterministic: bool, mut game: Game) -> Game {
+        if deterministic {
+            game.fixed_dt = Some(1.0 / 60.0);
+            game.rng.seed(0, game.error_mode);
+        }
+        game
+    }
+
+    /// Keep flags and saves under `dir` instead of the user's data directory.
+    /// Ignored if a backend factory is set.
+    pub fn data_dir(mut self, dir: impl Into<PathBuf>) -> Self {
+        self.data_dir = Some(dir.into());
+        self
+    }
+
+    /// Use the backend returned by `factory` for flags and saves.
+    pub fn backend(mut self, factory: impl FnOnce(&str) -> Box<dyn KvBackend> + 'static) -> Self {
+        self.backend_factory = Some(Box::new(move |identifier| Ok(factory(identifier))));
+        self
+    }
+
+    /// The old name of [`GameBuilder::backend`].
+    #[deprecated(note = "use GameBuilder::backend")]
+    pub fn backend_factory(
+        self,
+        factory: impl FnOnce(&str) -> Box<dyn KvBackend> + 'static,
+    ) -> Self {
+        self.backend(factory)
+    }
+
+    /// Load the game from the filesystem set with [`GameBuilder::fs`].
+    pub fn build(self) -> Result<Game, anyhow::Error> {
+        let fs = self
+            .fs
+            .ok_or(TetronError::Other("GameBuilder: no filesystem set".into()))?;
+        let config = load_config(fs.as_ref(), &self.overrides)?;
+        let backend_factory = match self.backend_factory {
+            Some(factory) => factory,
+            None => configured_backend(&config, self.data_dir)?,
+        };
+        let fs = OverlayFs::from_layers(vec![Box::new(fs)]);
+        let game = Game::new(
+            Arc::new(RwLock::new(fs)),
+            config,
+            backend_factory,
+            self.headless,
+        )?;
+        Ok(Self::finish(self.deterministic, game))
+    }
+
+    /// Load the game described by `args`. A filesystem set with
+    /// [`GameBuilder::fs`] is ignored in favour of the one `args` describes.
+    pub fn build_from_args(mut self, args: TetronArgs) -> Result<Game, anyhow::Error> {
+        if args.no_telemetry {
+            TetronError::set_telemetry_handler(|_| {});
+        }
         let game_path = match args.game {
             Some(p) => resolve_physical_fs_path(&p)?,
             None => {
@@ -122,38 +484,183 @@ impl TryFrom<TetronArgs> for Game {
             layers.push(to_vfs_layer(layer)?);
         }
 
-        let fs = OverlayFs::from_layers(layers);
+        let mut fs = OverlayFs::from_layers(layers);
+        self.overrides.extend(args.overrides);
+        if args.data_dir.is_some() {
+            self.data_dir = args.data_dir;
+        }
+        if let Some(backend) = args.flags_backend {
+            self.overrides.push(("flags:backend".into(), backend));
+        }
+        let config = load_config(&fs, &self.overrides)?;
+
+        let case_insensitive = config.get_bool("fs:case_insensitive")?.unwrap_or(false);
+        if case_insensitive {
+            fs.enable_case_insensitive();
+        }
 
-        let backend_factory = |identifier: &str| -> Result<Box<dyn KvBackend>, anyhow::Error> {
-            let data =
-                dirs::data_dir().ok_or(TetronError::Other("Error getting user data dir".into()))?;
-            let db_path = data.join("tetron").join(identifier);
-            std::fs::create_dir_all(&db_path)?;
-            Ok(Box::new(SqliteBackend::file(&db_path.join("flags.db"))?))
+        let backend_factory = match self.backend_factory {
+            Some(factory) => factory,
+            None => configured_backend(&config, self.data_dir)?,
         };
 
-        Self::new(Rc::new(fs), backend_factory)
+        let game = Game::new(
+            Arc::new(RwLock::new(fs)),
+            config,
+            backend_factory,
+            self.headless,
+        )?;
+        Ok(Self::finish(self.deterministic || args.deterministic, game))
+    }
+}
+
+impl TryFrom<TetronArgs> for Game {
+    type Error = anyhow::Error;
+
+    fn try_from(args: TetronArgs) -> Result<Self, Self::Error> {
+        GameBuilder::default().build_from_args(args)
+    }
+}
+
+/// Read a drawable font's `anchor`. An unknown anchor falls back to the
+/// top left, and is logged the first time it's seen so typos are noticed.
+fn parse_text_anchor(anchor: &str, warned: &mut HashSet<String>) -> TextAnchor {
+    TextAnchor::parse(anchor).unwrap_or_else(|| {
+        if warned.insert(anchor.to_owned()) {
+            system_log!(
+                "Unknown text anchor \"{anchor}\", using topleft. Valid anchors: topleft, \
+                 topcenter, topright, centerleft, center, centerright, bottomleft, \
+                 bottomcenter, bottomright"
+            );
+        }
+        TextAnchor::default()
+    })
+}
+
+/// Read a drawable font's `outline` object. The color defaults to black.
+fn parse_text_outline(outline: &TypedValue) -> Option<TextOutline> {
+    let TypedValue::Object(map) = outline else {
+        return None;
+    };
+    let Some(TypedValue::Number(width)) = map.get("width") else {
+        return None;
+    };
+    let color = match map.get("color") {
+        Some(TypedValue::String(color)) => parse_hex_color(color, Color::BLACK),
+        _ => Color::BLACK,
+    };
+    Some(TextOutline {
+        width: *width,
+        color,
+    })
+}
+
+/// Decide whether an error raised during a frame should end the game. In
+/// `ErrorMode::Recover` the error is logged and the rest of the frame skipped.
+fn recover_frame(mode: ErrorMode, err: TetronError) -> Result<(), TetronError> {
+    match (mode, err) {
+        (_, TetronError::Quit) => Err(TetronError::Quit),
+        (ErrorMode::Panic, err) => Err(err),
+        (ErrorMode::Recover, err) => {
+            system_log!("Skipping frame after error: {err}");
+            Ok(())
+        }
     }
 }
 
+/// Draw every button in the scene in the color for its current state, with
+/// its label inset slightly from the top-left corner.
+fn draw_buttons(sdl: &mut TetronSdlHandle, ctx: &Ctx) -> Result<(), TetronError> {
+    let behaviours = HashSet::from(["tetron:button".to_string(), "tetron:transform".to_string()]);
+    for entity in ctx.query_with_sets(HashSet::new(), behaviours, None, 0, None)? {
+        let (Some(button), Some(transform)) = (
+            entity.behaviour("tetron:button"),
+            entity.behaviour("tetron:transform"),
+        ) else {
+            continue;
+        };
+        let string = |field: &str| -> Result<Option<String>, TetronError> {
+            Ok(match button.get_typed(field)? {
+                Some(TypedValue::String(s)) => Some(s),
+                _ => None,
+            })
+        };
+        let number = |field: &str| -> Result<f64, TetronError> {
+            Ok(match button.get_typed(field)? {
+                Some(TypedValue::Number(n)) => n,
+                _ => 0.0,
+            })
+        };
+        let pos = match transform.get_typed("pos")? {
+            Some(TypedValue::Vector(pos)) => pos,
+            _ => Vec2::ZERO,
+        };
+        let state = string("state")?.unwrap_or_else(|| "normal".into());
+        let color = parse_hex_color(&string(&state)?.unwrap_or_default(), Color::GRAY);
+        sdl.draw_rect(pos, number("w")?, number("h")?, color, true)?;
+        if let Some(label) = string("label")? {
+            let text_color =
+                parse_hex_color(&string("text_color")?.unwrap_or_default(), Color::WHITE);
+            sdl.draw_text(
+                &label,
+                pos + Vec2::new(4.0, 4.0),
+                &TextStyle::new(text_color),
+            )?;
+        }
+    }
+    Ok(())
+}
+
 impl Game {
     fn update(&mut self, delta: f64) -> Result<(), TetronError> {
+        self.flags_cache.flush_every(FLAGS_FLUSH_INTERVAL)?;
         if let Some(world) = &mut self.world {
+            self.flags.poll_watches(world)?;
+            let ctx = Ctx::new(world.clone(), delta);
+            button::update_buttons(&ctx, &*self.input.read()?)?;
+            physics::step(&ctx, delta, self.physics_iterations)?;
             world.game_loop(delta)?;
         }
 
         Ok(())
     }
 
-    fn draw(&mut self, dt: f64) -> Result<(), TetronError> {
+    /// Run the audio commands scripts queued this frame. A sound that fails
+    /// to play isn't worth stopping the game over, so failures are logged.
+    fn process_audio(&mut self) -> Result<(), TetronError> {
+        for command in self.audio_queue.drain()? {
+            if let Err(e) = self.audio.execute(self.fs.as_ref(), command.clone()) {
+                system_log!("Audio command {command:?} failed: {e}");
+            }
+        }
+        self.audio.update();
+        self.audio_queue.set_playing(self.audio.playing_channels());
+        Ok(())
+    }
+
+    /// Clear the canvas and draw the frame. Does nothing when headless.
+    fn draw(&mut self, dt: f64, commands: Vec<DrawCommand>) -> Result<(), TetronError> {
+        let Some(sdl) = &mut self.sdl else {
+            return Ok(());
+        };
+        sdl.canvas.set_draw_color(sdl2::pixels::Color::RGB(0, 0, 0));
+        sdl.canvas.clear();
         if let Some(world) = self.world.clone() {
             let ctx = Ctx::new(world, dt);
+            if let Some(scene) = ctx.try_scene()
+                && let Some(background) = scene.config("background")
+            {
+                let path = String::from_value(background).map_err(|e| {
+                    TetronError::Runtime(format!("Scene background must be a path: {e}"))
+                })?;
+                sdl.draw_background(self.fs.as_ref(), &path)?;
+            }
             let behaviours: HashSet<String> = HashSet::from_iter([
                 "tetron:drawable".to_string(),
                 "tetron:transform".to_string(),
             ]);
             let tags = HashSet::new();
-            let queried = ctx.query_with_sets(tags, behaviours)?;
+            let queried = ctx.query_with_sets(tags, behaviours, None, 0, None)?;
             for entity in queried {
                 let drawable = match entity.behaviour("tetron:drawable") {
                     Some(d) => d,
@@ -166,7 +673,7 @@ impl Game {
                 // Get color from drawable (fallback white)
                 let color = parse_hex_color(
                     &drawable
-                        .get_typed("color")
+                        .get_typed("color")?
                         .and_then(|v| match v {
                             TypedValue::String(s) => Some(s),
                             _ => None,
@@ -175,168 +682,1027 @@ impl Game {
                     Color::WHITE,
                 );
                 // Parse position from transform
-                let pos: Option<Vec2> = transform.get_typed("pos").and_then(|v| match v {
+                let pos: Option<Vec2> = transform.get_typed("pos")?.and_then(|v| match v {
                     TypedValue::Vector(v2) => Some(v2),
                     _ => None,
                 });
                 let pos = pos.unwrap_or(Vec2::ZERO);
 
                 // Draw text if present
-                if let Some(TypedValue::String(txt)) = drawable.get_typed("text") {
+                if let Some(TypedValue::String(txt)) = drawable.get_typed("text")? {
                     // font config (optional)
-                    let font_conf = drawable.get_typed("font");
-                    let (font_name, font_size) = if let Some(TypedValue::Object(map)) = &font_conf {
-                        (
-                            map.get("face").and_then(|v| {
-                                if let TypedValue::String(s) = v {
-                                    Some(s.clone())
-                                } else {
-                                    None
-                                }
-                            }),
-                            map.get("size").and_then(|v| {
-                                if let TypedValue::Number(sz) = v {
-                                    Some(*sz)
-                                } else {
-                                    None
-                                }
-                            }),
-                        )
-                    } else {
-                        (None, None)
-                    };
-                    self.sdl.draw_text(&txt, pos, font_name, font_size, color)?;
+                    let font_conf = drawable.get_typed("font")?;
+                    let mut style = TextStyle::new(color);
+                    if let Some(TypedValue::Object(map)) = &font_conf {
+                        style.font_name = map.get("face").and_then(|v| {
+                            if let TypedValue::String(s) = v {
+                                Some(s.clone())
+                            } else {
+                                None
+                            }
+                        });
+                        style.font_size = map.get("size").and_then(|v| {
+                            if let TypedValue::Number(sz) = v {
+                                Some(*sz)
+                            } else {
+                                None
+                            }
+                        });
+                        style.outline = map.get("outline").and_then(parse_text_outline);
+                        if let Some(TypedValue::String(anchor)) = map.get("anchor") {
+                            style.anchor = parse_text_anchor(anchor, &mut self.warned_anchors);
+                        }
+                    }
+                    sdl.draw_text(&txt, pos, &style)?;
                     continue;
                 }
                 // TODO: Sprites and animations not implemented
-                if drawable.get_typed("sprite").is_some() {
+                if drawable.get_typed("sprite")?.is_some() {
                     todo!("Sprite rendering not implemented!");
                 }
-                if drawable.get_typed("anim").is_some() {
+                if drawable.get_typed("anim")?.is_some() {
                     todo!("Anim rendering not implemented!");
                 }
                 // Otherwise, try shape
-                if let Some(shape) = entity.behaviour("tetron:shape") {
-                    if let Some(TypedValue::String(sh_type)) = shape.get_typed("type") {
-                        match sh_type.as_str() {
-                            "rect" => {
-                                let w = shape
-                                    .get_typed("w")
-                                    .and_then(|v| match v {
-                                        TypedValue::Number(f) => Some(f),
-                                        _ => None,
-                                    })
-                                    .unwrap_or(1.0);
-                                let h = shape
-                                    .get_typed("h")
-                                    .and_then(|v| match v {
-                                        TypedValue::Number(f) => Some(f),
+                let mut drawn = false;
+                if let Some(shape) = entity.behaviour("tetron:shape")
+                    && let Some(TypedValue::String(sh_type)) = shape.get_typed("type")?
+                {
+                    match sh_type.as_str() {
+                        "rect" => {
+                            let (w, h) = (shape::size(&shape, "w"), shape::size(&shape, "h"));
+                            sdl.draw_rect(pos, w, h, color, true)?;
+                            drawn = true;
+                        }
+                        "circle" => {
+                            let r = shape::size(&shape, "r");
+                            sdl.draw_circle(pos, r, color, true)?;
+                            drawn = true;
+                        }
+                        "poly" => {
+                            if let Some(TypedValue::Array(points)) = shape.get_typed("points")? {
+                                let points: Vec<Vec2> = points
+                                    .into_iter()
+                                    .filter_map(|val| match val {
+                                        TypedValue::Vector(v) => Some(v),
                                         _ => None,
                                     })
-                                    .unwrap_or(1.0);
-                                self.sdl.draw_rect(pos, w, h, color, true)?;
+                                    .collect();
+                                if points.len() >= 3 {
+                                    sdl.draw_polygon(&points, color, true)?;
+                                    drawn = true;
+                                }
                             }
-                            "circle" => {
-                                let r = shape
-                                    .get_typed("r")
-                                    .and_then(|v| match v {
-                                        TypedValue::Number(f) => Some(f),
+                        }
+                        "line" => {
+                            if let Some(TypedValue::Array(points)) = shape.get_typed("points")? {
+                                let vv: Vec<Vec2> = points
+                                    .into_iter()
+                                    .filter_map(|val| match val {
+                                        TypedValue::Vector(v) => Some(v),
                                         _ => None,
                                     })
-                                    .unwrap_or(1.0);
-                                self.sdl.draw_circle(pos, r, color, true)?;
-                            }
-                            "poly" => {
-                                if let Some(TypedValue::Array(points)) = shape.get_typed("points") {
-                                    let points: Vec<Vec2> = points
-                                        .into_iter()
-                                        .filter_map(|val| match val {
-                                            TypedValue::Vector(v) => Some(v),
-                                            _ => None,
-                                        })
-                                        .collect();
-                                    if points.len() >= 3 {
-                                        self.sdl.draw_polygon(&points, color, true)?;
-                                    }
-                                }
-                            }
-                            "line" => {
-                                if let Some(TypedValue::Array(points)) = shape.get_typed("points") {
-                                    let vv: Vec<Vec2> = points
-                                        .into_iter()
-                                        .filter_map(|val| match val {
-                                            TypedValue::Vector(v) => Some(v),
-                                            _ => None,
-                                        })
-                                        .collect();
-                                    if vv.len() == 2 {
-                                        self.sdl.draw_line(vv[0], vv[1], color)?;
-                                    }
+                                    .collect();
+                                if vv.len() == 2 {
+                                    sdl.draw_line(vv[0], vv[1], color)?;
+                                    drawn = true;
                                 }
                             }
-                            _ => {}
                         }
+                        _ => {}
                     }
                 }
                 // If no text and no shape, nothing is rendered
+                if !drawn && self.warned_empty_drawables.insert(entity.id()) {
+                    scripting::log::engine_debug(&format!(
+                        "entity {} has a tetron:drawable but nothing was rendered for it; \
+                         give it text or a tetron:shape",
+                        entity.id()
+                    ));
+                }
             }
+            for command in commands {
+                match command {
+                    DrawCommand::Texture {
+                        path,
+                        src_pos,
+                        src_size,
+                        dst_pos,
+                        dst_size,
+                    } => {
+                        if let (Some(src), Some(dst)) =
+                            (to_rect(src_pos, src_size), to_rect(dst_pos, dst_size))
+                        {
+                            sdl.draw_texture(self.fs.as_ref(), &path, src, dst)?;
+                        }
+                    }
+                }
+            }
+            draw_buttons(sdl, &ctx)?;
             // Drawing logic ends here
         }
         Ok(())
     }
 
+    /// Run the game until the player quits. An error that ends the game is
+    /// passed to the telemetry handler before being returned; see
+    /// [`TetronError::set_telemetry_handler`].
     pub fn run(&mut self) -> Result<(), TetronError> {
-        let mut last_frame = Instant::now();
+        self.run_loop().inspect_err(TetronError::report)
+    }
 
-        let entrypoint: String = self
+    /// Run the game like [`Game::run`], recording the player's input to
+    /// `out` for [`Game::replay`]. The recording is written even if the game
+    /// ends in an error.
+    pub fn record(&mut self, out: impl Write) -> Result<(), TetronError> {
+        self.recording = Some(Recording::default());
+        let result = self.run();
+        let recording = self.recording.take().unwrap_or_default();
+        recording.save(out)?;
+        result
+    }
+
+    /// Play a recorded session back from the start, one frame per recorded
+    /// frame, ignoring the player's input. After each frame, the position of
+    /// every entity is written to `log` as a line of JSON; see
+    /// [`replay::positions_json`]. Replays only match the original run in
+    /// deterministic mode.
+    pub fn replay(&mut self, recording: &Recording, log: impl Write) -> Result<(), TetronError> {
+        self.replay_loop(recording, log)
+            .inspect_err(TetronError::report)
+    }
+
+    fn entrypoint(&self) -> Result<String, TetronError> {
+        Ok(self
             .config
             .get(&("entrypoint",).to_key())?
             .ok_or(TetronError::RequiredConfigNotFound("entrypoint".into()))?
-            .try_into()?;
+            .try_into()?)
+    }
 
-        let world = WorldRef::new();
+    /// The config the game is running with, after mod layers, `--set`
+    /// overrides and environment variables, as pretty-printed JSON in the
+    /// same form as game.json.
+    pub fn config_json(&self) -> Result<String, TetronError> {
+        // Dumping needs a mutable Kv, so dump a copy.
+        let mut copy = Kv::new(Box::new(MemoryBackend::new()));
+        for (key, value) in self.config.list().entries()? {
+            copy.set(&key, value)?;
+        }
+        serde_json::to_string_pretty(&copy.to_serde_json()?)
+            .map_err(|e| TetronError::Conversion(format!("Failed to write config: {e}")))
+    }
 
-        println!("tetron: running {}", self.identifier);
+    /// Check that the game could start: the entrypoint is configured and its
+    /// scripts compile. Nothing is run. Config errors are caught earlier,
+    /// when the game is built.
+    pub fn check(&mut self) -> Result<(), TetronError> {
+        let entrypoint = self.entrypoint()?;
+        self.scripting.compile(&entrypoint)
+    }
+
+    /// Create the world and run the entrypoint's `begin` with it. Call this
+    /// once before [`Game::run_frame`] when driving the game yourself.
+    pub fn begin(&mut self) -> Result<WorldRef, TetronError> {
+        let entrypoint = self.entrypoint()?;
         let level: String = self
             .config
             .get(&("log", "level").to_key())?
             .unwrap_or("info".into())
             .try_into()?;
-
         scripting::log::level(&level);
 
+        let overlay: bool = self
+            .config
+            .get(&("debug", "overlay").to_key())?
+            .unwrap_or(false.into())
+            .try_into()?;
+        self.inspect_key = if overlay {
+            Some(
+                self.config
+                    .get(&("debug", "inspect_key").to_key())?
+                    .unwrap_or("F1".into())
+                    .try_into()?,
+            )
+        } else {
+            None
+        };
+
+        let world = WorldRef::for_game(self.fs.clone(), self.error_mode);
         self.scripting
             .execute(&entrypoint, ["begin"], (world.clone(),))?;
-        self.world = Some(world);
+        self.world = Some(world.clone());
+        self.frames = 0;
+        Ok(world)
+    }
+
+    /// Run one frame, `delta` seconds long, after handling `events`: the SDL
+    /// events since the last frame. This is [`Game::run`]'s loop body, for
+    /// embedding the engine in an application that owns the event loop.
+    /// Returns [`FrameResult::Quit`] without running the frame if the player
+    /// closed the window or pressed Escape.
+    pub fn run_frame(
+        &mut self,
+        delta: f64,
+        events: Vec<Event>,
+    ) -> Result<FrameResult, TetronError> {
+        let mut recorded = Vec::new();
+        for event in events {
+            self.input.write()?.update(&event);
+            if let Event::Quit { .. }
+            | Event::KeyDown {
+                keycode: Some(Keycode::Escape),
+                ..
+            } = event
+            {
+                return Ok(FrameResult::Quit);
+            }
+            if self.recording.is_some() {
+                recorded.extend(SyntheticEvent::from_sdl(&event));
+            }
+        }
+        if let Some(recording) = &mut self.recording {
+            recording.frames.push(recorded);
+        }
+        self.step(delta)?;
+        Ok(FrameResult::Continue)
+    }
+
+    /// Run one frame after its input events have been applied.
+    fn step(&mut self, delta: f64) -> Result<(), TetronError> {
+        self.input.write()?.apply_injected();
+
+        self.frame(delta)?;
+
+        if let Some(key) = &self.inspect_key
+            && self.input.read()?.just_pressed(key)
+            && let Some(world) = &self.world
+            && let Some(scene) = Ctx::new(world.clone(), delta).try_scene()
+        {
+            scripting::log::engine_debug(&scripting::debug::dump(&scene));
+        }
+
+        self.input.write()?.next_frame(delta);
+        Ok(())
+    }
+
+    /// Update, draw and play audio for one frame, `delta` seconds long.
+    fn frame(&mut self, delta: f64) -> Result<(), TetronError> {
+        self.frame_state.set_first_frame(self.frames == 0);
+        self.frames += 1;
+        let updated = self.update(delta);
+        self.frame_state.set_first_frame(false);
+        // Drained every frame, even headless or after a failed update, so
+        // the queue can't grow forever.
+        let commands = self.frame_state.draw.drain()?;
+        if let Err(e) = updated {
+            recover_frame(self.error_mode, e)?;
+        } else {
+            if let Err(e) = self.draw(delta, commands) {
+                recover_frame(self.error_mode, e)?;
+            }
+            if let Some(sdl) = &mut self.sdl {
+                sdl.canvas.present();
+            }
+        }
+        self.process_audio()
+    }
+
+    fn run_loop(&mut self) -> Result<(), TetronError> {
+        println!("tetron: running {}", self.identifier);
+        let world = self.begin()?;
+        let mut last_frame = Instant::now();
 
-        'running: loop {
+        loop {
             let now = Instant::now();
-            let delta = now.duration_since(last_frame).as_secs_f64();
+            let delta = self
+                .fixed_dt
+                .unwrap_or_else(|| now.duration_since(last_frame).as_secs_f64());
             last_frame = now;
 
-            for event in self.sdl.events.poll_iter() {
-                self.input.write()?.update(&event);
-                match event {
-                    Event::Quit { .. }
-                    | Event::KeyDown {
-                        keycode: Some(Keycode::Escape),
-                        ..
-                    } => break 'running,
-                    _ => {}
+            let events = match &mut self.sdl {
+                Some(sdl) => sdl.events.poll_iter().collect(),
+                None => Vec::new(),
+            };
+            if let FrameResult::Quit = self.run_frame(delta, events)? {
+                break;
+            }
+        }
+
+        self.end(world)
+    }
+
+    fn replay_loop(
+        &mut self,
+        recording: &Recording,
+        mut log: impl Write,
+    ) -> Result<(), TetronError> {
+        let world = self.begin()?;
+        let delta = self.fixed_dt.unwrap_or(1.0 / 60.0);
+        let write_error =
+            |e: std::io::Error| TetronError::FsError(format!("Could not write replay log: {e}"));
+
+        for (frame, events) in recording.frames.iter().enumerate() {
+            if let Some(sdl) = &mut self.sdl {
+                let events: Vec<Event> = sdl.events.poll_iter().collect();
+                if events.iter().any(|e| matches!(e, Event::Quit { .. })) {
+                    break;
+                }
+            }
+            {
+                let mut input = self.input.write()?;
+                for event in events {
+                    input.apply(*event);
+                }
+            }
+            self.step(delta)?;
+
+            let scene = Ctx::new(world.clone(), delta).try_scene();
+            let line = replay::positions_json(frame as u64 + 1, scene.as_ref())?;
+            writeln!(log, "{line}").map_err(write_error)?;
+        }
+        log.flush().map_err(write_error)?;
+
+        self.end(world)
+    }
+
+    /// Run the entrypoint's `end`, if it has one, and save flags.
+    fn end(&mut self, world: WorldRef) -> Result<(), TetronError> {
+        self.scripting
+            .execute_if_defined(&self.entrypoint()?, ["end"], (world,))?;
+        self.flags_cache.flush()?;
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::fs::zip_fs::ZipFs;
+
+    /// A minimal game.json, for tests that don't care about the config.
+    const GAME_JSON: (&str, &str) = (
+        "game.json",
+        r#"{ "identifier": "test.game", "entrypoint": "main.rn" }"#,
+    );
+
+    /// A game made of `files`, with [`GAME_JSON`] added if they have no
+    /// game.json of their own.
+    fn game_fs(files: &[(&str, &str)]) -> Arc<dyn SimpleFs> {
+        let mut files = files.to_vec();
+        if files.iter().all(|(name, _)| *name != "game.json") {
+            files.insert(0, GAME_JSON);
+        }
+        Arc::new(ZipFs::from_files(&files))
+    }
+
+    /// A builder for a headless game made of `files`, with flags kept in
+    /// memory.
+    fn headless_game(files: &[(&str, &str)]) -> GameBuilder {
+        Game::builder()
+            .fs(game_fs(files))
+            .backend(|_| backend::memory())
+            .headless(true)
+    }
+
+    #[test]
+    fn test_unknown_text_anchor_is_reported_once() {
+        let mut warned = HashSet::new();
+        assert_eq!(parse_text_anchor("Center", &mut warned), TextAnchor::Center);
+        assert!(warned.is_empty());
+        assert_eq!(
+            parse_text_anchor("centre", &mut warned),
+            TextAnchor::TopLeft
+        );
+        assert_eq!(
+            parse_text_anchor("centre", &mut warned),
+            TextAnchor::TopLeft
+        );
+        assert_eq!(warned, HashSet::from(["centre".to_string()]));
+    }
+
+    #[test]
+    fn test_config_overrides() -> Result<(), TetronError> {
+        let json = r#"{ "identifier": "test", "sdl:width": 800 }"#.to_string();
+        let mut config = Kv::from_json_string(Box::new(MemoryBackend::new()), json)?;
+        let overrides = [
+            ("sdl:width".to_string(), "1024".to_string()),
+            ("start_level".to_string(), "3".to_string()),
+            ("sdl:title".to_string(), "Debug build".to_string()),
+        ];
+        apply_config_overrides(&mut config, &overrides)?;
+
+        assert_eq!(config.get(&("sdl", "width"))?, Some(KvValue::I64(1024)));
+        assert_eq!(config.get(&("start_level",))?, Some(KvValue::I64(3)));
+        assert_eq!(
+            config.get(&("sdl", "title"))?,
+            Some(KvValue::String("Debug build".into()))
+        );
+        assert_eq!(
+            config.get(&("identifier",))?,
+            Some(KvValue::String("test".into()))
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_builder_headless() -> Result<(), anyhow::Error> {
+        use std::{cell::RefCell, rc::Rc};
+
+        assert!(Game::builder().headless(true).build().is_err());
+
+        let seen = Rc::new(RefCell::new(None));
+        let game = Game::builder()
+            .fs(game_fs(&[]))
+            .set("identifier", "test.override")
+            .backend({
+                let seen = seen.clone();
+                move |identifier| {
+                    *seen.borrow_mut() = Some(identifier.to_owned());
+                    backend::memory()
+                }
+            })
+            .headless(true)
+            .build()?;
+
+        assert_eq!(game.identifier, "test.override");
+        assert_eq!(seen.borrow().as_deref(), Some("test.override"));
+        assert!(game.sdl.is_none());
+        Ok(())
+    }
+
+    #[test]
+    fn test_audio_without_device() -> Result<(), anyhow::Error> {
+        // Headless games get the same no-op backend as machines without audio.
+        const SCRIPT: &str = r#"
+            use tetron::audio;
+
+            pub fn begin(world) {
+                audio::play_sound("missing.wav");
+                audio::play_sound_ex("missing.wav", 0.5, -1.0);
+                audio::play_music("missing.ogg");
+                audio::play_music_fade("missing.ogg", 1, 500);
+                audio::fade_out_music(500);
+                audio::stop_music();
+                audio::set_volume(0.5);
+                audio::set_music_volume(0.5);
+                audio::allocate_channels(16);
+            }
+        "#;
+        let mut game = headless_game(&[("main.rn", SCRIPT)]).build()?;
+
+        game.begin()?;
+        assert_eq!(game.run_frame(0.016, Vec::new())?, FrameResult::Continue);
+        assert!(game.audio_queue.drain()?.is_empty());
+        assert_eq!(game.audio_queue.playing(), 0);
+        Ok(())
+    }
+
+    #[test]
+    fn test_injected_input() -> Result<(), anyhow::Error> {
+        use input::SyntheticEvent;
+        use sdl2::keyboard::Scancode;
+
+        let mut game = headless_game(&[("main.rn", "pub fn begin(world) {}")]).build()?;
+        game.begin()?;
+
+        game.input
+            .write()
+            .unwrap()
+            .inject(SyntheticEvent::KeyDown(Scancode::A));
+        assert!(!game.input.read().unwrap().is_down("A"));
+        game.run_frame(0.016, Vec::new())?;
+        assert!(game.input.read().unwrap().is_down("A"));
+
+        game.input
+            .write()
+            .unwrap()
+            .inject(SyntheticEvent::KeyUp(Scancode::A));
+        game.run_frame(0.016, Vec::new())?;
+        assert!(!game.input.read().unwrap().is_down("A"));
+        Ok(())
+    }
+
+    #[test]
+    fn test_flags_backend_from_config() -> Result<(), anyhow::Error> {
+        // No backend factory, so the configured backend is used.
+        let game = Game::builder()
+            .fs(game_fs(&[]))
+            .set("flags:backend", "memory")
+            .headless(true)
+            .build()?;
+        game.flags.set(&("seen",), KvValue::Bool(true))?;
+        assert_eq!(game.flags.get(&("seen",))?, Some(KvValue::Bool(true)));
+
+        assert!(
+            Game::builder()
+                .fs(game_fs(&[]))
+                .set("flags:backend", "floppy")
+                .headless(true)
+                .build()
+                .is_err()
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_config_env_expansion() -> Result<(), TetronError> {
+        let lookup = |name: &str| (name == "HOME").then(|| "/home/me".to_string());
+        let mut config = Kv::from_json_string(
+            Box::new(MemoryBackend::new()),
+            r#"{ "save": "${HOME}/saves", "paths": ["${HOME}", "${NOPE}"], "n": 3 }"#.into(),
+        )?;
+        expand_config_env(&mut config, lookup)?;
+        assert_eq!(
+            config.get(&("save",))?,
+            Some(KvValue::String("/home/me/saves".into()))
+        );
+        assert_eq!(
+            config.get(&("paths",))?,
+            Some(KvValue::Array(vec![
+                KvValue::String("/home/me".into()),
+                KvValue::String("${NOPE}".into()),
+            ]))
+        );
+        assert_eq!(config.get(&("n",))?, Some(KvValue::I64(3)));
+
+        let mut strict = Kv::from_json_string(
+            Box::new(MemoryBackend::new()),
+            r#"{ "config:env_missing": "error", "path": "${NOPE}" }"#.into(),
+        )?;
+        assert!(expand_config_env(&mut strict, lookup).is_err());
+
+        assert_eq!(expand_env("a ${HOME", &lookup, false)?, "a ${HOME");
+        Ok(())
+    }
+
+    #[test]
+    fn test_check() -> Result<(), anyhow::Error> {
+        let mut good = headless_game(&[
+            // Compiles, but fails if it's run.
+            ("main.rn", "pub fn begin(world) { world.no_such_method(); }"),
+        ])
+        .build()?;
+        good.check()?;
+        assert!(good.world.is_none());
+
+        let mut broken =
+            headless_game(&[("main.rn", "pub fn begin(world) { let = ; }")]).build()?;
+        assert!(broken.check().is_err());
+
+        let mut missing = headless_game(&[]).build()?;
+        assert!(matches!(
+            missing.check(),
+            Err(TetronError::ModuleNotFound(_))
+        ));
+
+        let mut no_entrypoint =
+            headless_game(&[("game.json", r#"{ "identifier": "test.game" }"#)]).build()?;
+        assert!(matches!(
+            no_entrypoint.check(),
+            Err(TetronError::RequiredConfigNotFound(_))
+        ));
+        Ok(())
+    }
+
+    #[test]
+    fn test_config_json_reflects_layers() -> Result<(), anyhow::Error> {
+        let base = ZipFs::from_files(&[(
+            "game.json",
+            r#"{ "identifier": "test.game", "entrypoint": "main.rn", "sdl:title": "Base" }"#,
+        )]);
+        let layer = ZipFs::from_files(&[(
+            "game.json",
+            r#"{ "identifier": "test.game", "entrypoint": "main.rn", "sdl:title": "Modded" }"#,
+        )]);
+        let fs = OverlayFs::from_layers(vec![Box::new(base), Box::new(layer)]);
+        let game = headless_game(&[])
+            .fs(Arc::new(RwLock::new(fs)))
+            .set("sdl:width", "1024")
+            .build()?;
+
+        let json: serde_json::Value = serde_json::from_str(&game.config_json()?)?;
+        assert_eq!(json["sdl:title"], "Modded");
+        assert_eq!(json["sdl:width"], 1024);
+        assert_eq!(json["identifier"], "test.game");
+        assert!(game.world.is_none());
+        Ok(())
+    }
+
+    #[test]
+    fn test_end_runs_in_the_unit_begin_compiled() -> Result<(), anyhow::Error> {
+        let base = ZipFs::from_files(&[
+            GAME_JSON,
+            (
+                "main.rn",
+                r#"
+                    use tetron::flags;
+                    pub fn begin(world) {}
+                    pub fn end(world) { flags::set(["ended"], true); }
+                "#,
+            ),
+        ]);
+        let fs = Arc::new(RwLock::new(OverlayFs::from_layers(vec![Box::new(base)])));
+        let mut game = headless_game(&[]).fs(fs.clone()).build()?;
+        let world = game.begin()?;
+
+        // A layer mounted mid-game doesn't get `end` recompiled from it.
+        fs.write()
+            .unwrap()
+            .push_layer(Box::new(ZipFs::from_files(&[(
+                "main.rn",
+                "pub fn end(world) { let = ; }",
+            )])));
+        game.end(world)?;
+        assert_eq!(game.flags.get(&("ended",))?, Some(KvValue::Bool(true)));
+        Ok(())
+    }
+
+    #[test]
+    fn test_invalid_width_names_key() {
+        let err = headless_game(&[(
+            "game.json",
+            r#"{ "identifier": "test.game", "entrypoint": "main.rn", "sdl:width": "wide" }"#,
+        )])
+        .build()
+        .err()
+        .expect("a string width should be rejected");
+        assert!(err.to_string().contains("\"sdl:width\""), "{err}");
+    }
+
+    #[test]
+    fn test_run_frame() -> Result<(), anyhow::Error> {
+        let mut game = headless_game(&[(
+            "main.rn",
+            r#"
+                use tetron::{flags, game};
+
+                fn count_first(ctx) {
+                    if game::is_first_frame() {
+                        flags::set(["first"], flags::get(["first"]).unwrap_or(0) + 1);
+                    }
+                }
+
+                pub fn begin(world) {
+                    let scene = world.scene("main", #{});
+                    scene.system("count_first", count_first);
+                    world.load_scene("main");
+                }
+                "#,
+        )])
+        .build()?;
+
+        let world = game.begin()?;
+        for _ in 0..5 {
+            assert_eq!(game.run_frame(0.016, Vec::new())?, FrameResult::Continue);
+        }
+        assert_eq!(world.tick_count(), 5);
+        assert_eq!(game.flags.get(&("first",))?, Some(KvValue::I64(1)));
+
+        let quit = Event::Quit { timestamp: 0 };
+        assert_eq!(game.run_frame(0.016, vec![quit])?, FrameResult::Quit);
+        assert_eq!(world.tick_count(), 5);
+        Ok(())
+    }
+
+    #[test]
+    fn test_invalid_set_many_entry_is_an_error() -> Result<(), anyhow::Error> {
+        let mut game = headless_game(&[(
+            "main.rn",
+            r#"
+                use tetron::flags;
+
+                pub fn begin(world) {
+                    let result = flags::set_many([[["a"], 1], [["b"]]]);
+                    flags::set(["failed"], result.is_err());
                 }
+                "#,
+        )])
+        .build()?;
+
+        game.begin()?;
+        assert_eq!(game.flags.get(&("failed",))?, Some(KvValue::Bool(true)));
+        assert_eq!(game.flags.get(&("a",))?, None);
+        Ok(())
+    }
+
+    #[test]
+    fn test_flag_watches_live_on_the_world() -> Result<(), anyhow::Error> {
+        let mut game = headless_game(&[(
+            "main.rn",
+            r#"
+                use tetron::flags;
+
+                fn bump(ctx) {
+                    flags::set(["score"], flags::get(["score"]).unwrap_or(0) + 1);
+                }
+
+                pub fn begin(world) {
+                    flags::on_change(world, ["score"], |new, old| {
+                        flags::set(["seen"], new);
+                    });
+                    let scene = world.scene("main", #{});
+                    scene.system("bump", bump);
+                    world.load_scene("main");
+                }
+                "#,
+        )])
+        .build()?;
+
+        game.begin()?;
+        for _ in 0..3 {
+            game.run_frame(0.016, Vec::new())?;
+        }
+        // Watches are checked before systems run, so the last bump is only
+        // seen next frame.
+        assert_eq!(game.flags.get(&("score",))?, Some(KvValue::I64(3)));
+        assert_eq!(game.flags.get(&("seen",))?, Some(KvValue::I64(2)));
+        Ok(())
+    }
+
+    #[test]
+    fn test_error_mode_is_per_game() -> Result<(), anyhow::Error> {
+        const SCRIPT: &str = r#"
+            fn fail(ctx) {
+                ctx.query(#{ order: "sideways" });
+            }
+
+            pub fn begin(world) {
+                let scene = world.scene("main", #{});
+                scene.system("fail", fail);
+                world.load_scene("main");
             }
+            "#;
+        let mut recovering = headless_game(&[("main.rn", SCRIPT)])
+            .error_mode(ErrorMode::Recover)
+            .build()?;
+        let mut panicking = headless_game(&[("main.rn", SCRIPT)]).build()?;
 
-            self.update(delta)?;
-            self.sdl
-                .canvas
-                .set_draw_color(sdl2::pixels::Color::RGB(0, 0, 0));
-            self.sdl.canvas.clear();
-            self.draw(delta)?;
-            self.sdl.canvas.present();
+        let world = recovering.begin()?;
+        assert_eq!(world.error_mode(), ErrorMode::Recover);
+        assert_eq!(panicking.begin()?.error_mode(), ErrorMode::Panic);
+        recovering.run_frame(0.016, Vec::new())?;
+        assert!(panicking.run_frame(0.016, Vec::new()).is_err());
+        Ok(())
+    }
 
-            self.input.write()?.next_frame();
+    #[test]
+    fn test_physics_iterations_config() -> Result<(), anyhow::Error> {
+        let build = |iterations: &str| {
+            headless_game(&[("main.rn", "")])
+                .set("physics:iterations", iterations)
+                .build()
+        };
+        assert_eq!(headless_game(&[]).build()?.physics_iterations, 1);
+        assert_eq!(build("4")?.physics_iterations, 4);
+        assert!(build("0").is_err());
+        assert!(build("many").is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_bad_migrations_are_script_errors() -> Result<(), anyhow::Error> {
+        const SCRIPT: &str = r#"
+            use tetron::flags;
+
+            pub fn begin(world) {
+                flags::migrate(world, 1, || flags::set(["v"], 1));
+                flags::migrate(world, 1, || flags::set(["v"], 2));
+            }
+            "#;
+        let mut game = headless_game(&[("main.rn", SCRIPT)]).build()?;
+        let Err(err) = game.begin() else {
+            panic!("duplicate migration was accepted");
+        };
+        assert!(err.to_string().contains("registered twice"), "{err}");
+        Ok(())
+    }
+
+    #[test]
+    fn test_first_frame_is_per_game_and_over_by_end() -> Result<(), anyhow::Error> {
+        const SCRIPT: &str = r#"
+            use tetron::{flags, game};
+
+            fn check(ctx) {
+                flags::set(["in_frame"], game::is_first_frame());
+            }
+
+            pub fn begin(world) {
+                let scene = world.scene("main", #{});
+                scene.system("check", check);
+                world.load_scene("main");
+            }
+
+            pub fn end(world) {
+                flags::set(["in_end"], game::is_first_frame());
+            }
+        "#;
+        let mut one = headless_game(&[("main.rn", SCRIPT)]).build()?;
+        let mut other = headless_game(&[("main.rn", SCRIPT)]).build()?;
+        let one_world = one.begin()?;
+        let other_world = other.begin()?;
+
+        one.run_frame(0.016, Vec::new())?;
+        assert_eq!(one.flags.get(&("in_frame",))?, Some(KvValue::Bool(true)));
+        // Another game's first frame doesn't leak into this one's `end`.
+        other.end(other_world)?;
+        assert_eq!(other.flags.get(&("in_end",))?, Some(KvValue::Bool(false)));
+        // Nor does a one-frame session's first frame last until `end`.
+        one.end(one_world)?;
+        assert_eq!(one.flags.get(&("in_end",))?, Some(KvValue::Bool(false)));
+        Ok(())
+    }
+
+    #[test]
+    fn test_draw_queue_drained_headless() -> Result<(), anyhow::Error> {
+        const SCRIPT: &str = r#"
+            use tetron::game;
+            use tetron::game::physics::vec2;
+
+            fn draw(ctx) {
+                game::draw_texture("tiles.png", vec2(0.0, 0.0), vec2(16.0, 16.0), vec2(0.0, 0.0), vec2(32.0, 32.0));
+            }
+
+            pub fn begin(world) {
+                let scene = world.scene("main", #{});
+                scene.system("draw", draw);
+                world.load_scene("main");
+            }
+        "#;
+        let mut game = headless_game(&[("main.rn", SCRIPT)]).build()?;
+        game.begin()?;
+        for _ in 0..3 {
+            game.run_frame(0.016, Vec::new())?;
+            assert!(game.frame_state.draw.drain()?.is_empty());
         }
+        Ok(())
+    }
+
+    #[test]
+    fn test_record_and_replay() -> Result<(), anyhow::Error> {
+        use sdl2::keyboard::{Mod, Scancode};
+
+        const SCRIPT: &str = r#"
+            use tetron::game::physics::vec2;
+            use tetron::game::transform;
+            use tetron::input;
+
+            fn walk(ctx) {
+                for player in ctx.query(#{ tag: ["player"] }) {
+                    if input::is_down("Right") {
+                        transform::translate(player.behaviour("tetron:transform")?, vec2(60.0 * ctx.dt, 0.0));
+                    }
+                }
+            }
+
+            pub fn begin(world) {
+                let scene = world.scene("main", #{});
+                let player = scene.spawn();
+                player.tag("player");
+                player.attach(transform::create(#{ pos: vec2(0.0, 0.0) }));
+                scene.system("walk", walk);
+                world.load_scene("main");
+            }
+        "#;
+        let key = |down: bool| {
+            let (keycode, scancode) = (Some(Keycode::Right), Some(Scancode::Right));
+            if down {
+                Event::KeyDown {
+                    timestamp: 0,
+                    window_id: 0,
+                    keycode,
+                    scancode,
+                    keymod: Mod::NOMOD,
+                    repeat: false,
+                }
+            } else {
+                Event::KeyUp {
+                    timestamp: 0,
+                    window_id: 0,
+                    keycode,
+                    scancode,
+                    keymod: Mod::NOMOD,
+                    repeat: false,
+                }
+            }
+        };
+        let game = || {
+            headless_game(&[("main.rn", SCRIPT)])
+                .deterministic(true)
+                .build()
+        };
 
+        // Record by hand, since a headless `Game::record` never quits.
+        let mut recorded = game()?;
+        recorded.recording = Some(Recording::default());
+        let world = recorded.begin()?;
+        let mut expected = Vec::new();
+        for frame in 0..6 {
+            let events = match frame {
+                1 => vec![key(true)],
+                4 => vec![key(false)],
+                _ => Vec::new(),
+            };
+            recorded.run_frame(recorded.fixed_dt.unwrap(), events)?;
+            let scene = Ctx::new(world.clone(), 0.0).try_scene();
+            expected.push(replay::positions_json(frame + 1, scene.as_ref())?.to_string());
+        }
+        let recording = recorded.recording.take().unwrap();
+        assert_eq!(recording.frames.len(), 6);
+        assert_eq!(
+            recording.frames[1],
+            [SyntheticEvent::KeyDown(Scancode::Right)]
+        );
+
+        let mut saved = Vec::new();
+        recording.save(&mut saved)?;
+        let mut log = Vec::new();
+        game()?.replay(&Recording::load(saved.as_slice())?, &mut log)?;
+        let log = String::from_utf8(log)?;
+        assert_eq!(log.lines().collect::<Vec<_>>(), expected);
+        // Right is down for frames 2 to 4, moving the player 1.0 each.
+        assert!(expected[0].contains(r#""pos":[0.0,0.0]"#));
+        assert!(expected[5].contains(r#""pos":[3.0,0.0]"#));
+        Ok(())
+    }
+
+    #[test]
+    fn test_deterministic_runs_match() -> Result<(), anyhow::Error> {
+        const SCRIPT: &str = r#"
+            use tetron::{game::physics::vec2, random};
+
+            fn drift(ctx) {
+                let rng = ctx.rng();
+                for dot in ctx.query(#{ tag: ["dot"] }) {
+                    let pos = dot.get_meta("pos")?;
+                    dot.set_meta("pos", vec2(pos.x + rng.float() * ctx.dt, pos.y + ctx.dt));
+                }
+            }
+
+            pub fn begin(world) {
+                let scene = world.scene("main", #{});
+                for i in 0..5 {
+                    let dot = scene.spawn();
+                    dot.tag("dot");
+                    dot.set_meta("pos", vec2(random::float(), random::float()));
+                }
+                scene.system("drift", drift);
+                world.load_scene("main");
+            }
+        "#;
+
+        let positions = || -> Result<Vec<Vec<Vec2>>, anyhow::Error> {
+            let mut game = headless_game(&[("main.rn", SCRIPT)])
+                .deterministic(true)
+                .build()?;
+            let world = game.begin()?;
+            let mut frames = Vec::new();
+            for _ in 0..10 {
+                game.frame(game.fixed_dt.unwrap())?;
+                let ctx = Ctx::new(world.clone(), 0.0);
+                let frame = ctx
+                    .scene()
+                    .unwrap()
+                    .entities()
+                    .iter()
+                    .filter_map(|e| match e.metadata().get("pos") {
+                        Some(TypedValue::Vector(v)) => Some(*v),
+                        _ => None,
+                    })
+                    .collect();
+                frames.push(frame);
+            }
+            Ok(frames)
+        };
+
+        let first = positions()?;
+        assert_eq!(first.len(), 10);
+        assert_eq!(first[0].len(), 5);
+        assert_ne!(first[0], first[9]);
+        assert_eq!(first, positions()?);
+        Ok(())
+    }
+
+    #[test]
+    fn test_deterministic_games_have_their_own_rng() -> Result<(), anyhow::Error> {
+        use crate::utils::rng::Rng;
+
+        let build = || {
+            headless_game(&[("main.rn", "")])
+                .deterministic(true)
+                .build()
+        };
+        let first = build()?;
+        let second = build()?;
+        let draw = |game: &Game| game.rng.with(game.error_mode, Rng::float);
+        let expected = Rng::new(0).float();
+        assert_eq!(draw(&first), Some(expected));
+        // Building or drawing from one game leaves the other's stream alone.
+        let third = build()?;
+        assert_eq!(draw(&second), Some(expected));
+        assert_eq!(draw(&third), Some(expected));
+        assert_ne!(draw(&first), Some(expected));
         Ok(())
     }
 }
@@ -1,12 +1,25 @@
-use super::behaviours::{BehaviourFactory, BehaviourRef};
-use crate::utils::typed_value::{TypedValue, schema::Schema};
-use rune::{ContextError, FromValue, Module, ToValue, docstring, runtime::Object};
+use super::{
+    behaviours::{BehaviourFactory, BehaviourRef},
+    entity::EntityRef,
+    shape,
+    systems::Ctx,
+};
+use crate::{
+    error::{EngineBug, IntoVmResult, TetronError},
+    utils::typed_value::{TypedValue, schema::Schema},
+};
+use rune::{
+    ContextError, FromValue, Module, ToValue, docstring,
+    runtime::{Object, VmResult},
+    vm_try,
+};
+use std::collections::HashSet;
 use vec2::Vec2;
 
 pub mod vec2;
 
-fn register_factory(module: &mut Module) -> Result<(), ContextError> {
-    let schema = Schema::object()
+pub(crate) fn schema() -> Schema {
+    Schema::object()
         .optional_field(
             "vel",
             Schema::vec2(),
@@ -15,39 +28,336 @@ fn register_factory(module: &mut Module) -> Result<(), ContextError> {
         .field("collision", Schema::string())
         .optional_field("mass", Schema::number(), None)
         .optional_field("friction", Schema::number(), None)
-        .build();
+        .optional_field("layer", Schema::number(), None)
+        .optional_field("mask", Schema::number(), None)
+        .optional_field("ccd", Schema::bool(), None)
+        .build()
+}
+
+/// Read an integer bitmask field, treating a missing one as every bit set.
+fn bitmask(body: &BehaviourRef, field: &str) -> u64 {
+    match body.get_typed(field) {
+        Ok(Some(TypedValue::Number(bits))) => bits as i64 as u64,
+        _ => u64::MAX,
+    }
+}
+
+/// Collision filtering: two bodies are only tested against each other, by
+/// [`step`] and [`overlapping`], if each one's `layer` shares a bit with the
+/// other's `mask`. Bodies without a layer or mask are on, and collide with,
+/// every layer.
+#[rune::function(keep)]
+pub fn layers_interact(a: &BehaviourRef, b: &BehaviourRef) -> bool {
+    bitmask(a, "layer") & bitmask(b, "mask") != 0 && bitmask(b, "layer") & bitmask(a, "mask") != 0
+}
+
+/// Swept AABB test for continuous collision detection. A box of `size`
+/// moves from `from` to `to` (both top-left corners); returns the fraction
+/// of the way along that path, in `[0, 1]`, where it first touches the
+/// static box at `wall_pos` of `wall_size`. Boxes that already overlap at
+/// `from` hit at 0. Grazing along an edge doesn't count as a hit.
+pub fn swept_aabb(
+    from: Vec2,
+    to: Vec2,
+    size: Vec2,
+    wall_pos: Vec2,
+    wall_size: Vec2,
+) -> Option<f64> {
+    // Grow the wall by the moving box's size so the box becomes a point.
+    let min = wall_pos - size;
+    let max = wall_pos + wall_size;
+    let delta = to - from;
+    let mut enter = f64::NEG_INFINITY;
+    let mut exit = f64::INFINITY;
+    for (start, d, lo, hi) in [
+        (from.x, delta.x, min.x, max.x),
+        (from.y, delta.y, min.y, max.y),
+    ] {
+        if d == 0.0 {
+            if start <= lo || start >= hi {
+                return None;
+            }
+            continue;
+        }
+        let (t0, t1) = ((lo - start) / d, (hi - start) / d);
+        enter = enter.max(t0.min(t1));
+        exit = exit.min(t0.max(t1));
+    }
+    if enter >= exit || exit <= 0.0 || enter > 1.0 {
+        return None;
+    }
+    Some(enter.max(0.0))
+}
+
+/// Script access to [`swept_aabb`]. Use `from.lerp(to, t)` with the result
+/// to clamp movement to the first contact.
+#[rune::function(keep)]
+pub fn sweep(from: Vec2, to: Vec2, size: Vec2, wall_pos: Vec2, wall_size: Vec2) -> Option<f64> {
+    swept_aabb(from, to, size, wall_pos, wall_size)
+}
+
+fn string(body: &BehaviourRef, field: &str) -> Option<String> {
+    match body.get_typed(field) {
+        Ok(Some(TypedValue::String(s))) => Some(s),
+        _ => None,
+    }
+}
+
+fn vector(body: &BehaviourRef, field: &str) -> Vec2 {
+    match body.get_typed(field) {
+        Ok(Some(TypedValue::Vector(v))) => v,
+        _ => Vec2::ZERO,
+    }
+}
+
+/// An entity in the current scene with a physics body and a transform.
+struct Body {
+    entity: EntityRef,
+    physics: BehaviourRef,
+    transform: BehaviourRef,
+    shape: Option<BehaviourRef>,
+}
+
+impl Body {
+    fn all(ctx: &Ctx) -> Result<Vec<Body>, TetronError> {
+        let behaviours =
+            HashSet::from(["tetron:physics".to_string(), "tetron:transform".to_string()]);
+        let entities = ctx.query_with_sets(HashSet::new(), behaviours, None, 0, None)?;
+        Ok(entities.into_iter().filter_map(Body::new).collect())
+    }
+
+    fn new(entity: EntityRef) -> Option<Body> {
+        Some(Body {
+            physics: entity.behaviour("tetron:physics")?,
+            transform: entity.behaviour("tetron:transform")?,
+            shape: entity.behaviour("tetron:shape"),
+            entity,
+        })
+    }
+
+    fn collision(&self) -> Option<String> {
+        string(&self.physics, "collision")
+    }
+
+    fn pos(&self) -> Vec2 {
+        vector(&self.transform, "pos")
+    }
+
+    /// The box around the body's shape if it were at `pos`.
+    fn bounds_at(&self, pos: Vec2) -> Option<(Vec2, Vec2)> {
+        shape::bounds(self.shape.as_ref()?, pos)
+    }
+}
+
+/// Whether two boxes, each a top-left corner and size, overlap. Boxes that
+/// only share an edge don't.
+fn boxes_overlap((a_pos, a_size): (Vec2, Vec2), (b_pos, b_size): (Vec2, Vec2)) -> bool {
+    a_pos.x < b_pos.x + b_size.x
+        && b_pos.x < a_pos.x + a_size.x
+        && a_pos.y < b_pos.y + b_size.y
+        && b_pos.y < a_pos.y + a_size.y
+}
+
+/// Move every simulated body with `ccd` set in the current scene by its
+/// velocity over `dt` seconds, sweeping it against the shapes of immovable
+/// bodies it interacts with so it stops at the first one it touches instead
+/// of passing through it. Triggers and "none" bodies never block. Other
+/// bodies are left for scripts to move, which keeps scenes without `ccd`
+/// bodies cheap. Then runs the separation pass [`separate`] `iterations`
+/// times. Run once per frame, before systems.
+pub(crate) fn step(ctx: &Ctx, dt: f64, iterations: u32) -> Result<(), TetronError> {
+    let bodies = Body::all(ctx)?;
+    let mut moving = Vec::new();
+    for body in &bodies {
+        if body.collision().as_deref() == Some("simulate")
+            && matches!(body.physics.get_typed("ccd")?, Some(TypedValue::Bool(true)))
+        {
+            moving.push(body);
+        }
+    }
+    if moving.is_empty() {
+        return Ok(());
+    }
+    let walls: Vec<(&BehaviourRef, (Vec2, Vec2))> = bodies
+        .iter()
+        .filter(|body| body.collision().as_deref() == Some("immovable"))
+        .filter_map(|body| Some((&body.physics, body.bounds_at(body.pos())?)))
+        .collect();
+
+    for body in &moving {
+        let vel = vector(&body.physics, "vel");
+        if vel == Vec2::ZERO {
+            continue;
+        }
+        let from = body.pos();
+        let mut to = from + vel * dt;
+        if let Some((corner, size)) = body.bounds_at(from) {
+            // Sweep the shape's box, which may not start at `pos`.
+            let offset = corner - from;
+            let hit = walls
+                .iter()
+                .filter(|(wall, _)| layers_interact(&body.physics, wall))
+                .filter_map(|(_, (pos, wall))| swept_aabb(corner, to + offset, size, *pos, *wall))
+                .min_by(f64::total_cmp);
+            if let Some(t) = hit {
+                to = from.lerp(to, t);
+            }
+        }
+        body.transform
+            .clone()
+            .set("pos", to.to_value()?)
+            .into_result()?;
+    }
+
+    let mut positions: Vec<Vec2> = moving.iter().map(|body| body.pos()).collect();
+    for _ in 0..iterations {
+        separate(&moving, &mut positions, &walls);
+    }
+    for (body, pos) in moving.iter().zip(positions) {
+        if pos != body.pos() {
+            body.transform
+                .clone()
+                .set("pos", pos.to_value()?)
+                .into_result()?;
+        }
+    }
+    Ok(())
+}
+
+/// How far to move box `a` so it no longer overlaps box `b`, along the axis
+/// where they overlap least. `None` if they don't overlap.
+fn push_out(a: (Vec2, Vec2), b: (Vec2, Vec2)) -> Option<Vec2> {
+    if !boxes_overlap(a, b) {
+        return None;
+    }
+    let ((a_pos, a_size), (b_pos, b_size)) = (a, b);
+    let depth = Vec2::new(
+        (a_pos.x + a_size.x - b_pos.x).min(b_pos.x + b_size.x - a_pos.x),
+        (a_pos.y + a_size.y - b_pos.y).min(b_pos.y + b_size.y - a_pos.y),
+    );
+    let a_center = a_pos + a_size * 0.5;
+    let b_center = b_pos + b_size * 0.5;
+    let away = |a: f64, b: f64| if a < b { -1.0 } else { 1.0 };
+    Some(if depth.x < depth.y {
+        Vec2::new(depth.x * away(a_center.x, b_center.x), 0.0)
+    } else {
+        Vec2::new(0.0, depth.y * away(a_center.y, b_center.y))
+    })
+}
 
-    let physics = BehaviourFactory::new("physics", schema, true);
+/// One separation pass: push each of `bodies`, at `positions`, out of the
+/// immovable `walls` it interacts with, then push overlapping pairs of
+/// `bodies` apart, half each. Fixing one overlap can cause another, so
+/// stacked bodies need several passes to settle.
+fn separate(bodies: &[&Body], positions: &mut [Vec2], walls: &[(&BehaviourRef, (Vec2, Vec2))]) {
+    for i in 0..bodies.len() {
+        for (wall, bounds) in walls {
+            if !layers_interact(&bodies[i].physics, wall) {
+                continue;
+            }
+            if let Some(own) = bodies[i].bounds_at(positions[i])
+                && let Some(push) = push_out(own, *bounds)
+            {
+                positions[i] += push;
+            }
+        }
+        for j in i + 1..bodies.len() {
+            if !layers_interact(&bodies[i].physics, &bodies[j].physics) {
+                continue;
+            }
+            let (Some(a), Some(b)) = (
+                bodies[i].bounds_at(positions[i]),
+                bodies[j].bounds_at(positions[j]),
+            ) else {
+                continue;
+            };
+            if let Some(push) = push_out(a, b) {
+                positions[i] += push * 0.5;
+                positions[j] -= push * 0.5;
+            }
+        }
+    }
+}
+
+/// Every other body in the current scene whose shape overlaps `entity`'s,
+/// going by their bounding boxes. Bodies with collision "none" never overlap
+/// anything, and neither do bodies whose layers don't interact. This is how
+/// triggers find what's inside them.
+#[rune::function(keep)]
+pub fn overlapping(ctx: &Ctx, entity: &EntityRef) -> VmResult<Vec<EntityRef>> {
+    let bodies = vm_try!(Body::all(ctx).into_vm_result());
+    let Some((physics, bounds)) = Body::new(entity.clone())
+        .filter(|body| body.collision().as_deref() != Some("none"))
+        .and_then(|body| Some((body.physics.clone(), body.bounds_at(body.pos())?)))
+    else {
+        return VmResult::Ok(Vec::new());
+    };
+    let found = bodies
+        .into_iter()
+        .filter(|body| body.entity.id() != entity.id())
+        .filter(|body| body.collision().as_deref() != Some("none"))
+        .filter(|body| layers_interact(&physics, &body.physics))
+        .filter(|body| {
+            body.bounds_at(body.pos())
+                .is_some_and(|other| boxes_overlap(bounds, other))
+        })
+        .map(|body| body.entity)
+        .collect();
+    VmResult::Ok(found)
+}
 
-    let func = move |obj: &Object| -> BehaviourRef {
-        let behaviour = physics.create(obj);
+fn register_factory(module: &mut Module) -> Result<(), ContextError> {
+    let physics = BehaviourFactory::new("physics", schema(), true);
+
+    let func = move |obj: &Object| -> VmResult<BehaviourRef> {
+        let behaviour = vm_try!(physics.create(obj));
         let collision = match behaviour.get_typed("collision") {
-            Some(TypedValue::String(s)) => s,
-            None => panic!("Physics bodies must have 'collision' field specified!"),
+            Ok(Some(TypedValue::String(s))) => s,
+            Ok(None) => panic!("Physics bodies must have 'collision' field specified!"),
             _ => panic!("Expected collision to be a string"),
         };
 
         match collision.as_str() {
             "simulate" => match behaviour.get_typed("mass") {
-                Some(TypedValue::Number(m)) if m > 0.0 => {}
+                Ok(Some(TypedValue::Number(m))) if m > 0.0 => {}
                 _ => panic!("Mass must be specified and > 0 for simulated bodies"),
             },
-            "immovable" | "none" => {}
+            "immovable" | "trigger" | "none" => {}
             _ => {
                 panic!("Invalid collision type {collision} specified");
             }
         }
-        behaviour
+        VmResult::Ok(behaviour)
     };
 
     module.function("create", func).build()?.docs(docstring! {
         /// Create a new physics behaviour.
         ///
         /// Fields:
-        /// * collision: string ("simulate", "immovable", or "none")
+        /// * collision: string ("simulate", "immovable", "trigger", or "none").
+        ///   Triggers don't block other bodies, and find what's inside them
+        ///   with `overlapping`, e.g. for pickups and checkpoints.
         /// * vel: Vec2 (optional, default (0,0))
         /// * mass: number (optional, required if collision=="simulate")
         /// * friction: number (optional)
+        /// * layer: integer bitmask of the layers this body is on (optional,
+        ///   default all layers)
+        /// * mask: integer bitmask of the layers this body collides with
+        ///   (optional, default all layers)
+        /// * ccd: bool (optional, default false). Fast bodies like bullets
+        ///   should set this so they can't pass through thin walls between
+        ///   frames.
+        ///
+        /// Every frame, before systems run, the engine moves "simulate"
+        /// bodies that have `ccd` set and a transform by `vel`, in units per
+        /// second. If the body has a shape, it stops at the first immovable
+        /// body with a shape in its path. Other bodies are moved by scripts.
+        ///
+        /// The engine then pushes those bodies out of immovable bodies and
+        /// each other. `physics.iterations` in game.json (default 1) sets how
+        /// many times per frame this separation pass runs. Stacked bodies
+        /// settle with less jitter at higher values, at the cost of checking
+        /// every pair of bodies again on each pass.
     })?;
 
     Ok(())
@@ -59,18 +369,19 @@ fn vec2(x: f64, y: f64) -> Vec2 {
 }
 
 #[rune::function(keep)]
-pub fn apply_force(b: &mut BehaviourRef, force: Vec2) {
-    let vel = if let Some(val) = b.get("vel") {
-        Vec2::from_value(val).expect("Engine bug: failed to convert velocity value")
-    } else {
-        Vec2::zero()
+pub fn apply_force(b: &mut BehaviourRef, force: Vec2) -> VmResult<()> {
+    let vel = match vm_try!(b.get("vel")) {
+        Some(val) => {
+            vm_try!(Vec2::from_value(val).throw_engine_bug("failed to convert velocity value"))
+        }
+        None => Vec2::zero(),
     };
-    b.set(
-        "vel",
+    let vel = vm_try!(
         (vel + force)
             .to_value()
-            .expect("Engine bug: failed to convert velocity to rune value"),
+            .throw_engine_bug("failed to convert velocity to rune value")
     );
+    b.set("vel", vel)
 }
 
 pub fn module() -> Result<Module, ContextError> {
@@ -78,5 +389,296 @@ pub fn module() -> Result<Module, ContextError> {
     register_factory(&mut module)?;
     module.function_meta(vec2)?;
     module.function_meta(apply_force__meta)?;
+    module.function_meta(layers_interact__meta)?;
+    module.function_meta(sweep__meta)?;
+    module.function_meta(overlapping__meta)?;
     Ok(module)
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::engine::{transform, world::WorldRef};
+    use std::collections::HashMap;
+
+    fn body(fields: &[(&str, f64)]) -> BehaviourRef {
+        let mut map = HashMap::from([("collision".to_string(), TypedValue::from("simulate"))]);
+        for (name, value) in fields {
+            map.insert(name.to_string(), TypedValue::Number(*value));
+        }
+        BehaviourFactory::new("physics", schema(), true).with_map(map)
+    }
+
+    #[test]
+    fn test_layers_interact() {
+        const PLAYER: f64 = 1.0;
+        const ENEMY: f64 = 2.0;
+        const BULLET: f64 = 4.0;
+        let bullet = || body(&[("layer", BULLET), ("mask", ENEMY)]);
+        let enemy = body(&[("layer", ENEMY), ("mask", PLAYER + BULLET)]);
+        let unfiltered = body(&[]);
+
+        assert!(layers_interact(&bullet(), &enemy));
+        assert!(!layers_interact(&bullet(), &bullet()));
+        assert!(layers_interact(&unfiltered, &enemy));
+        assert!(layers_interact(&unfiltered, &bullet()));
+        assert!(!layers_interact(&body(&[("layer", PLAYER)]), &bullet()));
+    }
+
+    /// Spawn a body at `pos` in `world`'s current scene with a `w`x`h` rect
+    /// shape and the given physics fields.
+    fn spawn_body(
+        world: &WorldRef,
+        pos: Vec2,
+        (w, h): (f64, f64),
+        fields: &[(&str, TypedValue)],
+    ) -> BehaviourRef {
+        let mut scene = world.current_scene().unwrap().unwrap().1;
+        let mut entity = scene.spawn();
+        let mut physics = HashMap::new();
+        for (name, value) in fields {
+            physics.insert(name.to_string(), value.clone());
+        }
+        entity.attach(BehaviourFactory::new("physics", schema(), true).with_map(physics));
+        entity.attach(
+            BehaviourFactory::new("shape", shape::schema(), true).with_map(HashMap::from([
+                ("type".to_string(), TypedValue::from("rect")),
+                ("w".to_string(), TypedValue::Number(w)),
+                ("h".to_string(), TypedValue::Number(h)),
+            ])),
+        );
+        let transform = BehaviourFactory::new("transform", transform::schema(), true).with_map(
+            HashMap::from([("pos".to_string(), TypedValue::Vector(pos))]),
+        );
+        entity.attach(transform.clone());
+        transform
+    }
+
+    #[test]
+    fn test_step_stops_ccd_bodies_at_walls() -> Result<(), TetronError> {
+        let world = WorldRef::new();
+        world.scene("level", Object::new());
+        world.load_scene("level");
+        spawn_body(
+            &world,
+            Vec2::new(10.0, -5.0),
+            (1.0, 20.0),
+            &[("collision", TypedValue::from("immovable"))],
+        );
+        let bullet = |ccd: bool| {
+            spawn_body(
+                &world,
+                Vec2::zero(),
+                (2.0, 2.0),
+                &[
+                    ("collision", TypedValue::from("simulate")),
+                    ("mass", TypedValue::Number(1.0)),
+                    ("vel", TypedValue::Vector(Vec2::new(6000.0, 0.0))),
+                    ("ccd", TypedValue::Bool(ccd)),
+                ],
+            )
+        };
+        let (fast, scripted) = (bullet(true), bullet(false));
+
+        step(&Ctx::new(world.clone(), 1.0 / 60.0), 1.0 / 60.0, 1)?;
+        assert_eq!(vector(&fast, "pos"), Vec2::new(8.0, 0.0));
+        // Only ccd bodies are moved by the engine.
+        assert_eq!(vector(&scripted, "pos"), Vec2::zero());
+
+        // Touching the wall, it stays put.
+        step(&Ctx::new(world, 1.0 / 60.0), 1.0 / 60.0, 1)?;
+        assert_eq!(vector(&fast, "pos"), Vec2::new(8.0, 0.0));
+        Ok(())
+    }
+
+    #[test]
+    fn test_triggers_report_overlaps_without_blocking() -> Result<(), TetronError> {
+        let world = WorldRef::new();
+        world.scene("level", Object::new());
+        world.load_scene("level");
+        let collision = |kind: &str| ("collision", TypedValue::from(kind));
+        spawn_body(
+            &world,
+            Vec2::new(10.0, 0.0),
+            (5.0, 5.0),
+            &[collision("trigger")],
+        );
+        let player = spawn_body(
+            &world,
+            Vec2::zero(),
+            (2.0, 2.0),
+            &[
+                collision("simulate"),
+                ("mass", TypedValue::Number(1.0)),
+                ("vel", TypedValue::Vector(Vec2::new(720.0, 0.0))),
+                ("ccd", TypedValue::Bool(true)),
+            ],
+        );
+        spawn_body(
+            &world,
+            Vec2::new(14.0, 4.0),
+            (1.0, 1.0),
+            &[collision("none")],
+        );
+        spawn_body(
+            &world,
+            Vec2::new(15.0, 0.0),
+            (1.0, 1.0),
+            &[collision("immovable")],
+        );
+
+        let ctx = Ctx::new(world.clone(), 1.0 / 60.0);
+        let entities = world.current_scene()?.unwrap().1.entities();
+        let trigger = &entities[0];
+        assert!(overlapping(&ctx, trigger).into_result()?.is_empty());
+
+        // The player moves into the trigger without being stopped by it.
+        step(&ctx, 1.0 / 60.0, 1)?;
+        assert_eq!(vector(&player, "pos"), Vec2::new(12.0, 0.0));
+        let ids = |found: Vec<EntityRef>| found.iter().map(EntityRef::id).collect::<Vec<_>>();
+        // The "none" body inside it doesn't count, and the wall only shares
+        // an edge with it.
+        assert_eq!(
+            ids(overlapping(&ctx, trigger).into_result()?),
+            vec![entities[1].id()]
+        );
+        assert_eq!(
+            ids(overlapping(&ctx, &entities[1]).into_result()?),
+            vec![trigger.id()]
+        );
+        assert!(overlapping(&ctx, &entities[2]).into_result()?.is_empty());
+        Ok(())
+    }
+
+    #[test]
+    fn test_layers_filter_sweeps_and_overlaps() -> Result<(), TetronError> {
+        let world = WorldRef::new();
+        world.scene("level", Object::new());
+        world.load_scene("level");
+        let layer = |bits: f64| ("layer", TypedValue::Number(bits));
+        let mask = |bits: f64| ("mask", TypedValue::Number(bits));
+        // A wall only bullets on layer 2 run into.
+        spawn_body(
+            &world,
+            Vec2::new(10.0, -5.0),
+            (1.0, 20.0),
+            &[("collision", TypedValue::from("immovable")), mask(2.0)],
+        );
+        let bullet = |bits: f64| {
+            spawn_body(
+                &world,
+                Vec2::zero(),
+                (2.0, 2.0),
+                &[
+                    ("collision", TypedValue::from("simulate")),
+                    ("mass", TypedValue::Number(1.0)),
+                    ("vel", TypedValue::Vector(Vec2::new(6000.0, 0.0))),
+                    ("ccd", TypedValue::Bool(true)),
+                    layer(bits),
+                    mask(bits),
+                ],
+            )
+        };
+        let (stopped, ghost) = (bullet(2.0), bullet(1.0));
+
+        let ctx = Ctx::new(world.clone(), 1.0 / 60.0);
+        step(&ctx, 1.0 / 60.0, 1)?;
+        assert_eq!(vector(&stopped, "pos"), Vec2::new(8.0, 0.0));
+        assert_eq!(vector(&ghost, "pos"), Vec2::new(100.0, 0.0));
+
+        // Only bullets on the same layer overlap each other.
+        let mut ghost = ghost;
+        ghost
+            .set("pos", Vec2::new(8.0, 0.0).to_value()?)
+            .into_result()?;
+        bullet(2.0)
+            .set("pos", Vec2::new(8.0, 0.0).to_value()?)
+            .into_result()?;
+        let entities = world.current_scene()?.unwrap().1.entities();
+        let ids: Vec<_> = overlapping(&ctx, &entities[1])
+            .into_result()?
+            .iter()
+            .map(EntityRef::id)
+            .collect();
+        assert_eq!(ids, vec![entities[3].id()]);
+        Ok(())
+    }
+
+    #[test]
+    fn test_iterations_settle_stacked_bodies() -> Result<(), TetronError> {
+        let settle = |iterations| -> Result<(Vec2, Vec2), TetronError> {
+            let world = WorldRef::new();
+            world.scene("level", Object::new());
+            world.load_scene("level");
+            spawn_body(
+                &world,
+                Vec2::new(-10.0, 10.0),
+                (40.0, 5.0),
+                &[("collision", TypedValue::from("immovable"))],
+            );
+            let crate_ = |y| {
+                spawn_body(
+                    &world,
+                    Vec2::new(0.0, y),
+                    (2.0, 2.0),
+                    &[
+                        ("collision", TypedValue::from("simulate")),
+                        ("mass", TypedValue::Number(1.0)),
+                        ("ccd", TypedValue::Bool(true)),
+                    ],
+                )
+            };
+            // Two crates stacked on the floor, each sunk into what's below.
+            let (bottom, top) = (crate_(9.0), crate_(7.5));
+            step(&Ctx::new(world, 1.0 / 60.0), 1.0 / 60.0, iterations)?;
+            Ok((vector(&bottom, "pos"), vector(&top, "pos")))
+        };
+
+        // One pass lifts the bottom crate out of the floor, but pushing the
+        // top crate off it sinks it back in.
+        assert_eq!(settle(1)?, (Vec2::new(0.0, 8.75), Vec2::new(0.0, 6.75)));
+        let (bottom, top) = settle(8)?;
+        assert!(bottom.y + 2.0 - 10.0 < 0.01, "{bottom:?}");
+        assert!(top.y + 2.0 - bottom.y < 0.01, "{top:?}");
+        Ok(())
+    }
+
+    #[test]
+    fn test_swept_aabb_stops_fast_body() {
+        let size = Vec2::new(2.0, 2.0);
+        let wall = (Vec2::new(10.0, -5.0), Vec2::new(1.0, 20.0));
+
+        // A bullet that would skip right over the wall in one step.
+        let t = swept_aabb(Vec2::zero(), Vec2::new(100.0, 0.0), size, wall.0, wall.1);
+        assert_eq!(t, Some(0.08));
+
+        assert_eq!(
+            swept_aabb(Vec2::zero(), Vec2::new(5.0, 0.0), size, wall.0, wall.1),
+            None
+        );
+        assert_eq!(
+            swept_aabb(Vec2::zero(), Vec2::new(-100.0, 0.0), size, wall.0, wall.1),
+            None
+        );
+        assert_eq!(
+            swept_aabb(
+                Vec2::new(0.0, 20.0),
+                Vec2::new(100.0, 20.0),
+                size,
+                wall.0,
+                wall.1
+            ),
+            None
+        );
+        assert_eq!(
+            swept_aabb(
+                Vec2::new(10.0, 0.0),
+                Vec2::new(50.0, 0.0),
+                size,
+                wall.0,
+                wall.1
+            ),
+            Some(0.0)
+        );
+    }
+}
@@ -5,7 +5,7 @@ use std::{
     ops::{Add, AddAssign, Div, DivAssign, Mul, MulAssign, Neg, Sub, SubAssign},
 };
 
-#[derive(rune::Any, Copy, Clone, Debug, PartialEq)]
+#[derive(rune::Any, Copy, Clone, Debug, Default, PartialEq)]
 pub struct Vec2 {
     #[rune(get, set)]
     pub x: f64,
@@ -45,6 +45,10 @@ impl Vec2 {
         self.x * self.x + self.y * self.y
     }
 
+    /// ```
+    /// # use tetron::engine::physics::vec2::Vec2;
+    /// assert_eq!(Vec2::new(1.0, 2.0).dot(Vec2::new(3.0, 4.0)), 11.0);
+    /// ```
     #[inline]
     #[rune::function(keep, instance)]
     pub fn dot(self, other: Vec2) -> f64 {
@@ -58,18 +62,58 @@ impl Vec2 {
         if len != 0.0 { self / len } else { Vec2::ZERO }
     }
 
+    /// ```
+    /// # use tetron::engine::physics::vec2::Vec2;
+    /// assert_eq!(Vec2::new(0.0, 0.0).distance(Vec2::new(3.0, 4.0)), 5.0);
+    /// ```
     #[inline]
     #[rune::function(keep, instance)]
     pub fn distance(self, b: Vec2) -> f64 {
         (self - b).length()
     }
 
+    /// Linear interpolation from `self` at `t = 0` to `b` at `t = 1`.
+    ///
+    /// ```
+    /// # use tetron::engine::physics::vec2::Vec2;
+    /// let mid = Vec2::new(0.0, 0.0).lerp(Vec2::new(2.0, 4.0), 0.5);
+    /// assert_eq!(mid, Vec2::new(1.0, 2.0));
+    /// ```
     #[inline]
     #[rune::function(keep, instance)]
     pub fn lerp(self, b: Vec2, t: f64) -> Vec2 {
         self + (b - self) * t
     }
 
+    /// The 2D cross product, `x1 * y2 - y1 * x2`. Positive when `other` is
+    /// counter-clockwise from `self`, negative when it's clockwise.
+    ///
+    /// ```
+    /// # use tetron::engine::physics::vec2::Vec2;
+    /// assert_eq!(Vec2::new(1.0, 0.0).cross(Vec2::new(0.0, 1.0)), 1.0);
+    /// ```
+    #[inline]
+    #[rune::function(keep, instance)]
+    pub fn cross(self, other: Vec2) -> f64 {
+        self.x * other.y - self.y * other.x
+    }
+
+    /// The unsigned angle between the two vectors in radians, in `[0, π]`.
+    /// Zero if either vector is zero.
+    ///
+    /// ```
+    /// # use tetron::engine::physics::vec2::Vec2;
+    /// use std::f64::consts::FRAC_PI_2;
+    /// let right = Vec2::new(1.0, 0.0);
+    /// assert_eq!(right.angle_between(Vec2::new(0.0, 1.0)), FRAC_PI_2);
+    /// assert_eq!(right.angle_between(Vec2::zero()), 0.0);
+    /// ```
+    #[inline]
+    #[rune::function(keep, instance)]
+    pub fn angle_between(self, other: Vec2) -> f64 {
+        self.cross(other).atan2(self.dot(other)).abs()
+    }
+
     #[inline]
     #[rune::function(keep, instance)]
     pub fn perp(self) -> Vec2 {
@@ -84,9 +128,56 @@ impl Vec2 {
         Self::ZERO
     }
 
-    #[rune::function(path = Self::one)]
+    #[rune::function(keep, path = Self::one)]
     pub fn one() -> Vec2 {
-        Self::ZERO
+        Self::ONE
+    }
+}
+
+impl Vec2 {
+    #[rune::function(instance, protocol = ADD_ASSIGN)]
+    fn add_assign_rune(&mut self, rhs: Vec2) {
+        *self += rhs;
+    }
+
+    #[rune::function(instance, protocol = SUB_ASSIGN)]
+    fn sub_assign_rune(&mut self, rhs: Vec2) {
+        *self -= rhs;
+    }
+
+    #[rune::function(instance, protocol = MUL_ASSIGN)]
+    fn mul_assign_rune(&mut self, rhs: Vec2) {
+        *self *= rhs;
+    }
+
+    #[rune::function(instance, protocol = DIV_ASSIGN)]
+    fn div_assign_rune(&mut self, rhs: Vec2) {
+        *self /= rhs;
+    }
+
+    #[rune::function(instance, protocol = DIV)]
+    fn div_rune(self, rhs: Vec2) -> Vec2 {
+        self / rhs
+    }
+
+    #[rune::function(instance, protocol = MUL)]
+    fn mul_rune(self, rhs: Vec2) -> Vec2 {
+        self * rhs
+    }
+
+    #[rune::function(instance, protocol = SUB)]
+    fn sub_rune(self, rhs: Vec2) -> Vec2 {
+        self - rhs
+    }
+
+    #[rune::function(instance, protocol = ADD)]
+    fn add_rune(self, rhs: Vec2) -> Vec2 {
+        self + rhs
+    }
+
+    #[rune::function(instance, protocol = PARTIAL_EQ)]
+    fn partial_eq_rune(&self, rhs: &Vec2) -> bool {
+        self == rhs
     }
 }
 
@@ -197,7 +288,36 @@ impl Registrable for Vec2 {
         module.function_meta(Vec2::sub_assign_rune)?;
         module.function_meta(Vec2::partial_eq_rune)?;
         module.function_meta(Vec2::display_fmt)?;
+        module.function_meta(Vec2::new__meta)?;
+        module.function_meta(Vec2::zero__meta)?;
+        module.function_meta(Vec2::one__meta)?;
+        module.function_meta(Vec2::length__meta)?;
+        module.function_meta(Vec2::length_sq__meta)?;
+        module.function_meta(Vec2::dot__meta)?;
+        module.function_meta(Vec2::cross__meta)?;
+        module.function_meta(Vec2::angle_between__meta)?;
+        module.function_meta(Vec2::normalize__meta)?;
+        module.function_meta(Vec2::distance__meta)?;
+        module.function_meta(Vec2::lerp__meta)?;
+        module.function_meta(Vec2::perp__meta)?;
 
         Ok(())
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::f64::consts::FRAC_PI_2;
+
+    #[test]
+    fn test_cross_and_angle_between() {
+        let x = Vec2::new(1.0, 0.0);
+        let y = Vec2::new(0.0, 1.0);
+        assert_eq!(x.cross(y), 1.0);
+        assert_eq!(y.cross(x), -1.0);
+        assert!((x.angle_between(y) - FRAC_PI_2).abs() < 1e-12);
+        assert!((y.angle_between(x) - FRAC_PI_2).abs() < 1e-12);
+        assert_eq!(x.angle_between(x * 3.0), 0.0);
+    }
+}
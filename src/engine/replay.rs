@@ -0,0 +1,102 @@
+use super::{input::SyntheticEvent, scene::SceneRef};
+use crate::{error::TetronError, utils::typed_value::TypedValue};
+use serde_json::{Value, json};
+use std::io::{BufRead, Write};
+
+/// The input of a play session, frame by frame, as recorded by
+/// `tetron record`. Replaying it in deterministic mode plays the session out
+/// again exactly.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Recording {
+    pub frames: Vec<Vec<SyntheticEvent>>,
+}
+
+impl Recording {
+    /// Write the recording as JSON lines: one array of events per frame, in
+    /// the form of [`SyntheticEvent::to_json`].
+    pub fn save(&self, mut out: impl Write) -> Result<(), TetronError> {
+        for frame in &self.frames {
+            let events: Vec<Value> = frame.iter().map(SyntheticEvent::to_json).collect();
+            writeln!(out, "{}", Value::Array(events)).map_err(write_error)?;
+        }
+        out.flush().map_err(write_error)
+    }
+
+    /// Read a recording written by [`Recording::save`].
+    pub fn load(input: impl BufRead) -> Result<Recording, TetronError> {
+        let mut frames = Vec::new();
+        for (n, line) in input.lines().enumerate() {
+            let line =
+                line.map_err(|e| TetronError::FsError(format!("Could not read recording: {e}")))?;
+            let bad_frame = |reason: &str| {
+                TetronError::Conversion(format!("Bad frame {} in recording: {reason}", n + 1))
+            };
+            let events: Vec<Value> =
+                serde_json::from_str(&line).map_err(|e| bad_frame(&e.to_string()))?;
+            let frame = events
+                .iter()
+                .map(|event| {
+                    SyntheticEvent::from_json(event)
+                        .ok_or_else(|| bad_frame(&format!("unknown event {event}")))
+                })
+                .collect::<Result<_, _>>()?;
+            frames.push(frame);
+        }
+        Ok(Recording { frames })
+    }
+}
+
+fn write_error(e: std::io::Error) -> TetronError {
+    TetronError::FsError(format!("Could not write recording: {e}"))
+}
+
+/// One line of the replay log: the position of every entity in `scene` with
+/// a transform, as `{"frame": n, "entities": [{"id": id, "pos": [x, y]}]}`.
+pub fn positions_json(frame: u64, scene: Option<&SceneRef>) -> Result<Value, TetronError> {
+    let mut entities = Vec::new();
+    for entity in scene.map(SceneRef::entities).unwrap_or_default() {
+        let Some(transform) = entity.behaviour("tetron:transform") else {
+            continue;
+        };
+        if let Some(TypedValue::Vector(pos)) = transform.get_typed("pos")? {
+            entities.push(json!({ "id": entity.id(), "pos": [pos.x, pos.y] }));
+        }
+    }
+    Ok(json!({ "frame": frame, "entities": entities }))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::engine::physics::vec2::Vec2;
+    use sdl2::keyboard::Scancode;
+
+    #[test]
+    fn test_recording_round_trip() -> Result<(), TetronError> {
+        let recording = Recording {
+            frames: vec![
+                vec![SyntheticEvent::KeyDown(Scancode::Right)],
+                vec![],
+                vec![
+                    SyntheticEvent::MouseMove(Vec2::new(12.0, 34.5)),
+                    SyntheticEvent::MouseButton { pressed: true },
+                    SyntheticEvent::KeyUp(Scancode::Right),
+                    SyntheticEvent::ReleaseAll,
+                ],
+            ],
+        };
+        let mut saved = Vec::new();
+        recording.save(&mut saved)?;
+        assert_eq!(
+            String::from_utf8_lossy(&saved).lines().next(),
+            Some(r#"[{"key_down":"Right"}]"#)
+        );
+        assert_eq!(Recording::load(saved.as_slice())?, recording);
+
+        let Err(e) = Recording::load(&b"[]\n[{\"key_down\":\"Nope\"}]\n"[..]) else {
+            panic!("unknown key loaded");
+        };
+        assert!(e.to_string().contains("frame 2"));
+        Ok(())
+    }
+}
@@ -1,28 +1,98 @@
-use super::{entity::EntityRef, systems::Ctx, world::WorldRef};
-use crate::{error::TetronError, system_log, utils::Registrable};
+use super::{
+    entity::{Entity, EntityId, EntityRef, TagIndex},
+    systems::Ctx,
+    tween::{self, TweenList},
+    world::WorldRef,
+};
+use crate::{
+    error::{EngineBug, ErrorMode, IntoVmResult, TetronError},
+    system_log,
+    utils::{
+        Registrable,
+        rng::{Rng, RngRef, derive_seed},
+        typed_value::TypedValue,
+    },
+};
 use rune::{
-    ContextError, Module, ToValue,
-    runtime::{Function, Object},
+    ContextError, Module, ToValue, Value,
+    alloc::clone::TryClone,
+    runtime::{Function, Object, VmResult},
+    vm_try,
 };
-use std::{cell::RefCell, collections::HashMap, rc::Rc};
+use std::{
+    cell::RefCell,
+    collections::{BTreeMap, BTreeSet, HashMap, HashSet},
+    rc::Rc,
+};
+
+/// The most despawned entities a pooling scene keeps for reuse. Any more
+/// are dropped as usual.
+const POOL_CAPACITY: usize = 1024;
+
+#[derive(Debug)]
+struct System {
+    handler: Function,
+    /// Systems with a higher priority run first.
+    priority: i64,
+    /// Run only on every `tick_every`th frame.
+    tick_every: u64,
+}
 
 #[derive(Debug)]
 pub struct Scene {
     world: WorldRef,
-    entities: Vec<EntityRef>,
-    systems: HashMap<String, Function>,
+    name: String,
+    entities: BTreeMap<EntityId, EntityRef>,
+    next_entity_id: EntityId,
+    tag_index: TagIndex,
+    systems: HashMap<String, System>,
+    /// Number of frames each system has been updated for, for `tick_every`.
+    frame_counters: HashMap<String, u64>,
     config: Object,
+    /// Created on first use, so the world seed can still be changed after
+    /// the scene is defined.
+    rng: Option<RngRef>,
+    /// Storage of despawned entities waiting to be reused, if the `pool`
+    /// config is set. Holds at most [`POOL_CAPACITY`] entries.
+    pool: Option<Vec<Entity>>,
+    /// Running tweens on this scene's entities, advanced before systems run.
+    /// Shared with the entities, so tweens started on them land here.
+    tweens: TweenList,
 }
 
 impl Scene {
-    pub fn new(world: WorldRef, config: Object) -> Self {
+    pub fn new(world: WorldRef, name: &str, config: Object) -> Self {
+        let pool = config
+            .get("pool")
+            .is_some_and(|pool| pool.as_bool().unwrap_or(false))
+            .then(Vec::new);
         Self {
             world,
-            entities: Vec::new(),
+            name: name.to_owned(),
+            entities: BTreeMap::new(),
+            next_entity_id: 0,
+            tag_index: TagIndex::default(),
             systems: HashMap::new(),
+            frame_counters: HashMap::new(),
             config,
+            rng: None,
+            pool,
+            tweens: TweenList::default(),
         }
     }
+
+    fn spawn(&mut self) -> EntityRef {
+        let id = self.next_entity_id;
+        self.next_entity_id += 1;
+        let tag_index = self.tag_index.clone();
+        let tweens = self.tweens.clone();
+        let entity = match self.pool.as_mut().and_then(Vec::pop) {
+            Some(storage) => EntityRef::from_storage(storage, id, tag_index, tweens),
+            None => EntityRef::with_index(id, tag_index, tweens),
+        };
+        self.entities.insert(id, entity.clone());
+        entity
+    }
 }
 
 #[derive(Clone, Debug, rune::Any)]
@@ -33,42 +103,610 @@ impl Registrable for SceneRef {
     fn register(module: &mut Module) -> Result<(), ContextError> {
         module.ty::<SceneRef>()?;
         module.function_meta(SceneRef::spawn__meta)?;
+        module.function_meta(SceneRef::spawn_many__meta)?;
+        module.function_meta(SceneRef::despawn__meta)?;
         module.function_meta(SceneRef::system)?;
+        module.function_meta(SceneRef::system_with_opts__meta)?;
+        module.function_meta(SceneRef::entities__meta)?;
+        module.function_meta(SceneRef::entity_count__meta)?;
+        module.function_meta(SceneRef::entities_with__meta)?;
+        module.function_meta(SceneRef::rng__meta)?;
+        module.function_meta(SceneRef::config__meta)?;
+        module.function_meta(SceneRef::config_all__meta)?;
+        module.function_meta(SceneRef::serialize_rune__meta)?;
         Ok(())
     }
 }
 
 impl SceneRef {
-    pub fn new(world: WorldRef, config: Object) -> Self {
-        SceneRef(Rc::new(RefCell::new(Scene::new(world, config))))
+    pub fn new(world: WorldRef, name: &str, config: Object) -> Self {
+        SceneRef(Rc::new(RefCell::new(Scene::new(world, name, config))))
     }
 
+    /// Spawn a new, empty entity. Ids are never reused, even when the scene
+    /// pools entities.
     #[rune::function(keep)]
-    fn spawn(&mut self) -> EntityRef {
-        let entity = EntityRef::new();
-        self.0.borrow_mut().entities.push(entity.clone());
-        entity
+    pub fn spawn(&mut self) -> EntityRef {
+        self.0.borrow_mut().spawn()
+    }
+
+    /// Spawn `n` empty entities at once, in order, e.g. when loading a level.
+    #[rune::function(instance, keep)]
+    pub fn spawn_many(&mut self, n: usize) -> Vec<EntityRef> {
+        let mut scene = self.0.borrow_mut();
+        (0..n).map(|_| scene.spawn()).collect()
+    }
+
+    /// Remove `entity` from the scene. Returns false if it wasn't in it.
+    ///
+    /// With the `pool` scene config set, the entity's storage is kept for the
+    /// next `spawn`. Handles to the despawned entity don't follow it: they
+    /// keep their old id, and `is_alive` returns false for them.
+    #[rune::function(instance, keep)]
+    pub fn despawn(&mut self, entity: EntityRef) -> bool {
+        let mut scene = self.0.borrow_mut();
+        let Some(removed) = scene.entities.remove(&entity.id()) else {
+            return false;
+        };
+        removed.detach_from_index();
+        if let Some(pool) = &mut scene.pool
+            && pool.len() < POOL_CAPACITY
+        {
+            pool.push(removed.take_storage());
+        }
+        true
     }
 
     #[rune::function(instance)]
     fn system(&mut self, name: &str, f: Function) {
-        self.0.borrow_mut().systems.insert(name.to_owned(), f);
+        self.add_system(name, f, 0, 1);
+    }
+
+    /// Like `system`, but takes an options object with `priority` (higher runs
+    /// first, default 0) and `tick_every` (run every N frames, default 1).
+    /// Invalid options are an error, and the system isn't added.
+    #[rune::function(instance, keep)]
+    fn system_with_opts(&mut self, name: &str, f: Function, opts: Object) -> VmResult<()> {
+        let get = |key: &str, default: i64| -> Result<i64, TetronError> {
+            match opts.get(key) {
+                Some(value) => value.as_integer::<i64>().map_err(|_| {
+                    TetronError::Runtime(format!(
+                        "Invalid {key} for system {name}: expected an integer"
+                    ))
+                }),
+                None => Ok(default),
+            }
+        };
+        let priority = vm_try!(get("priority", 0).into_vm_result());
+        let tick_every = vm_try!(get("tick_every", 1).into_vm_result());
+        if tick_every < 1 {
+            return Err(TetronError::Runtime(format!(
+                "Invalid tick_every for system {name}: must be at least 1"
+            )))
+            .into_vm_result();
+        }
+        self.add_system(name, f, priority, tick_every as u64);
+        VmResult::Ok(())
     }
 
+    fn add_system(&mut self, name: &str, handler: Function, priority: i64, tick_every: u64) {
+        let mut scene = self.0.borrow_mut();
+        scene.frame_counters.remove(name);
+        scene.systems.insert(
+            name.to_owned(),
+            System {
+                handler,
+                priority,
+                tick_every,
+            },
+        );
+    }
+
+    /// Step tweens and run the systems due this frame. A failing system is
+    /// reported with its name and this scene's, and handled according to the
+    /// world's error mode; see [`SceneRef::update_with`].
     pub fn update(&mut self, dt: f64) -> Result<(), TetronError> {
-        let scene = self.0.try_borrow_mut()?;
-        let ctx = Ctx::new(scene.world.clone(), dt);
-        for system in scene.systems.values() {
-            system
-                .call::<()>((ctx.clone().to_value()?,))
-                .into_result()
-                .inspect_err(|e| system_log!("SceneRef::update system error: {e:?}"))?;
+        let world = self.0.try_borrow()?.world.clone();
+        self.update_with(dt, world.error_mode())
+    }
+
+    /// Like [`SceneRef::update`], with an explicit error mode. In
+    /// `ErrorMode::Recover` a failing system is logged and skipped for this
+    /// frame while the others still run; in `ErrorMode::Panic` its error is
+    /// returned, ending the game from [`Game::run`](crate::engine::Game::run).
+    pub fn update_with(&mut self, dt: f64, mode: ErrorMode) -> Result<(), TetronError> {
+        // Tween callbacks may use the scene and start tweens too, so step
+        // them with neither borrowed.
+        let list = self.0.try_borrow()?.tweens.clone();
+        let tweens = std::mem::take(&mut *list.try_borrow_mut()?);
+        let (tweens, error) = tween::step(tweens, dt);
+        list.try_borrow_mut()?.extend(tweens);
+        if let Some(err) = error {
+            match mode {
+                ErrorMode::Recover => system_log!("{err}"),
+                ErrorMode::Panic => return Err(err),
+            }
+        }
+
+        // Release the borrow before running systems so they can use the scene.
+        let (ctx, scene_name, systems) = {
+            let scene = &mut *self.0.try_borrow_mut()?;
+            let mut due = Vec::new();
+            for (name, system) in &scene.systems {
+                let counter = scene.frame_counters.entry(name.clone()).or_default();
+                if *counter % system.tick_every == 0 {
+                    due.push((system.priority, name.clone(), system.handler.try_clone()?));
+                }
+                *counter += 1;
+            }
+            due.sort_by(|(a_priority, a_name, _), (b_priority, b_name, _)| {
+                b_priority.cmp(a_priority).then_with(|| a_name.cmp(b_name))
+            });
+            (Ctx::new(scene.world.clone(), dt), scene.name.clone(), due)
+        };
+        for (_, name, system) in systems {
+            if let Err(e) = system.call::<()>((ctx.clone().to_value()?,)).into_result() {
+                let err = TetronError::Runtime(format!(
+                    "system \"{name}\" in scene \"{scene_name}\" failed: {e}"
+                ));
+                match mode {
+                    ErrorMode::Recover => system_log!("Skipping {err}"),
+                    ErrorMode::Panic => return Err(err),
+                }
+            }
         }
 
         Ok(())
     }
 
+    /// A snapshot of the entities in this scene, in spawn order. Entities
+    /// spawned after this is called won't appear in the returned list.
+    #[rune::function(instance, keep)]
     pub fn entities(&self) -> Vec<EntityRef> {
-        self.0.borrow().entities.clone()
+        self.0.borrow().entities.values().cloned().collect()
+    }
+
+    /// Entities carrying any of `tags`, in spawn order, looked up through the
+    /// tag index rather than by checking every entity.
+    pub fn entities_tagged_any(&self, tags: &HashSet<String>) -> Vec<EntityRef> {
+        let scene = self.0.borrow();
+        let index = scene.tag_index.borrow();
+        let ids: BTreeSet<EntityId> = tags
+            .iter()
+            .filter_map(|tag| index.get(tag))
+            .flatten()
+            .copied()
+            .collect();
+        ids.iter()
+            .filter_map(|id| scene.entities.get(id))
+            .cloned()
+            .collect()
+    }
+
+    #[rune::function(instance, keep)]
+    pub fn entity_count(&self) -> i64 {
+        self.0.borrow().entities.len() as i64
+    }
+
+    /// All entities in this scene that have the behaviour `name` attached.
+    #[rune::function(instance, keep)]
+    fn entities_with(&self, name: &str) -> Vec<EntityRef> {
+        self.0
+            .borrow()
+            .entities
+            .values()
+            .filter(|entity| entity.has_behaviour(name))
+            .cloned()
+            .collect()
+    }
+
+    /// Read `key` from the config the scene was created with.
+    #[rune::function(instance, keep)]
+    pub fn config(&self, key: &str) -> Option<Value> {
+        self.0.borrow().config.get(key).cloned()
+    }
+
+    /// A copy of the whole config the scene was created with.
+    #[rune::function(instance, keep)]
+    pub fn config_all(&self) -> Object {
+        let scene = self.0.borrow();
+        scene
+            .config
+            .try_clone()
+            .engine_bug("failed to copy scene config", scene.world.error_mode())
+            .unwrap_or_default()
+    }
+
+    /// This scene's own random stream. It's seeded from the `seed` field of
+    /// the scene config if there is one, otherwise from the world seed and
+    /// the scene name, so draws here don't depend on any other randomness.
+    #[rune::function(instance, keep)]
+    pub fn rng(&self) -> RngRef {
+        let mut scene = self.0.borrow_mut();
+        if let Some(rng) = &scene.rng {
+            return rng.clone();
+        }
+
+        let seed = match scene
+            .config
+            .get("seed")
+            // Restored scenes hold every number as a float.
+            .and_then(|seed| {
+                seed.as_integer::<i64>()
+                    .ok()
+                    .or_else(|| seed.as_float().ok().map(|f| f as i64))
+            }) {
+            Some(seed) => seed as u64,
+            None => derive_seed(scene.world.seed() as u64, &scene.name),
+        };
+        let rng = RngRef::new(Rng::new(seed));
+        scene.rng = Some(rng.clone());
+        rng
+    }
+
+    /// Snapshot the scene's config and entities, for `WorldRef::deserialize_scene`.
+    ///
+    /// The snapshot is an object of the form
+    /// `{ config, entities: [{ id, tags, behaviours: { name: config }, meta }] }`,
+    /// with entities in spawn order. Systems aren't included, since they're
+    /// script functions; scripts re-add them after restoring. Restored
+    /// entities get fresh ids, so `id` is only used to compare snapshots
+    /// (see `diff::diff_scenes`).
+    pub fn serialize(&self) -> Result<TypedValue, TetronError> {
+        let scene = self.0.borrow();
+        let mut config = HashMap::new();
+        for (key, value) in &scene.config {
+            config.insert(key.as_str().to_owned(), TypedValue::try_from(value)?);
+        }
+
+        let entities = scene
+            .entities
+            .values()
+            .map(|entity| {
+                let behaviours = entity
+                    .behaviour_names()
+                    .into_iter()
+                    .filter_map(|name| {
+                        let config = entity.behaviour(&name)?.config();
+                        Some((name, TypedValue::Object(config)))
+                    })
+                    .collect();
+                let tags = entity.tags().into_iter().map(TypedValue::String).collect();
+                TypedValue::Object(HashMap::from([
+                    ("id".to_owned(), TypedValue::from(entity.id())),
+                    ("tags".to_owned(), TypedValue::Array(tags)),
+                    ("behaviours".to_owned(), TypedValue::Object(behaviours)),
+                    ("meta".to_owned(), TypedValue::Object(entity.metadata())),
+                ]))
+            })
+            .collect();
+
+        Ok(TypedValue::Object(HashMap::from([
+            ("config".to_owned(), TypedValue::Object(config)),
+            ("entities".to_owned(), TypedValue::Array(entities)),
+        ])))
+    }
+
+    #[rune::function(instance, keep, path = Self::serialize)]
+    pub fn serialize_rune(&self) -> VmResult<Value> {
+        self.serialize()
+            .and_then(Value::try_from)
+            .map_err(|e| TetronError::Runtime(format!("Could not serialize scene: {e}")))
+            .into_vm_result()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        engine::behaviours::BehaviourFactory,
+        utils::typed_value::{TypedValue, schema::Schema},
+    };
+
+    fn int_object(key: &str, value: i64) -> Result<Object, TetronError> {
+        let mut object = Object::new();
+        object
+            .insert_value(rune::alloc::String::try_from(key)?, value)
+            .into_result()
+            .map_err(|e| TetronError::Runtime(e.to_string()))?;
+        Ok(object)
+    }
+
+    #[test]
+    fn test_entities_snapshot() -> Result<(), TetronError> {
+        let mut scene = SceneRef::new(WorldRef::new(), "test", Object::new());
+        for _ in 0..5 {
+            scene.spawn();
+        }
+        assert_eq!(scene.entities().len(), 5);
+        assert_eq!(scene.entity_count(), 5);
+
+        let schema = Schema::object().field("hp", Schema::number()).build();
+        let factory = BehaviourFactory::new("health", schema, false);
+        let initial = HashMap::from([("hp".to_string(), TypedValue::Number(10.0))]);
+        scene.entities()[0].attach(factory.with_map(initial));
+
+        let mut health = scene.entities()[0].behaviour("health").unwrap();
+        health.set("hp", 3.0.to_value()?).into_result()?;
+
+        let found = scene.entities_with("health");
+        assert_eq!(found.len(), 1);
+        assert_eq!(
+            found[0].behaviour("health").unwrap().get_typed("hp")?,
+            Some(TypedValue::Number(3.0))
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_scene_rng_is_per_scene() -> Result<(), TetronError> {
+        let world = WorldRef::new();
+        let a = SceneRef::new(world.clone(), "a", Object::new());
+        let b = SceneRef::new(world.clone(), "b", Object::new());
+        let a_again = SceneRef::new(world.clone(), "a", Object::new());
+
+        let first = a.rng().float();
+        assert_eq!(first, a_again.rng().float());
+        assert_ne!(first, b.rng().float());
+        // The stream is shared, so the next draw moves on.
+        assert_ne!(first, a.rng().float());
+
+        let config = int_object("seed", 7)?;
+        let seeded = SceneRef::new(world, "seeded", config);
+        assert_eq!(seeded.rng().float(), Rng::new(7).float());
+        Ok(())
+    }
+
+    #[test]
+    fn test_tick_every() -> Result<(), TetronError> {
+        use std::sync::{
+            Arc,
+            atomic::{AtomicUsize, Ordering},
+        };
+
+        let world = WorldRef::new();
+        let mut scene = SceneRef::new(world.clone(), "test", Object::new());
+        let runs = Arc::new(AtomicUsize::new(0));
+        let handler = Function::new({
+            let runs = runs.clone();
+            move |_ctx: Ctx| {
+                runs.fetch_add(1, Ordering::Relaxed);
+            }
+        });
+        scene
+            .system_with_opts("slow", handler.try_clone()?, int_object("tick_every", 4)?)
+            .into_result()?;
+        let mut fractional = Object::new();
+        fractional
+            .insert_value(rune::alloc::String::try_from("priority")?, 1.5)
+            .into_result()
+            .map_err(|e| TetronError::Runtime(e.to_string()))?;
+        for opts in [int_object("tick_every", 0)?, fractional] {
+            assert!(
+                scene
+                    .system_with_opts("bad", handler.try_clone()?, opts)
+                    .is_err()
+            );
+        }
+
+        // Frames 0 through 8.
+        for _ in 0..9 {
+            scene.update(0.016)?;
+        }
+        assert_eq!(runs.load(Ordering::Relaxed), 3);
+        Ok(())
+    }
+
+    #[test]
+    fn test_failing_system() -> Result<(), TetronError> {
+        use std::sync::{
+            Arc,
+            atomic::{AtomicUsize, Ordering},
+        };
+
+        let mut scene = SceneRef::new(WorldRef::new(), "arena", Object::new());
+        let runs = Arc::new(AtomicUsize::new(0));
+        scene.add_system(
+            "counter",
+            Function::new({
+                let runs = runs.clone();
+                move |_ctx: Ctx| {
+                    runs.fetch_add(1, Ordering::Relaxed);
+                }
+            }),
+            0,
+            1,
+        );
+        // Systems are called with a Ctx, so this fails every time.
+        scene.add_system("broken", Function::new(|_ctx: i64| {}), 1, 1);
+
+        scene.update_with(0.016, ErrorMode::Recover)?;
+        assert_eq!(runs.load(Ordering::Relaxed), 1);
+
+        let err = scene.update_with(0.016, ErrorMode::Panic).unwrap_err();
+        let msg = err.to_string();
+        assert!(
+            msg.contains("\"broken\"") && msg.contains("\"arena\""),
+            "{msg}"
+        );
+        // The broken system runs first, so the counter was skipped.
+        assert_eq!(runs.load(Ordering::Relaxed), 1);
+        Ok(())
+    }
+
+    #[test]
+    fn test_config_read_back() -> Result<(), TetronError> {
+        let config = int_object("level", 3)?;
+        let scene = SceneRef::new(WorldRef::new(), "test", config);
+
+        assert_eq!(scene.config("level").unwrap().as_integer::<i64>()?, 3);
+        assert!(scene.config("nonexistent").is_none());
+        assert_eq!(scene.config_all().len(), 1);
+        Ok(())
+    }
+
+    #[test]
+    fn test_tag_index_matches_brute_force() {
+        let mut scene = SceneRef::new(WorldRef::new(), "test", Object::new());
+        let tags = ["enemy", "boss", "pickup", "wall"];
+        let mut rng = Rng::new(1);
+        for _ in 0..200 {
+            let mut entity = scene.spawn();
+            for tag in tags {
+                if rng.float() < 0.3 {
+                    entity.tag(tag);
+                }
+            }
+        }
+        // Untagging and despawning must keep the index in sync too.
+        for mut entity in scene.entities().into_iter().step_by(7) {
+            entity.untag("enemy");
+        }
+        for entity in scene.entities().into_iter().step_by(11) {
+            scene.despawn(entity);
+        }
+
+        for query in [vec!["enemy"], vec!["boss", "pickup"], vec!["missing"]] {
+            let query: HashSet<String> = query.into_iter().map(String::from).collect();
+            let brute_force: Vec<EntityId> = scene
+                .entities()
+                .iter()
+                .filter(|entity| query.iter().any(|tag| entity.has_tag(tag)))
+                .map(|entity| entity.id())
+                .collect();
+            let indexed: Vec<EntityId> = scene
+                .entities_tagged_any(&query)
+                .iter()
+                .map(|entity| entity.id())
+                .collect();
+            assert_eq!(indexed, brute_force, "{query:?}");
+        }
+    }
+
+    #[test]
+    fn test_serialize_round_trip() -> Result<(), TetronError> {
+        use crate::engine::{physics::vec2::Vec2, transform};
+
+        let world = WorldRef::new();
+        let mut scene = world.scene("level", int_object("seed", 3)?);
+        let health_schema = Schema::object().field("hp", Schema::number()).build();
+        let health = BehaviourFactory::new("health", health_schema.clone(), false);
+        let position = BehaviourFactory::new("transform", transform::schema(), true);
+
+        let mut player = scene.spawn();
+        player.attach(position.with_map(HashMap::from([(
+            "pos".to_string(),
+            TypedValue::Vector(Vec2::new(1.0, 2.0)),
+        )])));
+        player
+            .attach(health.with_map(HashMap::from([("hp".to_string(), TypedValue::Number(7.0))])));
+        player.tag("player");
+        player.insert_meta("name", "hero".into());
+        let mut crate_ = scene.spawn();
+        crate_.tag("prop");
+
+        let data = scene.serialize()?;
+        // Custom behaviours have to be defined before restoring.
+        assert!(world.deserialize_scene("copy", &data).is_err());
+        world.clone().define_behaviour("health", health_schema);
+
+        let copy = world.deserialize_scene("copy", &data)?;
+        assert_eq!(
+            copy.config("seed").and_then(|v| v.as_float().ok()),
+            Some(3.0)
+        );
+        let entities = copy.entities();
+        assert_eq!(entities.len(), 2);
+        assert_eq!(entities[0].tags(), vec!["player"]);
+        assert_eq!(
+            entities[0].behaviour("tetron:transform").unwrap().config(),
+            player.behaviour("tetron:transform").unwrap().config()
+        );
+        assert_eq!(
+            entities[0].behaviour("health").unwrap().get_typed("hp")?,
+            Some(TypedValue::Number(7.0))
+        );
+        assert_eq!(entities[0].metadata(), player.metadata());
+        assert_eq!(entities[1].tags(), vec!["prop"]);
+        assert_eq!(
+            copy.entities_tagged_any(&HashSet::from(["prop".into()]))
+                .len(),
+            1
+        );
+        assert_eq!(copy.serialize()?, data);
+        assert_eq!(copy.rng().float(), Rng::new(3).float());
+
+        assert!(world.deserialize_scene("copy", &data).is_err());
+        // Scripts get malformed saves back as errors.
+        let saved = copy.serialize_rune().into_result()?;
+        assert!(world.deserialize_scene_rune("other", saved).is_ok());
+        assert!(
+            world
+                .deserialize_scene_rune("broken", 5.to_value()?)
+                .is_err()
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_pooled_spawn_reuses_entities() -> Result<(), TetronError> {
+        let mut config = Object::new();
+        config
+            .insert_value(rune::alloc::String::try_from("pool")?, true)
+            .into_result()
+            .map_err(|e| TetronError::Runtime(e.to_string()))?;
+        let mut scene = SceneRef::new(WorldRef::new(), "test", config);
+
+        let mut bullet = scene.spawn();
+        bullet.tag("bullet");
+        bullet.set_meta("damage", Value::from(3i64)).into_result()?;
+        let old_id = bullet.id();
+        assert!(bullet.is_alive());
+        assert!(scene.despawn(bullet.clone()));
+        assert!(scene.0.borrow().tag_index.borrow().get("bullet").is_none());
+        assert_eq!(scene.0.borrow().pool.as_ref().map(Vec::len), Some(1));
+
+        let mut reused = scene.spawn();
+        assert_ne!(reused.id(), old_id);
+        assert_eq!(reused.generation(), 1);
+        assert!(reused.tags().is_empty());
+        assert!(reused.get_meta("damage").into_result()?.is_none());
+        assert_eq!(scene.0.borrow().pool.as_ref().map(Vec::len), Some(0));
+
+        // The old handle doesn't follow the storage to the new entity.
+        assert!(!bullet.is_alive());
+        assert!(reused.is_alive());
+        assert_eq!(bullet.id(), old_id);
+        assert_eq!(bullet.generation(), 0);
+        reused.tag("enemy");
+        assert!(!bullet.has_tag("enemy"));
+        assert!(!scene.despawn(bullet));
+
+        // The pool stops growing at its capacity.
+        let extra = scene.spawn_many(POOL_CAPACITY + 10);
+        for entity in extra {
+            scene.despawn(entity);
+        }
+        assert_eq!(
+            scene.0.borrow().pool.as_ref().map(Vec::len),
+            Some(POOL_CAPACITY)
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_spawn_many() {
+        let mut scene = SceneRef::new(WorldRef::new(), "test", Object::new());
+        let mut entities = scene.spawn_many(100);
+        for entity in &mut entities {
+            entity.tag("tile");
+        }
+
+        let ids: BTreeSet<EntityId> = entities.iter().map(EntityRef::id).collect();
+        assert_eq!(ids.len(), 100);
+        assert_eq!(scene.entity_count(), 100);
+        let tagged = scene.entities_tagged_any(&HashSet::from(["tile".to_string()]));
+        assert_eq!(tagged.len(), 100);
     }
 }
@@ -1,29 +1,164 @@
-use super::behaviours::{BehaviourFactory, BehaviourRef};
+use super::{
+    behaviours::{BehaviourFactory, BehaviourRef},
+    physics::vec2::Vec2,
+};
 use crate::{
+    error::EngineBug,
     log_and_die,
     utils::typed_value::{TypedValue, schema::Schema},
 };
-use rune::{ContextError, Module, docstring, runtime::Object};
+use rune::{
+    ContextError, Module, docstring,
+    runtime::{Object, VmResult},
+    vm_try,
+};
+use std::f64::consts::TAU;
 
-fn register_factory(module: &mut Module) -> Result<(), ContextError> {
-    let schema = Schema::object()
+pub(crate) fn schema() -> Schema {
+    Schema::object()
         .field("type", Schema::string())
         .optional_field("w", Schema::number(), None)
         .optional_field("h", Schema::number(), None)
         .optional_field("r", Schema::number(), None)
         .optional_field("points", Schema::array(Schema::vec2()).min(2), None)
-        .build();
+        .build()
+}
+
+/// Returns true if `points` form a convex polygon, in either winding order.
+///
+/// Every turn around the perimeter must go the same way, and the turns must
+/// add up to one full revolution, which rules out self-intersecting shapes
+/// like a pentagram. Collinear points are allowed.
+pub(crate) fn is_convex(points: &[Vec2]) -> bool {
+    let n = points.len();
+    if n < 3 {
+        return false;
+    }
+    let mut sign = 0.0;
+    let mut turned = 0.0;
+    for i in 0..n {
+        let a = points[(i + 1) % n] - points[i];
+        let b = points[(i + 2) % n] - points[(i + 1) % n];
+        let cross = a.cross(b);
+        if cross == 0.0 {
+            continue;
+        }
+        if sign * cross < 0.0 {
+            return false;
+        }
+        sign = cross.signum();
+        turned += a.angle_between(b);
+    }
+    sign != 0.0 && (turned - TAU).abs() < 1e-6
+}
+
+/// Returns true if `point` lies inside the convex polygon `points`,
+/// including its edges. Works for either winding order.
+pub(crate) fn point_in_convex_polygon(points: &[Vec2], point: Vec2) -> bool {
+    let n = points.len();
+    if n < 3 {
+        return false;
+    }
+    let mut sign = 0.0;
+    for i in 0..n {
+        let edge = points[(i + 1) % n] - points[i];
+        let cross = edge.cross(point - points[i]);
+        if cross == 0.0 {
+            continue;
+        }
+        if sign * cross < 0.0 {
+            return false;
+        }
+        sign = cross.signum();
+    }
+    true
+}
 
-    let shapes = BehaviourFactory::new("shape", schema, true);
+/// The size field `field` of `shape`: its `w`, `h` or `r`. A missing size
+/// is 1, both when the shape is drawn and when it's hit-tested.
+pub(crate) fn size(shape: &BehaviourRef, field: &str) -> f64 {
+    match shape.get_typed(field) {
+        Ok(Some(TypedValue::Number(n))) => n,
+        _ => 1.0,
+    }
+}
+
+/// Returns true if `point` is inside `shape`, placed the same way it is
+/// drawn: rects extend right and down from `pos`, circles are centered on
+/// `pos`, and poly points are already in world space. Lines have no area,
+/// so they never contain a point.
+pub(crate) fn contains(shape: &BehaviourRef, pos: Vec2, point: Vec2) -> bool {
+    match shape.get_typed("type") {
+        Ok(Some(TypedValue::String(kind))) if kind == "rect" => {
+            let local = point - pos;
+            (0.0..=size(shape, "w")).contains(&local.x)
+                && (0.0..=size(shape, "h")).contains(&local.y)
+        }
+        Ok(Some(TypedValue::String(kind))) if kind == "circle" => {
+            let r = size(shape, "r");
+            (point - pos).length_sq() <= r * r
+        }
+        Ok(Some(TypedValue::String(kind))) if kind == "poly" => {
+            let Ok(Some(TypedValue::Array(points))) = shape.get_typed("points") else {
+                return false;
+            };
+            let points: Vec<Vec2> = points
+                .into_iter()
+                .filter_map(|point| match point {
+                    TypedValue::Vector(v) => Some(v),
+                    _ => None,
+                })
+                .collect();
+            point_in_convex_polygon(&points, point)
+        }
+        _ => false,
+    }
+}
 
-    let func = move |name: &str, config: &Object| -> BehaviourRef {
+/// The box around `shape` placed at `pos`, as its top-left corner and size,
+/// placed the same way as [`contains`], with the same default [`size`].
+/// Lines and malformed shapes have no bounds.
+pub(crate) fn bounds(shape: &BehaviourRef, pos: Vec2) -> Option<(Vec2, Vec2)> {
+    match shape.get_typed("type") {
+        Ok(Some(TypedValue::String(kind))) if kind == "rect" => {
+            Some((pos, Vec2::new(size(shape, "w"), size(shape, "h"))))
+        }
+        Ok(Some(TypedValue::String(kind))) if kind == "circle" => {
+            let r = size(shape, "r");
+            Some((pos - Vec2::new(r, r), Vec2::new(r * 2.0, r * 2.0)))
+        }
+        Ok(Some(TypedValue::String(kind))) if kind == "poly" => {
+            let Ok(Some(TypedValue::Array(points))) = shape.get_typed("points") else {
+                return None;
+            };
+            let mut points = points.into_iter().filter_map(|point| match point {
+                TypedValue::Vector(v) => Some(v),
+                _ => None,
+            });
+            let first = points.next()?;
+            let (min, max) = points.fold((first, first), |(min, max), p| {
+                (
+                    Vec2::new(min.x.min(p.x), min.y.min(p.y)),
+                    Vec2::new(max.x.max(p.x), max.y.max(p.y)),
+                )
+            });
+            Some((min, max - min))
+        }
+        _ => None,
+    }
+}
+
+fn register_factory(module: &mut Module) -> Result<(), ContextError> {
+    let shapes = BehaviourFactory::new("shape", schema(), true);
+
+    let func = move |name: &str, config: &Object| -> VmResult<BehaviourRef> {
         let mut map = std::collections::HashMap::<String, TypedValue>::new();
         for (key, val) in config {
-            map.insert(
-                key.as_str().to_string(),
-                val.try_into()
-                    .expect("Engine bug: failed to convert rune value to typed value"),
+            let val = vm_try!(
+                TypedValue::try_from(val)
+                    .throw_engine_bug("failed to convert rune value to typed value")
             );
+            map.insert(key.as_str().to_string(), val);
         }
         map.insert("type".into(), String::from(name).into());
         let shape = shapes.with_map(map);
@@ -36,16 +171,29 @@ fn register_factory(module: &mut Module) -> Result<(), ContextError> {
                 }
             }
             "poly" => {
-                if let Some(TypedValue::Array(points)) = shape.get_typed("points") {
+                if let Ok(Some(TypedValue::Array(points))) = shape.get_typed("points") {
                     if points.len() < 3 {
                         log_and_die!(1, "poly shape requires at least 3 points");
                     }
+                    let points: Vec<Vec2> = points
+                        .into_iter()
+                        .filter_map(|point| match point {
+                            TypedValue::Vector(v) => Some(v),
+                            _ => None,
+                        })
+                        .collect();
+                    if !is_convex(&points) {
+                        log_and_die!(
+                            1,
+                            "poly shape must be convex and not self-intersecting: {points:?}"
+                        );
+                    }
                 } else {
                     log_and_die!(1, "poly requires 'points' array");
                 }
             }
             "line" => {
-                if let Some(TypedValue::Array(points)) = shape.get_typed("points") {
+                if let Ok(Some(TypedValue::Array(points))) = shape.get_typed("points") {
                     if points.len() != 2 {
                         log_and_die!(1, "line requires exactly 2 points");
                     }
@@ -62,7 +210,7 @@ fn register_factory(module: &mut Module) -> Result<(), ContextError> {
                 log_and_die!(1, "Invalid shape type {name} supplied");
             }
         }
-        shape
+        VmResult::Ok(shape)
     };
 
     module.function("create", func).build()?.docs(docstring! {
@@ -82,3 +230,94 @@ pub fn module() -> Result<Module, ContextError> {
     register_factory(&mut module)?;
     Ok(module)
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn points(coords: &[(f64, f64)]) -> Vec<Vec2> {
+        coords.iter().map(|&(x, y)| Vec2::new(x, y)).collect()
+    }
+
+    #[test]
+    fn test_convexity() {
+        let square = points(&[(0.0, 0.0), (1.0, 0.0), (1.0, 1.0), (0.0, 1.0)]);
+        assert!(is_convex(&square));
+        let reversed: Vec<Vec2> = square.iter().rev().copied().collect();
+        assert!(is_convex(&reversed));
+
+        let arrow = points(&[(0.0, 0.0), (2.0, 1.0), (0.0, 2.0), (0.5, 1.0)]);
+        assert!(!is_convex(&arrow));
+
+        let bowtie = points(&[(0.0, 0.0), (1.0, 1.0), (1.0, 0.0), (0.0, 1.0)]);
+        assert!(!is_convex(&bowtie));
+    }
+
+    fn shape(fields: &[(&str, TypedValue)]) -> BehaviourRef {
+        let map = fields
+            .iter()
+            .map(|(key, value)| (key.to_string(), value.clone()))
+            .collect();
+        BehaviourFactory::new("shape", schema(), true).with_map(map)
+    }
+
+    #[test]
+    fn test_rect_contains() {
+        let rect = shape(&[
+            ("type", "rect".to_string().into()),
+            ("w", TypedValue::Number(10.0)),
+            ("h", TypedValue::Number(5.0)),
+        ]);
+        let pos = Vec2::new(2.0, 2.0);
+        assert!(contains(&rect, pos, Vec2::new(2.0, 2.0)));
+        assert!(contains(&rect, pos, Vec2::new(11.0, 6.0)));
+        assert!(!contains(&rect, pos, Vec2::new(1.0, 3.0)));
+        assert!(!contains(&rect, pos, Vec2::new(5.0, 8.0)));
+
+        // Drawn 1x1 without a size, so it can be clicked as 1x1 too.
+        let no_size = shape(&[("type", "rect".to_string().into())]);
+        assert!(contains(&no_size, pos, Vec2::new(3.0, 2.5)));
+        assert!(!contains(&no_size, pos, Vec2::new(3.5, 2.0)));
+    }
+
+    #[test]
+    fn test_circle_contains() {
+        let circle = shape(&[
+            ("type", "circle".to_string().into()),
+            ("r", TypedValue::Number(5.0)),
+        ]);
+        let pos = Vec2::new(10.0, 10.0);
+        assert!(contains(&circle, pos, pos));
+        assert!(contains(&circle, pos, Vec2::new(13.0, 14.0)));
+        assert!(!contains(&circle, pos, Vec2::new(14.0, 14.0)));
+
+        let no_size = shape(&[("type", "circle".to_string().into())]);
+        assert!(contains(&no_size, pos, Vec2::new(11.0, 10.0)));
+        assert!(!contains(&no_size, pos, Vec2::new(11.0, 11.0)));
+    }
+
+    #[test]
+    fn test_poly_contains() {
+        let triangle = points(&[(0.0, 0.0), (4.0, 0.0), (0.0, 4.0)]);
+        assert!(point_in_convex_polygon(&triangle, Vec2::new(1.0, 1.0)));
+        assert!(point_in_convex_polygon(&triangle, Vec2::new(2.0, 2.0)));
+        assert!(!point_in_convex_polygon(&triangle, Vec2::new(3.0, 3.0)));
+        let reversed: Vec<Vec2> = triangle.iter().rev().copied().collect();
+        assert!(point_in_convex_polygon(&reversed, Vec2::new(1.0, 1.0)));
+
+        let poly = shape(&[
+            ("type", "poly".to_string().into()),
+            (
+                "points",
+                TypedValue::Array(triangle.into_iter().map(TypedValue::Vector).collect()),
+            ),
+        ]);
+        // Poly points are in world space, so the transform is ignored.
+        assert!(contains(
+            &poly,
+            Vec2::new(100.0, 100.0),
+            Vec2::new(1.0, 1.0)
+        ));
+        assert!(!contains(&poly, Vec2::zero(), Vec2::new(-1.0, 1.0)));
+    }
+}
@@ -1,7 +1,17 @@
-use super::{entity::EntityRef, world::WorldRef};
-use crate::{error::TetronError, utils::Registrable};
-use rune::{Value, runtime::Object};
-use std::collections::HashSet;
+use super::{entity::EntityRef, physics::vec2::Vec2, scene::SceneRef, shape, world::WorldRef};
+use crate::{
+    error::{EngineBug, ErrorMode, IntoVmResult, TetronError},
+    utils::{
+        Registrable,
+        rng::{Rng, RngRef},
+        typed_value::TypedValue,
+    },
+};
+use rune::{
+    Value,
+    runtime::{Object, VmResult},
+};
+use std::{cmp::Ordering, collections::HashSet};
 
 #[derive(Clone, rune::Any)]
 pub struct Ctx {
@@ -9,6 +19,9 @@ pub struct Ctx {
     world: WorldRef,
     #[rune(get)]
     dt: f64,
+    /// The world's error mode, read up front since the world may be borrowed
+    /// when an engine bug is hit.
+    error_mode: ErrorMode,
 }
 
 fn vec_str_to_hashset(v: &Value) -> Result<HashSet<String>, TetronError> {
@@ -23,31 +36,110 @@ fn vec_str_to_hashset(v: &Value) -> Result<HashSet<String>, TetronError> {
     }
 }
 
+/// Orders query results by a numeric field of one of their behaviours.
+///
+/// The field is written as `behaviour.field`, optionally followed by more
+/// segments to reach into objects or pick the `x`/`y` component of a vector,
+/// e.g. `tetron:transform.pos.y`. Entities without the behaviour or field, or
+/// whose field isn't a number, sort last in either direction.
+#[derive(Clone, Debug, PartialEq)]
+pub struct SortKey {
+    behaviour: String,
+    field: String,
+    path: Vec<String>,
+    descending: bool,
+}
+
+impl SortKey {
+    pub fn parse(spec: &str, descending: bool) -> Option<Self> {
+        let mut segments = spec.split('.');
+        let behaviour = segments.next().filter(|s| !s.is_empty())?.to_owned();
+        let field = segments.next().filter(|s| !s.is_empty())?.to_owned();
+        Some(Self {
+            behaviour,
+            field,
+            path: segments.map(str::to_owned).collect(),
+            descending,
+        })
+    }
+
+    fn extract(&self, entity: &EntityRef) -> Option<f64> {
+        let behaviour = entity.behaviour(&self.behaviour)?;
+        if !behaviour.has(&self.field) {
+            return None;
+        }
+        let mut value = behaviour.get_typed(&self.field).ok()??;
+        for segment in &self.path {
+            value = match (value, segment.as_str()) {
+                (TypedValue::Object(mut map), _) => map.remove(segment)?,
+                (TypedValue::Vector(v), "x") => TypedValue::Number(v.x),
+                (TypedValue::Vector(v), "y") => TypedValue::Number(v.y),
+                _ => return None,
+            };
+        }
+        match value {
+            TypedValue::Number(n) => Some(n),
+            _ => None,
+        }
+    }
+
+    /// Stable-sort `entities` by this key.
+    pub fn sort(&self, entities: &mut Vec<EntityRef>) {
+        let mut keyed: Vec<(Option<f64>, EntityRef)> = entities
+            .drain(..)
+            .map(|entity| (self.extract(&entity), entity))
+            .collect();
+        keyed.sort_by(|(a, _), (b, _)| match (a, b) {
+            (Some(a), Some(b)) if self.descending => b.total_cmp(a),
+            (Some(a), Some(b)) => a.total_cmp(b),
+            (Some(_), None) => Ordering::Less,
+            (None, Some(_)) => Ordering::Greater,
+            (None, None) => Ordering::Equal,
+        });
+        entities.extend(keyed.into_iter().map(|(_, entity)| entity));
+    }
+}
+
 impl Ctx {
     pub fn new(world: WorldRef, dt: f64) -> Self {
-        Self { world, dt }
+        let error_mode = world.error_mode();
+        Self {
+            world,
+            dt,
+            error_mode,
+        }
     }
 
+    /// Find entities in the current scene with any of `tags` and all of
+    /// `behaviours`. Results are in spawn order unless `sort` is given, and
+    /// `offset` and `limit` page through them after sorting.
     pub fn query_with_sets(
         &self,
         tags: HashSet<String>,
         behaviours: HashSet<String>,
+        sort: Option<&SortKey>,
+        offset: usize,
+        limit: Option<usize>,
     ) -> Result<Vec<EntityRef>, TetronError> {
         if let Some((_, scene)) = self.world.current_scene()? {
-            let entities = scene.entities();
-            if tags.is_empty() && behaviours.is_empty() {
-                return Ok(entities);
+            // Entities match if they have any of the tags, so the tag index
+            // gives exactly the candidates.
+            let entities = if tags.is_empty() {
+                scene.entities()
+            } else {
+                scene.entities_tagged_any(&tags)
+            };
+            let mut result: Vec<EntityRef> = entities
+                .into_iter()
+                .filter(|entity| behaviours.iter().all(|b| entity.has_behaviour(b)))
+                .collect();
+            if let Some(sort) = sort {
+                sort.sort(&mut result);
             }
-
-            let result = entities
+            let result = result
                 .into_iter()
-                .filter(|entity| {
-                    let tags_matched = tags.is_empty() || tags.iter().any(|t| entity.has_tag(t));
-                    let behaviours_matched =
-                        behaviours.is_empty() || behaviours.iter().all(|b| entity.has_behaviour(b));
-
-                    tags_matched && behaviours_matched
-                })
+                .skip(offset)
+                .take(limit.unwrap_or(usize::MAX))
                 .collect();
 
             return Ok(result);
@@ -56,22 +148,183 @@ impl Ctx {
         Ok(Vec::new())
     }
 
+    /// Entities matching `query`. Invalid query options are thrown as errors.
     #[rune::function(keep)]
-    pub fn query(&self, query: Object) -> Vec<EntityRef> {
-        let parse = |key| -> HashSet<String> {
+    pub fn query(&self, query: Object) -> VmResult<Vec<EntityRef>> {
+        self.run_query(&query).into_vm_result()
+    }
+
+    /// Parse a script query object (`tag`, `b`, `sort_by`, `order`, `offset`
+    /// and `limit`, all optional) and run it.
+    fn run_query(&self, query: &Object) -> Result<Vec<EntityRef>, TetronError> {
+        let invalid = |key: &str, expected: &str| {
+            TetronError::Runtime(format!("Invalid query {key}: expected {expected}"))
+        };
+        let parse = |key| -> Result<HashSet<String>, TetronError> {
+            match query.get(key) {
+                Some(value) => {
+                    vec_str_to_hashset(value).map_err(|_| invalid(key, "an array of strings"))
+                }
+                None => Ok(HashSet::new()),
+            }
+        };
+
+        let tags = parse("tag")?;
+        let behaviours = parse("b")?;
+        let parse_count = |key| -> Result<Option<usize>, TetronError> {
             query
                 .get(key)
-                .map(vec_str_to_hashset)
+                .map(|value| {
+                    value
+                        .as_integer::<usize>()
+                        .map_err(|_| invalid(key, "a non-negative integer"))
+                })
+                .transpose()
+        };
+        let offset = parse_count("offset")?.unwrap_or(0);
+        let limit = parse_count("limit")?;
+        let parse_str = |key| -> Result<Option<String>, TetronError> {
+            query
+                .get(key)
+                .map(|value| {
+                    value
+                        .borrow_string_ref()
+                        .map(|s| s.to_string())
+                        .map_err(|_| invalid(key, "a string"))
+                })
                 .transpose()
-                .expect("Engine bug: failed to convert query parameter")
-                .unwrap_or_default()
         };
+        let descending = match parse_str("order")?.as_deref() {
+            None | Some("asc") => false,
+            Some("desc") => true,
+            Some(other) => return Err(invalid(&format!("order {other}"), "asc or desc")),
+        };
+        let sort = parse_str("sort_by")?
+            .map(|spec| {
+                SortKey::parse(&spec, descending)
+                    .ok_or_else(|| invalid(&format!("sort_by {spec}"), "behaviour.field"))
+            })
+            .transpose()?;
+
+        self.query_with_sets(tags, behaviours, sort.as_ref(), offset, limit)
+    }
+
+    /// Run `query`, keeping only entities with a transform, paired with their
+    /// positions.
+    fn positioned(&self, query: &Object) -> Result<Vec<(Vec2, EntityRef)>, TetronError> {
+        Ok(self
+            .run_query(query)?
+            .into_iter()
+            .filter_map(|entity| {
+                let transform = entity.behaviour("tetron:transform")?;
+                let pos = match transform.get_typed("pos").ok()?? {
+                    TypedValue::Vector(pos) => pos,
+                    _ => return None,
+                };
+                Some((pos, entity))
+            })
+            .collect())
+    }
+
+    /// The entity matching `query` whose transform is closest to `from`.
+    /// Entities without a transform are ignored. Ties go to the entity that
+    /// comes first in the query's order.
+    #[rune::function(keep)]
+    pub fn nearest(&self, from: Vec2, query: Object) -> VmResult<Option<EntityRef>> {
+        self.positioned(&query)
+            .map(|positioned| {
+                let mut best: Option<(f64, EntityRef)> = None;
+                for (pos, entity) in positioned {
+                    let dist = (pos - from).length_sq();
+                    if best.as_ref().is_none_or(|(best_dist, _)| dist < *best_dist) {
+                        best = Some((dist, entity));
+                    }
+                }
+                best.map(|(_, entity)| entity)
+            })
+            .into_vm_result()
+    }
+
+    /// Entities matching `query` whose transform is at most `radius` away
+    /// from `center`, in the query's order.
+    #[rune::function(keep)]
+    pub fn within_radius(
+        &self,
+        center: Vec2,
+        radius: f64,
+        query: Object,
+    ) -> VmResult<Vec<EntityRef>> {
+        let radius_sq = radius * radius;
+        self.positioned(&query)
+            .map(|positioned| {
+                positioned
+                    .into_iter()
+                    .filter(|(pos, _)| (*pos - center).length_sq() <= radius_sq)
+                    .map(|(_, entity)| entity)
+                    .collect()
+            })
+            .into_vm_result()
+    }
+
+    /// The topmost entity matching `query` whose shape contains `point`.
+    /// Entities are drawn in query order, so later entities win. Entities
+    /// without both a transform and a shape are ignored.
+    #[rune::function(keep)]
+    pub fn pick(&self, point: Vec2, query: Object) -> VmResult<Option<EntityRef>> {
+        self.positioned(&query)
+            .map(|positioned| {
+                positioned
+                    .into_iter()
+                    .rev()
+                    .find(|(pos, entity)| {
+                        entity
+                            .behaviour("tetron:shape")
+                            .is_some_and(|shape| shape::contains(&shape, *pos, point))
+                    })
+                    .map(|(_, entity)| entity)
+            })
+            .into_vm_result()
+    }
+
+    fn current_scene(&self) -> Option<(String, SceneRef)> {
+        self.world
+            .current_scene()
+            .engine_bug("failed to get current scene", self.error_mode)
+            .flatten()
+    }
 
-        let tags = parse("tag");
-        let behaviours = parse("b");
+    /// The scene whose systems are running.
+    #[rune::function(keep)]
+    pub fn scene(&self) -> VmResult<SceneRef> {
+        self.try_scene()
+            .ok_or("no scene loaded")
+            .engine_bug("no current scene in system callback", self.error_mode)
+            .ok_or(TetronError::Runtime(
+                "No current scene in system callback".into(),
+            ))
+            .into_vm_result()
+    }
+
+    /// Like `scene`, but returns `None` instead of panicking when no scene
+    /// is loaded.
+    #[rune::function(keep)]
+    pub fn try_scene(&self) -> Option<SceneRef> {
+        self.current_scene().map(|(_, scene)| scene)
+    }
+
+    /// The name of the scene whose systems are running, for systems shared
+    /// between scenes, or `None` if no scene is loaded.
+    #[rune::function(keep)]
+    pub fn scene_name(&self) -> Option<String> {
+        self.current_scene().map(|(name, _)| name)
+    }
 
-        self.query_with_sets(tags, behaviours)
-            .expect("Engine bug: failed to execute query")
+    /// The current scene's random stream. See `SceneRef::rng`.
+    #[rune::function(keep)]
+    pub fn rng(&self) -> RngRef {
+        self.try_scene()
+            .map(|scene| scene.rng())
+            .unwrap_or_else(|| RngRef::new(Rng::from_time()))
     }
 }
 
@@ -79,6 +332,212 @@ impl Registrable for Ctx {
     fn register(module: &mut rune::Module) -> Result<(), rune::ContextError> {
         module.ty::<Ctx>()?;
         module.function_meta(Ctx::query__meta)?;
+        module.function_meta(Ctx::nearest__meta)?;
+        module.function_meta(Ctx::within_radius__meta)?;
+        module.function_meta(Ctx::pick__meta)?;
+        module.function_meta(Ctx::scene__meta)?;
+        module.function_meta(Ctx::try_scene__meta)?;
+        module.function_meta(Ctx::scene_name__meta)?;
+        module.function_meta(Ctx::rng__meta)?;
         Ok(())
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        engine::{
+            behaviours::{BehaviourFactory, BehaviourRef},
+            entity::EntityId,
+        },
+        utils::typed_value::schema::Schema,
+    };
+    use std::collections::HashMap;
+
+    fn transform_at(pos: Option<Vec2>) -> BehaviourRef {
+        let transform = BehaviourFactory::new(
+            "transform",
+            Schema::object()
+                .optional_field("pos", Schema::vec2(), None)
+                .build(),
+            true,
+        );
+        let map = pos
+            .map(|pos| HashMap::from([("pos".to_string(), TypedValue::Vector(pos))]))
+            .unwrap_or_default();
+        transform.with_map(map)
+    }
+
+    fn ids(entities: &[EntityRef]) -> Vec<EntityId> {
+        entities.iter().map(|entity| entity.id()).collect()
+    }
+
+    fn ctx_with_entities(count: usize) -> Ctx {
+        let world = WorldRef::new();
+        let mut scene = world.scene("test", Object::new());
+        world.load_scene("test");
+        for _ in 0..count {
+            scene.spawn();
+        }
+        Ctx::new(world, 0.0)
+    }
+
+    fn query_ids(ctx: &Ctx, offset: usize, limit: Option<usize>) -> Vec<EntityId> {
+        ctx.query_with_sets(HashSet::new(), HashSet::new(), None, offset, limit)
+            .unwrap()
+            .iter()
+            .map(|entity| entity.id())
+            .collect()
+    }
+
+    #[test]
+    fn test_scene_and_try_scene() {
+        let world = WorldRef::new();
+        world.scene("menu", Object::new());
+        let mut level = world.scene("level", Object::new());
+        level.spawn();
+        level.spawn();
+        let ctx = Ctx::new(world.clone(), 0.0);
+        assert!(ctx.try_scene().is_none());
+
+        world.load_scene("level");
+        assert_eq!(ctx.scene().unwrap().entities().len(), 2);
+        world.load_scene("menu");
+        assert!(ctx.scene().unwrap().entities().is_empty());
+        assert!(
+            ctx.try_scene()
+                .is_some_and(|scene| scene.entities().is_empty())
+        );
+    }
+
+    #[test]
+    #[should_panic(expected = "no current scene")]
+    fn test_scene_panics_without_scene() {
+        let _ = Ctx::new(WorldRef::new(), 0.0).scene();
+    }
+
+    #[test]
+    fn test_scene_name() {
+        let world = WorldRef::new();
+        world.scene("menu", Object::new());
+        world.scene("level", Object::new());
+        let ctx = Ctx::new(world.clone(), 0.0);
+        assert_eq!(ctx.scene_name(), None);
+
+        world.load_scene("menu");
+        assert_eq!(ctx.scene_name().as_deref(), Some("menu"));
+        world.load_scene("level");
+        assert_eq!(ctx.scene_name().as_deref(), Some("level"));
+    }
+
+    #[test]
+    fn test_query_limit_and_offset() {
+        let ctx = ctx_with_entities(5);
+        assert_eq!(query_ids(&ctx, 0, None), vec![0, 1, 2, 3, 4]);
+        assert_eq!(query_ids(&ctx, 0, Some(2)), vec![0, 1]);
+        assert_eq!(query_ids(&ctx, 3, None), vec![3, 4]);
+        assert_eq!(query_ids(&ctx, 1, Some(2)), vec![1, 2]);
+        assert_eq!(query_ids(&ctx, 4, Some(10)), vec![4]);
+        assert!(query_ids(&ctx, 10, None).is_empty());
+    }
+
+    #[test]
+    fn test_query_sort_by_transform_y() {
+        let world = WorldRef::new();
+        let mut scene = world.scene("test", Object::new());
+        world.load_scene("test");
+        for y in [30.0, 10.0, 20.0] {
+            scene.spawn().attach(transform_at(Some(Vec2::new(0.0, y))));
+        }
+        // No position, so it sorts last.
+        scene.spawn().attach(transform_at(None));
+        let ctx = Ctx::new(world, 0.0);
+
+        let sorted = |descending| -> Vec<EntityId> {
+            let sort = SortKey::parse("tetron:transform.pos.y", descending).unwrap();
+            ids(&ctx
+                .query_with_sets(HashSet::new(), HashSet::new(), Some(&sort), 0, None)
+                .unwrap())
+        };
+        assert_eq!(sorted(false), vec![1, 2, 0, 3]);
+        assert_eq!(sorted(true), vec![0, 2, 1, 3]);
+        assert!(SortKey::parse("tetron:transform", false).is_none());
+    }
+
+    #[test]
+    fn test_nearest_and_within_radius() {
+        let world = WorldRef::new();
+        let mut scene = world.scene("test", Object::new());
+        world.load_scene("test");
+        for pos in [
+            Vec2::new(10.0, 0.0),
+            Vec2::new(3.0, 4.0),
+            Vec2::new(-1.0, 0.0),
+        ] {
+            scene.spawn().attach(transform_at(Some(pos)));
+        }
+        // Entities without a transform are never matched.
+        scene.spawn();
+        let ctx = Ctx::new(world, 0.0);
+
+        let nearest = ctx.nearest(Vec2::zero(), Object::new()).unwrap();
+        assert_eq!(nearest.unwrap().id(), 2);
+        let nearest = ctx.nearest(Vec2::new(9.0, 0.0), Object::new()).unwrap();
+        assert_eq!(nearest.unwrap().id(), 0);
+
+        assert_eq!(
+            ids(&ctx.within_radius(Vec2::zero(), 5.0, Object::new()).unwrap()),
+            vec![1, 2]
+        );
+        assert!(
+            ctx.within_radius(Vec2::zero(), 0.5, Object::new())
+                .unwrap()
+                .is_empty()
+        );
+
+        let mut only_tagged = Object::new();
+        only_tagged
+            .insert(
+                rune::alloc::String::try_from("tag").unwrap(),
+                rune::to_value(vec!["enemy".to_string()]).unwrap(),
+            )
+            .unwrap();
+        assert!(ctx.nearest(Vec2::zero(), only_tagged).unwrap().is_none());
+    }
+
+    #[test]
+    fn test_invalid_query_is_an_error() {
+        let ctx = ctx_with_entities(2);
+        let query = |key: &str, value: Value| {
+            let mut query = Object::new();
+            query
+                .insert(rune::alloc::String::try_from(key).unwrap(), value)
+                .unwrap();
+            query
+        };
+        for (key, value) in [
+            ("limit", rune::to_value(-1i64).unwrap()),
+            ("offset", rune::to_value("one").unwrap()),
+            ("order", rune::to_value("sideways").unwrap()),
+            ("sort_by", rune::to_value("transform").unwrap()),
+            ("tag", rune::to_value(vec![1i64]).unwrap()),
+        ] {
+            let err = ctx.run_query(&query(key, value)).err();
+            assert!(
+                matches!(&err, Some(TetronError::Runtime(msg)) if msg.contains(key)),
+                "{key}: {err:?}"
+            );
+        }
+        assert!(
+            ctx.query(query("order", rune::to_value("up").unwrap()))
+                .is_err()
+        );
+        assert_eq!(
+            ctx.run_query(&query("limit", rune::to_value(1i64).unwrap()))
+                .unwrap()
+                .len(),
+            1
+        );
+    }
+}
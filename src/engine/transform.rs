@@ -2,53 +2,63 @@ use super::{
     behaviours::{BehaviourFactory, BehaviourRef},
     physics::vec2::Vec2,
 };
-use crate::utils::typed_value::{TypedValue, schema::Schema};
-use rune::{ContextError, FromValue, Module, ToValue, docstring, runtime::Object};
+use crate::{
+    error::EngineBug,
+    utils::typed_value::{TypedValue, schema::Schema},
+};
+use rune::{
+    ContextError, FromValue, Module, ToValue, docstring,
+    runtime::{Object, VmResult},
+    vm_try,
+};
 
 #[rune::function(keep)]
-pub fn rotate(b: &mut BehaviourRef, angle: f64) {
-    let old = if let Some(value) = b.get("rot") {
-        f64::from_value(value).expect("Engine bug: failed to convert rotation value")
-    } else {
-        0.0
+pub fn rotate(b: &mut BehaviourRef, angle: f64) -> VmResult<()> {
+    let old = match vm_try!(b.get("rot")) {
+        Some(value) => {
+            vm_try!(f64::from_value(value).throw_engine_bug("failed to convert rotation value"))
+        }
+        None => 0.0,
     };
-    b.set(
-        "rot",
+    let rot = vm_try!(
         (old + angle)
             .to_value()
-            .expect("Engine bug: failed to convert rotation to rune value"),
+            .throw_engine_bug("failed to convert rotation to rune value")
     );
+    b.set("rot", rot)
 }
 
 #[rune::function(keep)]
-pub fn translate(b: &mut BehaviourRef, delta: Vec2) {
-    let current_pos = if let Some(value) = b.get("pos") {
-        Vec2::from_value(value).expect("Engine bug: failed to convert position value")
-    } else {
-        Vec2::zero()
+pub fn translate(b: &mut BehaviourRef, delta: Vec2) -> VmResult<()> {
+    let current_pos = match vm_try!(b.get("pos")) {
+        Some(value) => {
+            vm_try!(Vec2::from_value(value).throw_engine_bug("failed to convert position value"))
+        }
+        None => Vec2::zero(),
     };
-    let new_pos = current_pos + delta;
-    b.set(
-        "pos",
-        new_pos
+    let new_pos = vm_try!(
+        (current_pos + delta)
             .to_value()
-            .expect("Engine bug: failed to convert position to rune value"),
+            .throw_engine_bug("failed to convert position to rune value")
     );
+    b.set("pos", new_pos)
 }
 
-fn register_factory(module: &mut Module) -> Result<(), ContextError> {
-    let schema = Schema::object()
+pub(crate) fn schema() -> Schema {
+    Schema::object()
         .optional_field(
             "pos",
             Schema::vec2(),
             Some(TypedValue::Vector(Vec2::zero())),
         )
         .optional_field("rot", Schema::number(), Some(TypedValue::Number(0.0)))
-        .build();
+        .build()
+}
 
-    let transform = BehaviourFactory::new("transform", schema, true);
+fn register_factory(module: &mut Module) -> Result<(), ContextError> {
+    let transform = BehaviourFactory::new("transform", schema(), true);
 
-    let func = move |obj: &Object| -> BehaviourRef { transform.create(obj) };
+    let func = move |obj: &Object| -> VmResult<BehaviourRef> { transform.create(obj) };
 
     module.function("create", func).build()?.docs(docstring! {
         /// Create a new transform behaviour. All fields are optional and default to zero if not specified.
@@ -0,0 +1,445 @@
+use super::entity::EntityRef;
+use crate::{
+    error::{IntoVmResult, TetronError},
+    utils::{Registrable, typed_value::TypedValue},
+};
+use rune::{
+    ContextError, Module, Value,
+    runtime::{Function, Object, VmResult},
+};
+use std::{cell::RefCell, rc::Rc};
+
+type Easing = fn(f64) -> f64;
+
+/// Look up an easing function by name. Each maps progress in `[0, 1]` to
+/// `[0, 1]`.
+fn easing(name: &str) -> Option<Easing> {
+    let easing: Easing = match name {
+        "linear" => |t| t,
+        "ease_in_quad" => |t| t * t,
+        "ease_out_quad" => |t| t * (2.0 - t),
+        "ease_in_out_quad" => |t| {
+            if t < 0.5 {
+                2.0 * t * t
+            } else {
+                -1.0 + (4.0 - 2.0 * t) * t
+            }
+        },
+        "ease_in_cubic" => |t| t * t * t,
+        "ease_out_cubic" => |t| {
+            let u = t - 1.0;
+            u * u * u + 1.0
+        },
+        "ease_in_out_cubic" => |t| {
+            if t < 0.5 {
+                4.0 * t * t * t
+            } else {
+                let u = 2.0 * t - 2.0;
+                0.5 * u * u * u + 1.0
+            }
+        },
+        _ => return None,
+    };
+    Some(easing)
+}
+
+/// Interpolate between two numbers or two vectors.
+fn lerp(from: &TypedValue, to: &TypedValue, t: f64) -> Option<TypedValue> {
+    match (from, to) {
+        (TypedValue::Number(a), TypedValue::Number(b)) => Some(TypedValue::Number(a + (b - a) * t)),
+        (TypedValue::Vector(a), TypedValue::Vector(b)) => Some(TypedValue::Vector(a.lerp(*b, t))),
+        _ => None,
+    }
+}
+
+/// A scene's running tweens, shared with its entities.
+pub(crate) type TweenList = Rc<RefCell<Vec<TweenHandle>>>;
+
+#[derive(Debug)]
+struct Tween {
+    entity: EntityRef,
+    behaviour: String,
+    field: String,
+    from: TypedValue,
+    to: TypedValue,
+    duration: f64,
+    easing: Easing,
+    elapsed: f64,
+    cancelled: bool,
+    on_complete: Option<Function>,
+    /// Started once this tween completes.
+    then: Vec<TweenHandle>,
+}
+
+/// An animation of one behaviour field, from `animate` or `then_animate`.
+#[derive(Clone, Debug, rune::Any)]
+#[rune(name = Tween)]
+pub struct TweenHandle(Rc<RefCell<Tween>>);
+
+impl Registrable for TweenHandle {
+    fn register(module: &mut Module) -> Result<(), ContextError> {
+        module.ty::<TweenHandle>()?;
+        module.function_meta(TweenHandle::cancel__meta)?;
+        module.function_meta(TweenHandle::on_complete__meta)?;
+        module.function_meta(TweenHandle::then_animate__meta)?;
+        Ok(())
+    }
+}
+
+impl TweenHandle {
+    /// Create a tween without starting it. `from` and `to` must both be
+    /// numbers or both be vectors.
+    pub fn new(
+        entity: EntityRef,
+        behaviour: &str,
+        field: &str,
+        from: &Value,
+        to: &Value,
+        duration: f64,
+        easing_name: &str,
+    ) -> Result<Self, TetronError> {
+        let easing = easing(easing_name)
+            .ok_or_else(|| TetronError::Other(format!("Unknown easing {easing_name}")))?;
+        let from = TypedValue::try_from(from)?;
+        let to = TypedValue::try_from(to)?;
+        if lerp(&from, &to, 0.0).is_none() {
+            return Err(TetronError::Conversion(format!(
+                "Cannot tween {behaviour}.{field} from {from} to {to}: expected two numbers or two vectors"
+            )));
+        }
+        Ok(Self(Rc::new(RefCell::new(Tween {
+            entity,
+            behaviour: behaviour.to_owned(),
+            field: field.to_owned(),
+            from,
+            to,
+            duration,
+            easing,
+            elapsed: 0.0,
+            cancelled: false,
+            on_complete: None,
+            then: Vec::new(),
+        }))))
+    }
+
+    /// Stop the animation, leaving the field at its current value. Neither
+    /// the completion callback nor chained tweens will run.
+    #[rune::function(keep)]
+    pub fn cancel(&self) {
+        self.0.borrow_mut().cancelled = true;
+    }
+
+    /// Call `callback` once the animation reaches its end.
+    #[rune::function(keep)]
+    pub fn on_complete(&self, callback: Function) {
+        self.0.borrow_mut().on_complete = Some(callback);
+    }
+
+    /// Start `next` once this tween completes.
+    pub fn then(&self, next: TweenHandle) {
+        self.0.borrow_mut().then.push(next);
+    }
+
+    /// Like `tween::animate`, but starts once this tween completes.
+    #[rune::function(instance, keep)]
+    fn then_animate(
+        &self,
+        entity: EntityRef,
+        behaviour: &str,
+        field: &str,
+        opts: Object,
+    ) -> VmResult<TweenHandle> {
+        tween_from_opts(entity, behaviour, field, &opts)
+            .inspect(|next| self.then(next.clone()))
+            .map_err(|e| TetronError::Runtime(format!("tween::then_animate: {e}")))
+            .into_vm_result()
+    }
+}
+
+/// Build a tween from a script's options object. Scripts pass `from`, `to`,
+/// `duration` and `easing` as an object, since native functions take at
+/// most five arguments.
+fn tween_from_opts(
+    entity: EntityRef,
+    behaviour: &str,
+    field: &str,
+    opts: &Object,
+) -> Result<TweenHandle, TetronError> {
+    let get = |key: &str| {
+        opts.get(key)
+            .ok_or_else(|| TetronError::Other(format!("missing {key}")))
+    };
+    let duration = get("duration")?;
+    let duration = duration
+        .as_float()
+        .or_else(|_| duration.as_integer::<i64>().map(|d| d as f64))
+        .map_err(|_| TetronError::Conversion("duration must be a number".into()))?;
+    let easing = match opts.get("easing") {
+        Some(easing) => easing.borrow_string_ref()?.to_string(),
+        None => "linear".to_owned(),
+    };
+    TweenHandle::new(
+        entity,
+        behaviour,
+        field,
+        get("from")?,
+        get("to")?,
+        duration,
+        &easing,
+    )
+}
+
+/// Animate `behaviour.field` of `entity` from `from` to `to` over `duration`
+/// seconds. The tween is advanced by the entity's scene, before its systems
+/// run, starting from the scene's next update.
+pub fn animate(
+    entity: EntityRef,
+    behaviour: &str,
+    field: &str,
+    from: &Value,
+    to: &Value,
+    duration: f64,
+    easing: &str,
+) -> Result<TweenHandle, TetronError> {
+    TweenHandle::new(entity, behaviour, field, from, to, duration, easing).and_then(start)
+}
+
+/// Add `tween` to the running tweens of its entity's scene. Tweens on
+/// entities that aren't in a scene never run.
+fn start(tween: TweenHandle) -> Result<TweenHandle, TetronError> {
+    let tweens = tween.0.try_borrow()?.entity.scene_tweens();
+    if let Some(tweens) = tweens {
+        tweens.try_borrow_mut()?.push(tween.clone());
+    }
+    Ok(tween)
+}
+
+/// Animate `behaviour.field` of `entity`. `opts` holds `from`, `to` (both
+/// numbers or both vectors), `duration` in seconds and optionally `easing`:
+/// one of "linear" (the default), "ease_in_quad", "ease_out_quad",
+/// "ease_in_out_quad", "ease_in_cubic", "ease_out_cubic" or
+/// "ease_in_out_cubic".
+#[rune::function(path = animate)]
+fn animate_rune(
+    entity: EntityRef,
+    behaviour: &str,
+    field: &str,
+    opts: Object,
+) -> VmResult<TweenHandle> {
+    tween_from_opts(entity, behaviour, field, &opts)
+        .and_then(start)
+        .map_err(|e| TetronError::Runtime(format!("tween::animate: {e}")))
+        .into_vm_result()
+}
+
+/// Advance `tweens` by `dt` seconds, returning the ones still running along
+/// with any chained tweens that just started. Tweens whose behaviour has
+/// been removed are dropped. A tween that fails doesn't stop the others:
+/// every tween is stepped, and the first error is returned alongside them.
+pub(crate) fn step(tweens: Vec<TweenHandle>, dt: f64) -> (Vec<TweenHandle>, Option<TetronError>) {
+    let mut running = Vec::new();
+    let mut error = None;
+    for handle in tweens {
+        if let Err(e) = step_one(handle, dt, &mut running) {
+            error.get_or_insert(e);
+        }
+    }
+    (running, error)
+}
+
+/// Advance one tween, adding it to `running` if it hasn't finished and its
+/// chained tweens if it just has.
+fn step_one(
+    handle: TweenHandle,
+    dt: f64,
+    running: &mut Vec<TweenHandle>,
+) -> Result<(), TetronError> {
+    let mut tween = handle.0.borrow_mut();
+    if tween.cancelled {
+        return Ok(());
+    }
+    let Some(mut behaviour) = tween.entity.behaviour(&tween.behaviour) else {
+        return Ok(());
+    };
+    tween.elapsed += dt;
+    let t = if tween.duration > 0.0 {
+        (tween.elapsed / tween.duration).min(1.0)
+    } else {
+        1.0
+    };
+    if let Some(value) = lerp(&tween.from, &tween.to, (tween.easing)(t)) {
+        behaviour
+            .set(&tween.field, Value::try_from(&value)?)
+            .into_result()?;
+    }
+    if t < 1.0 {
+        drop(tween);
+        running.push(handle);
+        return Ok(());
+    }
+
+    let on_complete = tween.on_complete.take();
+    running.append(&mut tween.then);
+    drop(tween);
+    if let Some(callback) = on_complete {
+        callback
+            .call::<()>(())
+            .into_result()
+            .map_err(|e| TetronError::Runtime(format!("tween on_complete callback failed: {e}")))?;
+    }
+    Ok(())
+}
+
+pub fn module() -> Result<Module, ContextError> {
+    let mut module = Module::with_crate_item("tetron", ["tween"])?;
+    TweenHandle::register(&mut module)?;
+    module.function_meta(animate_rune)?;
+    Ok(module)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        engine::{behaviours::BehaviourFactory, scene::SceneRef, world::WorldRef},
+        error::ErrorMode,
+        utils::typed_value::schema::Schema,
+    };
+    use std::collections::HashMap;
+
+    fn scene_with_fader() -> (SceneRef, EntityRef) {
+        let schema = Schema::object().field("alpha", Schema::number()).build();
+        let fade = BehaviourFactory::new("fade", schema, false);
+        let mut scene = SceneRef::new(WorldRef::new(), "test", Object::new());
+        let mut entity = scene.spawn();
+        entity.attach(fade.with_map(HashMap::from([(
+            "alpha".to_string(),
+            TypedValue::Number(0.0),
+        )])));
+        (scene, entity)
+    }
+
+    fn alpha(entity: &EntityRef) -> Option<TypedValue> {
+        entity.behaviour("fade")?.get_typed("alpha").ok()?
+    }
+
+    #[test]
+    fn test_tween_reaches_target() -> Result<(), TetronError> {
+        let (mut scene, entity) = scene_with_fader();
+        animate(
+            entity.clone(),
+            "fade",
+            "alpha",
+            &Value::from(0.0),
+            &Value::from(1.0),
+            1.0,
+            "ease_in_out_quad",
+        )?;
+
+        scene.update(0.5)?;
+        assert_eq!(alpha(&entity), Some(TypedValue::Number(0.5)));
+        scene.update(0.5)?;
+        assert_eq!(alpha(&entity), Some(TypedValue::Number(1.0)));
+        scene.update(0.5)?;
+        assert_eq!(alpha(&entity), Some(TypedValue::Number(1.0)));
+        Ok(())
+    }
+
+    #[test]
+    fn test_tweens_run_in_their_entitys_scene() -> Result<(), TetronError> {
+        let (mut scene, entity) = scene_with_fader();
+        let (mut other, _) = scene_with_fader();
+        animate(
+            entity.clone(),
+            "fade",
+            "alpha",
+            &Value::from(0.0),
+            &Value::from(1.0),
+            1.0,
+            "linear",
+        )?;
+
+        other.update(0.5)?;
+        assert_eq!(alpha(&entity), Some(TypedValue::Number(0.0)));
+        scene.update(0.5)?;
+        assert_eq!(alpha(&entity), Some(TypedValue::Number(0.5)));
+        Ok(())
+    }
+
+    #[test]
+    fn test_failing_callback_keeps_other_tweens() -> Result<(), TetronError> {
+        let (mut scene, entity) = scene_with_fader();
+        let schema = Schema::object().field("alpha", Schema::number()).build();
+        let mut other = scene.spawn();
+        other.attach(
+            BehaviourFactory::new("fade", schema, false).with_map(HashMap::from([(
+                "alpha".to_string(),
+                TypedValue::Number(0.0),
+            )])),
+        );
+
+        let short = animate(
+            entity.clone(),
+            "fade",
+            "alpha",
+            &Value::from(0.0),
+            &Value::from(1.0),
+            0.5,
+            "linear",
+        )?;
+        // Callbacks are called without arguments, so this one always fails.
+        short.on_complete(Function::new(|_: i64| {}));
+        animate(
+            other.clone(),
+            "fade",
+            "alpha",
+            &Value::from(0.0),
+            &Value::from(10.0),
+            1.0,
+            "linear",
+        )?;
+
+        let err = scene.update_with(0.5, ErrorMode::Panic).unwrap_err();
+        assert!(err.to_string().contains("on_complete"), "{err}");
+        assert_eq!(alpha(&entity), Some(TypedValue::Number(1.0)));
+        assert_eq!(alpha(&other), Some(TypedValue::Number(5.0)));
+
+        scene.update_with(0.5, ErrorMode::Panic)?;
+        assert_eq!(alpha(&other), Some(TypedValue::Number(10.0)));
+        Ok(())
+    }
+
+    #[test]
+    fn test_cancel_stops_updates() -> Result<(), TetronError> {
+        let (mut scene, entity) = scene_with_fader();
+        let tween = animate(
+            entity.clone(),
+            "fade",
+            "alpha",
+            &Value::from(0.0),
+            &Value::from(10.0),
+            1.0,
+            "linear",
+        )?;
+
+        scene.update(0.25)?;
+        assert_eq!(alpha(&entity), Some(TypedValue::Number(2.5)));
+        tween.cancel();
+        scene.update(0.25)?;
+        assert_eq!(alpha(&entity), Some(TypedValue::Number(2.5)));
+
+        assert!(
+            animate(
+                entity,
+                "fade",
+                "alpha",
+                &Value::from(0.0),
+                &Value::from(1.0),
+                1.0,
+                "bouncy"
+            )
+            .is_err()
+        );
+        Ok(())
+    }
+}
@@ -1,18 +1,78 @@
-use super::{behaviours::BehaviourFactory, scene::SceneRef};
-use crate::{error::TetronError, log_and_die, utils::typed_value::schema::Schema};
-use rune::{alloc::clone::TryClone, runtime::Object};
-use std::{cell::RefCell, collections::HashMap, rc::Rc, sync::Arc};
+use super::{
+    behaviours::{BehaviourFactory, BehaviourRef},
+    scene::SceneRef,
+};
+use crate::{
+    error::{ErrorMode, IntoVmResult, TetronError},
+    fs::SimpleFs,
+    log_and_die,
+    scripting::kv::flags::FlagHooks,
+    utils::typed_value::{
+        TypedValue,
+        schema::{Schema, SchemaError},
+    },
+};
+use rune::{
+    FromValue, Value,
+    alloc::clone::TryClone,
+    runtime::{Object, VmResult},
+};
+use std::{
+    cell::{RefCell, RefMut},
+    collections::HashMap,
+    fmt::{self, Debug},
+    rc::Rc,
+    sync::Arc,
+};
 
 #[derive(rune::Any, Clone, Debug)]
-pub struct BehaviourFactoryRef(
-    #[allow(dead_code)] Arc<BehaviourFactory>, /* Okay to ignore this warning, the Behaviour.create stuff is called on the Rune side. */
-);
+pub struct BehaviourFactoryRef(Arc<BehaviourFactory>);
 
-#[derive(Debug, Default)]
+impl BehaviourFactoryRef {
+    #[rune::function(instance, keep)]
+    pub fn create(&self, config: &Object) -> VmResult<BehaviourRef> {
+        self.0.create(config)
+    }
+
+    /// An instance with every field set to its schema default, for prefabs
+    /// and prototyping. Fails if a required field has no default.
+    #[rune::function(instance, keep)]
+    pub fn default_instance(&self) -> Result<BehaviourRef, SchemaError> {
+        self.0.default_instance()
+    }
+}
+
+#[derive(Default)]
 pub struct World {
     scenes: HashMap<String, SceneRef>,
     current_scene: Option<(String, SceneRef)>,
     behaviour_registry: HashMap<String, BehaviourFactoryRef>,
+    seed: u64,
+    /// Frames run by `game_loop` so far.
+    tick_count: u64,
+    /// Sum of every `dt` passed to `game_loop`, in seconds.
+    elapsed_time: f64,
+    /// The game's filesystem, for loading definitions from files. Unset in
+    /// worlds created without a game.
+    fs: Option<Arc<dyn SimpleFs>>,
+    /// Flag watches and migrations registered by scripts.
+    flag_hooks: FlagHooks,
+    /// The game's error mode, for code that only has the world.
+    error_mode: ErrorMode,
+}
+
+impl Debug for World {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("World")
+            .field("scenes", &self.scenes)
+            .field("current_scene", &self.current_scene)
+            .field("behaviour_registry", &self.behaviour_registry)
+            .field("seed", &self.seed)
+            .field("tick_count", &self.tick_count)
+            .field("elapsed_time", &self.elapsed_time)
+            .field("error_mode", &self.error_mode)
+            .finish_non_exhaustive()
+    }
 }
 
 #[derive(Clone, Debug, rune::Any, Default)]
@@ -31,10 +91,24 @@ use rune::{ContextError, Module};
 impl Registrable for WorldRef {
     fn register(module: &mut Module) -> Result<(), ContextError> {
         module.ty::<WorldRef>()?;
-        module.function_meta(WorldRef::define_behaviour)?;
-        module.function_meta(WorldRef::behaviour)?;
-        module.function_meta(WorldRef::scene)?;
-        module.function_meta(WorldRef::load_scene)?;
+        module.ty::<BehaviourFactoryRef>()?;
+        module.function_meta(BehaviourFactoryRef::create__meta)?;
+        module.function_meta(BehaviourFactoryRef::default_instance__meta)?;
+        module.function_meta(WorldRef::define_behaviour__meta)?;
+        module.function_meta(WorldRef::define_behaviour_ns__meta)?;
+        module.function_meta(WorldRef::define_behaviour_from_json__meta)?;
+        module.function_meta(WorldRef::define_behaviour_from_schema__meta)?;
+        module.function_meta(WorldRef::define_behaviour_from_file__meta)?;
+        module.function_meta(WorldRef::behaviour__meta)?;
+        module.function_meta(WorldRef::scene__meta)?;
+        module.function_meta(WorldRef::load_scene__meta)?;
+        module.function_meta(WorldRef::set_seed__meta)?;
+        module.function_meta(WorldRef::seed__meta)?;
+        module.function_meta(WorldRef::tick_count_rune__meta)?;
+        module.function_meta(WorldRef::elapsed_time__meta)?;
+        module.function_meta(WorldRef::deserialize_scene_rune__meta)?;
+        module.function_meta(WorldRef::behaviour_names__meta)?;
+        module.function_meta(WorldRef::scene_names__meta)?;
         Ok(())
     }
 }
@@ -44,8 +118,34 @@ impl WorldRef {
         Self::default()
     }
 
-    #[rune::function(instance)]
-    fn define_behaviour(&mut self, name: &str, schema: Schema) -> BehaviourFactoryRef {
+    /// A world for a game that loads files from `fs`, e.g. for
+    /// `define_behaviour_from_file`, and handles engine bugs per `error_mode`.
+    pub fn for_game(fs: Arc<dyn SimpleFs>, error_mode: ErrorMode) -> Self {
+        Self(Rc::new(RefCell::new(World {
+            fs: Some(fs),
+            error_mode,
+            ..Default::default()
+        })))
+    }
+
+    /// The error mode of the game this world belongs to. Worlds created
+    /// without a game panic on engine bugs.
+    pub fn error_mode(&self) -> ErrorMode {
+        self.0.borrow().error_mode
+    }
+
+    /// Define a behaviour in the default namespace.
+    ///
+    /// Behaviour names share one registry per world:
+    /// * `tetron:` is reserved for the engine's own behaviours.
+    /// * Defining a name that's already taken is an error, so two mods that
+    ///   both define `item` this way collide. Mods should use
+    ///   `define_behaviour_ns` instead.
+    /// * `define_behaviour_ns("ns", "item", ..)` is stored as `ns:item`, the
+    ///   same key as `define_behaviour("ns:item", ..)`, and is looked up and
+    ///   attached under that full name.
+    #[rune::function(instance, keep)]
+    pub fn define_behaviour(&mut self, name: &str, schema: Schema) -> BehaviourFactoryRef {
         let registry = &mut self
             .0
             .try_borrow_mut()
@@ -68,13 +168,82 @@ impl WorldRef {
         }
     }
 
-    #[rune::function(instance)]
-    fn behaviour(&self, name: &str) -> Option<BehaviourFactoryRef> {
+    /// Define the behaviour `namespace:name`. Namespaces can't be `tetron`
+    /// and neither part may contain `:`; either is an error. See
+    /// `define_behaviour` for the collision rules.
+    #[rune::function(instance, keep)]
+    pub fn define_behaviour_ns(
+        &mut self,
+        namespace: &str,
+        name: &str,
+        schema: Schema,
+    ) -> Result<BehaviourFactoryRef, TetronError> {
+        if namespace.is_empty() || namespace.contains(':') || name.contains(':') {
+            return Err(TetronError::Runtime(format!(
+                "Cannot define behaviour {name} in namespace {namespace}: namespaces must be non-empty and neither part may contain ':'"
+            )));
+        }
+        if namespace == "tetron" {
+            return Err(TetronError::Runtime(format!(
+                "Cannot define behaviour {name}: the tetron namespace is reserved"
+            )));
+        }
+        Ok(self.define_behaviour(&format!("{namespace}:{name}"), schema))
+    }
+
+    /// Like `define_behaviour`, with the schema written as JSON. See
+    /// `Schema::from_json` for the format, e.g.
+    /// `{"type": "object", "fields": {"hp": {"type": "number"}}}`.
+    #[rune::function(instance, keep)]
+    pub fn define_behaviour_from_json(
+        &mut self,
+        name: &str,
+        schema_json: &str,
+    ) -> Result<BehaviourFactoryRef, TetronError> {
+        let schema = Schema::from_json(schema_json)?;
+        Ok(self.define_behaviour(name, schema))
+    }
+
+    /// Another name for `define_behaviour_from_json`.
+    #[rune::function(instance, keep)]
+    pub fn define_behaviour_from_schema(
+        &mut self,
+        name: &str,
+        schema_json: &str,
+    ) -> Result<BehaviourFactoryRef, TetronError> {
+        self.define_behaviour_from_json(name, schema_json)
+    }
+
+    /// Like `define_behaviour_from_schema`, reading the schema from the JSON
+    /// file at `path` in the game's filesystem, e.g. `schemas/item.schema.json`.
+    #[rune::function(instance, keep)]
+    pub fn define_behaviour_from_file(
+        &mut self,
+        name: &str,
+        path: &str,
+    ) -> Result<BehaviourFactoryRef, TetronError> {
+        let fs = self.0.borrow().fs.clone().ok_or_else(|| {
+            TetronError::Runtime(format!("Cannot load {path}: this world has no filesystem"))
+        })?;
+        let json = fs.read_text_file(path)?;
+        self.define_behaviour_from_json(name, &json)
+    }
+
+    /// Look up a defined behaviour by its full name, e.g. `item` or `mod_a:item`.
+    #[rune::function(instance, keep)]
+    pub fn behaviour(&self, name: &str) -> Option<BehaviourFactoryRef> {
         self.0.borrow().behaviour_registry.get(name).cloned()
     }
 
-    #[rune::function(instance)]
-    fn scene(&self, name: &str, config: Object) -> SceneRef {
+    /// Create a scene. Besides anything scripts read back with
+    /// `SceneRef::config`, the engine understands these `config` keys:
+    ///
+    /// * pool: reuse despawned entities
+    /// * seed: the seed for the scene's RNG
+    /// * background: path to an image drawn behind everything, stretched
+    ///   to fill the window
+    #[rune::function(instance, keep)]
+    pub fn scene(&self, name: &str, config: Object) -> SceneRef {
         let mut world = self.0.borrow_mut();
         if world.scenes.contains_key(name) {
             log_and_die!(
@@ -83,14 +252,47 @@ impl WorldRef {
             );
         }
 
-        let scene = SceneRef::new(self.clone(), config);
+        let scene = SceneRef::new(self.clone(), name, config);
         world.scenes.insert(name.into(), scene.clone());
 
         scene
     }
 
-    #[rune::function(instance)]
-    fn load_scene(&self, name: &str) {
+    /// The names of every behaviour the game has defined, sorted. The
+    /// engine's own `tetron:` behaviours aren't included.
+    #[rune::function(instance, keep)]
+    pub fn behaviour_names(&self) -> Vec<String> {
+        let mut names = self
+            .0
+            .borrow()
+            .behaviour_registry
+            .keys()
+            .cloned()
+            .collect::<Vec<_>>();
+        names.sort();
+        names
+    }
+
+    /// The names of every scene created so far, sorted.
+    #[rune::function(instance, keep)]
+    pub fn scene_names(&self) -> Vec<String> {
+        let mut names = self.0.borrow().scenes.keys().cloned().collect::<Vec<_>>();
+        names.sort();
+        names
+    }
+
+    /// What the game has defined, as JSON, for `--dump-registry`.
+    pub fn registry_json(&self) -> Result<String, TetronError> {
+        let registry = serde_json::json!({
+            "behaviours": self.behaviour_names(),
+            "scenes": self.scene_names(),
+        });
+        serde_json::to_string_pretty(&registry)
+            .map_err(|e| TetronError::Conversion(format!("Failed to write registry: {e}")))
+    }
+
+    #[rune::function(instance, keep)]
+    pub fn load_scene(&self, name: &str) {
         let mut world = self.0.borrow_mut();
         let scene = world.scenes.get(name).cloned();
         if let Some(scene) = scene {
@@ -98,22 +300,326 @@ impl WorldRef {
         }
     }
 
+    /// Set the seed scene RNGs are derived from. Scenes that have already
+    /// drawn from their RNG keep their current stream.
+    #[rune::function(instance, keep)]
+    pub fn set_seed(&self, seed: i64) {
+        self.0.borrow_mut().seed = seed as u64;
+    }
+
+    #[rune::function(instance, keep)]
+    pub fn seed(&self) -> i64 {
+        self.0.borrow().seed as i64
+    }
+
+    /// Rebuild a scene from a `SceneRef::serialize` snapshot and add it under
+    /// `name`. Behaviours are recreated through their factories, so custom
+    /// behaviours must be defined with `define_behaviour` before restoring.
+    pub fn deserialize_scene(
+        &self,
+        name: &str,
+        data: &TypedValue,
+    ) -> Result<SceneRef, TetronError> {
+        let invalid = |msg: &str| TetronError::Conversion(format!("Invalid scene data: {msg}"));
+        let TypedValue::Object(data) = data else {
+            return Err(invalid("expected an object"));
+        };
+        if self.0.borrow().scenes.contains_key(name) {
+            return Err(TetronError::Runtime(format!(
+                "Could not restore scene {name} - a scene with that name already exists"
+            )));
+        }
+
+        let config = match data.get("config") {
+            Some(config @ TypedValue::Object(_)) => Object::from_value(Value::try_from(config)?)?,
+            Some(_) => return Err(invalid("config must be an object")),
+            None => Object::new(),
+        };
+        let Some(TypedValue::Array(entities)) = data.get("entities") else {
+            return Err(invalid("expected an entities array"));
+        };
+
+        // Build the whole scene before adding it, so bad data doesn't leave
+        // a half-restored scene in the world.
+        let mut scene = SceneRef::new(self.clone(), name, config);
+        for entity_data in entities {
+            let TypedValue::Object(entity_data) = entity_data else {
+                return Err(invalid("entities must be objects"));
+            };
+            let mut entity = scene.spawn();
+            if let Some(TypedValue::Object(behaviours)) = entity_data.get("behaviours") {
+                for (behaviour, config) in behaviours {
+                    let TypedValue::Object(config) = config else {
+                        return Err(invalid(&format!("config of {behaviour} must be an object")));
+                    };
+                    let factory = match BehaviourFactory::builtin(behaviour) {
+                        Some(factory) => Arc::new(factory),
+                        None => self
+                            .0
+                            .borrow()
+                            .behaviour_registry
+                            .get(behaviour)
+                            .map(|factory| factory.0.clone())
+                            .ok_or_else(|| invalid(&format!("unknown behaviour {behaviour}")))?,
+                    };
+                    let behaviour = factory
+                        .try_with_map(config.clone())
+                        .map_err(|e| invalid(&format!("{behaviour}: {e}")))?;
+                    entity.attach(behaviour);
+                }
+            }
+            if let Some(TypedValue::Array(tags)) = entity_data.get("tags") {
+                for tag in tags {
+                    match tag {
+                        TypedValue::String(tag) => entity.tag(tag),
+                        _ => return Err(invalid("tags must be strings")),
+                    }
+                }
+            }
+            if let Some(TypedValue::Object(meta)) = entity_data.get("meta") {
+                for (key, value) in meta {
+                    entity.insert_meta(key, value.clone());
+                }
+            }
+        }
+
+        self.0
+            .borrow_mut()
+            .scenes
+            .insert(name.to_owned(), scene.clone());
+        Ok(scene)
+    }
+
+    #[rune::function(instance, keep, path = Self::deserialize_scene)]
+    pub fn deserialize_scene_rune(&self, name: &str, data: Value) -> VmResult<SceneRef> {
+        TypedValue::try_from(data)
+            .and_then(|data| self.deserialize_scene(name, &data))
+            .map_err(|e| TetronError::Runtime(format!("Could not restore scene {name}: {e}")))
+            .into_vm_result()
+    }
+
     pub fn game_loop(&mut self, dt: f64) -> Result<(), TetronError> {
-        self.0.try_borrow_mut()?.game_loop(dt)?;
+        // Don't hold the world borrowed while systems run, they need it for queries.
+        let current_scene = self.0.try_borrow()?.current_scene.clone();
+        if let Some((_, mut scene)) = current_scene {
+            scene.update(dt)?;
+        }
+
+        let mut world = self.0.try_borrow_mut()?;
+        world.tick_count += 1;
+        world.elapsed_time += dt;
         Ok(())
     }
 
+    /// How many frames have finished. Systems see 0 during the first frame.
+    pub fn tick_count(&self) -> u64 {
+        self.0.borrow().tick_count
+    }
+
+    #[rune::function(instance, keep, path = Self::tick_count)]
+    fn tick_count_rune(&self) -> i64 {
+        self.tick_count() as i64
+    }
+
+    /// Total game time of the finished frames, in seconds.
+    #[rune::function(instance, keep)]
+    pub fn elapsed_time(&self) -> f64 {
+        self.0.borrow().elapsed_time
+    }
+
     pub fn current_scene(&self) -> Result<Option<(String, SceneRef)>, TetronError> {
         Ok(self.0.try_borrow()?.current_scene.clone())
     }
+
+    /// The flag watches and migrations scripts have registered on this world.
+    pub(crate) fn flag_hooks(&self) -> Result<RefMut<'_, FlagHooks>, TetronError> {
+        Ok(RefMut::map(self.0.try_borrow_mut()?, |world| {
+            &mut world.flag_hooks
+        }))
+    }
 }
 
-impl World {
-    fn game_loop(&mut self, dt: f64) -> Result<(), TetronError> {
-        if let Some((_, scene)) = &mut self.current_scene {
-            scene.update(dt)?;
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::engine::entity::EntityRef;
+
+    #[test]
+    fn test_namespaced_behaviours() -> Result<(), TetronError> {
+        let mut world = WorldRef::new();
+        let a = world.define_behaviour_ns(
+            "mod_a",
+            "item",
+            Schema::object().field("weight", Schema::number()).build(),
+        )?;
+        let b = world.define_behaviour_ns(
+            "mod_b",
+            "item",
+            Schema::object().field("value", Schema::string()).build(),
+        )?;
+        world.define_behaviour("item", Schema::object().build());
+        for (namespace, name) in [
+            ("", "item"),
+            ("mod:a", "item"),
+            ("mod_a", "a:b"),
+            ("tetron", "item"),
+        ] {
+            assert!(
+                world
+                    .define_behaviour_ns(namespace, name, Schema::object().build())
+                    .is_err()
+            );
+        }
+
+        assert!(world.behaviour("mod_a:item").is_some());
+        assert!(world.behaviour("mod_b:item").is_some());
+        assert!(world.behaviour("item").is_some());
+        assert!(world.behaviour("mod_c:item").is_none());
+
+        let mut entity = EntityRef::new();
+        entity.attach(a.0.with_map(HashMap::from([(
+            "weight".to_string(),
+            TypedValue::Number(2.0),
+        )])));
+        entity.attach(b.0.with_map(HashMap::from([(
+            "value".to_string(),
+            TypedValue::String("gem".into()),
+        )])));
+        assert_eq!(entity.behaviour_names(), vec!["mod_a:item", "mod_b:item"]);
+        assert_eq!(
+            entity
+                .behaviour("mod_b:item")
+                .unwrap()
+                .get_typed("value")
+                .unwrap(),
+            Some(TypedValue::String("gem".into()))
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_registry_names() -> Result<(), TetronError> {
+        let mut world = WorldRef::new();
+        assert!(world.behaviour_names().is_empty());
+        assert!(world.scene_names().is_empty());
+
+        world.define_behaviour("health", Schema::object().build());
+        world.define_behaviour_ns("mod_a", "armor", Schema::object().build())?;
+        world.scene("town", Object::new());
+        world.scene("dungeon", Object::new());
+
+        assert_eq!(world.behaviour_names(), vec!["health", "mod_a:armor"]);
+        assert_eq!(world.scene_names(), vec!["dungeon", "town"]);
+        let json: serde_json::Value = serde_json::from_str(&world.registry_json()?)
+            .map_err(|e| TetronError::Conversion(e.to_string()))?;
+        assert_eq!(json["scenes"], serde_json::json!(["dungeon", "town"]));
+        Ok(())
+    }
+
+    #[test]
+    fn test_default_instance() {
+        let mut world = WorldRef::new();
+        let prefab = world.define_behaviour(
+            "prefab",
+            Schema::object()
+                .field("hp", Schema::number().default(TypedValue::Number(10.0)))
+                .optional_field("name", Schema::string(), Some("crate".into()))
+                .optional_field("note", Schema::string(), None)
+                .build(),
+        );
+        let instance = prefab.default_instance().unwrap();
+        assert_eq!(
+            instance.get_typed("hp").unwrap(),
+            Some(TypedValue::Number(10.0))
+        );
+        assert_eq!(
+            instance.get_typed("name").unwrap(),
+            Some(TypedValue::String("crate".into()))
+        );
+        assert!(!instance.has("note"));
+
+        let incomplete = world.define_behaviour(
+            "incomplete",
+            Schema::object().field("hp", Schema::number()).build(),
+        );
+        assert_eq!(
+            incomplete.default_instance().err(),
+            Some(SchemaError::MissingField("hp".into()))
+        );
+    }
+
+    #[test]
+    fn test_define_behaviour_from_json() -> Result<(), TetronError> {
+        use crate::{engine::physics::vec2::Vec2, fs::zip_fs::ZipFs};
+
+        let schema_json = r#"{
+            "type": "object",
+            "fields": {
+                "hp": { "type": "number" },
+                "pos": { "type": "vec2", "optional": true, "default": [1, 2] }
+            }
+        }"#;
+        let fs = ZipFs::from_files(&[
+            ("game.json", "{}"),
+            ("schemas/unit.schema.json", schema_json),
+        ]);
+        let mut world = WorldRef::for_game(Arc::new(fs), ErrorMode::Panic);
+        let from_json = world.define_behaviour_from_json("unit", schema_json)?;
+        let from_schema = world.define_behaviour_from_schema("unit_schema", schema_json)?;
+        let from_file =
+            world.define_behaviour_from_file("unit_file", "schemas/unit.schema.json")?;
+        let from_rust = BehaviourFactory::new(
+            "unit_rust",
+            Schema::object()
+                .field("hp", Schema::number())
+                .optional_field(
+                    "pos",
+                    Schema::vec2(),
+                    Some(TypedValue::Vector(Vec2::new(1.0, 2.0))),
+                )
+                .build(),
+            false,
+        );
+
+        let config = HashMap::from([("hp".to_string(), TypedValue::Number(4.0))]);
+        let expected = from_rust.with_map(config.clone());
+        for (name, factory) in [
+            ("unit", from_json),
+            ("unit_schema", from_schema),
+            ("unit_file", from_file),
+        ] {
+            let mut entity = EntityRef::new();
+            entity.attach(factory.0.with_map(config.clone()));
+            let behaviour = entity.behaviour(name).unwrap();
+            assert_eq!(behaviour.get_typed("hp")?, expected.get_typed("hp")?);
+            assert_eq!(behaviour.get_typed("pos")?, expected.get_typed("pos")?);
         }
 
+        assert!(world.define_behaviour_from_json("bad", "{").is_err());
+        assert!(world.define_behaviour_from_schema("bad", "{").is_err());
+        assert!(
+            world
+                .define_behaviour_from_file("missing", "nope.json")
+                .is_err()
+        );
+        assert!(
+            WorldRef::new()
+                .define_behaviour_from_file("unit", "schemas/unit.schema.json")
+                .is_err()
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_tick_count_and_elapsed_time() -> Result<(), TetronError> {
+        let mut world = WorldRef::new();
+        world.scene("main", Object::new());
+        world.load_scene("main");
+        for _ in 0..10 {
+            world.game_loop(0.016)?;
+        }
+        assert_eq!(world.tick_count(), 10);
+        assert!((world.elapsed_time() - 0.16).abs() < 1e-9);
         Ok(())
     }
 }
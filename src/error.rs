@@ -1,17 +1,104 @@
 use std::{
     cell::{BorrowError, BorrowMutError},
     convert::Infallible,
-    sync::{PoisonError, RwLockReadGuard, RwLockWriteGuard},
+    fmt::Debug,
+    sync::{OnceLock, PoisonError, RwLockReadGuard, RwLockWriteGuard},
 };
 
 use rune::{
     ContextError,
     diagnostics::EmitError,
-    runtime::{RuntimeError, VmError},
+    runtime::{RuntimeError, VmError, VmResult},
 };
 use stupid_simple_kv::KvError;
 
-use crate::fs::FsError;
+use crate::{fs::FsError, system_log};
+
+type TelemetryHandler = Box<dyn Fn(&TetronError) + Send + Sync>;
+
+/// Called with every error that ends the game, e.g. to send crash reports.
+static TELEMETRY_HANDLER: OnceLock<TelemetryHandler> = OnceLock::new();
+
+/// How the engine reacts to "engine bug" conditions (poisoned locks, failed
+/// internal conversions and the like). Each game has its own, set from
+/// `errors:mode` in game.json or with [`GameBuilder::error_mode`].
+///
+/// [`GameBuilder::error_mode`]: crate::engine::GameBuilder::error_mode
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum ErrorMode {
+    /// Panic immediately. Useful during development.
+    #[default]
+    Panic,
+    /// Log the error and try to carry on, skipping the current frame if needed.
+    Recover,
+}
+
+impl ErrorMode {
+    pub fn parse(s: &str) -> Option<Self> {
+        match s.to_lowercase().as_str() {
+            "panic" => Some(ErrorMode::Panic),
+            "recover" => Some(ErrorMode::Recover),
+            _ => None,
+        }
+    }
+
+    pub fn as_str(self) -> &'static str {
+        match self {
+            ErrorMode::Panic => "panic",
+            ErrorMode::Recover => "recover",
+        }
+    }
+}
+
+/// Extension for results whose failure indicates a bug in the engine itself.
+pub trait EngineBug<T> {
+    /// Unwraps the value, or handles the failure according to `mode`, which
+    /// callers read from their game or world: panics in `Panic` mode, logs
+    /// and returns `None` in `Recover` mode.
+    fn engine_bug(self, msg: &str, mode: ErrorMode) -> Option<T>;
+
+    /// Unwraps the value, or throws the failure at the calling script. For
+    /// script functions with no game or world to read the mode from; the
+    /// game then handles the script error according to its mode.
+    fn throw_engine_bug(self, msg: &str) -> VmResult<T>;
+}
+
+impl<T, E: Debug> EngineBug<T> for Result<T, E> {
+    fn engine_bug(self, msg: &str, mode: ErrorMode) -> Option<T> {
+        match self {
+            Ok(v) => Some(v),
+            Err(e) => match mode {
+                ErrorMode::Panic => panic!("Engine bug: {msg}: {e:?}"),
+                ErrorMode::Recover => {
+                    system_log!("Engine bug: {msg}: {e:?}");
+                    None
+                }
+            },
+        }
+    }
+
+    fn throw_engine_bug(self, msg: &str) -> VmResult<T> {
+        match self {
+            Ok(v) => VmResult::Ok(v),
+            Err(e) => VmResult::panic(format!("Engine bug: {msg}: {e:?}")),
+        }
+    }
+}
+
+/// Extension for results returned to scripts, whose errors should be thrown
+/// at the script that made the call rather than handed back as a value.
+pub trait IntoVmResult<T> {
+    fn into_vm_result(self) -> VmResult<T>;
+}
+
+impl<T> IntoVmResult<T> for Result<T, TetronError> {
+    fn into_vm_result(self) -> VmResult<T> {
+        match self {
+            Ok(v) => VmResult::Ok(v),
+            Err(e) => VmResult::panic(e),
+        }
+    }
+}
 
 #[derive(Debug, rune::Any)]
 pub enum TetronError {
@@ -26,6 +113,27 @@ pub enum TetronError {
     Quit,
 }
 
+impl TetronError {
+    /// Install a handler that is called with any error that ends the game,
+    /// before it is returned from [`Game::run`](crate::engine::Game::run).
+    /// Only the first handler installed takes effect; returns false if one
+    /// was already set.
+    pub fn set_telemetry_handler(handler: impl Fn(&TetronError) + Send + Sync + 'static) -> bool {
+        TELEMETRY_HANDLER.set(Box::new(handler)).is_ok()
+    }
+
+    /// Pass this error to the telemetry handler, if one is installed.
+    /// Quitting isn't an error, so [`TetronError::Quit`] is never reported.
+    pub fn report(&self) {
+        if matches!(self, TetronError::Quit) {
+            return;
+        }
+        if let Some(handler) = TELEMETRY_HANDLER.get() {
+            handler(self);
+        }
+    }
+}
+
 impl From<String> for TetronError {
     fn from(value: String) -> Self {
         Self::Other(value)
@@ -132,3 +240,62 @@ impl<'a> From<PoisonError<RwLockWriteGuard<'a, crate::engine::input::KeyState>>>
 }
 
 impl std::error::Error for TetronError {}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{engine::Game, fs::zip_fs::ZipFs, kv::backend};
+    use std::sync::{Arc, Mutex};
+
+    #[test]
+    fn test_engine_bug_follows_error_mode() {
+        let failed = || Err::<i32, _>("lock poisoned");
+        for mode in [ErrorMode::Panic, ErrorMode::Recover] {
+            assert_eq!(Ok::<_, ()>(3).engine_bug("unreachable", mode), Some(3));
+        }
+        assert_eq!(failed().engine_bug("input", ErrorMode::Recover), None);
+
+        let panic = std::panic::catch_unwind(|| failed().engine_bug("input", ErrorMode::Panic))
+            .expect_err("Panic mode should panic");
+        let message = panic.downcast_ref::<String>().unwrap();
+        assert_eq!(message, r#"Engine bug: input: "lock poisoned""#);
+
+        assert!(Ok::<_, ()>(3).throw_engine_bug("unreachable").is_ok());
+        assert!(failed().throw_engine_bug("input").is_err());
+    }
+
+    #[test]
+    fn test_telemetry_handler() -> Result<(), anyhow::Error> {
+        // The handler is process-wide, so other tests' errors may show up
+        // here too; only look for this game's.
+        static REPORTED: Mutex<Vec<String>> = Mutex::new(Vec::new());
+        assert!(TetronError::set_telemetry_handler(|err| {
+            REPORTED.lock().unwrap().push(err.to_string());
+        }));
+        assert!(!TetronError::set_telemetry_handler(|_| {}));
+
+        let fs = ZipFs::from_files(&[
+            (
+                "game.json",
+                r#"{ "identifier": "test.game", "entrypoint": "main.rn" }"#,
+            ),
+            (
+                "main.rn",
+                "pub fn begin(world) { world.telemetry_test_failure(); }",
+            ),
+        ]);
+        let mut game = Game::builder()
+            .fs(Arc::new(fs))
+            .backend(|_| backend::memory())
+            .headless(true)
+            .build()?;
+        let err = game.run().expect_err("begin should fail");
+        assert!(matches!(err, TetronError::Runtime(_)), "{err:?}");
+        assert!(REPORTED.lock().unwrap().contains(&err.to_string()));
+
+        TetronError::Quit.report();
+        let reported = REPORTED.lock().unwrap();
+        assert!(!reported.contains(&TetronError::Quit.to_string()));
+        Ok(())
+    }
+}
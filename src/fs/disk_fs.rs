@@ -2,7 +2,7 @@
 use std::{
     fs::{self, File},
     io::Read,
-    path::{Path, PathBuf},
+    path::{Component, Path, PathBuf},
 };
 
 #[cfg(not(target_arch = "wasm32"))]
@@ -10,14 +10,45 @@ use crate::fs::{FileMetadata, FsError, SimpleFs, join_path, normalize_path};
 
 #[cfg(not(target_arch = "wasm32"))]
 pub struct DiskFs {
+    /// Canonicalized where possible, so resolved paths can be compared to it.
     base: PathBuf,
 }
 
 #[cfg(not(target_arch = "wasm32"))]
 impl DiskFs {
     pub fn new<P: AsRef<Path>>(p: P) -> Self {
+        let base = p.as_ref();
         DiskFs {
-            base: p.as_ref().to_owned(),
+            base: base.canonicalize().unwrap_or_else(|_| base.to_owned()),
+        }
+    }
+
+    /// Map a virtual path onto the disk. `normalize_path` already resolves
+    /// `..` without going above the root, but the result is checked again
+    /// here in case the platform treats something else as a separator or
+    /// prefix (e.g. `..\` or `C:` on Windows). The final path is also
+    /// canonicalized, so a symlink inside a mod can't point outside `base`
+    /// either. Mod layers are untrusted, so anything that escapes is treated
+    /// as not found.
+    fn real_path(&self, path: &str) -> Result<PathBuf, FsError> {
+        let normalized = normalize_path(path);
+        let relative = Path::new(&normalized);
+        if !relative
+            .components()
+            .all(|component| matches!(component, Component::Normal(_)))
+        {
+            return Err(FsError::NotFound);
+        }
+
+        let real = self
+            .base
+            .join(relative)
+            .canonicalize()
+            .map_err(|_| FsError::NotFound)?;
+        if real.starts_with(&self.base) {
+            Ok(real)
+        } else {
+            Err(FsError::NotFound)
         }
     }
 }
@@ -26,7 +57,7 @@ impl DiskFs {
 impl SimpleFs for DiskFs {
     fn read_dir(&self, path: &str) -> Result<Vec<String>, FsError> {
         let normalized = normalize_path(path);
-        let real = self.base.join(normalized.clone());
+        let real = self.real_path(&normalized)?;
         let mut entries = Vec::new();
         for entry in fs::read_dir(&real).map_err(FsError::Io)? {
             let e = entry.map_err(FsError::Io)?;
@@ -39,16 +70,19 @@ impl SimpleFs for DiskFs {
     }
 
     fn open_file(&self, path: &str) -> Result<Vec<u8>, FsError> {
-        let path = normalize_path(path);
-        let real = self.base.join(path);
+        let real = self.real_path(path)?;
         let mut buf: Vec<u8> = Vec::new();
         File::open(real)?.read_to_end(&mut buf)?;
         Ok(buf)
     }
 
+    fn open_reader(&self, path: &str) -> Result<Box<dyn Read + Send>, FsError> {
+        let real = self.real_path(path)?;
+        Ok(Box::new(File::open(real)?))
+    }
+
     fn metadata(&self, path: &str) -> Result<FileMetadata, FsError> {
-        let path = normalize_path(path);
-        let real = self.base.join(path);
+        let real = self.real_path(path)?;
         let meta = fs::metadata(real).map_err(FsError::Io)?;
         Ok(FileMetadata {
             len: meta.len(),
@@ -57,8 +91,72 @@ impl SimpleFs for DiskFs {
     }
 
     fn exists(&self, path: &str) -> bool {
-        let path = normalize_path(path);
-        let real = self.base.join(path);
-        real.exists()
+        self.real_path(path).is_ok_and(|real| real.exists())
+    }
+}
+
+#[cfg(all(test, not(target_arch = "wasm32")))]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_path_traversal_stays_in_base() {
+        let root = std::env::temp_dir().join(format!("tetron-disk-fs-{}", std::process::id()));
+        let _ = fs::remove_dir_all(&root);
+        let base = root.join("game");
+        fs::create_dir_all(base.join("assets")).unwrap();
+        fs::write(root.join("secret.txt"), "secret").unwrap();
+        fs::write(base.join("assets/a.txt"), "a").unwrap();
+
+        let disk = DiskFs::new(&base);
+        for path in [
+            "../secret.txt",
+            "../../secret.txt",
+            "/../secret.txt",
+            "assets/../../secret.txt",
+            "./../secret.txt",
+            // Absolute paths are relative to the layer root, not the disk.
+            root.join("secret.txt").to_str().unwrap(),
+        ] {
+            assert!(disk.open_file(path).is_err(), "{path}");
+            assert!(!disk.exists(path), "{path}");
+        }
+        assert_eq!(disk.read_text_file("assets/../assets/a.txt").unwrap(), "a");
+        assert_eq!(disk.read_text_file("/assets/a.txt").unwrap(), "a");
+
+        #[cfg(unix)]
+        {
+            std::os::unix::fs::symlink(root.join("secret.txt"), base.join("link.txt")).unwrap();
+            assert!(disk.open_file("link.txt").is_err());
+            assert!(disk.open_reader("link.txt").is_err());
+            assert!(!disk.exists("link.txt"));
+        }
+
+        fs::remove_dir_all(&root).unwrap();
+    }
+
+    #[test]
+    fn test_open_reader_streams_in_chunks() {
+        let root = std::env::temp_dir().join(format!("tetron-disk-reader-{}", std::process::id()));
+        fs::create_dir_all(&root).unwrap();
+        let contents = "0123456789".repeat(100);
+        fs::write(root.join("music.txt"), &contents).unwrap();
+
+        let disk = DiskFs::new(&root);
+        let mut reader = disk.open_reader("music.txt").unwrap();
+        let mut out = Vec::new();
+        let mut chunk = [0u8; 64];
+        loop {
+            let n = reader.read(&mut chunk).unwrap();
+            if n == 0 {
+                break;
+            }
+            assert!(n <= chunk.len());
+            out.extend_from_slice(&chunk[..n]);
+        }
+        assert_eq!(out, contents.as_bytes());
+        assert!(disk.open_reader("missing.txt").is_err());
+
+        fs::remove_dir_all(&root).unwrap();
     }
 }
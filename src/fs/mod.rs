@@ -1,4 +1,8 @@
-use std::io;
+use sha2::{Digest, Sha256};
+use std::{
+    io::{self, Cursor},
+    sync::Arc,
+};
 
 #[derive(Debug)]
 pub enum FsError {
@@ -43,13 +47,65 @@ pub trait SimpleFs: Send + Sync {
     fn metadata(&self, path: &str) -> Result<FileMetadata, FsError>;
     fn exists(&self, path: &str) -> bool;
 
+    /// Open a file for streaming reads. The default implementation reads the
+    /// whole file with `open_file`; implementations that can avoid that should
+    /// override it.
+    fn open_reader(&self, path: &str) -> Result<Box<dyn io::Read + Send>, FsError> {
+        Ok(Box::new(Cursor::new(self.open_file(path)?)))
+    }
+
     fn read_text_file(&self, path: &str) -> Result<String, FsError> {
-        let bytes = self.open_file(path)?;
-        String::from_utf8(bytes)
-            .map_err(|_| FsError::ReadError(format!("Error converting {path} as UTF-8")))
+        decode_text(path, self.open_file(path)?)
+    }
+
+    /// SHA-256 digest of the file at `path`, as a lowercase hex string.
+    fn hash_file(&self, path: &str) -> Result<String, FsError> {
+        let mut reader = self.open_reader(path)?;
+        let mut hasher = Sha256::new();
+        io::copy(&mut reader, &mut hasher)?;
+        Ok(hasher
+            .finalize()
+            .iter()
+            .map(|byte| format!("{byte:02x}"))
+            .collect())
     }
 }
 
+/// Read every file in `paths` on a pool of scoped threads, returning the
+/// results in the same order as `paths`. Used to preload assets at startup;
+/// decoding anything that needs SDL still has to happen on the main thread.
+pub fn read_files_parallel(fs: &dyn SimpleFs, paths: &[&str]) -> Vec<Result<Vec<u8>, FsError>> {
+    let threads = std::thread::available_parallelism()
+        .map(|n| n.get())
+        .unwrap_or(1)
+        .min(paths.len());
+    if threads <= 1 {
+        return paths.iter().map(|path| fs.open_file(path)).collect();
+    }
+
+    let chunk_size = paths.len().div_ceil(threads);
+    std::thread::scope(|scope| {
+        let handles = paths
+            .chunks(chunk_size)
+            .map(|chunk| {
+                scope.spawn(move || {
+                    chunk
+                        .iter()
+                        .map(|path| fs.open_file(path))
+                        .collect::<Vec<_>>()
+                })
+            })
+            .collect::<Vec<_>>();
+        handles
+            .into_iter()
+            .flat_map(|handle| match handle.join() {
+                Ok(results) => results,
+                Err(panic) => std::panic::resume_unwind(panic),
+            })
+            .collect()
+    })
+}
+
 /// Normalize a path: always forward slash, no leading or trailing slash unless root.
 /// Root is always normalized as an empty string, not "/".
 pub fn normalize_path(path: &str) -> String {
@@ -90,6 +146,28 @@ use std::{
 #[cfg(not(target_arch = "wasm32"))]
 use crate::fs::{disk_fs::DiskFs, zip_fs::ZipFs};
 
+impl<T: SimpleFs + ?Sized> SimpleFs for Arc<T> {
+    fn read_dir(&self, path: &str) -> Result<Vec<String>, FsError> {
+        (**self).read_dir(path)
+    }
+
+    fn open_file(&self, path: &str) -> Result<Vec<u8>, FsError> {
+        (**self).open_file(path)
+    }
+
+    fn metadata(&self, path: &str) -> Result<FileMetadata, FsError> {
+        (**self).metadata(path)
+    }
+
+    fn exists(&self, path: &str) -> bool {
+        (**self).exists(path)
+    }
+
+    fn open_reader(&self, path: &str) -> Result<Box<dyn io::Read + Send>, FsError> {
+        (**self).open_reader(path)
+    }
+}
+
 #[cfg(not(target_arch = "wasm32"))]
 pub(crate) fn to_vfs_layer(layer: &PathBuf) -> Result<Box<dyn SimpleFs>, anyhow::Error> {
     if layer.extension().is_some_and(|v| v == "zip") {
@@ -102,7 +180,90 @@ pub(crate) fn to_vfs_layer(layer: &PathBuf) -> Result<Box<dyn SimpleFs>, anyhow:
     }
 }
 
+/// Decode the contents of the text file at `path`, dropping a leading UTF-8
+/// byte order mark as written by some Windows editors.
+fn decode_text(path: &str, mut bytes: Vec<u8>) -> Result<String, FsError> {
+    const BOM: &[u8] = b"\xEF\xBB\xBF";
+    let bom_len = if bytes.starts_with(BOM) { BOM.len() } else { 0 };
+    bytes.drain(..bom_len);
+    String::from_utf8(bytes).map_err(|e| {
+        FsError::ReadError(format!(
+            "Error converting {path} as UTF-8: invalid byte at offset {}",
+            bom_len + e.utf8_error().valid_up_to()
+        ))
+    })
+}
+
 pub mod disk_fs;
 pub mod noop_fs;
 pub mod overlay_fs;
 pub mod zip_fs;
+
+#[cfg(test)]
+mod tests {
+    use super::{
+        FsError, SimpleFs, decode_text, normalize_path, read_files_parallel, zip_fs::ZipFs,
+    };
+
+    #[test]
+    fn test_normalize_path() {
+        assert_eq!(normalize_path(""), "");
+        assert_eq!(normalize_path("/"), "");
+        assert_eq!(normalize_path("/foo//bar/"), "foo/bar");
+        assert_eq!(normalize_path("./foo/./bar"), "foo/bar");
+        assert_eq!(normalize_path("foo/../bar"), "bar");
+        // `..` never climbs above the root.
+        assert_eq!(normalize_path("../../etc/passwd"), "etc/passwd");
+        assert_eq!(normalize_path("foo/../../bar"), "bar");
+    }
+
+    #[test]
+    fn test_hash_file() {
+        let fs = ZipFs::from_files(&[
+            ("game.json", "{}"),
+            ("a.txt", "hello"),
+            ("b.txt", "hello"),
+            ("c.txt", "hellp"),
+        ]);
+        let a = fs.hash_file("a.txt").unwrap();
+        assert_eq!(
+            a,
+            "2cf24dba5fb0a30e26e83b2ac5b9e29e1b161e5c1fa7425e73043362938b9824"
+        );
+        assert_eq!(a, fs.hash_file("b.txt").unwrap());
+        assert_ne!(a, fs.hash_file("c.txt").unwrap());
+        assert!(fs.hash_file("missing.txt").is_err());
+    }
+
+    #[test]
+    fn test_read_text_file_encodings() {
+        let fs = ZipFs::from_files(&[("game.json", "\u{feff}{}"), ("plain.txt", "ok")]);
+        assert_eq!(fs.read_text_file("game.json").unwrap(), "{}");
+        assert_eq!(fs.read_text_file("plain.txt").unwrap(), "ok");
+
+        let bytes = b"\xEF\xBB\xBFabc\xFFdef".to_vec();
+        match decode_text("bad.txt", bytes) {
+            Err(FsError::ReadError(msg)) => assert!(msg.contains("offset 6"), "{msg}"),
+            other => panic!("expected a read error, got {other:?}"),
+        }
+    }
+
+    #[test]
+    fn test_read_files_parallel_keeps_order() {
+        let files = (0..64)
+            .map(|i| (format!("assets/{i}.txt"), i.to_string()))
+            .collect::<Vec<_>>();
+        let mut entries = vec![("game.json", "{}")];
+        entries.extend(files.iter().map(|(p, c)| (p.as_str(), c.as_str())));
+        let fs = ZipFs::from_files(&entries);
+
+        let mut paths = files.iter().map(|(p, _)| p.as_str()).collect::<Vec<_>>();
+        paths.push("missing.txt");
+        let results = read_files_parallel(&fs, &paths);
+        assert_eq!(results.len(), 65);
+        for (i, result) in results[..64].iter().enumerate() {
+            assert_eq!(result.as_ref().unwrap(), i.to_string().as_bytes());
+        }
+        assert!(results[64].is_err());
+    }
+}
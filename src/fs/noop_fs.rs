@@ -1,5 +1,8 @@
 use crate::fs::{FileMetadata, FsError, SimpleFs};
 
+/// An empty filesystem: every path is missing. Useful as a placeholder, or
+/// as the bottom layer of an [`OverlayFs`](crate::fs::overlay_fs::OverlayFs)
+/// whose real layers are pushed later.
 #[derive(Default)]
 pub struct NoOpFs {}
 
@@ -11,18 +14,46 @@ impl NoOpFs {
 
 impl SimpleFs for NoOpFs {
     fn read_dir(&self, _: &str) -> Result<Vec<String>, FsError> {
-        unimplemented!("no-op filesystem")
+        Err(FsError::NotFound)
     }
 
     fn open_file(&self, _: &str) -> Result<Vec<u8>, FsError> {
-        unimplemented!("no-op filesystem")
+        Err(FsError::NotFound)
     }
 
     fn metadata(&self, _: &str) -> Result<FileMetadata, FsError> {
-        unimplemented!("no-op filesystem")
+        Err(FsError::NotFound)
     }
 
     fn exists(&self, _: &str) -> bool {
-        unimplemented!("no-op filesystem")
+        false
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::fs::{overlay_fs::OverlayFs, zip_fs::ZipFs};
+
+    #[test]
+    fn test_everything_is_missing() {
+        let fs = NoOpFs::new();
+        assert!(matches!(fs.read_dir(""), Err(FsError::NotFound)));
+        assert!(matches!(fs.open_file("game.json"), Err(FsError::NotFound)));
+        assert!(fs.open_reader("game.json").is_err());
+        assert!(matches!(fs.metadata(""), Err(FsError::NotFound)));
+        assert!(!fs.exists("game.json"));
+    }
+
+    #[test]
+    fn test_real_layer_over_noop() {
+        let real = ZipFs::from_files(&[("game.json", "{}"), ("assets/a.txt", "a")]);
+        let mut fs = OverlayFs::from_layers(vec![Box::new(NoOpFs::new()), Box::new(real)]);
+        fs.enable_case_insensitive();
+
+        assert_eq!(fs.read_text_file("assets/a.txt").unwrap(), "a");
+        assert_eq!(fs.read_text_file("ASSETS/A.TXT").unwrap(), "a");
+        assert_eq!(fs.read_dir("").unwrap(), vec!["assets", "game.json"]);
+        assert!(!fs.exists("missing.txt"));
     }
 }
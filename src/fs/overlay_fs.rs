@@ -1,30 +1,177 @@
-use std::collections::HashSet;
+use std::{collections::HashMap, io::Read, sync::RwLock};
 
 use crate::fs::{FileMetadata, FsError, SimpleFs, normalize_path};
 
+/// A stack of filesystems, where upper layers take precedence over lower ones.
+///
+/// Resolution rules:
+/// * A path resolves to the topmost layer containing it. `open_file` and
+///   `metadata` only ever look at that layer.
+/// * `read_dir` merges the listings of every layer where the path is a
+///   directory, from the top down.
+/// * A file in an upper layer shadows a directory of the same name (and
+///   everything under it) in lower layers, and a directory in an upper layer
+///   shadows a file of the same name in lower layers.
+///
+/// With case-insensitive lookup enabled, a path that can't be found as-is is
+/// looked up again ignoring case. If two entries in a layer differ only by
+/// case, whichever sorts first wins, so mods should avoid relying on this.
+///
+/// To add or remove layers while the game runs, share it as
+/// `Arc<RwLock<OverlayFs>>`, which is itself a [`SimpleFs`].
 pub struct OverlayFs {
     layers: Vec<Box<dyn SimpleFs>>,
+    /// Per-layer map of lowercased path -> actual path, only built when
+    /// case-insensitive lookup is enabled.
+    lowercase_index: Option<Vec<HashMap<String, String>>>,
 }
 
 impl OverlayFs {
     pub fn from_layers(layers: Vec<Box<dyn SimpleFs>>) -> Self {
         let mut layers = layers;
         layers.reverse(); // Last is topmost
-        OverlayFs { layers }
+        OverlayFs {
+            layers,
+            lowercase_index: None,
+        }
     }
-}
 
-impl SimpleFs for OverlayFs {
-    fn read_dir(&self, path: &str) -> Result<Vec<String>, FsError> {
+    /// Enable case-insensitive fallback lookups. This walks every layer once
+    /// to build its lowercase index.
+    pub fn enable_case_insensitive(&mut self) {
+        let index = self
+            .layers
+            .iter()
+            .map(|fs| Self::build_index(fs.as_ref()))
+            .collect();
+        self.lowercase_index = Some(index);
+    }
+
+    /// Add `layer` on top of the stack, so it is searched first.
+    pub fn push_layer(&mut self, layer: Box<dyn SimpleFs>) {
+        if let Some(index) = &mut self.lowercase_index {
+            index.insert(0, Self::build_index(layer.as_ref()));
+        }
+        self.layers.insert(0, layer);
+    }
+
+    /// Remove and return the topmost layer.
+    pub fn pop_layer(&mut self) -> Option<Box<dyn SimpleFs>> {
+        if self.layers.is_empty() {
+            return None;
+        }
+        if let Some(index) = &mut self.lowercase_index {
+            index.remove(0);
+        }
+        Some(self.layers.remove(0))
+    }
+
+    fn build_index(fs: &dyn SimpleFs) -> HashMap<String, String> {
+        let mut index = HashMap::new();
+        Self::index_layer(fs, "", &mut index);
+        index
+    }
+
+    fn index_layer(fs: &dyn SimpleFs, dir: &str, index: &mut HashMap<String, String>) {
+        let Ok(mut entries) = fs.read_dir(dir) else {
+            return;
+        };
+        entries.sort();
+        for entry in entries {
+            index
+                .entry(entry.to_lowercase())
+                .or_insert_with(|| entry.clone());
+            if fs.metadata(&entry).is_ok_and(|meta| meta.is_dir) {
+                Self::index_layer(fs, &entry, index);
+            }
+        }
+    }
+
+    /// Iterate over the layers alongside the actual path `path` maps to in
+    /// each, ignoring case. Empty unless case-insensitive lookup is enabled.
+    fn case_insensitive_matches<'a>(
+        &'a self,
+        path: &str,
+    ) -> impl Iterator<Item = (usize, &'a dyn SimpleFs, &'a str)> {
+        let lower = path.to_lowercase();
+        self.lowercase_index
+            .iter()
+            .flat_map(|index| self.layers.iter().zip(index).enumerate())
+            .filter_map(move |(i, (fs, index))| {
+                index
+                    .get(&lower)
+                    .map(|actual| (i, fs.as_ref(), actual.as_str()))
+            })
+    }
+
+    /// The index a layer was given in [`OverlayFs::from_layers`], counting
+    /// from the bottom, for a position in `self.layers`.
+    fn layer_index(&self, position: usize) -> usize {
+        self.layers.len() - 1 - position
+    }
+
+    /// Returns true if any ancestor directory of `path` is a file in `fs`,
+    /// meaning `fs` hides `path` in every layer below it.
+    fn ancestor_is_file(fs: &dyn SimpleFs, path: &str) -> bool {
+        path.match_indices('/')
+            .any(|(pos, _)| fs.metadata(&path[..pos]).is_ok_and(|meta| !meta.is_dir))
+    }
+
+    /// Find the topmost layer containing `path`, along with the actual path
+    /// in that layer and its metadata there.
+    fn resolve(&self, path: &str) -> Option<(&dyn SimpleFs, String, FileMetadata)> {
+        for fs in &self.layers {
+            if let Ok(meta) = fs.metadata(path) {
+                return Some((fs.as_ref(), path.to_owned(), meta));
+            }
+            if Self::ancestor_is_file(fs.as_ref(), path) {
+                return None;
+            }
+        }
+        self.case_insensitive_matches(path)
+            .find_map(|(_, fs, actual)| {
+                fs.metadata(actual)
+                    .ok()
+                    .map(|meta| (fs, actual.to_owned(), meta))
+            })
+    }
+
+    /// Like [`SimpleFs::read_dir`], but pairs each entry with the layer that
+    /// provides it. Where several layers have the same entry, the topmost one
+    /// wins. Layers are numbered as in [`OverlayFs::from_layers`], so the
+    /// bottom layer is 0 and pushed layers get the highest indices.
+    ///
+    /// Entries are sorted by name, matching `read_dir`.
+    pub fn read_dir_detailed(&self, path: &str) -> Result<Vec<(String, usize)>, FsError> {
         let path = normalize_path(path);
-        let mut all: HashSet<String> = HashSet::new();
+        let mut all: HashMap<String, usize> = HashMap::new();
         let mut entries_found = false;
+        let add = |all: &mut HashMap<String, usize>, entries: Vec<String>, position| {
+            for entry in entries {
+                all.entry(entry).or_insert(self.layer_index(position));
+            }
+        };
 
-        for fs in &self.layers {
-            if let Ok(entries) = fs.read_dir(&path) {
-                for entry in entries {
+        for (position, fs) in self.layers.iter().enumerate() {
+            match fs.metadata(&path) {
+                Ok(meta) if meta.is_dir => {
+                    if let Ok(entries) = fs.read_dir(&path) {
+                        entries_found = true;
+                        add(&mut all, entries, position);
+                    }
+                }
+                // A file at this path shadows directories in lower layers.
+                Ok(_) => break,
+                Err(_) if Self::ancestor_is_file(fs.as_ref(), &path) => break,
+                Err(_) => {}
+            }
+        }
+
+        if !entries_found {
+            for (position, fs, actual) in self.case_insensitive_matches(&path) {
+                if let Ok(entries) = fs.read_dir(actual) {
                     entries_found = true;
-                    all.insert(entry);
+                    add(&mut all, entries, position);
                 }
             }
         }
@@ -37,29 +184,180 @@ impl SimpleFs for OverlayFs {
             Ok(out)
         }
     }
+}
+
+impl SimpleFs for OverlayFs {
+    fn read_dir(&self, path: &str) -> Result<Vec<String>, FsError> {
+        Ok(self
+            .read_dir_detailed(path)?
+            .into_iter()
+            .map(|(entry, _)| entry)
+            .collect())
+    }
 
     fn open_file(&self, path: &str) -> Result<Vec<u8>, FsError> {
         let path = normalize_path(path);
-        for fs in &self.layers {
-            if let Ok(file) = fs.open_file(&path) {
-                return Ok(file);
-            }
+        match self.resolve(&path) {
+            Some((fs, actual, meta)) if !meta.is_dir => fs.open_file(&actual),
+            _ => Err(FsError::NotFound),
         }
-        Err(FsError::NotFound)
     }
 
-    fn metadata(&self, path: &str) -> Result<FileMetadata, FsError> {
+    fn open_reader(&self, path: &str) -> Result<Box<dyn Read + Send>, FsError> {
         let path = normalize_path(path);
-        for fs in &self.layers {
-            if let Ok(meta) = fs.metadata(&path) {
-                return Ok(meta);
-            }
+        match self.resolve(&path) {
+            Some((fs, actual, meta)) if !meta.is_dir => fs.open_reader(&actual),
+            _ => Err(FsError::NotFound),
         }
-        Err(FsError::NotFound)
+    }
+
+    fn metadata(&self, path: &str) -> Result<FileMetadata, FsError> {
+        let path = normalize_path(path);
+        self.resolve(&path)
+            .map(|(_, _, meta)| meta)
+            .ok_or(FsError::NotFound)
     }
 
     fn exists(&self, path: &str) -> bool {
         let path = normalize_path(path);
-        self.layers.iter().any(|fs| fs.exists(&path))
+        self.resolve(&path).is_some()
+    }
+}
+
+fn poisoned() -> FsError {
+    FsError::ReadError("overlay lock poisoned".into())
+}
+
+impl SimpleFs for RwLock<OverlayFs> {
+    fn read_dir(&self, path: &str) -> Result<Vec<String>, FsError> {
+        self.read().map_err(|_| poisoned())?.read_dir(path)
+    }
+
+    fn open_file(&self, path: &str) -> Result<Vec<u8>, FsError> {
+        self.read().map_err(|_| poisoned())?.open_file(path)
+    }
+
+    fn open_reader(&self, path: &str) -> Result<Box<dyn Read + Send>, FsError> {
+        self.read().map_err(|_| poisoned())?.open_reader(path)
+    }
+
+    fn metadata(&self, path: &str) -> Result<FileMetadata, FsError> {
+        self.read().map_err(|_| poisoned())?.metadata(path)
+    }
+
+    fn exists(&self, path: &str) -> bool {
+        self.read().is_ok_and(|fs| fs.exists(path))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::fs::zip_fs::ZipFs;
+
+    fn zip_layer(files: &[(&str, &str)]) -> Box<dyn SimpleFs> {
+        Box::new(ZipFs::from_files(files))
+    }
+
+    #[test]
+    fn test_read_dir_merges_layers() {
+        let base = zip_layer(&[("game.json", "{}"), ("assets/a.txt", "a")]);
+        let top = zip_layer(&[("mod.json", "{}"), ("assets/b.txt", "b")]);
+        let fs = OverlayFs::from_layers(vec![base, top]);
+
+        assert_eq!(
+            fs.read_dir("assets").unwrap(),
+            vec!["assets/a.txt", "assets/b.txt"]
+        );
+        assert_eq!(
+            fs.read_dir("").unwrap(),
+            vec!["assets", "game.json", "mod.json"]
+        );
+    }
+
+    #[test]
+    fn test_read_dir_detailed_reports_winning_layer() {
+        let base = zip_layer(&[("a.txt", "base"), ("b.txt", "base")]);
+        let middle = zip_layer(&[("b.txt", "middle"), ("c.txt", "middle")]);
+        let mut fs = OverlayFs::from_layers(vec![base, middle]);
+        fs.push_layer(zip_layer(&[("c.txt", "top")]));
+
+        assert_eq!(
+            fs.read_dir_detailed("").unwrap(),
+            vec![
+                ("a.txt".to_owned(), 0),
+                ("b.txt".to_owned(), 1),
+                ("c.txt".to_owned(), 2),
+            ]
+        );
+        assert!(matches!(
+            fs.read_dir_detailed("missing"),
+            Err(FsError::NotFound)
+        ));
+    }
+
+    #[test]
+    fn test_open_file_prefers_topmost_layer() {
+        let base = zip_layer(&[("game.json", "base"), ("only_base.txt", "base")]);
+        let top = zip_layer(&[("game.json", "top"), ("only_top.txt", "top")]);
+        let fs = OverlayFs::from_layers(vec![base, top]);
+
+        assert_eq!(fs.read_text_file("game.json").unwrap(), "top");
+        assert_eq!(fs.read_text_file("only_base.txt").unwrap(), "base");
+        assert_eq!(fs.read_text_file("only_top.txt").unwrap(), "top");
+    }
+
+    #[test]
+    fn test_file_shadows_lower_directory() {
+        let base = zip_layer(&[("game.json", "{}"), ("foo/bar.txt", "bar")]);
+        let top = zip_layer(&[("game.json", "{}"), ("foo", "file")]);
+        let fs = OverlayFs::from_layers(vec![base, top]);
+
+        assert!(!fs.metadata("foo").unwrap().is_dir);
+        assert_eq!(fs.read_text_file("foo").unwrap(), "file");
+        assert!(fs.read_dir("foo").is_err());
+        assert!(!fs.exists("foo/bar.txt"));
+        assert!(fs.open_file("foo/bar.txt").is_err());
+    }
+
+    #[test]
+    fn test_directory_shadows_lower_file() {
+        let base = zip_layer(&[("game.json", "{}"), ("foo", "file")]);
+        let top = zip_layer(&[("game.json", "{}"), ("foo/bar.txt", "bar")]);
+        let fs = OverlayFs::from_layers(vec![base, top]);
+
+        assert!(fs.metadata("foo").unwrap().is_dir);
+        assert!(fs.open_file("foo").is_err());
+        assert_eq!(fs.read_dir("foo").unwrap(), vec!["foo/bar.txt"]);
+    }
+
+    #[test]
+    fn test_case_insensitive_lookup() {
+        let base = zip_layer(&[("game.json", "{}"), ("assets/foo.png", "png")]);
+        let mut fs = OverlayFs::from_layers(vec![base]);
+        assert!(fs.open_file("Assets/Foo.PNG").is_err());
+
+        fs.enable_case_insensitive();
+        assert_eq!(fs.read_text_file("Assets/Foo.PNG").unwrap(), "png");
+        assert!(fs.exists("ASSETS/foo.png"));
+        assert_eq!(fs.read_dir("Assets").unwrap(), vec!["assets/foo.png"]);
+        assert!(fs.open_file("assets/bar.png").is_err());
+    }
+
+    #[test]
+    fn test_push_and_pop_layer() {
+        let base = zip_layer(&[("game.json", "base"), ("assets/a.txt", "a")]);
+        let fs = RwLock::new(OverlayFs::from_layers(vec![base]));
+
+        fs.write()
+            .unwrap()
+            .push_layer(zip_layer(&[("game.json", "dlc"), ("dlc.txt", "new")]));
+        assert_eq!(fs.read_text_file("game.json").unwrap(), "dlc");
+        assert_eq!(fs.read_text_file("dlc.txt").unwrap(), "new");
+        assert_eq!(fs.read_text_file("assets/a.txt").unwrap(), "a");
+
+        assert!(fs.write().unwrap().pop_layer().is_some());
+        assert_eq!(fs.read_text_file("game.json").unwrap(), "base");
+        assert!(!fs.exists("dlc.txt"));
     }
 }
@@ -5,7 +5,22 @@ use std::{
 
 use zip::ZipArchive;
 
-use crate::fs::{FileMetadata, FsError, SimpleFs, join_path, normalize_path};
+use crate::{
+    fs::{FileMetadata, FsError, SimpleFs, join_path, normalize_path},
+    system_log,
+};
+
+/// Upper bound on how much memory is reserved up front when reading an entry.
+/// Sizes come from the archive headers, so they can't be trusted blindly.
+const MAX_PREALLOC: u64 = 64 * 1024 * 1024;
+
+/// Split a normalized path into its parent directory and final component.
+fn split_parent(path: &str) -> (String, String) {
+    match path.rfind('/') {
+        Some(pos) => (path[..pos].to_string(), path[pos + 1..].to_string()),
+        None => (String::new(), path.to_string()),
+    }
+}
 
 #[derive(Clone, Debug)]
 struct ZipEntry {
@@ -28,8 +43,9 @@ impl ZipFs {
         // Gather all entry names. Find a root prefix if one exists.
         let mut names = Vec::with_capacity(archive.len());
         for i in 0..archive.len() {
-            let file = archive.by_index(i)?;
-            names.push(file.name().to_string());
+            if let Some(name) = archive.name_for_index(i) {
+                names.push(name.to_string());
+            }
         }
 
         let root_prefix = Self::detect_and_strip_root_prefix(&names);
@@ -42,45 +58,54 @@ impl ZipFs {
         let prefix_len = root_prefix.as_ref().map(|s| s.len()).unwrap_or(0);
 
         for i in 0..archive.len() {
-            let file = archive.by_index(i)?;
+            // A single bad entry shouldn't take the whole archive down with it.
+            let entry_name = archive.name_for_index(i).unwrap_or("<unknown>").to_string();
+            let file = match archive.by_index(i) {
+                Ok(file) => file,
+                Err(e) => {
+                    system_log!("Warning: skipping unreadable zip entry {entry_name}: {e}");
+                    continue;
+                }
+            };
             let name = &file.name()[prefix_len..]; // strip root prefix, if any
             let norm_path = normalize_path(name); // always no leading/trailing
             let is_dir = file.name().ends_with('/');
             let norm: String = norm_path.to_string();
-            if !norm.is_empty() {
+            if norm.is_empty() {
                 // skip synthetic root
-                entries.insert(
-                    norm.clone(),
-                    ZipEntry {
-                        index: i,
-                        is_dir,
-                        len: file.size(),
-                    },
-                );
+                continue;
             }
+            entries.insert(
+                norm.clone(),
+                ZipEntry {
+                    index: i,
+                    is_dir,
+                    len: file.size(),
+                },
+            );
             // Now: populate dir_map for listing
             // Insert this entry's name in its parent dir.
-            let (parent, entry_name) = if let Some(pos) = norm.rfind('/') {
-                let parent = &norm[..pos];
-                let entry_name = &norm[pos + 1..];
-                (parent.to_string(), entry_name.to_string())
-            } else {
-                (String::new(), norm.clone())
-            };
+            let (parent, entry_name) = split_parent(&norm);
             dir_map.entry(parent).or_default().insert(entry_name);
         }
 
-        // Now: also ensure that all ancestor directories exist as entries (as dirs).
-        for dir in dir_map.keys() {
-            if !entries.contains_key(dir) && !dir.is_empty() {
-                entries.insert(
-                    dir.clone(),
-                    ZipEntry {
-                        index: 0, // Index 0 is never read for directories.
-                        is_dir: true,
-                        len: 0,
-                    },
-                );
+        // Now: also ensure that all ancestor directories exist as entries (as dirs),
+        // and are listed in their own parent even if the archive has no entry for them.
+        let dirs: Vec<String> = dir_map.keys().cloned().collect();
+        for dir in dirs {
+            let mut current = dir;
+            while !current.is_empty() {
+                entries.entry(current.clone()).or_insert(ZipEntry {
+                    index: 0, // Index 0 is never read for directories.
+                    is_dir: true,
+                    len: 0,
+                });
+                let (parent, entry_name) = split_parent(&current);
+                dir_map
+                    .entry(parent.clone())
+                    .or_default()
+                    .insert(entry_name);
+                current = parent;
             }
         }
 
@@ -113,6 +138,25 @@ impl ZipFs {
     }
 }
 
+#[cfg(test)]
+impl ZipFs {
+    /// Build an archive in memory from `(path, contents)` pairs.
+    pub(crate) fn from_files(files: &[(&str, &str)]) -> Self {
+        use std::io::Write;
+        use zip::{ZipWriter, write::SimpleFileOptions};
+
+        let mut writer = ZipWriter::new(Cursor::new(Vec::new()));
+        for (name, contents) in files {
+            writer
+                .start_file(*name, SimpleFileOptions::default())
+                .unwrap();
+            writer.write_all(contents.as_bytes()).unwrap();
+        }
+        let buf = writer.finish().unwrap().into_inner();
+        ZipFs::new(buf).unwrap()
+    }
+}
+
 impl SimpleFs for ZipFs {
     fn read_dir(&self, path: &str) -> Result<Vec<String>, FsError> {
         let normalized = normalize_path(path);
@@ -135,9 +179,12 @@ impl SimpleFs for ZipFs {
         match self.entries.get(&path) {
             Some(zip_entry) if !zip_entry.is_dir => {
                 let mut archive = self.open_archive()?;
-                let mut file = archive.by_index(zip_entry.index).map_err(FsError::from)?;
-                let mut buf = Vec::with_capacity(file.size() as usize);
-                file.read_to_end(&mut buf)?;
+                let mut file = archive
+                    .by_index(zip_entry.index)
+                    .map_err(|e| FsError::ReadError(format!("{path}: {e}")))?;
+                let mut buf = Vec::with_capacity(file.size().min(MAX_PREALLOC) as usize);
+                file.read_to_end(&mut buf)
+                    .map_err(|e| FsError::ReadError(format!("{path}: {e}")))?;
                 Ok(buf)
             }
             _ => Err(FsError::NotFound),
@@ -146,6 +193,12 @@ impl SimpleFs for ZipFs {
 
     fn metadata(&self, path: &str) -> Result<FileMetadata, FsError> {
         let path = normalize_path(path);
+        if path.is_empty() {
+            return Ok(FileMetadata {
+                len: 0,
+                is_dir: true,
+            });
+        }
         match self.entries.get(&path) {
             Some(e) => Ok(FileMetadata {
                 len: e.len,
@@ -157,6 +210,96 @@ impl SimpleFs for ZipFs {
 
     fn exists(&self, path: &str) -> bool {
         let path = normalize_path(path);
-        self.entries.contains_key(&path)
+        path.is_empty() || self.entries.contains_key(&path)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Write;
+    use zip::{CompressionMethod, ZipWriter, write::SimpleFileOptions};
+
+    #[test]
+    fn test_corrupt_entry_reports_read_error() {
+        let stored = SimpleFileOptions::default().compression_method(CompressionMethod::Stored);
+        let mut writer = ZipWriter::new(Cursor::new(Vec::new()));
+        writer.start_file("good.txt", stored).unwrap();
+        writer.write_all(b"good").unwrap();
+        writer.start_file("bad.txt", stored).unwrap();
+        writer.write_all(b"CORRUPT ME").unwrap();
+        let mut buf = writer.finish().unwrap().into_inner();
+
+        // Damage the stored bytes so the entry no longer matches its checksum.
+        let pos = buf.windows(10).position(|w| w == b"CORRUPT ME").unwrap();
+        buf[pos..pos + 10].copy_from_slice(b"XXXXXXXXXX");
+
+        let fs = ZipFs::new(buf).unwrap();
+        assert_eq!(fs.read_text_file("good.txt").unwrap(), "good");
+        match fs.open_file("bad.txt") {
+            Err(FsError::ReadError(msg)) => assert!(msg.contains("bad.txt")),
+            other => panic!("expected ReadError, got {other:?}"),
+        }
+        // Streaming the entry catches the damage too.
+        assert!(fs.open_reader("bad.txt").is_err());
+    }
+
+    #[test]
+    fn test_truncated_entry_leaves_others_readable() {
+        let stored = SimpleFileOptions::default().compression_method(CompressionMethod::Stored);
+        let mut writer = ZipWriter::new(Cursor::new(Vec::new()));
+        writer.start_file("good.txt", stored).unwrap();
+        writer.write_all(b"good").unwrap();
+        writer.start_file("bad.txt", stored).unwrap();
+        writer.write_all(b"bad").unwrap();
+        writer.start_file("also_good.txt", stored).unwrap();
+        writer.write_all(b"also good").unwrap();
+        let mut buf = writer.finish().unwrap().into_inner();
+
+        // Claim bad.txt runs far past the end of the archive, as if the file
+        // had been cut off partway through it.
+        let name = buf
+            .windows(7)
+            .rposition(|w| w == b"bad.txt")
+            .expect("central directory entry");
+        let header = name - 46;
+        assert_eq!(&buf[header..header + 4], b"PK\x01\x02");
+        for size in [header + 20, header + 24] {
+            buf[size..size + 4].copy_from_slice(&1_000_000u32.to_le_bytes());
+        }
+
+        let fs = ZipFs::new(buf).unwrap();
+        assert_eq!(fs.read_text_file("good.txt").unwrap(), "good");
+        assert_eq!(fs.read_text_file("also_good.txt").unwrap(), "also good");
+        assert!(fs.open_file("bad.txt").is_err());
+        assert!(fs.open_reader("bad.txt").is_err());
+    }
+
+    #[test]
+    fn test_open_reader_streams_in_chunks() {
+        let contents = "0123456789".repeat(100);
+        let stored = SimpleFileOptions::default().compression_method(CompressionMethod::Stored);
+        let deflated = SimpleFileOptions::default().compression_method(CompressionMethod::Deflated);
+        let mut writer = ZipWriter::new(Cursor::new(Vec::new()));
+        writer.start_file("stored.txt", stored).unwrap();
+        writer.write_all(contents.as_bytes()).unwrap();
+        writer.start_file("deflated.txt", deflated).unwrap();
+        writer.write_all(contents.as_bytes()).unwrap();
+        let fs = ZipFs::new(writer.finish().unwrap().into_inner()).unwrap();
+
+        for path in ["stored.txt", "deflated.txt"] {
+            let mut reader = fs.open_reader(path).unwrap();
+            let mut out = Vec::new();
+            let mut chunk = [0u8; 64];
+            loop {
+                let n = reader.read(&mut chunk).unwrap();
+                if n == 0 {
+                    break;
+                }
+                out.extend_from_slice(&chunk[..n]);
+            }
+            assert_eq!(out, contents.as_bytes(), "{path}");
+        }
+        assert!(fs.open_reader("missing.txt").is_err());
     }
 }
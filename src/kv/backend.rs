@@ -0,0 +1,178 @@
+use std::path::{Path, PathBuf};
+
+use crate::error::TetronError;
+
+pub use stupid_simple_kv::{KvBackend, MemoryBackend, SqliteBackend};
+
+/// Creates the backend for a game's flags, given the game's identifier.
+pub type BackendFactory = Box<dyn FnOnce(&str) -> Result<Box<dyn KvBackend>, anyhow::Error>>;
+
+/// An in-memory backend. Nothing is persisted once the game exits.
+pub fn memory() -> Box<dyn KvBackend> {
+    Box::new(MemoryBackend::new())
+}
+
+/// Overrides the directory game data is kept in, like `--data-dir`.
+pub const DATA_DIR_VAR: &str = "TETRON_DATA_DIR";
+
+/// The directory each game's data directory is created in: `data_dir` if
+/// given, else `$TETRON_DATA_DIR`, else `{user data dir}/tetron`.
+pub fn data_dir(data_dir: Option<&Path>) -> Result<PathBuf, TetronError> {
+    if let Some(dir) = data_dir {
+        return Ok(dir.to_owned());
+    }
+    if let Some(dir) = std::env::var_os(DATA_DIR_VAR) {
+        return Ok(dir.into());
+    }
+    let data = dirs::data_dir().ok_or(TetronError::Other("Error getting user data dir".into()))?;
+    Ok(data.join("tetron"))
+}
+
+/// A SQLite database at `{base}/{identifier}/flags.db`.
+pub fn sqlite_in(base: &Path, identifier: &str) -> Result<Box<dyn KvBackend>, anyhow::Error> {
+    let db_path = base.join(identifier);
+    std::fs::create_dir_all(&db_path).map_err(|e| {
+        TetronError::Other(format!(
+            "Could not create data directory {}: {e}",
+            db_path.display()
+        ))
+    })?;
+    Ok(Box::new(SqliteBackend::file(&db_path.join("flags.db"))?))
+}
+
+/// The default backend: a SQLite database at
+/// `{user data dir}/tetron/{identifier}/flags.db`, or under
+/// `$TETRON_DATA_DIR` if that's set.
+pub fn sqlite_in_data_dir(identifier: &str) -> Result<Box<dyn KvBackend>, anyhow::Error> {
+    sqlite_in(&data_dir(None)?, identifier)
+}
+
+/// The factory for a backend named in config or on the command line:
+/// `sqlite` (the default) or `memory`. SQLite databases go under
+/// [`data_dir`]`(dir)`.
+pub fn by_name(name: &str, dir: Option<PathBuf>) -> Result<BackendFactory, TetronError> {
+    match name {
+        "sqlite" => Ok(Box::new(move |identifier| {
+            sqlite_in(&data_dir(dir.as_deref())?, identifier)
+        })),
+        "memory" => Ok(Box::new(|_| Ok(memory()))),
+        _ => Err(TetronError::Other(format!(
+            "Unknown flags backend \"{name}\". Valid backends: sqlite, memory"
+        ))),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{engine::Game, fs::zip_fs::ZipFs};
+    use std::sync::{Arc, Mutex};
+    use stupid_simple_kv::{Kv, KvKey, KvResult, KvValue};
+
+    /// Wraps a memory backend, recording every call made to it.
+    struct LoggingBackend {
+        inner: MemoryBackend,
+        log: Arc<Mutex<Vec<String>>>,
+    }
+
+    impl KvBackend for LoggingBackend {
+        fn get_range(
+            &self,
+            start: Option<KvKey>,
+            end: Option<KvKey>,
+        ) -> KvResult<Vec<(KvKey, Vec<u8>)>> {
+            self.log.lock().unwrap().push("get".into());
+            self.inner.get_range(start, end)
+        }
+
+        fn set(&mut self, key: KvKey, value: Option<Vec<u8>>) -> KvResult<()> {
+            let op = if value.is_some() { "set" } else { "delete" };
+            self.log.lock().unwrap().push(op.into());
+            self.inner.set(key, value)
+        }
+
+        fn clear(&mut self) -> KvResult<()> {
+            self.log.lock().unwrap().push("clear".into());
+            self.inner.clear()
+        }
+    }
+
+    #[test]
+    fn test_memory_backend_by_name() -> Result<(), anyhow::Error> {
+        let mut kv = Kv::new(by_name("memory", None)?("test.game")?);
+        kv.set(&("level",), KvValue::I64(2))?;
+        assert_eq!(kv.get(&("level",))?, Some(KvValue::I64(2)));
+
+        // Nothing carries over to the next run.
+        let kv = Kv::new(by_name("memory", None)?("test.game")?);
+        assert_eq!(kv.get(&("level",))?, None);
+
+        assert!(by_name("postgres", None).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_data_dir_override() -> Result<(), anyhow::Error> {
+        let root = std::env::temp_dir().join(format!("tetron-data-dir-{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&root);
+
+        let factory = by_name("sqlite", Some(root.join("saves")))?;
+        let mut kv = Kv::new(factory("test.game")?);
+        kv.set(&("level",), KvValue::I64(2))?;
+        drop(kv);
+        assert!(root.join("saves/test.game/flags.db").is_file());
+
+        let kv = Kv::new(by_name("sqlite", Some(root.join("saves")))?("test.game")?);
+        assert_eq!(kv.get(&("level",))?, Some(KvValue::I64(2)));
+
+        // A file where the directory should be can't be created over.
+        std::fs::write(root.join("blocked"), "")?;
+        let err = sqlite_in(&root.join("blocked"), "test.game").err().unwrap();
+        assert!(err.to_string().contains("Could not create data directory"));
+
+        std::fs::remove_dir_all(&root)?;
+        Ok(())
+    }
+
+    #[test]
+    fn test_custom_backend_receives_calls() -> Result<(), anyhow::Error> {
+        let fs = ZipFs::from_files(&[
+            (
+                "game.json",
+                r#"{ "identifier": "test.game", "entrypoint": "main.rn" }"#,
+            ),
+            (
+                "main.rn",
+                r#"
+                    use tetron::flags;
+                    pub fn begin(world) {
+                        flags::set(["level"], 2);
+                        flags::delete(["level"]);
+                    }
+                "#,
+            ),
+        ]);
+        let log = Arc::new(Mutex::new(Vec::new()));
+        let mut game = Game::with_backend_factory({
+            let log = log.clone();
+            move |identifier| {
+                log.lock().unwrap().push(format!("open {identifier}"));
+                Box::new(LoggingBackend {
+                    inner: MemoryBackend::new(),
+                    log,
+                })
+            }
+        })
+        .fs(Arc::new(fs))
+        .headless(true)
+        .build()?;
+        game.begin()?;
+
+        let log = log.lock().unwrap();
+        assert_eq!(log.first().map(String::as_str), Some("open test.game"));
+        let set = log.iter().position(|op| op == "set");
+        let delete = log.iter().position(|op| op == "delete");
+        assert!(set.is_some() && set < delete, "{log:?}");
+        Ok(())
+    }
+}
@@ -0,0 +1,179 @@
+use std::{
+    collections::BTreeMap,
+    sync::{Arc, Mutex},
+    time::{Duration, Instant},
+};
+
+use stupid_simple_kv::{KvBackend, KvError, KvKey, KvResult, MemoryBackend};
+
+use crate::{error::TetronError, system_log};
+
+/// Writes made to a [`CachedBackend`] that haven't reached the real backend.
+struct Pending {
+    inner: Box<dyn KvBackend>,
+    /// The latest value for each changed key; `None` is a delete.
+    writes: BTreeMap<KvKey, Option<Vec<u8>>>,
+    /// Whether the store was cleared before `writes` were made.
+    cleared: bool,
+    last_flush: Instant,
+}
+
+impl Pending {
+    fn flush(&mut self) -> KvResult<()> {
+        if self.cleared {
+            self.inner.clear()?;
+            self.cleared = false;
+        }
+        // Written one at a time so a failure leaves the rest to retry.
+        while let Some((key, value)) = self.writes.pop_first() {
+            if let Err(e) = self.inner.set(key.clone(), value.clone()) {
+                self.writes.insert(key, value);
+                return Err(e);
+            }
+        }
+        self.last_flush = Instant::now();
+        Ok(())
+    }
+}
+
+/// A backend that keeps a full copy of another in memory. Reads never touch
+/// the wrapped backend, and writes reach it only when flushed through the
+/// [`FlushHandle`] returned by [`CachedBackend::new`], or when the cache is
+/// dropped.
+pub struct CachedBackend {
+    cache: MemoryBackend,
+    pending: Arc<Mutex<Pending>>,
+}
+
+impl CachedBackend {
+    /// Wrap `inner`, reading everything in it into memory.
+    pub fn new(inner: Box<dyn KvBackend>) -> KvResult<(Self, FlushHandle)> {
+        let mut cache = MemoryBackend::new();
+        for (key, value) in inner.get_range(None, None)? {
+            cache.set(key, Some(value))?;
+        }
+        let pending = Arc::new(Mutex::new(Pending {
+            inner,
+            writes: BTreeMap::new(),
+            cleared: false,
+            last_flush: Instant::now(),
+        }));
+        let handle = FlushHandle(Some(pending.clone()));
+        Ok((Self { cache, pending }, handle))
+    }
+
+    fn pending(&self) -> KvResult<std::sync::MutexGuard<'_, Pending>> {
+        self.pending
+            .lock()
+            .map_err(|e| KvError::Other(format!("flags cache lock poisoned: {e}")))
+    }
+}
+
+impl KvBackend for CachedBackend {
+    fn get_range(
+        &self,
+        start: Option<KvKey>,
+        end: Option<KvKey>,
+    ) -> KvResult<Vec<(KvKey, Vec<u8>)>> {
+        self.cache.get_range(start, end)
+    }
+
+    fn set(&mut self, key: KvKey, value: Option<Vec<u8>>) -> KvResult<()> {
+        self.pending()?.writes.insert(key.clone(), value.clone());
+        self.cache.set(key, value)
+    }
+
+    fn clear(&mut self) -> KvResult<()> {
+        {
+            let mut pending = self.pending()?;
+            pending.writes.clear();
+            pending.cleared = true;
+        }
+        self.cache.clear()
+    }
+}
+
+impl Drop for CachedBackend {
+    fn drop(&mut self) {
+        if let Ok(mut pending) = self.pending.lock()
+            && let Err(e) = pending.flush()
+        {
+            system_log!("Could not save flags on exit: {e}");
+        }
+    }
+}
+
+/// Flushes a [`CachedBackend`]'s writes. The default handle has no cache
+/// behind it, and flushing it does nothing.
+#[derive(Clone, Default)]
+pub struct FlushHandle(Option<Arc<Mutex<Pending>>>);
+
+impl FlushHandle {
+    /// Write everything changed since the last flush to the real backend.
+    pub fn flush(&self) -> Result<(), TetronError> {
+        let Some(pending) = &self.0 else {
+            return Ok(());
+        };
+        pending
+            .lock()
+            .map_err(|e| TetronError::KvError(format!("flags cache lock poisoned: {e}")))?
+            .flush()?;
+        Ok(())
+    }
+
+    /// Flush if there are changes and it's been at least `interval` since
+    /// the last flush, so frequent writes are batched.
+    pub fn flush_every(&self, interval: Duration) -> Result<(), TetronError> {
+        let Some(pending) = &self.0 else {
+            return Ok(());
+        };
+        let mut pending = pending
+            .lock()
+            .map_err(|e| TetronError::KvError(format!("flags cache lock poisoned: {e}")))?;
+        let dirty = pending.cleared || !pending.writes.is_empty();
+        if dirty && pending.last_flush.elapsed() >= interval {
+            pending.flush()?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use stupid_simple_kv::{Kv, KvValue};
+
+    #[test]
+    fn test_writes_reach_backend_on_flush() -> Result<(), TetronError> {
+        let disk = MemoryBackend::new();
+        Kv::new(Box::new(disk.clone())).set(&("lives",), KvValue::I64(3))?;
+
+        let (cached, handle) = CachedBackend::new(Box::new(disk.clone()))?;
+        let mut flags = Kv::new(Box::new(cached));
+        let on_disk = Kv::new(Box::new(disk));
+
+        // Existing data was loaded into the cache.
+        assert_eq!(flags.get(&("lives",))?, Some(KvValue::I64(3)));
+
+        flags.set(&("lives",), KvValue::I64(2))?;
+        flags.set(&("level",), KvValue::I64(4))?;
+        assert_eq!(flags.get(&("lives",))?, Some(KvValue::I64(2)));
+        assert_eq!(on_disk.get(&("lives",))?, Some(KvValue::I64(3)));
+
+        handle.flush_every(Duration::from_secs(3600))?;
+        assert_eq!(on_disk.get(&("level",))?, None);
+
+        handle.flush()?;
+        assert_eq!(on_disk.get(&("lives",))?, Some(KvValue::I64(2)));
+        assert_eq!(on_disk.get(&("level",))?, Some(KvValue::I64(4)));
+
+        flags.clear()?;
+        flags.set(&("score",), KvValue::I64(1))?;
+        drop(flags);
+        assert_eq!(on_disk.get(&("lives",))?, None);
+        assert_eq!(on_disk.get(&("score",))?, Some(KvValue::I64(1)));
+
+        FlushHandle::default().flush()?;
+        Ok(())
+    }
+}
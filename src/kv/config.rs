@@ -0,0 +1,228 @@
+use stupid_simple_kv::{Kv, KvKey, KvValue, display::parse_display_string_to_key};
+
+use crate::{engine::physics::vec2::Vec2, error::TetronError};
+
+/// Typed accessors for `game.json` values. Keys are written as in the
+/// config file, e.g. `"sdl:width"`, and can also reach into object values:
+/// `"level:spawn"` finds `spawn` in `"level": { "spawn": ... }`. Each returns
+/// `Ok(None)` for a missing key, and an error naming the key if the value has
+/// the wrong type.
+pub trait ConfigExt {
+    /// An integer. Whole-number floats like `800.0` are accepted.
+    fn get_i64(&self, key: &str) -> Result<Option<i64>, TetronError>;
+    /// A number, integer or not.
+    fn get_f64(&self, key: &str) -> Result<Option<f64>, TetronError>;
+    fn get_string(&self, key: &str) -> Result<Option<String>, TetronError>;
+    fn get_bool(&self, key: &str) -> Result<Option<bool>, TetronError>;
+    /// A vector, written as `{ "x": 1, "y": 2 }`; see [`as_vec2`].
+    fn get_vec2(&self, key: &str) -> Result<Option<Vec2>, TetronError>;
+    /// An array of strings, e.g. a list of paths.
+    fn get_strings(&self, key: &str) -> Result<Option<Vec<String>>, TetronError>;
+}
+
+fn describe(value: &KvValue) -> &'static str {
+    match value {
+        KvValue::Null => "null",
+        KvValue::Bool(_) => "a boolean",
+        KvValue::I64(_) => "an integer",
+        KvValue::F64(_) => "a number",
+        KvValue::String(_) => "a string",
+        KvValue::Array(_) => "an array",
+        KvValue::Object(_) => "an object",
+        KvValue::Binary(_) => "binary data",
+    }
+}
+
+fn wrong_type(key: &str, expected: &str, value: &KvValue) -> TetronError {
+    TetronError::Conversion(format!(
+        "Config value \"{key}\" must be {expected}, but it is {}",
+        describe(value)
+    ))
+}
+
+fn as_f64(value: &KvValue) -> Option<f64> {
+    match value {
+        KvValue::I64(i) => Some(*i as f64),
+        KvValue::F64(f) => Some(*f),
+        _ => None,
+    }
+}
+
+/// The vector a config value describes, if it is an object with exactly the
+/// fields `x` and `y`, both numbers.
+pub fn as_vec2(value: &KvValue) -> Option<Vec2> {
+    let KvValue::Object(map) = value else {
+        return None;
+    };
+    if map.len() != 2 {
+        return None;
+    }
+    Some(Vec2::new(as_f64(map.get("x")?)?, as_f64(map.get("y")?)?))
+}
+
+fn parse_key(key: &str) -> Result<KvKey, TetronError> {
+    parse_display_string_to_key(key)
+        .ok_or(TetronError::Other(format!("Invalid config key \"{key}\"")))
+}
+
+/// Look up `key`, or failing that, the longest prefix of it that is set,
+/// following the rest of the key through nested objects.
+fn lookup(config: &Kv, key: &str) -> Result<Option<KvValue>, TetronError> {
+    if let Some(value) = config.get(&parse_key(key)?)? {
+        return Ok(Some(value));
+    }
+    // Escaped colons are part of a key, so there's nothing to split on.
+    if key.contains('\\') {
+        return Ok(None);
+    }
+
+    let parts = key.split(':').collect::<Vec<_>>();
+    for split in (1..parts.len()).rev() {
+        let Some(mut value) = config.get(&parse_key(&parts[..split].join(":"))?)? else {
+            continue;
+        };
+        for field in &parts[split..] {
+            let KvValue::Object(mut map) = value else {
+                return Ok(None);
+            };
+            let Some(inner) = map.remove(*field) else {
+                return Ok(None);
+            };
+            value = inner;
+        }
+        return Ok(Some(value));
+    }
+    Ok(None)
+}
+
+/// Look up `key`, converting the value with `convert`, which returns `None`
+/// if it has the wrong type.
+fn get_as<T>(
+    config: &Kv,
+    key: &str,
+    expected: &str,
+    convert: impl Fn(&KvValue) -> Option<T>,
+) -> Result<Option<T>, TetronError> {
+    match lookup(config, key)? {
+        Some(value) => convert(&value)
+            .map(Some)
+            .ok_or_else(|| wrong_type(key, expected, &value)),
+        None => Ok(None),
+    }
+}
+
+impl ConfigExt for Kv {
+    fn get_i64(&self, key: &str) -> Result<Option<i64>, TetronError> {
+        get_as(self, key, "a whole number", |value| match value {
+            KvValue::I64(i) => Some(*i),
+            KvValue::F64(f) if f.fract() == 0.0 => Some(*f as i64),
+            _ => None,
+        })
+    }
+
+    fn get_f64(&self, key: &str) -> Result<Option<f64>, TetronError> {
+        get_as(self, key, "a number", as_f64)
+    }
+
+    fn get_string(&self, key: &str) -> Result<Option<String>, TetronError> {
+        get_as(self, key, "a string", |value| match value {
+            KvValue::String(s) => Some(s.clone()),
+            _ => None,
+        })
+    }
+
+    fn get_bool(&self, key: &str) -> Result<Option<bool>, TetronError> {
+        get_as(self, key, "true or false", |value| match value {
+            KvValue::Bool(b) => Some(*b),
+            _ => None,
+        })
+    }
+
+    fn get_vec2(&self, key: &str) -> Result<Option<Vec2>, TetronError> {
+        get_as(self, key, "an object with numbers x and y", as_vec2)
+    }
+
+    fn get_strings(&self, key: &str) -> Result<Option<Vec<String>>, TetronError> {
+        get_as(self, key, "an array of strings", |value| match value {
+            KvValue::Array(items) => items
+                .iter()
+                .map(|item| match item {
+                    KvValue::String(s) => Some(s.clone()),
+                    _ => None,
+                })
+                .collect(),
+            _ => None,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use stupid_simple_kv::MemoryBackend;
+
+    fn config(json: &str) -> Kv {
+        Kv::from_json_string(Box::new(MemoryBackend::new()), json.into()).unwrap()
+    }
+
+    #[test]
+    fn test_coercion() -> Result<(), TetronError> {
+        let config = config(
+            r#"{
+                "sdl:width": 1024.0,
+                "sdl:height": 768,
+                "sdl:scale": 2,
+                "sdl:title": "Game",
+                "debug:overlay": true,
+                "camera:offset": { "x": 1, "y": 2.5 },
+                "preload": { "images": ["a.png", "b.png"], "sounds": ["a.wav", 1] }
+            }"#,
+        );
+        assert_eq!(config.get_i64("sdl:width")?, Some(1024));
+        assert_eq!(config.get_i64("sdl:height")?, Some(768));
+        assert_eq!(config.get_f64("sdl:scale")?, Some(2.0));
+        assert_eq!(config.get_string("sdl:title")?, Some("Game".into()));
+        assert_eq!(config.get_bool("debug:overlay")?, Some(true));
+        assert_eq!(config.get_vec2("camera:offset")?, Some(Vec2::new(1.0, 2.5)));
+        assert_eq!(
+            config.get_strings("preload:images")?,
+            Some(vec!["a.png".into(), "b.png".into()])
+        );
+        assert!(config.get_strings("preload:sounds").is_err());
+        assert_eq!(config.get_i64("sdl:missing")?, None);
+        Ok(())
+    }
+
+    #[test]
+    fn test_nested_vec2() -> Result<(), TetronError> {
+        let config = config(
+            r#"{
+                "level": {
+                    "spawn": { "x": 32, "y": 48.5 },
+                    "exit": { "x": 1, "y": 2, "z": 3 },
+                    "size": 3
+                }
+            }"#,
+        );
+        assert_eq!(config.get_vec2("level:spawn")?, Some(Vec2::new(32.0, 48.5)));
+        assert_eq!(config.get_i64("level:size")?, Some(3));
+        assert_eq!(config.get_vec2("level:missing")?, None);
+        assert_eq!(config.get_vec2("level:size:x")?, None);
+        assert!(config.get_vec2("level:exit").is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_wrong_type_names_key() {
+        let config = config(r#"{ "sdl:width": "wide", "sdl:height": 600.5 }"#);
+        let err = config.get_i64("sdl:width").unwrap_err();
+        assert!(
+            matches!(&err, TetronError::Conversion(msg)
+                if msg.contains("\"sdl:width\"") && msg.contains("a string")),
+            "{err:?}"
+        );
+        assert!(config.get_i64("sdl:height").is_err());
+        assert!(config.get_vec2("sdl:width").is_err());
+        assert!(config.get_bool("sdl:height").is_err());
+    }
+}
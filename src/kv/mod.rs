@@ -0,0 +1,5 @@
+//! Storage for persistent game data such as flags and saves.
+
+pub mod backend;
+pub mod cache;
+pub mod config;
@@ -1,6 +1,8 @@
+pub mod audio;
 pub mod engine;
 pub mod error;
 pub mod fs;
+pub mod kv;
 pub mod scripting;
 pub mod sdl;
 pub mod utils;
@@ -1,4 +1,11 @@
-use tetron::{engine::input::KeyState, scripting};
+use tetron::{
+    audio::AudioQueue,
+    engine::input::KeyState,
+    error::ErrorMode,
+    fs::{noop_fs::NoOpFs, overlay_fs::OverlayFs},
+    kv::cache::FlushHandle,
+    scripting::{self, Flags, FrameState, GameHandles, GameRng},
+};
 
 use scripting::tetron_context;
 use std::sync::{Arc, RwLock};
@@ -12,10 +19,21 @@ pub fn main() {
             Box::new(MemoryBackend::new()),
             Box::new(MemoryBackend::new()),
         );
-        let flags = Arc::new(RwLock::new(Kv::new(backends.0)));
+        let flags = Flags::new(Arc::new(RwLock::new(Kv::new(backends.0))));
         let config = Arc::new(Kv::new(backends.1));
         let input = Arc::new(RwLock::new(KeyState::new()));
-        Ok(tetron_context(flags.clone(), config.clone(), input.clone()).expect("Error building tetron context"))
+        let fs = Arc::new(RwLock::new(OverlayFs::from_layers(vec![Box::new(NoOpFs::new())])));
+        Ok(tetron_context(GameHandles {
+            flags,
+            flags_cache: FlushHandle::default(),
+            config,
+            input,
+            fs,
+            audio: AudioQueue::new(),
+            frame: FrameState::new(),
+            rng: GameRng::new(),
+            mode: ErrorMode::default(),
+        }).expect("Error building tetron context"))
     })
     .run();
 }
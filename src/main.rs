@@ -1,11 +1,63 @@
-use tetron::{engine, error};
+use tetron::{engine, error, kv::backend};
 
 use clap::Parser;
-use engine::{Game, TetronArgs};
+use engine::{Command, Game, TetronArgs, replay::Recording};
 pub use error::TetronError;
+use std::{
+    fs::File,
+    io::{self, BufReader, BufWriter},
+};
+
+/// Load the game for inspection: no window, and flags that aren't saved.
+fn load_headless(args: TetronArgs) -> Result<Game, anyhow::Error> {
+    Game::builder()
+        .headless(true)
+        .backend(|_| backend::memory())
+        .build_from_args(args)
+}
+
+/// Load the game for recording or replaying a session, which must start
+/// from the same state every time: deterministic, with empty flags.
+fn load_deterministic(args: TetronArgs, headless: bool) -> Result<Game, anyhow::Error> {
+    Game::builder()
+        .headless(headless)
+        .deterministic(true)
+        .backend(|_| backend::memory())
+        .build_from_args(args)
+}
 
 pub fn main() -> Result<(), anyhow::Error> {
-    let args = TetronArgs::parse();
+    let mut args = TetronArgs::parse();
+    match args.command.take() {
+        Some(Command::Record { out }) => {
+            let out = BufWriter::new(File::create(out)?);
+            load_deterministic(args, false)?.record(out)?;
+            return Ok(());
+        }
+        Some(Command::Replay { input, headless }) => {
+            let recording = Recording::load(BufReader::new(File::open(input)?))?;
+            let mut game = load_deterministic(args, headless)?;
+            game.replay(&recording, io::stdout().lock())?;
+            return Ok(());
+        }
+        None => {}
+    }
+    if args.check {
+        let mut game = load_headless(args)?;
+        game.check()?;
+        println!("tetron: {} is OK", game.identifier);
+        return Ok(());
+    }
+    if args.dump_config {
+        println!("{}", load_headless(args)?.config_json()?);
+        return Ok(());
+    }
+    if args.dump_registry {
+        let world = load_headless(args)?.begin()?;
+        println!("{}", world.registry_json()?);
+        return Ok(());
+    }
+
     let mut game = Game::try_from(args)?;
 
     game.run()?;
@@ -0,0 +1,198 @@
+use rune::{ContextError, Module, docstring};
+
+use crate::{
+    audio::{AudioCommand, AudioQueue, clamp_channels, clamp_fade_ms, positional},
+    engine::physics::vec2::Vec2,
+    error::{EngineBug, ErrorMode},
+    system_log,
+};
+
+pub fn module(queue: AudioQueue, mode: ErrorMode) -> Result<Module, ContextError> {
+    let mut module = Module::with_crate_item("tetron", ["audio"])?;
+
+    module
+        .function("play_sound", {
+            let queue = queue.clone();
+            move |path: &str| {
+                queue
+                    .push(AudioCommand::PlaySound(path.to_owned()))
+                    .engine_bug("failed to queue sound", mode);
+            }
+        })
+        .build()?
+        .docs(docstring! {
+            /// Play the sound effect at `path` once.
+        })?;
+
+    module
+        .function("play_sound_ex", {
+            let queue = queue.clone();
+            move |path: &str, volume: f64, pan: f64| {
+                queue
+                    .push(AudioCommand::PlaySoundEx {
+                        path: path.to_owned(),
+                        volume,
+                        pan,
+                    })
+                    .engine_bug("failed to queue sound", mode);
+            }
+        })
+        .build()?
+        .docs(docstring! {
+            /// Play the sound effect at `path` once at `volume` (0.0 to 1.0,
+            /// relative to `set_volume`) and `pan` (-1.0 for hard left, 1.0
+            /// for hard right).
+        })?;
+
+    module
+        .function("play_sound_at", {
+            let queue = queue.clone();
+            move |path: &str, source: Vec2, listener: Vec2, max_dist: f64| {
+                let (volume, pan) = positional(source, listener, max_dist);
+                if volume <= 0.0 {
+                    return;
+                }
+                queue
+                    .push(AudioCommand::PlaySoundEx {
+                        path: path.to_owned(),
+                        volume,
+                        pan,
+                    })
+                    .engine_bug("failed to queue sound", mode);
+            }
+        })
+        .build()?
+        .docs(docstring! {
+            /// Play the sound effect at `path` as if it came from `source`,
+            /// heard from `listener` (usually the player or camera). It gets
+            /// linearly quieter with distance, is silent beyond `max_dist`,
+            /// and is panned by the horizontal offset.
+        })?;
+
+    module
+        .function("play_music", {
+            let queue = queue.clone();
+            move |path: &str| {
+                queue
+                    .push(AudioCommand::PlayMusic {
+                        path: path.to_owned(),
+                        loops: -1,
+                    })
+                    .engine_bug("failed to queue music", mode);
+            }
+        })
+        .build()?
+        .docs(docstring! {
+            /// Loop the music track at `path`, replacing the current track.
+        })?;
+
+    module
+        .function("stop_music", {
+            let queue = queue.clone();
+            move || {
+                queue
+                    .push(AudioCommand::StopMusic)
+                    .engine_bug("failed to queue music", mode);
+            }
+        })
+        .build()?
+        .docs(docstring! {
+            /// Stop the current music track immediately, cutting short any
+            /// fade in progress.
+        })?;
+
+    module
+        .function("play_music_fade", {
+            let queue = queue.clone();
+            move |path: &str, loops: i64, fade_ms: i64| {
+                queue
+                    .push(AudioCommand::FadeInMusic {
+                        path: path.to_owned(),
+                        loops: loops.clamp(-1, i32::MAX as i64) as i32,
+                        fade_ms: clamp_fade_ms(fade_ms),
+                    })
+                    .engine_bug("failed to queue music", mode);
+            }
+        })
+        .build()?
+        .docs(docstring! {
+            /// Play the music track at `path`, fading in over `fade_ms`
+            /// milliseconds. `loops` of -1 repeats forever.
+        })?;
+
+    module
+        .function("fade_out_music", {
+            let queue = queue.clone();
+            move |fade_ms: i64| {
+                queue
+                    .push(AudioCommand::FadeOutMusic(clamp_fade_ms(fade_ms)))
+                    .engine_bug("failed to queue music", mode);
+            }
+        })
+        .build()?
+        .docs(docstring! {
+            /// Fade the current music track out over `fade_ms` milliseconds,
+            /// then stop it. `stop_music` during the fade stops it at once.
+        })?;
+
+    module
+        .function("set_volume", {
+            let queue = queue.clone();
+            move |volume: f64| {
+                queue
+                    .push(AudioCommand::SetSoundVolume(volume))
+                    .engine_bug("failed to queue volume change", mode);
+            }
+        })
+        .build()?
+        .docs(docstring! {
+            /// Set the volume of sound effects, from 0.0 to 1.0.
+        })?;
+
+    module
+        .function("set_music_volume", {
+            let queue = queue.clone();
+            move |volume: f64| {
+                queue
+                    .push(AudioCommand::SetMusicVolume(volume))
+                    .engine_bug("failed to queue volume change", mode);
+            }
+        })
+        .build()?
+        .docs(docstring! {
+            /// Set the music volume, from 0.0 to 1.0.
+        })?;
+
+    module
+        .function("allocate_channels", {
+            let queue = queue.clone();
+            move |channels: i64| {
+                let clamped = clamp_channels(channels);
+                if clamped as i64 != channels {
+                    system_log!("allocate_channels: {channels} is out of range, using {clamped}");
+                }
+                queue
+                    .push(AudioCommand::AllocateChannels(clamped))
+                    .engine_bug("failed to queue channel change", mode);
+            }
+        })
+        .build()?
+        .docs(docstring! {
+            /// Set how many sounds can play at once, from 1 up to 256. The
+            /// starting count comes from the `audio:channels` config key and
+            /// defaults to 8.
+        })?;
+
+    module
+        .function("playing_channels", {
+            let queue = queue.clone();
+            move || -> i64 { queue.playing() }
+        })
+        .build()?
+        .docs(docstring! {
+            /// How many channels were playing a sound at the end of the last
+            /// frame.
+        })?;
+
+    Ok(module)
+}
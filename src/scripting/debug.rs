@@ -0,0 +1,80 @@
+use rune::{ContextError, Module};
+
+use crate::engine::{entity::EntityRef, scene::SceneRef, systems::Ctx};
+
+/// A multi-line description of `entity`: its id, tags and every behaviour's
+/// fields.
+#[rune::function(keep)]
+pub fn inspect(entity: EntityRef) -> String {
+    let mut out = format!("Entity {}", entity.id());
+    let tags = entity.tags();
+    if !tags.is_empty() {
+        out.push_str(&format!("\n  tags: {}", tags.join(", ")));
+    }
+    for name in entity.behaviour_names() {
+        if let Some(behaviour) = entity.behaviour(&name) {
+            for line in behaviour.to_debug_string().lines() {
+                out.push_str("\n  ");
+                out.push_str(line);
+            }
+        }
+    }
+    out
+}
+
+/// [`inspect`] every entity in `scene`, in spawn order.
+pub fn dump(scene: &SceneRef) -> String {
+    scene
+        .entities()
+        .into_iter()
+        .map(inspect)
+        .collect::<Vec<_>>()
+        .join("\n")
+}
+
+/// [`inspect`] every entity in the current scene.
+#[rune::function(keep)]
+fn dump_scene(ctx: Ctx) -> String {
+    ctx.try_scene()
+        .map(|scene| dump(&scene))
+        .unwrap_or_default()
+}
+
+pub fn module() -> Result<Module, ContextError> {
+    let mut module = Module::with_crate_item("tetron", ["debug"])?;
+    module.function_meta(inspect__meta)?;
+    module.function_meta(dump_scene__meta)?;
+    Ok(module)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{engine::behaviours::BehaviourFactory, utils::typed_value::TypedValue};
+    use std::collections::HashMap;
+
+    #[test]
+    fn test_inspect_lists_behaviours() {
+        let transform = BehaviourFactory::builtin("tetron:transform")
+            .unwrap()
+            .default_instance()
+            .unwrap();
+        let drawable = BehaviourFactory::builtin("tetron:drawable")
+            .unwrap()
+            .with_map(HashMap::from([(
+                "text".to_string(),
+                TypedValue::from("hello"),
+            )]));
+
+        let mut entity = EntityRef::new();
+        entity.tag("player");
+        entity.attach(transform);
+        entity.attach(drawable);
+
+        let out = inspect(entity);
+        assert!(out.contains("tetron:transform"));
+        assert!(out.contains("tetron:drawable"));
+        assert!(out.contains("text: \"hello\""));
+        assert!(out.contains("tags: player"));
+    }
+}
@@ -0,0 +1,113 @@
+use rune::{ContextError, Module};
+
+/// The first character of `fill`, or a space if it's empty.
+fn fill_char(fill: &str) -> char {
+    fill.chars().next().unwrap_or(' ')
+}
+
+fn padding(s: &str, width: i64, fill: &str) -> String {
+    let missing = (width.max(0) as usize).saturating_sub(s.chars().count());
+    std::iter::repeat_n(fill_char(fill), missing).collect()
+}
+
+/// Pad `s` on the left with the first character of `fill` until it is
+/// `width` characters long. Longer strings are returned unchanged.
+#[rune::function(keep)]
+pub fn pad_left(s: &str, width: i64, fill: &str) -> String {
+    padding(s, width, fill) + s
+}
+
+/// Pad `s` on the right with the first character of `fill` until it is
+/// `width` characters long. Longer strings are returned unchanged.
+#[rune::function(keep)]
+pub fn pad_right(s: &str, width: i64, fill: &str) -> String {
+    s.to_owned() + &padding(s, width, fill)
+}
+
+/// Shorten `s` to at most `max_chars` characters, ending it with
+/// `ellipsis` if anything was cut. The ellipsis counts towards the limit.
+#[rune::function(keep)]
+pub fn truncate(s: &str, max_chars: i64, ellipsis: &str) -> String {
+    let max = max_chars.max(0) as usize;
+    if s.chars().count() <= max {
+        return s.to_owned();
+    }
+
+    let ellipsis_len = ellipsis.chars().count();
+    if ellipsis_len >= max {
+        return ellipsis.chars().take(max).collect();
+    }
+
+    s.chars()
+        .take(max - ellipsis_len)
+        .chain(ellipsis.chars())
+        .collect()
+}
+
+/// Format `n` with exactly `decimal_places` digits after the point.
+#[rune::function(keep)]
+pub fn number(n: f64, decimal_places: i64) -> String {
+    format!("{:.*}", decimal_places.clamp(0, 17) as usize, n)
+}
+
+/// Round `n` to a whole number and separate thousands with commas, e.g.
+/// `1,234,567`.
+#[rune::function(keep)]
+pub fn thousands(n: f64) -> String {
+    let digits = format!("{:.0}", n.abs());
+    let mut out = String::new();
+    for (i, c) in digits.chars().enumerate() {
+        if i > 0 && (digits.len() - i) % 3 == 0 {
+            out.push(',');
+        }
+        out.push(c);
+    }
+
+    if n.round() < 0.0 {
+        out.insert(0, '-');
+    }
+
+    out
+}
+
+/// Format a number of seconds as `MM:SS`, rounding down. Minutes keep
+/// counting past 59 rather than rolling over into hours.
+#[rune::function(keep)]
+pub fn duration(seconds: f64) -> String {
+    let total = seconds.max(0.0) as u64;
+    format!("{:02}:{:02}", total / 60, total % 60)
+}
+
+pub fn module() -> Result<Module, ContextError> {
+    let mut module = Module::with_crate_item("tetron", ["fmt"])?;
+    module.function_meta(pad_left__meta)?;
+    module.function_meta(pad_right__meta)?;
+    module.function_meta(truncate__meta)?;
+    module.function_meta(number__meta)?;
+    module.function_meta(thousands__meta)?;
+    module.function_meta(duration__meta)?;
+    Ok(module)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    #[allow(clippy::approx_constant)]
+    fn test_formatting() {
+        assert_eq!(number(3.14159, 2), "3.14");
+        assert_eq!(number(2.0, 0), "2");
+        assert_eq!(thousands(1234567.0), "1,234,567");
+        assert_eq!(thousands(-1000.0), "-1,000");
+        assert_eq!(thousands(999.0), "999");
+        assert_eq!(duration(90.5), "01:30");
+        assert_eq!(duration(3600.0), "60:00");
+        assert_eq!(pad_left("7", 3, "0"), "007");
+        assert_eq!(pad_right("ab", 4, ""), "ab  ");
+        assert_eq!(pad_left("long", 2, "0"), "long");
+        assert_eq!(truncate("hello world", 8, "..."), "hello...");
+        assert_eq!(truncate("hi", 8, "..."), "hi");
+        assert_eq!(truncate("hello", 2, "..."), "..");
+    }
+}
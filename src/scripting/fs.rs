@@ -0,0 +1,146 @@
+use std::{
+    path::Path,
+    sync::{
+        Arc, RwLock,
+        atomic::{AtomicUsize, Ordering},
+    },
+};
+
+use rune::{ContextError, Module, docstring};
+
+use crate::{
+    error::{EngineBug, ErrorMode, TetronError},
+    fs::{SimpleFs, overlay_fs::OverlayFs, zip_fs::ZipFs},
+    system_log,
+};
+
+/// Open the zip at `path` in the game's own files as a new layer. Layers can
+/// only come from files the game can already see, so a script or mod can't
+/// mount arbitrary places on the machine. Absolute paths and `..` are
+/// refused outright rather than resolved against the root.
+fn load_layer(fs: &OverlayFs, path: &str) -> Result<Box<dyn SimpleFs>, TetronError> {
+    if path.starts_with(['/', '\\'])
+        || Path::new(path).is_absolute()
+        || path.split(['/', '\\']).any(|part| part == "..")
+    {
+        return Err(TetronError::FsError(format!(
+            "{path} is not a path inside the game's files"
+        )));
+    }
+    let zip = ZipFs::new(fs.open_file(path)?)
+        .map_err(|e| TetronError::FsError(format!("{path} is not a zip file: {e}")))?;
+    Ok(Box::new(zip))
+}
+
+pub fn module(fs: Arc<RwLock<OverlayFs>>, mode: ErrorMode) -> Result<Module, ContextError> {
+    let mut module = Module::with_crate_item("tetron", ["fs"])?;
+    // Only layers pushed by scripts can be popped, never the game itself.
+    let pushed = Arc::new(AtomicUsize::new(0));
+
+    module
+        .function("hash_file", {
+            let fs = fs.clone();
+            move |path: &str| -> Option<String> {
+                fs.hash_file(path)
+                    .inspect_err(|e| system_log!("Could not hash {path}: {e}"))
+                    .ok()
+            }
+        })
+        .build()?
+        .docs(docstring! {
+            /// SHA-256 digest of the file at `path` as a lowercase hex string,
+            /// or `None` if the file could not be read.
+        })?;
+
+    module
+        .function("push_layer", {
+            let fs = fs.clone();
+            let pushed = pushed.clone();
+            move |zip_path: &str| -> bool {
+                let Some(mut fs) = fs.write().engine_bug("fs lock poisoned", mode) else {
+                    return false;
+                };
+                let layer = match load_layer(&fs, zip_path) {
+                    Ok(layer) => layer,
+                    Err(e) => {
+                        system_log!("Could not load layer {zip_path}: {e}");
+                        return false;
+                    }
+                };
+                fs.push_layer(layer);
+                pushed.fetch_add(1, Ordering::Relaxed);
+                true
+            }
+        })
+        .build()?
+        .docs(docstring! {
+            /// Add the zip at `zip_path`, a path inside the game's files, on
+            /// top of them, so it shadows them. Absolute paths and paths
+            /// using `..` are refused. Returns false if it could not be
+            /// loaded.
+        })?;
+
+    module
+        .function("pop_layer", move || -> bool {
+            if pushed
+                .fetch_update(Ordering::Relaxed, Ordering::Relaxed, |n| n.checked_sub(1))
+                .is_err()
+            {
+                return false;
+            }
+            fs.write()
+                .engine_bug("fs lock poisoned", mode)
+                .and_then(|mut fs| fs.pop_layer())
+                .is_some()
+        })
+        .build()?
+        .docs(docstring! {
+            /// Remove the layer most recently added with `push_layer`. Returns
+            /// false if there is none.
+        })?;
+
+    Ok(module)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::fs::disk_fs::DiskFs;
+    use std::{fs, io::Write};
+    use zip::{ZipWriter, write::SimpleFileOptions};
+
+    fn write_zip(path: &Path, name: &str, contents: &str) {
+        let mut writer = ZipWriter::new(fs::File::create(path).unwrap());
+        writer
+            .start_file(name, SimpleFileOptions::default())
+            .unwrap();
+        writer.write_all(contents.as_bytes()).unwrap();
+        writer.finish().unwrap();
+    }
+
+    #[test]
+    fn test_load_layer_stays_in_game_files() {
+        let root = std::env::temp_dir().join(format!("tetron-push-layer-{}", std::process::id()));
+        let _ = fs::remove_dir_all(&root);
+        let base = root.join("game");
+        fs::create_dir_all(base.join("dlc")).unwrap();
+        write_zip(&base.join("dlc/extra.zip"), "extra.txt", "extra");
+        write_zip(&root.join("outside.zip"), "outside.txt", "outside");
+        let overlay = OverlayFs::from_layers(vec![Box::new(DiskFs::new(&base))]);
+
+        let layer = load_layer(&overlay, "dlc/extra.zip").unwrap();
+        assert_eq!(layer.read_text_file("extra.txt").unwrap(), "extra");
+
+        for path in [
+            "../outside.zip",
+            "dlc/../../outside.zip",
+            "dlc/../extra.zip",
+            root.join("outside.zip").to_str().unwrap(),
+            base.join("dlc/extra.zip").to_str().unwrap(),
+            "/dlc/extra.zip",
+        ] {
+            assert!(load_layer(&overlay, path).is_err(), "{path}");
+        }
+        fs::remove_dir_all(&root).unwrap();
+    }
+}
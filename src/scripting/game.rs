@@ -2,16 +2,67 @@ use crate::{
     engine::{
         behaviours::{BehaviourFactory, BehaviourRef},
         entity::EntityRef,
+        physics::vec2::Vec2,
         scene::SceneRef,
         systems::Ctx,
         world::WorldRef,
     },
+    error::{EngineBug, ErrorMode},
+    sdl::{DrawCommand, DrawQueue},
     utils::Registrable,
 };
 
-use rune::{ContextError, Module};
+use rune::{ContextError, Module, docstring};
+use std::{
+    f64::consts::TAU,
+    sync::{
+        Arc,
+        atomic::{AtomicBool, Ordering},
+    },
+};
+
+/// What a game shares with its `tetron::game` module: the queue scripts
+/// draw to, and whether the frame being updated is the first since `begin`.
+#[derive(Clone, Debug, Default)]
+pub struct FrameState {
+    pub draw: DrawQueue,
+    first_frame: Arc<AtomicBool>,
+}
+
+impl FrameState {
+    pub fn new() -> Self {
+        Self::default()
+    }
 
-pub fn module() -> Result<Module, ContextError> {
+    /// Set before a frame's update runs, and cleared once it's done so
+    /// `end` and later frames don't see it.
+    pub(crate) fn set_first_frame(&self, first: bool) {
+        self.first_frame.store(first, Ordering::Relaxed);
+    }
+
+    fn is_first_frame(&self) -> bool {
+        self.first_frame.load(Ordering::Relaxed)
+    }
+}
+
+/// The direction `dir` points in, snapped to one of `ways` evenly spaced
+/// facings, for picking a row of a directional sprite sheet. Index 0 faces
+/// right and indices go clockwise on screen, so with 4 ways they are right,
+/// down, left, up, and with 8 the diagonals fall in between.
+///
+/// Returns `None` for the zero vector (or fewer than one way), so a
+/// character that stops moving can keep its last facing.
+#[rune::function(keep)]
+pub fn facing(dir: Vec2, ways: i64) -> Option<i64> {
+    if ways < 1 || dir == Vec2::ZERO {
+        return None;
+    }
+    let sector = TAU / ways as f64;
+    let angle = dir.y.atan2(dir.x).rem_euclid(TAU);
+    Some(((angle / sector).round() as i64).rem_euclid(ways))
+}
+
+pub fn module(frame: FrameState, mode: ErrorMode) -> Result<Module, ContextError> {
     let mut module = Module::with_crate_item("tetron", ["game"])?;
     WorldRef::register(&mut module)?;
     SceneRef::register(&mut module)?;
@@ -19,6 +70,69 @@ pub fn module() -> Result<Module, ContextError> {
     EntityRef::register(&mut module)?;
     BehaviourFactory::register(&mut module)?;
     Ctx::register(&mut module)?;
+    module.function_meta(facing__meta)?;
+
+    module
+        .function("is_first_frame", {
+            let frame = frame.clone();
+            move || frame.is_first_frame()
+        })
+        .build()?
+        .docs(docstring! {
+            /// Whether this is the first frame since `begin` returned, for
+            /// one-time setup in a system that needs the first frame's `dt`.
+        })?;
+
+    let draw = frame.draw;
+    module
+        .function(
+            "draw_texture",
+            move |path: &str, src_pos: Vec2, src_size: Vec2, dst_pos: Vec2, dst_size: Vec2| {
+                draw.push(DrawCommand::Texture {
+                    path: path.to_owned(),
+                    src_pos,
+                    src_size,
+                    dst_pos,
+                    dst_size,
+                })
+                .engine_bug("failed to queue texture", mode);
+            },
+        )
+        .build()?
+        .docs(docstring! {
+            /// Draw the `src_size` region at `src_pos` of the image at `path`
+            /// at `dst_pos`, stretched to `dst_size`. Drawn once, after the
+            /// scene's drawables and before buttons, so call it every frame
+            /// the image should show.
+        })?;
 
     Ok(module)
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_facing() {
+        let four = |x, y| facing(Vec2::new(x, y), 4);
+        assert_eq!(four(1.0, 0.0), Some(0));
+        assert_eq!(four(0.0, 1.0), Some(1));
+        assert_eq!(four(-1.0, 0.0), Some(2));
+        assert_eq!(four(0.0, -1.0), Some(3));
+        // Mostly rightwards, slightly up.
+        assert_eq!(four(5.0, -1.0), Some(0));
+
+        let eight = |x, y| facing(Vec2::new(x, y), 8);
+        assert_eq!(eight(1.0, 0.0), Some(0));
+        assert_eq!(eight(1.0, 1.0), Some(1));
+        assert_eq!(eight(0.0, 1.0), Some(2));
+        assert_eq!(eight(-1.0, 1.0), Some(3));
+        assert_eq!(eight(-1.0, 0.0), Some(4));
+        assert_eq!(eight(-1.0, -1.0), Some(5));
+        assert_eq!(eight(0.0, -1.0), Some(6));
+        assert_eq!(eight(1.0, -1.0), Some(7));
+
+        assert_eq!(facing(Vec2::ZERO, 8), None);
+    }
+}
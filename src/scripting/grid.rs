@@ -0,0 +1,246 @@
+use std::{
+    cmp::Reverse,
+    collections::{BinaryHeap, HashMap},
+};
+
+use rune::{ContextError, Module, Value, runtime::Function};
+
+use crate::{engine::physics::vec2::Vec2, system_log, utils::Registrable};
+
+/// A cell coordinate in a [`Grid`].
+#[derive(rune::Any, Copy, Clone, Debug, PartialEq, Eq, Hash)]
+pub struct Vec2i {
+    #[rune(get, set)]
+    pub x: i64,
+    #[rune(get, set)]
+    pub y: i64,
+}
+
+impl Vec2i {
+    #[rune::function(path = Self::new, keep)]
+    pub fn new(x: i64, y: i64) -> Self {
+        Self { x, y }
+    }
+}
+
+const NEIGHBORS_4: [(i64, i64); 4] = [(0, -1), (1, 0), (0, 1), (-1, 0)];
+const NEIGHBORS_8: [(i64, i64); 8] = [
+    (-1, -1),
+    (0, -1),
+    (1, -1),
+    (1, 0),
+    (1, 1),
+    (0, 1),
+    (-1, 1),
+    (-1, 0),
+];
+
+/// A sparse grid of square cells, `cell_size` world units wide, holding
+/// arbitrary script values.
+#[derive(rune::Any, Debug)]
+pub struct Grid {
+    #[rune(get)]
+    cell_size: f64,
+    data: HashMap<(i64, i64), Value>,
+}
+
+impl Registrable for Grid {
+    fn register(module: &mut Module) -> Result<(), ContextError> {
+        module.ty::<Vec2i>()?;
+        module.function_meta(Vec2i::new__meta)?;
+        module.ty::<Grid>()?;
+        module.function_meta(Grid::new__meta)?;
+        module.function_meta(Grid::set__meta)?;
+        module.function_meta(Grid::get__meta)?;
+        module.function_meta(Grid::get_cell__meta)?;
+        module.function_meta(Grid::set_cell__meta)?;
+        module.function_meta(Grid::neighbors_4__meta)?;
+        module.function_meta(Grid::neighbors_8__meta)?;
+        module.function_meta(Grid::world_to_cell__meta)?;
+        module.function_meta(Grid::cell_to_world__meta)?;
+        module.function_meta(Grid::find_path_rune__meta)?;
+        Ok(())
+    }
+}
+
+impl Grid {
+    #[rune::function(path = Self::new, keep)]
+    pub fn new(cell_size: f64) -> Self {
+        Self {
+            cell_size,
+            data: HashMap::new(),
+        }
+    }
+
+    /// The cell containing `world_pos`.
+    #[rune::function(instance, keep)]
+    pub fn world_to_cell(&self, world_pos: Vec2) -> Vec2i {
+        Vec2i::new(
+            (world_pos.x / self.cell_size).floor() as i64,
+            (world_pos.y / self.cell_size).floor() as i64,
+        )
+    }
+
+    /// The top-left corner of cell `(cx, cy)` in world space.
+    #[rune::function(instance, keep)]
+    pub fn cell_to_world(&self, cx: i64, cy: i64) -> Vec2 {
+        Vec2::new(cx as f64 * self.cell_size, cy as f64 * self.cell_size)
+    }
+
+    /// Store `value` in the cell containing `world_pos`.
+    #[rune::function(instance, keep)]
+    pub fn set(&mut self, world_pos: Vec2, value: Value) {
+        let cell = self.world_to_cell(world_pos);
+        self.set_cell(cell.x, cell.y, value);
+    }
+
+    /// The value in the cell containing `world_pos`.
+    #[rune::function(instance, keep)]
+    pub fn get(&self, world_pos: Vec2) -> Option<Value> {
+        let cell = self.world_to_cell(world_pos);
+        self.get_cell(cell.x, cell.y)
+    }
+
+    #[rune::function(instance, keep)]
+    pub fn set_cell(&mut self, cx: i64, cy: i64, value: Value) {
+        self.data.insert((cx, cy), value);
+    }
+
+    #[rune::function(instance, keep)]
+    pub fn get_cell(&self, cx: i64, cy: i64) -> Option<Value> {
+        self.data.get(&(cx, cy)).cloned()
+    }
+
+    fn neighbors(&self, cx: i64, cy: i64, offsets: &[(i64, i64)]) -> Vec<(Vec2i, Value)> {
+        offsets
+            .iter()
+            .filter_map(|(dx, dy)| {
+                let (x, y) = (cx + dx, cy + dy);
+                self.data
+                    .get(&(x, y))
+                    .map(|value| (Vec2i::new(x, y), value.clone()))
+            })
+            .collect()
+    }
+
+    /// The occupied cells sharing an edge with `(cx, cy)`.
+    #[rune::function(instance, keep)]
+    pub fn neighbors_4(&self, cx: i64, cy: i64) -> Vec<(Vec2i, Value)> {
+        self.neighbors(cx, cy, &NEIGHBORS_4)
+    }
+
+    /// The occupied cells sharing an edge or corner with `(cx, cy)`.
+    #[rune::function(instance, keep)]
+    pub fn neighbors_8(&self, cx: i64, cy: i64) -> Vec<(Vec2i, Value)> {
+        self.neighbors(cx, cy, &NEIGHBORS_8)
+    }
+
+    /// Find a shortest path between the cells containing `from` and `to`,
+    /// moving between edge-adjacent cells that `passable` accepts, using A*.
+    /// Returns the corner of each cell along the way, including both ends.
+    ///
+    /// The grid is unbounded, so the search is limited to the bounding box
+    /// of every occupied cell plus `from` and `to`, grown by one cell.
+    pub fn find_path(
+        &self,
+        from: Vec2,
+        to: Vec2,
+        mut passable: impl FnMut(Vec2i) -> bool,
+    ) -> Option<Vec<Vec2>> {
+        let start = self.world_to_cell(from);
+        let goal = self.world_to_cell(to);
+        let cells = self
+            .data
+            .keys()
+            .copied()
+            .chain([(start.x, start.y), (goal.x, goal.y)]);
+        let (mut min, mut max) = ((i64::MAX, i64::MAX), (i64::MIN, i64::MIN));
+        for (x, y) in cells {
+            min = (min.0.min(x - 1), min.1.min(y - 1));
+            max = (max.0.max(x + 1), max.1.max(y + 1));
+        }
+        let in_bounds = |(x, y): (i64, i64)| x >= min.0 && x <= max.0 && y >= min.1 && y <= max.1;
+        let heuristic = |(x, y): (i64, i64)| (x - goal.x).abs() + (y - goal.y).abs();
+
+        let start = (start.x, start.y);
+        let goal = (goal.x, goal.y);
+        let mut open = BinaryHeap::from([Reverse((heuristic(start), 0, start))]);
+        let mut cost = HashMap::from([(start, 0)]);
+        let mut came_from: HashMap<(i64, i64), (i64, i64)> = HashMap::new();
+
+        while let Some(Reverse((_, g, cell))) = open.pop() {
+            if cell == goal {
+                let mut path = vec![self.cell_to_world(cell.0, cell.1)];
+                let mut cell = cell;
+                while let Some(&prev) = came_from.get(&cell) {
+                    path.push(self.cell_to_world(prev.0, prev.1));
+                    cell = prev;
+                }
+                path.reverse();
+                return Some(path);
+            }
+            if cost.get(&cell).is_some_and(|&best| g > best) {
+                continue;
+            }
+            for (dx, dy) in NEIGHBORS_4 {
+                let next = (cell.0 + dx, cell.1 + dy);
+                if !in_bounds(next) || cost.get(&next).is_some_and(|&best| best <= g + 1) {
+                    continue;
+                }
+                if !passable(Vec2i::new(next.0, next.1)) {
+                    continue;
+                }
+                cost.insert(next, g + 1);
+                came_from.insert(next, cell);
+                open.push(Reverse((g + 1 + heuristic(next), g + 1, next)));
+            }
+        }
+        None
+    }
+
+    /// Find a shortest path from `from` to `to`. `passable` is called with
+    /// each cell coordinate the search considers entering.
+    #[rune::function(instance, keep, path = Self::find_path)]
+    fn find_path_rune(&self, from: Vec2, to: Vec2, passable: Function) -> Option<Vec<Vec2>> {
+        self.find_path(from, to, |cell| {
+            passable
+                .call::<bool>((cell,))
+                .into_result()
+                .inspect_err(|e| system_log!("grid::find_path passable error: {e:?}"))
+                .unwrap_or(false)
+        })
+    }
+}
+
+pub fn module() -> Result<Module, ContextError> {
+    let mut module = Module::with_crate_item("tetron", ["grid"])?;
+    Grid::register(&mut module)?;
+    Ok(module)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_find_path_around_obstacle() {
+        let mut grid = Grid::new(10.0);
+        grid.set(Vec2::new(15.0, 5.0), Value::from(true));
+        assert_eq!(grid.world_to_cell(Vec2::new(15.0, 5.0)), Vec2i::new(1, 0));
+        assert!(grid.get_cell(1, 0).is_some());
+
+        // A wall at (1, 0) between (0, 0) and (2, 0).
+        let path = grid
+            .find_path(Vec2::new(5.0, 5.0), Vec2::new(25.0, 5.0), |cell| {
+                grid.get_cell(cell.x, cell.y).is_none()
+            })
+            .unwrap();
+        assert_eq!(path.len(), 5);
+        assert_eq!(path.first(), Some(&Vec2::new(0.0, 0.0)));
+        assert_eq!(path.last(), Some(&Vec2::new(20.0, 0.0)));
+        assert!(!path.contains(&Vec2::new(10.0, 0.0)));
+
+        let open = grid.find_path(Vec2::new(5.0, 5.0), Vec2::new(25.0, 5.0), |_| true);
+        assert_eq!(open.unwrap().len(), 3);
+    }
+}
@@ -0,0 +1,241 @@
+use std::{
+    collections::HashMap,
+    sync::{Arc, RwLock},
+};
+
+use rune::{ContextError, Module, docstring, runtime::Object};
+use stupid_simple_kv::Kv;
+
+use crate::{
+    error::{EngineBug, ErrorMode, TetronError},
+    fs::SimpleFs,
+    kv::config::ConfigExt,
+    system_log,
+    utils::typed_value::TypedValue,
+};
+
+/// Translated strings, loaded from `locales/{lang}.json` files in the game's filesystem.
+pub struct I18n {
+    fs: Arc<dyn SimpleFs>,
+    language: Option<String>,
+    cache: HashMap<String, HashMap<String, String>>,
+}
+
+impl I18n {
+    pub fn new(fs: Arc<dyn SimpleFs>) -> Self {
+        Self {
+            fs,
+            language: None,
+            cache: HashMap::new(),
+        }
+    }
+
+    /// Switch to `lang`, loading its locale file if it hasn't been loaded yet.
+    pub fn set_language(&mut self, lang: &str) -> Result<(), TetronError> {
+        if !self.cache.contains_key(lang) {
+            let path = format!("locales/{lang}.json");
+            let json = self.fs.read_text_file(&path)?;
+            let strings: HashMap<String, String> = serde_json::from_str(&json)
+                .map_err(|e| TetronError::Conversion(format!("Invalid locale file {path}: {e}")))?;
+            self.cache.insert(lang.to_owned(), strings);
+        }
+        self.language = Some(lang.to_owned());
+        Ok(())
+    }
+
+    pub fn language(&self) -> Option<&str> {
+        self.language.as_deref()
+    }
+
+    /// Look up `key` in the current language, falling back to the key itself.
+    pub fn t(&self, key: &str) -> String {
+        self.language
+            .as_ref()
+            .and_then(|lang| self.cache.get(lang))
+            .and_then(|strings| strings.get(key))
+            .cloned()
+            .unwrap_or_else(|| key.to_owned())
+    }
+
+    /// Like [`I18n::t`], but replaces `{name}` placeholders with values from
+    /// `args`. Placeholders without a matching arg are left as-is.
+    pub fn t_fmt(&self, key: &str, args: &HashMap<String, String>) -> String {
+        let template = self.t(key);
+        let mut out = String::with_capacity(template.len());
+        let mut rest = template.as_str();
+        while let Some(start) = rest.find('{') {
+            out.push_str(&rest[..start]);
+            let after = &rest[start + 1..];
+            match after.find('}') {
+                Some(end) => {
+                    let name = &after[..end];
+                    match args.get(name) {
+                        Some(value) => out.push_str(value),
+                        None => out.push_str(&rest[start..start + end + 2]),
+                    }
+                    rest = &after[end + 1..];
+                }
+                None => {
+                    out.push_str(&rest[start..]);
+                    rest = "";
+                }
+            }
+        }
+        out.push_str(rest);
+        out
+    }
+}
+
+fn format_arg(value: &TypedValue) -> String {
+    match value {
+        TypedValue::String(s) => s.clone(),
+        TypedValue::Number(n) => n.to_string(),
+        TypedValue::Bool(b) => b.to_string(),
+        TypedValue::Vector(v) => v.to_string(),
+        other => format!("{other:?}"),
+    }
+}
+
+pub fn module(
+    fs: Arc<dyn SimpleFs>,
+    config: Arc<Kv>,
+    mode: ErrorMode,
+) -> Result<Module, ContextError> {
+    let mut module = Module::with_crate_item("tetron", ["i18n"])?;
+
+    let mut i18n = I18n::new(fs);
+    let default_language = config
+        .get_string("i18n:default_language")
+        .unwrap_or_else(|e| {
+            system_log!("Ignoring i18n:default_language: {e}");
+            None
+        });
+    if let Some(lang) = default_language
+        && let Err(e) = i18n.set_language(&lang)
+    {
+        system_log!("Could not load default language {lang}: {e}");
+    }
+    let i18n = Arc::new(RwLock::new(i18n));
+
+    module
+        .function("set_language", {
+            let i18n = i18n.clone();
+            move |lang: &str| -> bool {
+                i18n.write()
+                    .engine_bug("i18n lock poisoned", mode)
+                    .is_some_and(|mut i18n| match i18n.set_language(lang) {
+                        Ok(()) => true,
+                        Err(e) => {
+                            system_log!("Could not load language {lang}: {e}");
+                            false
+                        }
+                    })
+            }
+        })
+        .build()?
+        .docs(docstring! {
+            /// Load `locales/{lang}.json` and make it the current language.
+            /// Returns false if the locale file could not be loaded.
+        })?;
+
+    module
+        .function("language", {
+            let i18n = i18n.clone();
+            move || -> Option<String> {
+                i18n.read()
+                    .engine_bug("i18n lock poisoned", mode)
+                    .and_then(|i18n| i18n.language().map(str::to_owned))
+            }
+        })
+        .build()?
+        .docs(docstring! {
+            /// The current language, if one has been set.
+        })?;
+
+    module
+        .function("t", {
+            let i18n = i18n.clone();
+            move |key: &str| -> String {
+                i18n.read()
+                    .engine_bug("i18n lock poisoned", mode)
+                    .map(|i18n| i18n.t(key))
+                    .unwrap_or_else(|| key.to_owned())
+            }
+        })
+        .build()?
+        .docs(docstring! {
+            /// Translate `key` into the current language. Returns the key
+            /// itself if there is no translation.
+        })?;
+
+    module
+        .function("t_fmt", {
+            let i18n = i18n.clone();
+            move |key: &str, args: &Object| -> String {
+                let mut map = HashMap::new();
+                for (name, value) in args {
+                    if let Some(value) = TypedValue::try_from(value)
+                        .engine_bug("failed to convert format argument", mode)
+                    {
+                        map.insert(name.as_str().to_owned(), format_arg(&value));
+                    }
+                }
+                i18n.read()
+                    .engine_bug("i18n lock poisoned", mode)
+                    .map(|i18n| i18n.t_fmt(key, &map))
+                    .unwrap_or_else(|| key.to_owned())
+            }
+        })
+        .build()?
+        .docs(docstring! {
+            /// Translate `key`, replacing `{name}` placeholders with the
+            /// matching fields of `args`.
+        })?;
+
+    Ok(module)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::fs::zip_fs::ZipFs;
+
+    fn i18n() -> I18n {
+        let fs = ZipFs::from_files(&[
+            ("game.json", "{}"),
+            (
+                "locales/en.json",
+                r#"{ "greeting": "Hello!", "score": "{name} scored {points} points" }"#,
+            ),
+        ]);
+        I18n::new(Arc::new(fs))
+    }
+
+    #[test]
+    fn test_lookup_and_fallback() -> Result<(), TetronError> {
+        let mut i18n = i18n();
+        assert_eq!(i18n.t("greeting"), "greeting");
+
+        i18n.set_language("en")?;
+        assert_eq!(i18n.t("greeting"), "Hello!");
+        assert_eq!(i18n.t("missing"), "missing");
+        assert!(i18n.set_language("fr").is_err());
+        assert_eq!(i18n.language(), Some("en"));
+        Ok(())
+    }
+
+    #[test]
+    fn test_format_substitution() -> Result<(), TetronError> {
+        let mut i18n = i18n();
+        i18n.set_language("en")?;
+        let args = HashMap::from([
+            ("name".to_string(), "Ada".to_string()),
+            ("points".to_string(), "42".to_string()),
+        ]);
+        assert_eq!(i18n.t_fmt("score", &args), "Ada scored 42 points");
+
+        let partial = HashMap::from([("name".to_string(), "Ada".to_string())]);
+        assert_eq!(i18n.t_fmt("score", &partial), "Ada scored {points} points");
+        Ok(())
+    }
+}
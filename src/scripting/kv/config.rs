@@ -1,26 +1,30 @@
 use std::sync::Arc;
 
-use rune::{ContextError, Module, Value};
+use rune::{ContextError, Module, Value, docstring};
 use stupid_simple_kv::Kv;
 
-use super::utils::{kv_value_to_rune, rune_vec_to_kv_key};
+use super::utils::{config_value_to_rune, rune_vec_to_kv_key};
+use crate::error::{EngineBug, ErrorMode};
 
-pub fn module(config: Arc<Kv>) -> Result<Module, ContextError> {
+pub fn module(config: Arc<Kv>, mode: ErrorMode) -> Result<Module, ContextError> {
     let mut module = Module::with_crate_item("tetron", ["config"])?;
     let getter = config.clone();
 
     module
         .function("get", move |key_array: Vec<Value>| -> Option<Value> {
             let kv_key =
-                rune_vec_to_kv_key(key_array).expect("Engine bug: failed to convert key array");
-            let val = getter
+                rune_vec_to_kv_key(key_array).engine_bug("failed to convert key array", mode)?;
+            let value = getter
                 .get(&kv_key)
-                .expect("Engine bug: failed to get from config");
-            val.map(|value| {
-                kv_value_to_rune(&value).expect("Engine bug: failed to convert value to rune")
-            })
+                .engine_bug("failed to get from config", mode)??;
+            config_value_to_rune(&value).engine_bug("failed to convert value to rune", mode)
         })
-        .build()?;
+        .build()?
+        .docs(docstring! {
+            /// Read the game.json value under `key`, or `None` if it isn't set.
+            /// Objects with exactly two number fields, `x` and `y`, are returned
+            /// as `Vec2`s, including inside arrays and other objects.
+        })?;
 
     Ok(module)
 }
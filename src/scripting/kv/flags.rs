@@ -1,68 +1,1188 @@
-use std::sync::{Arc, RwLock};
+use std::{
+    collections::{BTreeMap, BTreeSet, btree_map::Entry},
+    sync::{Arc, Mutex, MutexGuard, RwLock, RwLockReadGuard, RwLockWriteGuard},
+};
 
-use rune::{ContextError, Module, Value};
-use stupid_simple_kv::Kv;
+use rune::{
+    ContextError, Module, Value,
+    alloc::clone::TryClone,
+    docstring,
+    runtime::{Function, VmResult},
+};
+use stupid_simple_kv::{IntoKey, Kv, KvKey, KvValue};
 
-use super::utils::{kv_value_to_rune, rune_value_to_kv, rune_vec_to_kv_key};
+use super::utils::{kv_value_to_rune, push_rune_key_parts, rune_value_to_kv};
+use crate::{
+    engine::world::WorldRef,
+    error::{EngineBug, ErrorMode, IntoVmResult, TetronError},
+    kv::cache::FlushHandle,
+    system_log,
+    utils::Registrable,
+};
 
-pub fn module(flags: Arc<RwLock<Kv>>) -> Result<Module, ContextError> {
+/// Where the version of the game's flags layout is kept, for migrations.
+const VERSION_KEY: (&str, &str) = ("tetron", "flags_version");
+
+/// First key part of every flag stored in a named slot.
+const SLOT_PREFIX: &str = "__slot__";
+
+/// The save slot that scripts' flags are read from and written to. Flags in a
+/// named slot are stored under `("__slot__", name, ...key)`, while the default
+/// slot uses keys as given.
+#[derive(Default)]
+pub struct FlagSlot(Mutex<Option<String>>);
+
+impl FlagSlot {
+    /// Switch to the slot called `name`, or back to the default with `None`.
+    pub fn select(&self, name: Option<String>) -> Result<(), TetronError> {
+        *self
+            .0
+            .lock()
+            .map_err(|e| TetronError::KvError(format!("flag slot lock poisoned: {e}")))? = name;
+        Ok(())
+    }
+
+    /// The name of the current slot, or `None` for the default.
+    pub fn current(&self) -> Result<Option<String>, TetronError> {
+        Ok(self
+            .0
+            .lock()
+            .map_err(|e| TetronError::KvError(format!("flag slot lock poisoned: {e}")))?
+            .clone())
+    }
+
+    /// The key every flag in the current slot starts with. Empty for the
+    /// default slot.
+    pub fn prefix(&self) -> Result<KvKey, TetronError> {
+        let mut key = KvKey::new();
+        if let Some(name) = self.current()? {
+            key.push(&SLOT_PREFIX);
+            key.push(&name);
+        }
+        Ok(key)
+    }
+
+    /// The key a script's `key_array` is stored under in the current slot.
+    pub fn key(&self, key_array: Vec<Value>) -> Result<KvKey, TetronError> {
+        let mut key = self.prefix()?;
+        push_rune_key_parts(&mut key, key_array)?;
+        Ok(key)
+    }
+}
+
+/// The game's flags as scripts see them, along with the slot they're using.
+/// Created by the game and shared with the `flags` and `save` modules, so
+/// both read and write through this rather than the KV itself, and `save`
+/// slots live inside the current flag slot.
+#[derive(Clone)]
+pub struct Flags {
+    kv: Arc<RwLock<Kv>>,
+    slot: Arc<FlagSlot>,
+    journal: Arc<Mutex<Option<Journal>>>,
+}
+
+/// While a migration runs, the value every key it writes had before it
+/// started, so a failed migration can be undone.
+type Journal = BTreeMap<KvKey, Option<KvValue>>;
+
+impl Flags {
+    /// Script access to `kv`.
+    pub fn new(kv: Arc<RwLock<Kv>>) -> Self {
+        Self {
+            kv,
+            slot: Arc::new(FlagSlot::default()),
+            journal: Arc::new(Mutex::new(None)),
+        }
+    }
+
+    /// The flag slot in use. Keys passed to the other methods are full keys,
+    /// so build them from [`FlagSlot::prefix`] or [`FlagSlot::key`] to stay
+    /// inside it.
+    pub fn slot(&self) -> &FlagSlot {
+        &self.slot
+    }
+
+    fn read(&self) -> Result<RwLockReadGuard<'_, Kv>, TetronError> {
+        self.kv
+            .read()
+            .map_err(|e| TetronError::KvError(format!("flags lock poisoned: {e}")))
+    }
+
+    fn write(&self) -> Result<RwLockWriteGuard<'_, Kv>, TetronError> {
+        self.kv
+            .write()
+            .map_err(|e| TetronError::KvError(format!("flags lock poisoned: {e}")))
+    }
+
+    pub fn get(&self, key: &dyn IntoKey) -> Result<Option<KvValue>, TetronError> {
+        Ok(self.read()?.get(key)?)
+    }
+
+    /// If a migration is running, remember the current values of the keys
+    /// from `keys` that it hasn't written yet.
+    fn journal(
+        &self,
+        kv: &Kv,
+        keys: impl FnOnce() -> Result<Vec<KvKey>, TetronError>,
+    ) -> Result<(), TetronError> {
+        let mut journal = lock(&self.journal, "flags journal")?;
+        let Some(journal) = journal.as_mut() else {
+            return Ok(());
+        };
+        for key in keys()? {
+            if let Entry::Vacant(entry) = journal.entry(key) {
+                let old = kv.get(entry.key())?;
+                entry.insert(old);
+            }
+        }
+        Ok(())
+    }
+
+    pub fn set(&self, key: &dyn IntoKey, value: KvValue) -> Result<(), TetronError> {
+        let mut kv = self.write()?;
+        self.journal(&kv, || Ok(vec![key.to_key()]))?;
+        kv.set(key, value)?;
+        Ok(())
+    }
+
+    pub fn delete(&self, key: &dyn IntoKey) -> Result<(), TetronError> {
+        let mut kv = self.write()?;
+        self.journal(&kv, || Ok(vec![key.to_key()]))?;
+        kv.delete(key)?;
+        Ok(())
+    }
+
+    /// Every key starting with `prefix`, in order.
+    pub fn keys(&self, prefix: &dyn IntoKey) -> Result<Vec<KvKey>, TetronError> {
+        let entries = self.read()?.list().prefix(prefix).entries()?;
+        Ok(entries.into_iter().map(|(key, _)| key).collect())
+    }
+
+    /// Delete every flag whose key starts with `prefix`.
+    pub fn delete_all(&self, prefix: &dyn IntoKey) -> Result<(), TetronError> {
+        let mut kv = self.write()?;
+        let keys: Vec<KvKey> = kv
+            .list()
+            .prefix(prefix)
+            .entries()?
+            .into_iter()
+            .map(|(key, _)| key)
+            .collect();
+        self.journal(&kv, || Ok(keys.clone()))?;
+        for key in keys {
+            kv.delete(&key)?;
+        }
+        Ok(())
+    }
+
+    /// Delete every flag in every slot.
+    pub fn clear(&self) -> Result<(), TetronError> {
+        let mut kv = self.write()?;
+        self.journal(&kv, || {
+            Ok(kv
+                .list()
+                .entries()?
+                .into_iter()
+                .map(|(key, _)| key)
+                .collect())
+        })?;
+        kv.clear()?;
+        Ok(())
+    }
+
+    /// See [`get_many`].
+    pub fn get_many(&self, keys: &[KvKey]) -> Result<Vec<Option<KvValue>>, TetronError> {
+        get_many(&*self.read()?, keys)
+    }
+
+    /// See [`set_many`].
+    pub fn set_many(&self, entries: Vec<(KvKey, KvValue)>) -> Result<(), TetronError> {
+        let mut kv = self.write()?;
+        self.journal(&kv, || {
+            Ok(entries.iter().map(|(key, _)| key.clone()).collect())
+        })?;
+        set_many(&mut kv, entries)
+    }
+
+    /// The names of every slot holding at least one flag, sorted.
+    pub fn list_slots(&self) -> Result<Vec<String>, TetronError> {
+        let mut slots = BTreeSet::new();
+        for key in self.keys(&(SLOT_PREFIX,))? {
+            let (_, name) = <(String, String)>::try_from(key)?;
+            slots.insert(name);
+        }
+        Ok(slots.into_iter().collect())
+    }
+
+    /// Delete every flag stored in the slot called `name`.
+    pub fn delete_slot(&self, name: &str) -> Result<(), TetronError> {
+        self.delete_all(&(SLOT_PREFIX, name))
+    }
+
+    /// Call `handler` with `(new, old)` whenever the value under `key`
+    /// changes. The watch is kept on `world`, which checks it every frame.
+    pub fn watch(
+        &self,
+        world: &WorldRef,
+        key: KvKey,
+        handler: Function,
+    ) -> Result<WatchHandle, TetronError> {
+        let last = self.get(&key)?;
+        let error_mode = world.error_mode();
+        let mut hooks = world.flag_hooks()?;
+        let id = hooks.next_watch_id;
+        hooks.next_watch_id += 1;
+        hooks.watches.push(Watch {
+            id,
+            key,
+            last,
+            handler,
+        });
+        Ok(WatchHandle {
+            id,
+            world: world.clone(),
+            error_mode,
+        })
+    }
+
+    /// Compare every flag watched on `world` to its last seen value and call
+    /// the handlers of the ones that changed. Run once per frame. A handler
+    /// that fails is logged, and the rest still run.
+    pub fn poll_watches(&self, world: &WorldRef) -> Result<(), TetronError> {
+        let mut changed = Vec::new();
+        {
+            let kv = self.read()?;
+            let mut hooks = world.flag_hooks()?;
+            for watch in hooks.watches.iter_mut() {
+                let current = kv.get(&watch.key)?;
+                if current != watch.last {
+                    let old = std::mem::replace(&mut watch.last, current.clone());
+                    changed.push((watch.handler.try_clone()?, current, old));
+                }
+            }
+        }
+
+        // Handlers may set flags or add and remove watches, so nothing can be
+        // locked while they run.
+        let to_rune = |value: &Option<KvValue>| match value {
+            Some(value) => kv_value_to_rune(value),
+            None => Ok(Value::empty()),
+        };
+        for (handler, new, old) in changed {
+            if let Err(e) = handler
+                .call::<()>((to_rune(&new)?, to_rune(&old)?))
+                .into_result()
+            {
+                system_log!("flags::on_change handler error: {e}");
+            }
+        }
+        Ok(())
+    }
+
+    /// The version recorded by the last migration that ran, or 0 if none has.
+    pub fn current_version(&self) -> Result<i64, TetronError> {
+        current_version(&*self.read()?)
+    }
+
+    /// Register `migration` on `world` as the upgrade to layout `version`,
+    /// to be run by [`Flags::run_migrations`]. Versions start at 1, and each
+    /// can only be registered once.
+    pub fn add_migration(
+        &self,
+        world: &WorldRef,
+        version: i64,
+        migration: Function,
+    ) -> Result<(), TetronError> {
+        if version < 1 {
+            return Err(TetronError::Runtime(format!(
+                "flags::migrate: version {version} is below 1"
+            )));
+        }
+        let migrations = &mut world.flag_hooks()?.migrations;
+        if migrations.contains_key(&version) {
+            return Err(TetronError::Runtime(format!(
+                "flags::migrate: version {version} registered twice"
+            )));
+        }
+        migrations.insert(version, migration);
+        Ok(())
+    }
+
+    /// Run every migration registered on `world` that is newer than the
+    /// flags, in version order, recording each version as it completes.
+    /// Returns how many ran.
+    ///
+    /// The KV has no transactions, so while a migration runs the old value of
+    /// every key it writes is kept, and put back if it fails. Migrations
+    /// before it stay applied, and the version stays at the last one that
+    /// succeeded.
+    pub fn run_migrations(&self, world: &WorldRef) -> Result<i64, TetronError> {
+        let migrations = world
+            .flag_hooks()?
+            .migrations
+            .iter()
+            .map(|(version, migration)| Ok((*version, migration.try_clone()?)))
+            .collect::<Result<Vec<_>, TetronError>>()?;
+        for (expected, (version, _)) in (1..).zip(&migrations) {
+            if *version != expected {
+                return Err(TetronError::Runtime(format!(
+                    "flags::migrate: version {version} registered without version {expected}"
+                )));
+            }
+        }
+
+        let mut ran = 0;
+        for (version, migration) in migrations {
+            if self.current_version()? >= version {
+                continue;
+            }
+            *lock(&self.journal, "flags journal")? = Some(BTreeMap::new());
+            // Nothing is locked while the migration runs, so it can use
+            // `flags::get` and `flags::set`.
+            let result = migration.call::<()>(()).into_result();
+            let journal = lock(&self.journal, "flags journal")?
+                .take()
+                .unwrap_or_default();
+            let mut kv = self.write()?;
+            if let Err(e) = result {
+                for (key, old) in journal {
+                    match old {
+                        Some(value) => {
+                            kv.set(&key, value)?;
+                        }
+                        None => {
+                            kv.delete(&key)?;
+                        }
+                    }
+                }
+                return Err(TetronError::Runtime(format!(
+                    "flags migration to version {version} failed: {e}"
+                )));
+            }
+            kv.set(&VERSION_KEY, KvValue::I64(version))?;
+            ran += 1;
+        }
+        Ok(ran)
+    }
+}
+
+struct Watch {
+    id: u64,
+    key: KvKey,
+    last: Option<KvValue>,
+    handler: Function,
+}
+
+/// What scripts have registered through the flags module: `on_change`
+/// watches and `migrate` upgrades. Rune functions can't leave the thread
+/// running scripts, so these are kept on the world rather than the [`Flags`].
+#[derive(Default)]
+pub struct FlagHooks {
+    next_watch_id: u64,
+    watches: Vec<Watch>,
+    migrations: BTreeMap<i64, Function>,
+}
+
+fn lock<'a, T>(mutex: &'a Mutex<T>, what: &str) -> Result<MutexGuard<'a, T>, TetronError> {
+    mutex
+        .lock()
+        .map_err(|e| TetronError::KvError(format!("{what} lock poisoned: {e}")))
+}
+
+/// Returned by `flags::on_change`, used to stop watching.
+#[derive(Clone, rune::Any)]
+pub struct WatchHandle {
+    id: u64,
+    world: WorldRef,
+    /// The world's error mode, kept for when the world is already borrowed.
+    error_mode: ErrorMode,
+}
+
+impl WatchHandle {
+    /// Stop calling the handler. Returns false if it was already removed.
+    #[rune::function(instance, keep)]
+    pub fn remove(&self) -> bool {
+        let Some(mut hooks) = self
+            .world
+            .flag_hooks()
+            .engine_bug("failed to remove watch", self.error_mode)
+        else {
+            return false;
+        };
+        let watches = &mut hooks.watches;
+        let len = watches.len();
+        watches.retain(|watch| watch.id != self.id);
+        watches.len() != len
+    }
+}
+
+impl Registrable for WatchHandle {
+    fn register(module: &mut Module) -> Result<(), ContextError> {
+        module.ty::<WatchHandle>()?;
+        module.function_meta(WatchHandle::remove__meta)?;
+        Ok(())
+    }
+}
+
+/// Read several flags with a single backend query, where separate `get`s
+/// would make one each. The query covers every key from the smallest to the
+/// largest of `keys`, so it's cheapest when they share a prefix, like the
+/// fields of one entity.
+pub fn get_many(flags: &Kv, keys: &[KvKey]) -> Result<Vec<Option<KvValue>>, TetronError> {
+    let (Some(first), Some(last)) = (keys.iter().min(), keys.iter().max()) else {
+        return Ok(Vec::new());
+    };
+    let mut range = flags.list();
+    range.start(first);
+    if let Some(end) = last.successor() {
+        range.end(&end);
+    }
+    let found: BTreeMap<KvKey, KvValue> = range.entries()?.into_iter().collect();
+    Ok(keys.iter().map(|key| found.get(key).cloned()).collect())
+}
+
+/// Write several flags under one lock, so nothing else in the game sees
+/// them half set.
+///
+/// This isn't a backend transaction. `stupid_simple_kv` backends write one
+/// key per call, with no way to group calls, and its SQLite backend commits
+/// each write as a statement of its own. So this makes one read for the
+/// previous values (see [`get_many`]) and then one write per entry, which is
+/// no faster than separate `set`s.
+///
+/// If a write fails, the entries already written are put back to their
+/// previous values, newest first, and the error is returned. Those are
+/// single writes too, so if one of them fails, its error is returned instead
+/// and the batch is left partly written. A crash partway through also leaves
+/// it partly written, since nothing is journaled on disk.
+pub fn set_many(flags: &mut Kv, entries: Vec<(KvKey, KvValue)>) -> Result<(), TetronError> {
+    let keys = entries
+        .iter()
+        .map(|(key, _)| key.clone())
+        .collect::<Vec<_>>();
+    let previous = get_many(flags, &keys)?;
+
+    for (written, (key, value)) in entries.iter().enumerate() {
+        if let Err(e) = flags.set(key, value.clone()) {
+            for ((key, _), old) in entries.iter().zip(previous).take(written).rev() {
+                match old {
+                    Some(old) => flags.set(key, old)?,
+                    None => {
+                        flags.delete(key)?;
+                    }
+                }
+            }
+            return Err(e.into());
+        }
+    }
+    Ok(())
+}
+
+/// A `set_many` entry, either a `(key, value)` tuple or a `[key, value]` array.
+fn flag_entry(entry: Value) -> Result<(Vec<Value>, Value), TetronError> {
+    if let Ok(pair) = rune::from_value::<(Vec<Value>, Value)>(entry.clone()) {
+        return Ok(pair);
+    }
+    let items = rune::from_value::<Vec<Value>>(entry)?;
+    match <[Value; 2]>::try_from(items) {
+        Ok([key, value]) => Ok((rune::from_value(key)?, value)),
+        Err(items) => Err(TetronError::Conversion(format!(
+            "flags::set_many entries must be [key, value] pairs, got {} items",
+            items.len()
+        ))),
+    }
+}
+
+/// The version recorded by the last migration that ran, or 0 if none has.
+pub fn current_version(flags: &Kv) -> Result<i64, TetronError> {
+    match flags.get(&VERSION_KEY)? {
+        Some(version) => Ok(version.try_into()?),
+        None => Ok(0),
+    }
+}
+
+/// `flags::on_change` and its alias `flags::watch`.
+fn watch_function(
+    flags: Flags,
+    mode: ErrorMode,
+) -> impl Fn(&WorldRef, Vec<Value>, Function) -> VmResult<WatchHandle> + Send + Sync {
+    move |world, key_array, handler| {
+        let handle = flags
+            .slot()
+            .key(key_array)
+            .and_then(|kv_key| flags.watch(world, kv_key, handler))
+            .engine_bug("failed to watch flag", mode);
+        match handle {
+            Some(handle) => VmResult::Ok(handle),
+            None => VmResult::panic("Could not watch flag"),
+        }
+    }
+}
+
+/// `tetron::flags`: persistent key-value flags, in the current save slot.
+///
+/// The engine doesn't run flag migrations on its own. A game that registers
+/// them with `flags::migrate` runs them at startup by calling
+/// `flags::run_migrations(world)` itself, at the top of `begin` before any
+/// flags are read.
+pub fn module(flags: Flags, cache: FlushHandle, mode: ErrorMode) -> Result<Module, ContextError> {
     let mut module = Module::with_crate_item("tetron", ["flags"])?;
 
-    let setter = flags.clone();
-    let getter = flags.clone();
-    let remover = flags.clone();
-    let clearer = flags.clone();
+    WatchHandle::register(&mut module)?;
 
     module
-        .function("clear", move || {
-            clearer
-                .try_write()
-                .expect("Engine bug: flags lock poisoned")
-                .clear()
-                .expect("Engine bug: failed to clear flags");
+        .function("clear", {
+            let flags = flags.clone();
+            move || {
+                flags.clear().engine_bug("failed to clear flags", mode);
+            }
         })
         .build()?;
 
     module
-        .function("delete", move |key_array: Vec<Value>| {
-            let kv_key =
-                rune_vec_to_kv_key(key_array).expect("Engine bug: failed to convert key array");
-            remover
-                .try_write()
-                .expect("Engine bug: flags lock poisoned")
-                .delete(&kv_key)
-                .expect("Engine bug: failed to delete from flags");
+        .function("delete", {
+            let flags = flags.clone();
+            move |key_array: Vec<Value>| {
+                let Some(kv_key) = flags
+                    .slot()
+                    .key(key_array)
+                    .engine_bug("failed to convert key array", mode)
+                else {
+                    return;
+                };
+                flags
+                    .delete(&kv_key)
+                    .engine_bug("failed to delete from flags", mode);
+            }
         })
         .build()?;
 
     module
-        .function("get", move |key_array: Vec<Value>| -> Option<Value> {
-            let kv_key =
-                rune_vec_to_kv_key(key_array).expect("Engine bug: failed to convert key array");
-            let val = getter
-                .try_read()
-                .expect("Engine bug: flags lock poisoned")
-                .get(&kv_key)
-                .expect("Engine bug: failed to get from flags");
-            val.map(|value| {
-                kv_value_to_rune(&value).expect("Engine bug: failed to convert value to rune")
-            })
+        .function("get", {
+            let flags = flags.clone();
+            move |key_array: Vec<Value>| -> Option<Value> {
+                let kv_key = flags
+                    .slot()
+                    .key(key_array)
+                    .engine_bug("failed to convert key array", mode)?;
+                let value = flags
+                    .get(&kv_key)
+                    .engine_bug("failed to get from flags", mode)??;
+                kv_value_to_rune(&value).engine_bug("failed to convert value to rune", mode)
+            }
         })
         .build()?;
 
     module
-        .function("set", move |key_array: Vec<Value>, value: Value| {
-            let kv_value =
-                rune_value_to_kv(value).expect("Engine bug: failed to convert value to kv");
-            let kv_key =
-                rune_vec_to_kv_key(key_array).expect("Engine bug: failed to convert key array");
-            setter
-                .try_write()
-                .expect("Engine bug: flags lock poisoned")
-                .set(&kv_key, kv_value)
-                .expect("Engine bug: failed to set flags value");
+        .function("set", {
+            let flags = flags.clone();
+            move |key_array: Vec<Value>, value: Value| {
+                let (Some(kv_value), Some(kv_key)) = (
+                    rune_value_to_kv(value).engine_bug("failed to convert value to kv", mode),
+                    flags
+                        .slot()
+                        .key(key_array)
+                        .engine_bug("failed to convert key array", mode),
+                ) else {
+                    return;
+                };
+                flags
+                    .set(&kv_key, kv_value)
+                    .engine_bug("failed to set flags value", mode);
+            }
         })
         .build()?;
 
+    module
+        .function("get_many", {
+            let flags = flags.clone();
+            move |keys: Vec<Vec<Value>>| -> Vec<Value> {
+                let Some(keys) = keys
+                    .into_iter()
+                    .map(|key| flags.slot().key(key))
+                    .collect::<Result<Vec<_>, _>>()
+                    .engine_bug("failed to convert key array", mode)
+                else {
+                    return Vec::new();
+                };
+                flags
+                    .get_many(&keys)
+                    .engine_bug("failed to get from flags", mode)
+                    .unwrap_or_default()
+                    .into_iter()
+                    .map(|value| match value {
+                        Some(value) => kv_value_to_rune(&value)
+                            .engine_bug("failed to convert value to rune", mode)
+                            .unwrap_or_else(Value::empty),
+                        None => Value::empty(),
+                    })
+                    .collect()
+            }
+        })
+        .build()?
+        .docs(docstring! {
+            /// Get the flags under each of `keys` at once, in the same order.
+            /// Missing flags are `()`. Reads the backend once rather than once
+            /// per key, covering everything between the first and last key in
+            /// sort order, so batch keys that share a prefix, e.g.
+            /// `[["enemy", 1, "hp"], ["enemy", 1, "armor"]]`.
+        })?;
+
+    module
+        .function("set_many", {
+            let flags = flags.clone();
+            move |entries: Vec<Value>| -> Result<(), TetronError> {
+                let entries = entries
+                    .into_iter()
+                    .map(|entry| {
+                        let (key, value) = flag_entry(entry).map_err(|e| {
+                            TetronError::Runtime(format!(
+                                "Invalid entry passed to flags::set_many: {e}"
+                            ))
+                        })?;
+                        Ok((flags.slot().key(key)?, rune_value_to_kv(value)?))
+                    })
+                    .collect::<Result<Vec<_>, TetronError>>()?;
+                flags.set_many(entries)
+            }
+        })
+        .build()?
+        .docs(docstring! {
+            /// Set several flags at once from `[[key, value], ...]` pairs
+            /// (tuples work too). Nothing is written if any entry is invalid,
+            /// and an error is returned instead.
+            ///
+            /// This is not a transaction: each flag is still a separate write
+            /// to the backend, so it's no faster than separate `set`s. If a
+            /// write fails, the flags already written are set back to their
+            /// old values. The batch can still end up partly written if
+            /// undoing fails as well, or if the game crashes partway through.
+        })?;
+
+    module
+        .function("on_change", watch_function(flags.clone(), mode))
+        .build()?
+        .docs(docstring! {
+            /// Call `handler(new, old)` at the start of the next frame whenever
+            /// the flag under `key` changes. Missing values are passed as `()`.
+            /// The watch is kept on `world`, so pass the one given to `begin`:
+            /// `flags::on_change(world, ["score"], |new, old| ..)`.
+            ///
+            /// Returns a handle whose `remove()` stops watching. The watch stays
+            /// on the slot that was current when it was added. Errors in
+            /// `handler` are logged, and don't stop the frame.
+        })?;
+
+    module
+        .function("watch", watch_function(flags.clone(), mode))
+        .build()?
+        .docs(docstring! {
+            /// Same as `on_change`: call `handler(new, old)` for the flag under
+            /// `key` watched on `world`, at most once per frame when the value
+            /// differs from the last poll. Polls happen before systems run, so
+            /// several changes within one frame arrive as a single call. Each
+            /// watch costs one flag read per frame. Pass the returned handle to
+            /// `unwatch` to stop.
+        })?;
+
+    module
+        .function("unwatch", |handle: &WatchHandle| -> bool {
+            handle.remove()
+        })
+        .build()?
+        .docs(docstring! {
+            /// Stop a watch added with `watch` or `on_change`. Returns false
+            /// if it was already removed.
+        })?;
+
+    module
+        .function("use_slot", {
+            let flags = flags.clone();
+            move |name: &str| {
+                flags
+                    .slot()
+                    .select(Some(name.to_owned()))
+                    .engine_bug("failed to select flag slot", mode);
+            }
+        })
+        .build()?
+        .docs(docstring! {
+            /// Keep all following flags in the save slot called `name`, apart
+            /// from every other slot. `get`, `set`, `delete`, `get_many`,
+            /// `set_many` and `on_change` all use the current slot, while
+            /// `clear` still removes every slot. Until this is called, flags
+            /// are in the default slot, which isn't part of any named slot.
+            ///
+            /// `tetron::save` data is stored as flags too, so its numbered
+            /// save slots live inside the current flag slot: switching flag
+            /// slots switches to a separate set of save slots.
+        })?;
+
+    module
+        .function("use_default_slot", {
+            let flags = flags.clone();
+            move || {
+                flags
+                    .slot()
+                    .select(None)
+                    .engine_bug("failed to select flag slot", mode);
+            }
+        })
+        .build()?
+        .docs(docstring! {
+            /// Go back to the default slot, used before any `use_slot` call.
+        })?;
+
+    module
+        .function("current_slot", {
+            let flags = flags.clone();
+            move || -> Option<String> {
+                flags
+                    .slot()
+                    .current()
+                    .engine_bug("failed to read flag slot", mode)
+                    .flatten()
+            }
+        })
+        .build()?
+        .docs(docstring! {
+            /// The name of the current slot, or `None` in the default slot.
+        })?;
+
+    module
+        .function("list_slots", {
+            let flags = flags.clone();
+            move || -> Vec<String> {
+                flags
+                    .list_slots()
+                    .engine_bug("failed to list flag slots", mode)
+                    .unwrap_or_default()
+            }
+        })
+        .build()?
+        .docs(docstring! {
+            /// The names of all flag slots holding any flags, sorted. See
+            /// `save::list_slots` for the save slots in the current one.
+        })?;
+
+    module
+        .function("delete_slot", {
+            let flags = flags.clone();
+            move |name: &str| {
+                flags
+                    .delete_slot(name)
+                    .engine_bug("failed to delete flag slot", mode);
+            }
+        })
+        .build()?
+        .docs(docstring! {
+            /// Delete every flag in the slot called `name`, including any
+            /// `tetron::save` data kept in it.
+        })?;
+
+    module
+        .function("flush", move || {
+            cache
+                .flush()
+                .unwrap_or_else(|e| system_log!("flags::flush failed: {e}"));
+        })
+        .build()?
+        .docs(docstring! {
+            /// Write cached flag changes to disk now. With `flags.cache` on in
+            /// game.json, flags are otherwise written at most once a second and
+            /// when the game exits. Does nothing without the cache.
+        })?;
+
+    module
+        .function("current_version", {
+            let flags = flags.clone();
+            move || -> i64 {
+                flags
+                    .current_version()
+                    .engine_bug("failed to read flags version", mode)
+                    .unwrap_or_default()
+            }
+        })
+        .build()?
+        .docs(docstring! {
+            /// The flags layout version set by the last migration that ran,
+            /// or 0 for data that has never been migrated.
+        })?;
+
+    module
+        .function("migrate", {
+            let flags = flags.clone();
+            move |world: &WorldRef, version: i64, migration: Function| -> VmResult<()> {
+                flags
+                    .add_migration(world, version, migration)
+                    .into_vm_result()
+            }
+        })
+        .build()?
+        .docs(docstring! {
+            /// Register `migration()` on `world` as the upgrade of saved flags
+            /// to layout `version`. Versions start at 1 and can't be repeated;
+            /// registering one twice is an error. Nothing runs until the game
+            /// calls `flags::run_migrations(world)`.
+        })?;
+
+    module
+        .function("run_migrations", move |world: &WorldRef| -> VmResult<i64> {
+            flags.run_migrations(world).into_vm_result()
+        })
+        .build()?
+        .docs(docstring! {
+            /// Run the migrations registered on `world` that are newer than the
+            /// saved flags, in version order. Call before reading any flags,
+            /// e.g. at the top of `begin`:
+            ///
+            /// ```rune,ignore
+            /// flags::migrate(world, 1, || flags::set(["hp"], flags::get(["health"])));
+            /// flags::migrate(world, 2, || flags::delete(["health"]));
+            /// flags::run_migrations(world);
+            /// ```
+            ///
+            /// The engine never calls this for you. If a version was skipped or
+            /// a migration fails, the error is thrown at the caller; a failed
+            /// migration's changes are undone first, leaving the flags at the
+            /// last version that succeeded. Returns how many migrations ran.
+        })?;
+
     Ok(module)
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::Mutex;
+    use stupid_simple_kv::{IntoKey, MemoryBackend};
+
+    fn memory_flags() -> Flags {
+        Flags::new(Arc::new(RwLock::new(Kv::new(Box::new(
+            MemoryBackend::new(),
+        )))))
+    }
+
+    #[test]
+    fn test_migrations_run_in_order() -> Result<(), TetronError> {
+        let flags = memory_flags();
+        let world = WorldRef::new();
+        flags.set(&("health",), KvValue::I64(3))?;
+        assert_eq!(flags.current_version()?, 0);
+
+        let ran = Arc::new(Mutex::new(Vec::new()));
+        let rename = Function::new({
+            let (flags, ran) = (flags.clone(), ran.clone());
+            move || {
+                ran.lock().unwrap().push(1);
+                let health = flags.get(&("health",)).unwrap().unwrap();
+                flags.delete(&("health",)).unwrap();
+                flags.set(&("hp",), health).unwrap();
+            }
+        });
+        let double = Function::new({
+            let (flags, ran) = (flags.clone(), ran.clone());
+            move || {
+                ran.lock().unwrap().push(2);
+                let hp = i64::try_from(flags.get(&("hp",)).unwrap().unwrap()).unwrap();
+                flags.set(&("hp",), KvValue::I64(hp * 2)).unwrap();
+            }
+        });
+
+        // Registering runs nothing, and the order doesn't matter.
+        flags.add_migration(&world, 2, double)?;
+        flags.add_migration(&world, 1, rename)?;
+        assert!(ran.lock().unwrap().is_empty());
+        assert_eq!(flags.run_migrations(&world)?, 2);
+        // Already at version 2, so running them again does nothing.
+        assert_eq!(flags.run_migrations(&world)?, 0);
+
+        assert_eq!(*ran.lock().unwrap(), vec![1, 2]);
+        assert_eq!(flags.current_version()?, 2);
+        assert_eq!(flags.get(&("hp",))?, Some(KvValue::I64(6)));
+        assert_eq!(flags.get(&("health",))?, None);
+        Ok(())
+    }
+
+    #[test]
+    fn test_migration_versions_are_checked() -> Result<(), TetronError> {
+        let flags = memory_flags();
+        let world = WorldRef::new();
+        flags.add_migration(&world, 2, Function::new(|| {}))?;
+        assert!(
+            flags
+                .add_migration(&world, 2, Function::new(|| {}))
+                .is_err()
+        );
+        assert!(
+            flags
+                .add_migration(&world, 0, Function::new(|| {}))
+                .is_err()
+        );
+
+        let Err(e) = flags.run_migrations(&world) else {
+            panic!("ran version 2 without version 1");
+        };
+        assert!(e.to_string().contains("without version 1"));
+        assert_eq!(flags.current_version()?, 0);
+        Ok(())
+    }
+
+    #[test]
+    fn test_failed_migration_is_undone() -> Result<(), TetronError> {
+        let flags = memory_flags();
+        let world = WorldRef::new();
+        flags.set(&("hp",), KvValue::I64(3))?;
+        flags.set(&("gold",), KvValue::I64(10))?;
+        flags.add_migration(&world, 1, Function::new(|| {}))?;
+        // Returning a value where the migration should return nothing makes
+        // the call fail after its writes.
+        flags.add_migration(
+            &world,
+            2,
+            Function::new({
+                let flags = flags.clone();
+                move || -> i64 {
+                    flags.set(&("hp",), KvValue::I64(0)).unwrap();
+                    flags.set(&("hp",), KvValue::I64(1)).unwrap();
+                    flags.delete(&("gold",)).unwrap();
+                    flags.set(&("new",), KvValue::I64(1)).unwrap();
+                    0
+                }
+            }),
+        )?;
+
+        assert!(flags.run_migrations(&world).is_err());
+        assert_eq!(flags.current_version()?, 1);
+        assert_eq!(flags.get(&("hp",))?, Some(KvValue::I64(3)));
+        assert_eq!(flags.get(&("gold",))?, Some(KvValue::I64(10)));
+        assert_eq!(flags.get(&("new",))?, None);
+
+        // Writes after the migration aren't journaled.
+        flags.set(&("hp",), KvValue::I64(4))?;
+        assert!(lock(&flags.journal, "flags journal")?.is_none());
+        Ok(())
+    }
+
+    #[test]
+    fn test_get_and_set_many() -> Result<(), TetronError> {
+        let mut flags = Kv::new(Box::new(MemoryBackend::new()));
+        set_many(
+            &mut flags,
+            vec![
+                (("enemy", 1i64, "hp").to_key(), KvValue::I64(10)),
+                (("enemy", 2i64, "hp").to_key(), KvValue::I64(4)),
+            ],
+        )?;
+
+        let keys = [
+            ("enemy", 2i64, "hp").to_key(),
+            ("enemy", 3i64, "hp").to_key(),
+            ("enemy", 1i64, "hp").to_key(),
+        ];
+        assert_eq!(
+            get_many(&flags, &keys)?,
+            vec![Some(KvValue::I64(4)), None, Some(KvValue::I64(10))]
+        );
+        Ok(())
+    }
+
+    /// A memory backend that counts the queries made to it.
+    struct CountingBackend {
+        inner: MemoryBackend,
+        reads: Arc<Mutex<usize>>,
+    }
+
+    impl stupid_simple_kv::KvBackend for CountingBackend {
+        fn get_range(
+            &self,
+            start: Option<KvKey>,
+            end: Option<KvKey>,
+        ) -> stupid_simple_kv::KvResult<Vec<(KvKey, Vec<u8>)>> {
+            *self.reads.lock().unwrap() += 1;
+            self.inner.get_range(start, end)
+        }
+
+        fn set(&mut self, key: KvKey, value: Option<Vec<u8>>) -> stupid_simple_kv::KvResult<()> {
+            self.inner.set(key, value)
+        }
+
+        fn clear(&mut self) -> stupid_simple_kv::KvResult<()> {
+            self.inner.clear()
+        }
+    }
+
+    #[test]
+    fn test_get_many_reads_backend_once() -> Result<(), TetronError> {
+        let reads = Arc::new(Mutex::new(0));
+        let mut flags = Kv::new(Box::new(CountingBackend {
+            inner: MemoryBackend::new(),
+            reads: reads.clone(),
+        }));
+        for i in 0..10i64 {
+            flags.set(&("enemy", i, "hp"), KvValue::I64(i))?;
+        }
+        // Keys outside the batch, and ones that only start with a batch key,
+        // aren't returned.
+        flags.set(&("enemy", 3i64, "hp", "max"), KvValue::I64(99))?;
+        flags.set(&("player", "hp"), KvValue::I64(5))?;
+
+        *reads.lock().unwrap() = 0;
+        let keys = [
+            ("enemy", 7i64, "hp").to_key(),
+            ("enemy", 3i64, "hp").to_key(),
+            ("enemy", 20i64, "hp").to_key(),
+        ];
+        assert_eq!(
+            get_many(&flags, &keys)?,
+            vec![Some(KvValue::I64(7)), Some(KvValue::I64(3)), None]
+        );
+        assert_eq!(*reads.lock().unwrap(), 1);
+        assert!(get_many(&flags, &[])?.is_empty());
+        Ok(())
+    }
+
+    #[test]
+    fn test_set_many_hundred_pairs() -> Result<(), TetronError> {
+        let mut flags = Kv::new(Box::new(MemoryBackend::new()));
+        let entries = (0..100i64)
+            .map(|i| (("item", i).to_key(), KvValue::I64(i * i)))
+            .collect();
+        set_many(&mut flags, entries)?;
+
+        for i in 0..100i64 {
+            assert_eq!(flags.get(&("item", i))?, Some(KvValue::I64(i * i)));
+        }
+        assert_eq!(flags.list().prefix(&("item",)).entries()?.len(), 100);
+
+        let array = rune::to_value(vec![
+            rune::to_value(vec![rune::to_value("item")?])?,
+            rune::to_value(1i64)?,
+        ])?;
+        let (key, value) = flag_entry(array)?;
+        assert_eq!(key.len(), 1);
+        assert_eq!(value.as_integer::<i64>()?, 1);
+        assert!(flag_entry(rune::to_value(vec![rune::to_value(1i64)?])?).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_slots_are_independent() -> Result<(), TetronError> {
+        let flags = Flags::new(Arc::new(RwLock::new(Kv::new(Box::new(
+            MemoryBackend::new(),
+        )))));
+        let slot = flags.slot();
+        let hp = || vec![rune::to_value("hp").unwrap()];
+
+        flags.set(&slot.key(hp())?, KvValue::I64(1))?;
+        slot.select(Some("first".into()))?;
+        flags.set(&slot.key(hp())?, KvValue::I64(10))?;
+        slot.select(Some("second".into()))?;
+        assert_eq!(flags.get(&slot.key(hp())?)?, None);
+        flags.set(&slot.key(hp())?, KvValue::I64(20))?;
+
+        slot.select(Some("first".into()))?;
+        assert_eq!(flags.get(&slot.key(hp())?)?, Some(KvValue::I64(10)));
+        slot.select(None)?;
+        assert_eq!(flags.get(&slot.key(hp())?)?, Some(KvValue::I64(1)));
+        assert_eq!(flags.list_slots()?, vec!["first", "second"]);
+
+        flags.delete_slot("first")?;
+        assert_eq!(flags.list_slots()?, vec!["second"]);
+        assert_eq!(flags.get(&("hp",))?, Some(KvValue::I64(1)));
+        slot.select(Some("second".into()))?;
+        assert_eq!(flags.get(&slot.key(hp())?)?, Some(KvValue::I64(20)));
+        Ok(())
+    }
+
+    #[test]
+    fn test_watch_fires_once_per_change() -> Result<(), TetronError> {
+        let flags = memory_flags();
+        let world = WorldRef::new();
+        let calls = Arc::new(Mutex::new(0));
+        let handler = Function::new({
+            let calls = calls.clone();
+            move |_new: Value, _old: Value| *calls.lock().unwrap() += 1
+        });
+        let handle = flags.watch(&world, ("hp",).to_key(), handler)?;
+
+        for hp in 1..=3 {
+            flags.set(&("hp",), KvValue::I64(hp))?;
+            flags.poll_watches(&world)?;
+            flags.poll_watches(&world)?;
+        }
+        assert_eq!(*calls.lock().unwrap(), 3);
+
+        // A change back and forth between polls isn't seen.
+        flags.set(&("hp",), KvValue::I64(9))?;
+        flags.set(&("hp",), KvValue::I64(3))?;
+        flags.poll_watches(&world)?;
+        assert_eq!(*calls.lock().unwrap(), 3);
+
+        assert!(handle.remove());
+        flags.delete(&("hp",))?;
+        flags.poll_watches(&world)?;
+        assert_eq!(*calls.lock().unwrap(), 3);
+        Ok(())
+    }
+
+    #[test]
+    fn test_on_change() -> Result<(), TetronError> {
+        let flags = memory_flags();
+        let world = WorldRef::new();
+        flags.set(&("score",), KvValue::I64(1))?;
+
+        let calls = Arc::new(Mutex::new(Vec::new()));
+        let handler = Function::new({
+            let calls = calls.clone();
+            move |new: Value, old: Value| {
+                calls
+                    .lock()
+                    .unwrap()
+                    .push((new.as_integer::<i64>().ok(), old.as_integer::<i64>().ok()));
+            }
+        });
+        let handle = flags.watch(&world, ("score",).to_key(), handler)?;
+
+        // Setting the same value isn't a change.
+        flags.set(&("score",), KvValue::I64(1))?;
+        flags.poll_watches(&world)?;
+        assert!(calls.lock().unwrap().is_empty());
+
+        flags.set(&("score",), KvValue::I64(5))?;
+        flags.poll_watches(&world)?;
+        flags.poll_watches(&world)?;
+        assert_eq!(*calls.lock().unwrap(), vec![(Some(5), Some(1))]);
+
+        assert!(handle.remove());
+        assert!(!handle.remove());
+        flags.set(&("score",), KvValue::I64(6))?;
+        flags.poll_watches(&world)?;
+        assert_eq!(calls.lock().unwrap().len(), 1);
+        Ok(())
+    }
+
+    #[test]
+    fn test_failing_handler_keeps_other_watches() -> Result<(), TetronError> {
+        let flags = memory_flags();
+        let world = WorldRef::new();
+        // Handlers are called with two arguments, so this one always fails.
+        flags.watch(&world, ("hp",).to_key(), Function::new(|| {}))?;
+        let calls = Arc::new(Mutex::new(0));
+        flags.watch(
+            &world,
+            ("hp",).to_key(),
+            Function::new({
+                let calls = calls.clone();
+                move |_new: Value, _old: Value| *calls.lock().unwrap() += 1
+            }),
+        )?;
+
+        flags.set(&("hp",), KvValue::I64(1))?;
+        flags.poll_watches(&world)?;
+        flags.set(&("hp",), KvValue::I64(2))?;
+        flags.poll_watches(&world)?;
+        assert_eq!(*calls.lock().unwrap(), 2);
+        Ok(())
+    }
+}
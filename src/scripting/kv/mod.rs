@@ -1,3 +1,4 @@
 pub mod config;
 pub mod flags;
+pub mod save;
 mod utils;
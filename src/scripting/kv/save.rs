@@ -0,0 +1,264 @@
+use std::{
+    collections::BTreeSet,
+    sync::{
+        Arc,
+        atomic::{AtomicI64, Ordering},
+    },
+};
+
+use rune::{ContextError, Module, Value, docstring};
+use stupid_simple_kv::{KvKey, KvValue};
+
+use super::{
+    flags::Flags,
+    utils::{kv_value_to_rune, rune_value_to_kv},
+};
+use crate::error::{EngineBug, ErrorMode, TetronError};
+
+/// Slot-based save data, stored in the flags under `["save", slot, key]`.
+/// Like any other flag, that key is inside the current flag slot (see
+/// `flags::use_slot`), so each flag slot has its own numbered save slots.
+pub struct SaveSlots {
+    flags: Flags,
+    slot: AtomicI64,
+}
+
+impl SaveSlots {
+    pub fn new(flags: Flags) -> Self {
+        Self {
+            flags,
+            slot: AtomicI64::new(0),
+        }
+    }
+
+    pub fn set_slot(&self, slot: i64) {
+        self.slot.store(slot, Ordering::Relaxed);
+    }
+
+    pub fn slot(&self) -> i64 {
+        self.slot.load(Ordering::Relaxed)
+    }
+
+    /// `["save"]` in the current flag slot, followed by `slot` and `key` if
+    /// given.
+    fn key(&self, slot: Option<i64>, key: Option<&str>) -> Result<KvKey, TetronError> {
+        let mut full = self.flags.slot().prefix()?;
+        full.push(&"save");
+        if let Some(slot) = slot {
+            full.push(&slot);
+        }
+        if let Some(key) = key {
+            full.push(&key);
+        }
+        Ok(full)
+    }
+
+    pub fn write(&self, key: &str, value: KvValue) -> Result<(), TetronError> {
+        self.flags
+            .set(&self.key(Some(self.slot()), Some(key))?, value)
+    }
+
+    pub fn read(&self, key: &str) -> Result<Option<KvValue>, TetronError> {
+        self.flags.get(&self.key(Some(self.slot()), Some(key))?)
+    }
+
+    pub fn save_exists(&self, slot: i64) -> Result<bool, TetronError> {
+        Ok(!self.flags.keys(&self.key(Some(slot), None)?)?.is_empty())
+    }
+
+    pub fn delete_slot(&self, slot: i64) -> Result<(), TetronError> {
+        self.flags.delete_all(&self.key(Some(slot), None)?)
+    }
+
+    /// Every slot holding save data, in ascending order. Flags under
+    /// `["save"]` that a script set by hand, without a numbered slot, are
+    /// skipped.
+    pub fn list_slots(&self) -> Result<Vec<i64>, TetronError> {
+        // Keys in a named flag slot start with `("__slot__", name)`.
+        let named = self.flags.slot().current()?.is_some();
+        let slots: BTreeSet<i64> = self
+            .flags
+            .keys(&self.key(None, None)?)?
+            .into_iter()
+            .filter_map(|key| {
+                if named {
+                    <(String, String, String, i64)>::try_from(key)
+                        .ok()
+                        .map(|key| key.3)
+                } else {
+                    <(String, i64)>::try_from(key).ok().map(|key| key.1)
+                }
+            })
+            .collect();
+        Ok(slots.into_iter().collect())
+    }
+}
+
+pub fn module(flags: Flags, mode: ErrorMode) -> Result<Module, ContextError> {
+    let mut module = Module::with_crate_item("tetron", ["save"])?;
+    let save = Arc::new(SaveSlots::new(flags));
+
+    module
+        .function("set_slot", {
+            let save = save.clone();
+            move |slot: i64| save.set_slot(slot)
+        })
+        .build()?
+        .docs(docstring! {
+            /// Select the save slot used by `write` and `read`. Defaults to 0.
+            /// Save slots are kept inside the current `flags::use_slot` slot,
+            /// so each flag slot has its own set of them.
+        })?;
+
+    module
+        .function("write", {
+            let save = save.clone();
+            move |key: &str, value: Value| {
+                rune_value_to_kv(value)
+                    .and_then(|value| save.write(key, value))
+                    .engine_bug("failed to write save data", mode);
+            }
+        })
+        .build()?
+        .docs(docstring! {
+            /// Store a value under `key` in the current save slot.
+        })?;
+
+    module
+        .function("read", {
+            let save = save.clone();
+            move |key: &str| -> Option<Value> {
+                save.read(key)
+                    .and_then(|value| value.as_ref().map(kv_value_to_rune).transpose())
+                    .engine_bug("failed to read save data", mode)
+                    .flatten()
+            }
+        })
+        .build()?
+        .docs(docstring! {
+            /// Read the value stored under `key` in the current save slot.
+        })?;
+
+    module
+        .function("save_exists", {
+            let save = save.clone();
+            move |slot: i64| -> bool {
+                save.save_exists(slot)
+                    .engine_bug("failed to check save slot", mode)
+                    .unwrap_or(false)
+            }
+        })
+        .build()?
+        .docs(docstring! {
+            /// Returns true if any data has been saved in `slot`.
+        })?;
+
+    module
+        .function("delete_slot", {
+            let save = save.clone();
+            move |slot: i64| {
+                save.delete_slot(slot)
+                    .engine_bug("failed to delete save slot", mode);
+            }
+        })
+        .build()?
+        .docs(docstring! {
+            /// Remove all data saved in `slot`.
+        })?;
+
+    module
+        .function("list_slots", {
+            let save = save.clone();
+            move || -> Vec<i64> {
+                save.list_slots()
+                    .engine_bug("failed to list save slots", mode)
+                    .unwrap_or_default()
+            }
+        })
+        .build()?
+        .docs(docstring! {
+            /// List all slots that contain save data, in ascending order, in
+            /// the current flag slot. `flags::list_slots` lists flag slots.
+        })?;
+
+    Ok(module)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::RwLock;
+    use stupid_simple_kv::{Kv, MemoryBackend};
+
+    fn memory_flags() -> Flags {
+        Flags::new(Arc::new(RwLock::new(Kv::new(Box::new(
+            MemoryBackend::new(),
+        )))))
+    }
+
+    #[test]
+    fn test_slots_are_isolated() -> Result<(), TetronError> {
+        let save = SaveSlots::new(memory_flags());
+
+        save.set_slot(1);
+        save.write("level", KvValue::I64(3))?;
+        save.write("name", KvValue::String("hero".into()))?;
+        assert_eq!(save.read("level")?, Some(KvValue::I64(3)));
+        assert!(save.save_exists(1)?);
+        assert_eq!(save.list_slots()?, vec![1]);
+
+        save.set_slot(2);
+        assert_eq!(save.read("level")?, None);
+        assert!(!save.save_exists(2)?);
+
+        save.delete_slot(1)?;
+        assert!(!save.save_exists(1)?);
+        save.set_slot(1);
+        assert_eq!(save.read("level")?, None);
+        assert_eq!(save.read("name")?, None);
+        assert!(save.list_slots()?.is_empty());
+        Ok(())
+    }
+
+    #[test]
+    fn test_list_slots_skips_other_keys() -> Result<(), TetronError> {
+        let flags = memory_flags();
+        flags.set(&("save", "notes"), KvValue::String("not a slot".into()))?;
+        flags.set(&("save",), KvValue::Bool(true))?;
+        flags.set(&("saved", 4i64), KvValue::I64(1))?;
+
+        let save = SaveSlots::new(flags);
+        save.set_slot(3);
+        save.write("level", KvValue::I64(1))?;
+        assert_eq!(save.list_slots()?, vec![3]);
+        Ok(())
+    }
+
+    #[test]
+    fn test_save_slots_follow_flag_slot() -> Result<(), TetronError> {
+        let flags = memory_flags();
+        let save = SaveSlots::new(flags.clone());
+        save.set_slot(1);
+        save.write("level", KvValue::I64(1))?;
+
+        flags.slot().select(Some("alice".into()))?;
+        assert_eq!(save.read("level")?, None);
+        assert!(save.list_slots()?.is_empty());
+        save.write("level", KvValue::I64(7))?;
+        save.set_slot(2);
+        save.write("level", KvValue::I64(8))?;
+        assert_eq!(save.list_slots()?, vec![1, 2]);
+        assert_eq!(flags.list_slots()?, vec!["alice"]);
+
+        flags.slot().select(None)?;
+        assert_eq!(save.list_slots()?, vec![1]);
+        save.set_slot(1);
+        assert_eq!(save.read("level")?, Some(KvValue::I64(1)));
+
+        // Deleting the flag slot takes its saves with it.
+        flags.delete_slot("alice")?;
+        flags.slot().select(Some("alice".into()))?;
+        assert!(save.list_slots()?.is_empty());
+        Ok(())
+    }
+}
@@ -1,10 +1,16 @@
-use crate::{error::TetronError, utils::RuneString};
+use crate::{error::TetronError, kv::config::as_vec2, utils::RuneString};
 use rune::{FromValue, ToValue, TypeHash, Value, runtime::Object};
 use std::collections::BTreeMap;
 use stupid_simple_kv::{KvKey, KvValue};
 
 pub fn rune_vec_to_kv_key(value: Vec<Value>) -> Result<KvKey, TetronError> {
     let mut key = KvKey::new();
+    push_rune_key_parts(&mut key, value)?;
+    Ok(key)
+}
+
+/// Append the parts of a Rune key array to `key`.
+pub fn push_rune_key_parts(key: &mut KvKey, value: Vec<Value>) -> Result<(), TetronError> {
     for item in value {
         match item.type_hash() {
             bool::HASH => {
@@ -22,7 +28,7 @@ pub fn rune_vec_to_kv_key(value: Vec<Value>) -> Result<KvKey, TetronError> {
         }
     }
 
-    Ok(key)
+    Ok(())
 }
 
 pub fn rune_value_to_kv(value: Value) -> Result<KvValue, TetronError> {
@@ -57,6 +63,19 @@ pub fn rune_value_to_kv(value: Value) -> Result<KvValue, TetronError> {
 }
 
 pub fn kv_value_to_rune(value: &KvValue) -> Result<Value, TetronError> {
+    to_rune(value, false)
+}
+
+/// Like [`kv_value_to_rune`], but `{ "x": .., "y": .. }` objects become
+/// `Vec2`s, as described in [`as_vec2`].
+pub fn config_value_to_rune(value: &KvValue) -> Result<Value, TetronError> {
+    to_rune(value, true)
+}
+
+fn to_rune(value: &KvValue, vectors: bool) -> Result<Value, TetronError> {
+    if vectors && let Some(vec) = as_vec2(value) {
+        return Ok(vec.to_value()?);
+    }
     match value {
         KvValue::Null => Ok(Value::empty()),
         KvValue::Bool(b) => Ok(b.to_value()?),
@@ -67,14 +86,14 @@ pub fn kv_value_to_rune(value: &KvValue) -> Result<Value, TetronError> {
             // recursively convert, then to rune::runtime::Vec, then to Value
             let mut rune_vec = Vec::with_capacity(arr.len());
             for elem in arr {
-                rune_vec.push(kv_value_to_rune(elem)?);
+                rune_vec.push(to_rune(elem, vectors)?);
             }
             Ok(rune_vec.to_value()?)
         }
         KvValue::Object(map) => {
             let mut obj = Object::new();
             for (k, v) in map {
-                obj.insert_value(RuneString::try_from(k.to_owned())?, kv_value_to_rune(v)?)
+                obj.insert_value(RuneString::try_from(k.to_owned())?, to_rune(v, vectors)?)
                     .into_result()
                     .map_err(|e| TetronError::Runtime(e.to_string()))?;
             }
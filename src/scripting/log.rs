@@ -61,6 +61,16 @@ fn native_log(level_str: &str, file: &str, line: i64, message: &str) {
         return;
     };
 
+    emit(level, &format!("{file}:{line}"), message);
+}
+
+/// Log a debug-level message from the engine itself, subject to the same
+/// log level as script logging.
+pub(crate) fn engine_debug(message: &str) {
+    emit(LogLevel::Debug, "tetron", message);
+}
+
+fn emit(level: LogLevel, location: &str, message: &str) {
     let current_level = LogLevel::from_str(&current_log_level()).unwrap_or(LogLevel::Info);
 
     // Only log if the message level is <= current log level
@@ -69,7 +79,7 @@ fn native_log(level_str: &str, file: &str, line: i64, message: &str) {
         let color = level.color();
 
         println!(
-            "tetron::log {color}[{}]{reset} {file}:{line}: {message}",
+            "tetron::log {color}[{}]{reset} {location}: {message}",
             level.as_str(),
         );
     }
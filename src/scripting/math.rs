@@ -102,53 +102,6 @@ fn lerp(a: f64, b: f64, t: f64) -> f64 {
     (1.0 - t) * a + t * b
 }
 
-impl Vec2 {
-    #[rune::function(instance, protocol = ADD_ASSIGN)]
-    fn add_assign_rune(&mut self, rhs: Vec2) {
-        *self += rhs;
-    }
-
-    #[rune::function(instance, protocol = SUB_ASSIGN)]
-    fn sub_assign_rune(&mut self, rhs: Vec2) {
-        *self -= rhs;
-    }
-
-    #[rune::function(instance, protocol = MUL_ASSIGN)]
-    fn mul_assign_rune(&mut self, rhs: Vec2) {
-        *self *= rhs;
-    }
-
-    #[rune::function(instance, protocol = DIV_ASSIGN)]
-    fn div_assign_rune(&mut self, rhs: Vec2) {
-        *self /= rhs;
-    }
-
-    #[rune::function(instance, protocol = DIV)]
-    fn div_rune(self, rhs: Vec2) -> Vec2 {
-        self / rhs
-    }
-
-    #[rune::function(instance, protocol = MUL)]
-    fn mul_rune(self, rhs: Vec2) -> Vec2 {
-        self * rhs
-    }
-
-    #[rune::function(instance, protocol = SUB)]
-    fn sub_rune(self, rhs: Vec2) -> Vec2 {
-        self - rhs
-    }
-
-    #[rune::function(instance, protocol = ADD)]
-    fn add_rune(self, rhs: Vec2) -> Vec2 {
-        self + rhs
-    }
-
-    #[rune::function(instance, protocol = PARTIAL_EQ)]
-    fn partial_eq_rune(&self, rhs: &Vec2) -> bool {
-        self == rhs
-    }
-}
-
 pub fn module() -> Result<Module, ContextError> {
     let mut module = Module::with_crate_item("tetron", ["math"])?;
 
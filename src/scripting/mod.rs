@@ -1,63 +1,131 @@
 use crate::{
-    engine::{drawable, input, input::KeyState, physics, shape, transform},
-    error::TetronError,
-    fs::SimpleFs,
+    audio::AudioQueue,
+    engine::{button, drawable, input, input::KeyState, physics, shape, transform, tween},
+    error::{ErrorMode, TetronError},
+    fs::{SimpleFs, overlay_fs::OverlayFs},
+    kv::cache::FlushHandle,
 };
 use rune::{
-    Context, Diagnostics, Module, Source, Sources, ToTypeHash, Vm,
+    Context, Diagnostics, Module, Source, Sources, ToTypeHash, Unit, Vm,
     runtime::RuntimeContext,
     termcolor::{ColorChoice, StandardStream},
 };
-use source_loader::SimpleFsSourceLoader;
+use source_loader::{SimpleFsSourceLoader, resolve_module};
 use std::{
+    collections::HashMap,
     path::Path,
-    rc::Rc,
     sync::{Arc, RwLock},
 };
 use stupid_simple_kv::Kv;
 
-mod game;
-mod kv;
+mod audio;
+pub(crate) mod debug;
+mod fmt;
+mod fs;
+pub(crate) mod game;
+pub use game::FrameState;
+mod grid;
+mod i18n;
+pub(crate) mod kv;
+pub use kv::flags::Flags;
 pub mod log;
 mod math;
+pub(crate) mod random;
+pub use random::GameRng;
 mod source_loader;
+mod state_machine;
+mod time;
 
 pub struct TetronScripting {
     context: Arc<Context>,
     runtime: Arc<RuntimeContext>,
     loader: SimpleFsSourceLoader,
-    fs: Rc<dyn SimpleFs>,
+    fs: Arc<dyn SimpleFs>,
+    /// Compiled modules by path. Each is compiled once, so `end` runs in the
+    /// same unit as `begin` even if the files have changed since.
+    units: HashMap<String, Arc<Unit>>,
 }
 
-fn tetron_modules(
-    flags: Arc<RwLock<Kv>>,
-    config: Arc<Kv>,
-    input: Arc<RwLock<KeyState>>,
-) -> Result<Vec<Module>, TetronError> {
+/// The parts of a game that its script modules use.
+pub struct GameHandles {
+    pub flags: Flags,
+    pub flags_cache: FlushHandle,
+    pub config: Arc<Kv>,
+    pub input: Arc<RwLock<KeyState>>,
+    pub fs: Arc<RwLock<OverlayFs>>,
+    pub audio: AudioQueue,
+    pub frame: FrameState,
+    /// The RNG behind `tetron::random`.
+    pub rng: GameRng,
+    /// How the modules handle engine bugs.
+    pub mode: ErrorMode,
+}
+
+fn tetron_modules(handles: GameHandles) -> Result<Vec<Module>, TetronError> {
+    let GameHandles {
+        flags,
+        flags_cache,
+        config,
+        input,
+        fs,
+        audio,
+        frame,
+        rng,
+        mode,
+    } = handles;
     // custom tetron modules
     let math = math::module()?;
+    let random = random::module(rng, mode)?;
     let log = log::module()?;
-    let flags = kv::flags::module(flags)?;
-    let config = kv::config::module(config)?;
-    let game = game::module()?;
+    let debug = debug::module()?;
+    let save = kv::save::module(flags.clone(), mode)?;
+    let flags = kv::flags::module(flags, flags_cache, mode)?;
+    let i18n = i18n::module(fs.clone(), config.clone(), mode)?;
+    let fs = fs::module(fs, mode)?;
+    let config = kv::config::module(config, mode)?;
+    let game = game::module(frame, mode)?;
     let physics = physics::module()?;
     let shape = shape::module()?;
     let drawable = drawable::module()?;
+    let button = button::module()?;
     let transform = transform::module()?;
-    let input = input::module(input)?;
+    let input = input::module(input, mode)?;
+    let audio = audio::module(audio, mode)?;
+    let time = time::module()?;
+    let grid = grid::module()?;
+    let tween = tween::module()?;
+    let state_machine = state_machine::module()?;
+    let fmt = fmt::module()?;
 
     Ok(vec![
-        math, log, flags, config, game, shape, drawable, transform, physics, input,
+        math,
+        random,
+        log,
+        debug,
+        flags,
+        save,
+        config,
+        i18n,
+        fs,
+        game,
+        shape,
+        drawable,
+        button,
+        transform,
+        physics,
+        input,
+        audio,
+        time,
+        grid,
+        tween,
+        state_machine,
+        fmt,
     ])
 }
 
-pub fn tetron_context(
-    flags: Arc<RwLock<Kv>>,
-    config: Arc<Kv>,
-    input: Arc<RwLock<KeyState>>,
-) -> Result<Context, TetronError> {
+pub fn tetron_context(handles: GameHandles) -> Result<Context, TetronError> {
     let mut context = Context::with_config(false)?;
-    for module in tetron_modules(flags, config, input.clone())? {
+    for module in tetron_modules(handles)? {
         context.install(module)?;
     }
 
@@ -65,13 +133,9 @@ pub fn tetron_context(
 }
 
 impl TetronScripting {
-    pub fn new(
-        fs: Rc<dyn SimpleFs>,
-        flags: Arc<RwLock<Kv>>,
-        config: Arc<Kv>,
-        input: Arc<RwLock<KeyState>>,
-    ) -> Result<TetronScripting, TetronError> {
-        let context = tetron_context(flags, config, input)?;
+    pub fn new(handles: GameHandles) -> Result<TetronScripting, TetronError> {
+        let fs: Arc<dyn SimpleFs> = handles.fs.clone();
+        let context = tetron_context(handles)?;
         let runtime = context.runtime()?;
         let loader = SimpleFsSourceLoader::new(fs.clone());
 
@@ -80,6 +144,7 @@ impl TetronScripting {
             context: Arc::new(context),
             runtime: Arc::new(runtime),
             loader,
+            units: HashMap::new(),
         })
     }
 
@@ -89,6 +154,48 @@ impl TetronScripting {
         func: impl ToTypeHash,
         args: impl rune::runtime::Args,
     ) -> Result<(), TetronError> {
+        let mut vm = self.prepare(path)?;
+        vm.execute(func, args)?.complete().into_result()?;
+        Ok(())
+    }
+
+    /// Like [`TetronScripting::execute`], but does nothing if the module
+    /// doesn't define `func`.
+    pub fn execute_if_defined(
+        &mut self,
+        path: &str,
+        func: impl ToTypeHash,
+        args: impl rune::runtime::Args,
+    ) -> Result<(), TetronError> {
+        let mut vm = self.prepare(path)?;
+        let hash = func.to_type_hash();
+        if vm.lookup_function(hash).is_ok() {
+            vm.execute(hash, args)?.complete().into_result()?;
+        }
+        Ok(())
+    }
+
+    /// Compile the module at `path` and everything it uses without running
+    /// anything. Compile errors are printed to stderr.
+    pub fn compile(&mut self, path: &str) -> Result<(), TetronError> {
+        self.prepare(path).map(|_| ())
+    }
+
+    fn prepare(&mut self, path: &str) -> Result<Vm, TetronError> {
+        let unit = match self.units.get(path) {
+            Some(unit) => unit.clone(),
+            None => {
+                let unit = Arc::new(self.build(path)?);
+                self.units.insert(path.to_owned(), unit.clone());
+                unit
+            }
+        };
+        Ok(Vm::new(self.runtime.clone(), unit))
+    }
+
+    fn build(&mut self, path: &str) -> Result<Unit, TetronError> {
+        let path = &resolve_module(self.fs.as_ref(), path)
+            .ok_or(TetronError::ModuleNotFound(path.into()))?;
         let p = Path::new(path);
         let filename = p
             .file_name()
@@ -114,9 +221,6 @@ impl TetronScripting {
             diagnostics.emit(&mut writer, &sources)?;
         }
 
-        let unit = result?;
-        let mut vm = Vm::new(self.runtime.clone(), Arc::new(unit));
-        vm.execute(func, args)?.complete().into_result()?;
-        Ok(())
+        Ok(result?)
     }
 }
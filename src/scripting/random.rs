@@ -0,0 +1,81 @@
+use std::sync::{Arc, Mutex};
+
+use rune::{ContextError, Module, docstring};
+
+use crate::{
+    error::{EngineBug, ErrorMode},
+    utils::{
+        Registrable,
+        rng::{Rng, RngRef},
+    },
+};
+
+/// A game's own RNG behind `tetron::random`, for randomness that doesn't
+/// need to be reproducible. Scenes have their own streams, see `Ctx::rng`.
+/// Seeded from the clock unless the game is deterministic.
+#[derive(Clone, Debug)]
+pub struct GameRng(Arc<Mutex<Rng>>);
+
+impl Default for GameRng {
+    fn default() -> Self {
+        Self(Arc::new(Mutex::new(Rng::from_time())))
+    }
+}
+
+impl GameRng {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Restart the stream from `seed`, e.g. for deterministic runs.
+    pub(crate) fn seed(&self, seed: u64, mode: ErrorMode) {
+        if let Some(mut rng) = self.0.lock().engine_bug("rng lock poisoned", mode) {
+            *rng = Rng::new(seed);
+        }
+    }
+
+    /// Run `f` on the RNG, or return `None` if its lock is poisoned and
+    /// `mode` recovers.
+    pub(crate) fn with<T>(&self, mode: ErrorMode, f: impl FnOnce(&mut Rng) -> T) -> Option<T> {
+        self.0
+            .lock()
+            .engine_bug("rng lock poisoned", mode)
+            .map(|mut rng| f(&mut rng))
+    }
+}
+
+pub fn module(rng: GameRng, mode: ErrorMode) -> Result<Module, ContextError> {
+    let mut module = Module::with_crate_item("tetron", ["random"])?;
+    RngRef::register(&mut module)?;
+
+    module
+        .function("seed", {
+            let rng = rng.clone();
+            move |seed: i64| rng.seed(seed as u64, mode)
+        })
+        .build()?
+        .docs(docstring! {
+            /// Reseed the game's RNG.
+        })?;
+
+    module
+        .function("float", {
+            let rng = rng.clone();
+            move || -> f64 { rng.with(mode, Rng::float).unwrap_or_default() }
+        })
+        .build()?
+        .docs(docstring! {
+            /// A random float in `[0, 1)` from the game's RNG.
+        })?;
+
+    module
+        .function("range", move |min: i64, max: i64| -> i64 {
+            rng.with(mode, |rng| rng.range(min, max)).unwrap_or(min)
+        })
+        .build()?
+        .docs(docstring! {
+            /// A random integer in `[min, max)` from the game's RNG.
+        })?;
+
+    Ok(module)
+}
@@ -1,4 +1,4 @@
-use std::rc::Rc;
+use std::sync::Arc;
 
 use rune::{
     Item, Source,
@@ -7,15 +7,32 @@ use rune::{
     compile,
 };
 
-use crate::fs::SimpleFs;
+use crate::fs::{SimpleFs, join_path};
 use rune::compile::SourceLoader; // Replace with your actual module
 
+/// Candidate source files for the module at `base`, in order of preference:
+/// `base/mod.rn`, then `base.rn`.
+pub(crate) fn module_candidates(base: &str) -> [String; 2] {
+    [join_path(base, "mod.rn"), format!("{base}.rn")]
+}
+
+/// Resolve a module path like `main` or `src/levels` to a source file.
+/// Paths that already point at a file are returned as-is.
+pub(crate) fn resolve_module(fs: &dyn SimpleFs, path: &str) -> Option<String> {
+    if fs.metadata(path).is_ok_and(|meta| !meta.is_dir) {
+        return Some(path.to_owned());
+    }
+    module_candidates(path)
+        .into_iter()
+        .find(|candidate| fs.exists(candidate))
+}
+
 pub struct SimpleFsSourceLoader {
-    fs: Rc<dyn SimpleFs>,
+    fs: Arc<dyn SimpleFs>,
 }
 
 impl SimpleFsSourceLoader {
-    pub fn new(fs: Rc<dyn SimpleFs>) -> Self {
+    pub fn new(fs: Arc<dyn SimpleFs>) -> Self {
         Self { fs }
     }
 }
@@ -37,8 +54,8 @@ impl SourceLoader for SimpleFsSourceLoader {
         }
 
         // Consider both mod.rn inside the dir and dir.rn as file candidates
-        let candidate1 = base.join("mod.rn").to_string_lossy().to_string();
-        let candidate2 = base.with_extension("rn").to_string_lossy().to_string();
+        let base = base.to_string_lossy().to_string();
+        let [candidate1, candidate2] = module_candidates(&base);
 
         let path = if self.fs.exists(&candidate1) {
             candidate1
@@ -61,3 +78,33 @@ impl SourceLoader for SimpleFsSourceLoader {
         Ok(Source::new(path, src)?)
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::fs::zip_fs::ZipFs;
+
+    #[test]
+    fn test_resolve_module() {
+        let fs = ZipFs::from_files(&[
+            ("game.json", "{}"),
+            ("main.rn", ""),
+            ("levels/mod.rn", ""),
+            ("src/game.rn", ""),
+        ]);
+        assert_eq!(resolve_module(&fs, "main").as_deref(), Some("main.rn"));
+        assert_eq!(
+            resolve_module(&fs, "levels").as_deref(),
+            Some("levels/mod.rn")
+        );
+        assert_eq!(
+            resolve_module(&fs, "src/game").as_deref(),
+            Some("src/game.rn")
+        );
+        assert_eq!(
+            resolve_module(&fs, "src/game.rn").as_deref(),
+            Some("src/game.rn")
+        );
+        assert_eq!(resolve_module(&fs, "missing"), None);
+    }
+}
@@ -0,0 +1,245 @@
+use std::collections::HashMap;
+
+use rune::{
+    ContextError, Module, Value,
+    runtime::{Function, Object, VmResult},
+};
+
+use crate::{
+    error::EngineBug,
+    system_log,
+    utils::{Registrable, typed_value::TypedValue},
+};
+
+/// Callbacks run when entering, leaving or updating a state. All optional.
+#[derive(Debug, Default)]
+struct State {
+    on_enter: Option<Function>,
+    on_exit: Option<Function>,
+    on_update: Option<Function>,
+}
+
+#[derive(Debug)]
+struct Transition {
+    from: String,
+    to: String,
+    condition: Function,
+}
+
+/// A finite state machine. Transitions are checked in the order they were
+/// defined, and at most one is taken per `update`.
+#[derive(rune::Any, Debug)]
+pub struct StateMachine {
+    current: String,
+    states: HashMap<String, State>,
+    transitions: Vec<Transition>,
+}
+
+impl Registrable for StateMachine {
+    fn register(module: &mut Module) -> Result<(), ContextError> {
+        module.ty::<StateMachine>()?;
+        module.function_meta(StateMachine::new__meta)?;
+        module.function_meta(StateMachine::state__meta)?;
+        module.function_meta(StateMachine::transition__meta)?;
+        module.function_meta(StateMachine::update__meta)?;
+        module.function_meta(StateMachine::current_state__meta)?;
+        module.function_meta(StateMachine::transition_to__meta)?;
+        module.function_meta(StateMachine::snapshot__meta)?;
+        module.function_meta(StateMachine::restore__meta)?;
+        Ok(())
+    }
+}
+
+fn call(callback: Option<&Function>, args: impl rune::runtime::GuardedArgs, what: &str) {
+    if let Some(callback) = callback {
+        let _ = callback
+            .call::<()>(args)
+            .into_result()
+            .inspect_err(|e| system_log!("state machine {what} error: {e:?}"));
+    }
+}
+
+impl StateMachine {
+    /// Create a state machine starting in `initial`. The initial state's
+    /// `on_enter` is not called.
+    #[rune::function(path = Self::new, keep)]
+    pub fn new(initial: &str) -> Self {
+        Self {
+            current: initial.to_owned(),
+            states: HashMap::new(),
+            transitions: Vec::new(),
+        }
+    }
+
+    /// Define the callbacks for state `name`, replacing any set before.
+    /// `on_update` is called with the frame's `dt`.
+    #[rune::function(instance, keep)]
+    pub fn state(
+        &mut self,
+        name: &str,
+        on_enter: Option<Function>,
+        on_exit: Option<Function>,
+        on_update: Option<Function>,
+    ) {
+        self.states.insert(
+            name.to_owned(),
+            State {
+                on_enter,
+                on_exit,
+                on_update,
+            },
+        );
+    }
+
+    /// Move from `from` to `to` during `update` once `condition()` returns
+    /// true.
+    #[rune::function(instance, keep)]
+    pub fn transition(&mut self, from: &str, to: &str, condition: Function) {
+        self.transitions.push(Transition {
+            from: from.to_owned(),
+            to: to.to_owned(),
+            condition,
+        });
+    }
+
+    /// Take the first transition out of the current state whose condition
+    /// holds, then run the current state's `on_update`.
+    #[rune::function(instance, keep)]
+    pub fn update(&mut self, dt: f64) {
+        let next = self
+            .transitions
+            .iter()
+            .filter(|transition| transition.from == self.current)
+            .find(|transition| {
+                transition
+                    .condition
+                    .call::<bool>(())
+                    .into_result()
+                    .inspect_err(|e| system_log!("state machine condition error: {e:?}"))
+                    .unwrap_or(false)
+            })
+            .map(|transition| transition.to.clone());
+        if let Some(next) = next {
+            self.transition_to(&next);
+        }
+        let state = self.states.get(&self.current);
+        call(state.and_then(|s| s.on_update.as_ref()), (dt,), "on_update");
+    }
+
+    #[rune::function(instance, keep)]
+    pub fn current_state(&self) -> String {
+        self.current.clone()
+    }
+
+    /// Switch to `state` immediately, running the old state's `on_exit` and
+    /// the new state's `on_enter`.
+    #[rune::function(instance, keep)]
+    pub fn transition_to(&mut self, state: &str) {
+        let old = self.states.get(&self.current);
+        call(old.and_then(|s| s.on_exit.as_ref()), (), "on_exit");
+        self.current = state.to_owned();
+        let new = self.states.get(&self.current);
+        call(new.and_then(|s| s.on_enter.as_ref()), (), "on_enter");
+    }
+
+    /// The machine's serializable state, suitable for storing in a
+    /// behaviour field. Callbacks can't be serialized, so only the current
+    /// state and the transition graph are kept.
+    pub fn to_typed(&self) -> TypedValue {
+        let mut states: Vec<&String> = self.states.keys().collect();
+        states.sort();
+        let transitions = self
+            .transitions
+            .iter()
+            .map(|transition| {
+                TypedValue::Object(HashMap::from([
+                    ("from".to_string(), transition.from.as_str().into()),
+                    ("to".to_string(), transition.to.as_str().into()),
+                ]))
+            })
+            .collect();
+        TypedValue::Object(HashMap::from([
+            ("current".to_string(), self.current.as_str().into()),
+            (
+                "states".to_string(),
+                TypedValue::Array(states.into_iter().map(|s| s.as_str().into()).collect()),
+            ),
+            ("transitions".to_string(), TypedValue::Array(transitions)),
+        ]))
+    }
+
+    /// See [`StateMachine::to_typed`].
+    #[rune::function(instance, keep)]
+    pub fn snapshot(&self) -> VmResult<Object> {
+        Value::try_from(self.to_typed())
+            .and_then(|value| Ok(rune::from_value::<Object>(value)?))
+            .throw_engine_bug("could not convert state machine snapshot")
+    }
+
+    /// Set the current state from a `snapshot`, without running any
+    /// callbacks. Returns false if the snapshot has no current state.
+    #[rune::function(instance, keep)]
+    pub fn restore(&mut self, snapshot: &Object) -> bool {
+        let current = snapshot
+            .get("current")
+            .and_then(|value| TypedValue::try_from(value).ok());
+        match current {
+            Some(TypedValue::String(current)) => {
+                self.current = current;
+                true
+            }
+            _ => false,
+        }
+    }
+}
+
+pub fn module() -> Result<Module, ContextError> {
+    let mut module = Module::with_crate_item("tetron", ["state_machine"])?;
+    StateMachine::register(&mut module)?;
+    Ok(module)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::{
+        Arc,
+        atomic::{AtomicBool, AtomicUsize, Ordering},
+    };
+
+    #[test]
+    fn test_transitions_once_when_condition_holds() {
+        let ready = Arc::new(AtomicBool::new(false));
+        let entered = Arc::new(AtomicUsize::new(0));
+
+        let mut sm = StateMachine::new("idle");
+        sm.state("idle", None, None, None);
+        let counter = entered.clone();
+        let on_enter = Function::new(move || {
+            counter.fetch_add(1, Ordering::Relaxed);
+        });
+        sm.state("running", Some(on_enter), None, None);
+        let flag = ready.clone();
+        sm.transition(
+            "idle",
+            "running",
+            Function::new(move || flag.load(Ordering::Relaxed)),
+        );
+
+        sm.update(0.1);
+        assert_eq!(sm.current_state(), "idle");
+
+        ready.store(true, Ordering::Relaxed);
+        for _ in 0..3 {
+            sm.update(0.1);
+        }
+        assert_eq!(sm.current_state(), "running");
+        assert_eq!(entered.load(Ordering::Relaxed), 1);
+
+        let snapshot = sm.to_typed();
+        let TypedValue::Object(fields) = &snapshot else {
+            panic!("snapshot should be an object");
+        };
+        assert_eq!(fields.get("current"), Some(&"running".into()));
+    }
+}
@@ -0,0 +1,40 @@
+use std::time::Instant;
+
+use rune::{ContextError, Module, docstring};
+
+/// Whole milliseconds elapsed since `start`.
+fn millis_since(start: Instant) -> i64 {
+    start.elapsed().as_millis() as i64
+}
+
+pub fn module() -> Result<Module, ContextError> {
+    let mut module = Module::with_crate_item("tetron", ["time"])?;
+    let start = Instant::now();
+
+    module
+        .function("ticks", move || -> i64 { millis_since(start) })
+        .build()?
+        .docs(docstring! {
+            /// Milliseconds of real time since the game started. Unlike
+            /// summing frame `dt`s, this keeps counting while a scene is
+            /// paused and isn't affected by deterministic mode.
+        })?;
+
+    Ok(module)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+
+    #[test]
+    fn test_ticks_count_real_time() {
+        let start = Instant::now() - Duration::from_millis(1500);
+        let first = millis_since(start);
+        assert!(first >= 1500, "{first}");
+        std::thread::sleep(Duration::from_millis(5));
+        assert!(millis_since(start) >= first + 5);
+        assert!(module().is_ok());
+    }
+}
@@ -1,31 +1,195 @@
 use sdl2::{
     AudioSubsystem, EventPump, Sdl, VideoSubsystem,
     gfx::primitives::DrawRenderer,
+    image::LoadTexture,
     pixels::Color,
     rect::{Point, Rect},
-    render::Canvas,
+    render::{Canvas, Texture, TextureCreator},
     ttf::Sdl2TtfContext,
-    video::Window,
+    video::{Window, WindowContext},
 };
-use std::{collections::HashMap, rc::Rc};
+use std::{
+    collections::HashMap,
+    sync::{Arc, Mutex},
+};
+
+use crate::{
+    engine::physics::vec2::Vec2,
+    error::TetronError,
+    fs::{SimpleFs, read_files_parallel},
+    system_log,
+};
+
+/// An outline drawn around text, `width` pixels thick.
+#[derive(Debug, Clone, Copy)]
+pub struct TextOutline {
+    pub width: f64,
+    pub color: Color,
+}
+
+/// The point of a piece of text that is placed at its draw position.
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub enum TextAnchor {
+    #[default]
+    TopLeft,
+    TopCenter,
+    TopRight,
+    CenterLeft,
+    Center,
+    CenterRight,
+    BottomLeft,
+    BottomCenter,
+    BottomRight,
+}
+
+impl TextAnchor {
+    pub fn parse(s: &str) -> Option<Self> {
+        match s.to_lowercase().as_str() {
+            "topleft" => Some(Self::TopLeft),
+            "topcenter" => Some(Self::TopCenter),
+            "topright" => Some(Self::TopRight),
+            "centerleft" => Some(Self::CenterLeft),
+            "center" => Some(Self::Center),
+            "centerright" => Some(Self::CenterRight),
+            "bottomleft" => Some(Self::BottomLeft),
+            "bottomcenter" => Some(Self::BottomCenter),
+            "bottomright" => Some(Self::BottomRight),
+            _ => None,
+        }
+    }
+
+    /// How far to move text `width` by `height` pixels from its draw
+    /// position so this point of it lands there.
+    pub fn offset(self, width: u32, height: u32) -> (i32, i32) {
+        let (fx, fy) = match self {
+            Self::TopLeft => (0.0, 0.0),
+            Self::TopCenter => (0.5, 0.0),
+            Self::TopRight => (1.0, 0.0),
+            Self::CenterLeft => (0.0, 0.5),
+            Self::Center => (0.5, 0.5),
+            Self::CenterRight => (1.0, 0.5),
+            Self::BottomLeft => (0.0, 1.0),
+            Self::BottomCenter => (0.5, 1.0),
+            Self::BottomRight => (1.0, 1.0),
+        };
+        (
+            -(width as f64 * fx).round() as i32,
+            -(height as f64 * fy).round() as i32,
+        )
+    }
+}
+
+/// How to draw a piece of text. Without a font name, the first configured
+/// font is used.
+#[derive(Debug, Clone)]
+pub struct TextStyle {
+    pub font_name: Option<String>,
+    pub font_size: Option<f64>,
+    pub color: Color,
+    pub outline: Option<TextOutline>,
+    pub anchor: TextAnchor,
+}
+
+impl TextStyle {
+    /// Text in `color`, in the default font and size, anchored at its
+    /// top-left corner.
+    pub fn new(color: Color) -> Self {
+        Self {
+            font_name: None,
+            font_size: None,
+            color,
+            outline: None,
+            anchor: TextAnchor::TopLeft,
+        }
+    }
+}
+
+/// Something a script asked to draw this frame, on top of the scene.
+#[derive(Clone, Debug, PartialEq)]
+pub enum DrawCommand {
+    /// Copy the `src_size` region at `src_pos` of the image at `path` to
+    /// `dst_pos`, stretched to `dst_size`.
+    Texture {
+        path: String,
+        src_pos: Vec2,
+        src_size: Vec2,
+        dst_pos: Vec2,
+        dst_size: Vec2,
+    },
+}
+
+/// Draw commands from scripts. Like audio, drawing happens on the main
+/// thread once scripts have run, so scripts queue commands and the game
+/// drains them each frame.
+#[derive(Clone, Debug, Default)]
+pub struct DrawQueue(Arc<Mutex<Vec<DrawCommand>>>);
+
+impl DrawQueue {
+    pub fn new() -> Self {
+        Self::default()
+    }
 
-use crate::{engine::physics::vec2::Vec2, error::TetronError, fs::SimpleFs};
+    pub fn push(&self, command: DrawCommand) -> Result<(), TetronError> {
+        self.0
+            .lock()
+            .map_err(|e| TetronError::Other(format!("draw queue lock poisoned: {e}")))?
+            .push(command);
+        Ok(())
+    }
+
+    pub fn drain(&self) -> Result<Vec<DrawCommand>, TetronError> {
+        Ok(std::mem::take(&mut *self.0.lock().map_err(|e| {
+            TetronError::Other(format!("draw queue lock poisoned: {e}"))
+        })?))
+    }
+}
+
+/// The rectangle at `pos` with `size`, or `None` if it's less than a pixel
+/// across, since SDL can't copy to or from that.
+pub(crate) fn to_rect(pos: Vec2, size: Vec2) -> Option<Rect> {
+    if size.x < 1.0 || size.y < 1.0 {
+        return None;
+    }
+    Some(Rect::new(
+        pos.x as i32,
+        pos.y as i32,
+        size.x as u32,
+        size.y as u32,
+    ))
+}
 
+#[allow(dead_code)] // The SDL subsystems must be kept alive for as long as the handle is.
 pub struct TetronSdlHandle {
     pub(crate) context: Sdl,
     pub(crate) video: VideoSubsystem,
-    pub(crate) audio: AudioSubsystem,
+    /// `None` if there's no audio device, in which case the game is silent.
+    pub(crate) audio: Option<AudioSubsystem>,
     pub(crate) canvas: Canvas<Window>,
     pub(crate) events: EventPump,
     pub(crate) ttf_context: Sdl2TtfContext,
     pub(crate) font_data: HashMap<String, Vec<u8>>,
+    /// Whether text has been drawn with [`DEFAULT_FONT`] yet.
+    used_default_font: bool,
+    /// Creates the cached textures below. Textures borrow their creator, so
+    /// it's leaked to let them live in the same struct; there's one per
+    /// window and it's needed for the rest of the run anyway. It also keeps
+    /// the renderer alive for as long as any texture is.
+    texture_creator: &'static TextureCreator<WindowContext>,
+    /// Images by path, decoded and uploaded the first time they're drawn.
+    textures: HashMap<String, Texture<'static>>,
 }
 
+/// Roboto Regular, used for text when the game hasn't configured any fonts.
+const DEFAULT_FONT: &[u8] = include_bytes!("fonts/Roboto-Regular.ttf");
+
 impl TetronSdlHandle {
     pub fn new(title: &str, w: u32, h: u32) -> Result<Self, TetronError> {
         let context = sdl2::init()?;
         let video = context.video()?;
-        let audio = context.audio()?;
+        let audio = context
+            .audio()
+            .inspect_err(|e| system_log!("Audio unavailable, running without sound: {e}"))
+            .ok();
         let window = video
             .window(title, w, h)
             .position_centered()
@@ -36,6 +200,7 @@ impl TetronSdlHandle {
         let events = context.event_pump()?;
         let ttf_context = sdl2::ttf::init().map_err(|e| e.to_string())?;
         let font_data = HashMap::new();
+        let texture_creator = Box::leak(Box::new(canvas.texture_creator()));
 
         Ok(Self {
             context,
@@ -45,21 +210,79 @@ impl TetronSdlHandle {
             events,
             ttf_context,
             font_data,
+            used_default_font: false,
+            texture_creator,
+            textures: HashMap::new(),
         })
     }
 
     pub fn load_fonts(
         &mut self,
         font_list: &[(String, String)],
-        fs: Rc<dyn SimpleFs>,
+        fs: Arc<dyn SimpleFs>,
+    ) -> Result<(), TetronError> {
+        let paths = font_list
+            .iter()
+            .map(|(_, path)| path.as_str())
+            .collect::<Vec<_>>();
+        let loaded = read_files_parallel(fs.as_ref(), &paths);
+        for ((name, _), font_data) in font_list.iter().zip(loaded) {
+            self.font_data.insert(name.clone(), font_data?);
+        }
+        Ok(())
+    }
+
+    /// Create textures for the images at `paths` up front, reading their
+    /// files in parallel. Texture creation itself stays on this thread.
+    pub fn preload_images(&mut self, paths: &[&str], fs: &dyn SimpleFs) -> Result<(), TetronError> {
+        for (path, bytes) in paths.iter().zip(read_files_parallel(fs, paths)) {
+            let texture = self
+                .texture_creator
+                .load_texture_bytes(&bytes?)
+                .map_err(|e| TetronError::Runtime(format!("Could not load image {path}: {e}")))?;
+            self.textures.insert((*path).to_owned(), texture);
+        }
+        Ok(())
+    }
+
+    /// Copy the `src` region of the image at `path` to `dst` on the canvas.
+    /// `None` for either means the whole image or canvas.
+    fn copy_image(
+        &mut self,
+        fs: &dyn SimpleFs,
+        path: &str,
+        src: Option<Rect>,
+        dst: Option<Rect>,
     ) -> Result<(), TetronError> {
-        for (name, path) in font_list {
-            let font_data = fs.open_file(path)?;
-            self.font_data.insert(name.clone(), font_data);
+        if !self.textures.contains_key(path) {
+            let texture = self
+                .texture_creator
+                .load_texture_bytes(&fs.open_file(path)?)
+                .map_err(|e| TetronError::Runtime(format!("Could not load image {path}: {e}")))?;
+            self.textures.insert(path.to_owned(), texture);
         }
+        self.canvas.copy(&self.textures[path], src, dst)?;
         Ok(())
     }
 
+    /// Draw the image at `path` stretched over the whole canvas. Backgrounds
+    /// share the texture cache with `draw_texture`, so a scene's background
+    /// is only decoded the first frame it shows.
+    pub fn draw_background(&mut self, fs: &dyn SimpleFs, path: &str) -> Result<(), TetronError> {
+        self.copy_image(fs, path, None, None)
+    }
+
+    /// Draw the `src` region of the image at `path` into `dst`.
+    pub fn draw_texture(
+        &mut self,
+        fs: &dyn SimpleFs,
+        path: &str,
+        src: Rect,
+        dst: Rect,
+    ) -> Result<(), TetronError> {
+        self.copy_image(fs, path, Some(src), Some(dst))
+    }
+
     pub fn draw_rect(
         &mut self,
         pos: Vec2,
@@ -135,23 +358,29 @@ impl TetronSdlHandle {
         &mut self,
         text: &str,
         pos: Vec2,
-        font_name: Option<String>,
-        font_size: Option<f64>,
-        color: Color,
+        style: &TextStyle,
     ) -> Result<(), TetronError> {
         use sdl2::rwops::RWops;
 
-        let font_key = font_name
+        let font_key = style
+            .font_name
             .clone()
-            .or_else(|| self.font_data.keys().next().cloned())
-            .ok_or_else(|| {
-                TetronError::Runtime("No font available for text rendering".to_string())
-            })?;
-        let font_bytes = self
-            .font_data
-            .get(&font_key)
-            .ok_or_else(|| TetronError::Runtime(format!("Font '{}' not loaded", font_key)))?;
-        let font_size = font_size.map(|fs| fs as u16).unwrap_or(16);
+            .or_else(|| self.font_data.keys().next().cloned());
+        if font_key.is_none() && !self.used_default_font {
+            self.used_default_font = true;
+            system_log!(
+                "No fonts are configured, so text is drawn with the built-in default. \
+                 Add fonts to game.json to choose your own."
+            );
+        }
+        let font_bytes: &[u8] = match &font_key {
+            Some(key) => self
+                .font_data
+                .get(key)
+                .ok_or_else(|| TetronError::Runtime(format!("Font '{}' not loaded", key)))?,
+            None => DEFAULT_FONT,
+        };
+        let font_size = style.font_size.map(|fs| fs as u16).unwrap_or(16);
         let rw = RWops::from_bytes(font_bytes)
             .map_err(|e| TetronError::Runtime(format!("RWops error: {e}")))?;
         let font = self
@@ -160,24 +389,81 @@ impl TetronSdlHandle {
             .map_err(|e| {
                 TetronError::Runtime(format!("ttf_context.load_font_from_rwops error: {e}"))
             })?;
-        let sdl_color = color;
-        let surface = font
-            .render(text)
-            .blended(sdl_color)
-            .map_err(|e| TetronError::Runtime(format!("Font render error: {e}")))?;
         let texture_creator = self.canvas.texture_creator();
-        let texture = texture_creator
-            .create_texture_from_surface(&surface)
-            .map_err(|e| TetronError::Runtime(format!("texture creation error: {e}")))?;
-        let target = sdl2::rect::Rect::new(
-            pos.x as i32,
-            pos.y as i32,
-            surface.width(),
-            surface.height(),
-        );
-        self.canvas
-            .copy(&texture, None, Some(target))
-            .map_err(|e| TetronError::Runtime(format!("canvas.copy error: {e}")))?;
+        let mut render = |color: Color, offsets: &[(i32, i32)]| -> Result<(), TetronError> {
+            let surface = font
+                .render(text)
+                .blended(color)
+                .map_err(|e| TetronError::Runtime(format!("Font render error: {e}")))?;
+            let texture = texture_creator
+                .create_texture_from_surface(&surface)
+                .map_err(|e| TetronError::Runtime(format!("texture creation error: {e}")))?;
+            let (ax, ay) = style.anchor.offset(surface.width(), surface.height());
+            for (dx, dy) in offsets {
+                let target = sdl2::rect::Rect::new(
+                    pos.x as i32 + ax + dx,
+                    pos.y as i32 + ay + dy,
+                    surface.width(),
+                    surface.height(),
+                );
+                self.canvas
+                    .copy(&texture, None, Some(target))
+                    .map_err(|e| TetronError::Runtime(format!("canvas.copy error: {e}")))?;
+            }
+            Ok(())
+        };
+
+        // The outline is the text drawn in the outline color, offset in all
+        // eight directions, underneath the fill.
+        if let Some(outline) = style.outline {
+            let w = outline.width.round() as i32;
+            if w > 0 {
+                let offsets = [
+                    (-w, -w),
+                    (0, -w),
+                    (w, -w),
+                    (-w, 0),
+                    (w, 0),
+                    (-w, w),
+                    (0, w),
+                    (w, w),
+                ];
+                render(outline.color, &offsets)?;
+            }
+        }
+        render(style.color, &[(0, 0)])?;
         Ok(())
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_to_rect() {
+        assert_eq!(
+            to_rect(Vec2::new(16.0, 32.0), Vec2::new(8.0, 4.0)),
+            Some(Rect::new(16, 32, 8, 4))
+        );
+        assert_eq!(
+            to_rect(Vec2::new(-2.5, 0.0), Vec2::new(1.0, 1.0)),
+            Some(Rect::new(-2, 0, 1, 1))
+        );
+        assert_eq!(to_rect(Vec2::ZERO, Vec2::new(0.0, 4.0)), None);
+        assert_eq!(to_rect(Vec2::ZERO, Vec2::new(4.0, -1.0)), None);
+    }
+
+    #[test]
+    fn test_text_anchor() {
+        assert_eq!(
+            TextAnchor::parse("BottomCenter"),
+            Some(TextAnchor::BottomCenter)
+        );
+        assert_eq!(TextAnchor::parse("middle"), None);
+        assert_eq!(TextAnchor::TopLeft.offset(40, 20), (0, 0));
+        assert_eq!(TextAnchor::Center.offset(40, 20), (-20, -10));
+        assert_eq!(TextAnchor::BottomCenter.offset(41, 20), (-21, -20));
+        assert_eq!(TextAnchor::CenterRight.offset(40, 20), (-40, -10));
+    }
+}
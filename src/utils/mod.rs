@@ -14,6 +14,7 @@ pub fn resolve_physical_fs_path(path: &Path) -> Result<PathBuf, anyhow::Error> {
 pub type RuneString = ::rune::alloc::String;
 pub type RuneVec = ::rune::runtime::Vec;
 
+pub mod rng;
 pub mod typed_value;
 
 pub trait Registrable {
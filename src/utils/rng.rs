@@ -0,0 +1,125 @@
+use rune::{ContextError, Module};
+use std::{
+    cell::RefCell,
+    rc::Rc,
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use crate::utils::Registrable;
+
+/// A small seedable SplitMix64 generator. This is implemented here rather
+/// than pulled from a crate so that a given seed produces the same sequence
+/// on every platform and engine version, which replays and tests rely on.
+#[derive(Clone, Debug)]
+pub struct Rng {
+    state: u64,
+}
+
+impl Rng {
+    pub fn new(seed: u64) -> Self {
+        Self { state: seed }
+    }
+
+    /// Seed from the system clock, for when reproducibility doesn't matter.
+    pub fn from_time() -> Self {
+        let nanos = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|d| d.as_nanos() as u64)
+            .unwrap_or_default();
+        Self::new(nanos)
+    }
+
+    pub fn next_u64(&mut self) -> u64 {
+        self.state = self.state.wrapping_add(0x9E37_79B9_7F4A_7C15);
+        let mut z = self.state;
+        z = (z ^ (z >> 30)).wrapping_mul(0xBF58_476D_1CE4_E5B9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94D0_49BB_1331_11EB);
+        z ^ (z >> 31)
+    }
+
+    /// A float in `[0, 1)`.
+    pub fn float(&mut self) -> f64 {
+        (self.next_u64() >> 11) as f64 / (1u64 << 53) as f64
+    }
+
+    /// An integer in `[min, max)`. Returns `min` if the range is empty.
+    pub fn range(&mut self, min: i64, max: i64) -> i64 {
+        if max <= min {
+            return min;
+        }
+        let span = max.wrapping_sub(min) as u64;
+        min.wrapping_add((self.next_u64() % span) as i64)
+    }
+}
+
+/// Derive a seed from a base seed and a name, e.g. a world seed and a scene
+/// name. Uses FNV-1a so the result is stable across Rust versions.
+pub fn derive_seed(base: u64, name: &str) -> u64 {
+    let mut hash: u64 = 0xCBF2_9CE4_8422_2325 ^ base;
+    for byte in name.bytes() {
+        hash ^= byte as u64;
+        hash = hash.wrapping_mul(0x0000_0100_0000_01B3);
+    }
+    hash
+}
+
+#[derive(Clone, Debug, rune::Any)]
+#[rune(name = Rng)]
+pub struct RngRef(Rc<RefCell<Rng>>);
+
+impl Registrable for RngRef {
+    fn register(module: &mut Module) -> Result<(), ContextError> {
+        module.ty::<RngRef>()?;
+        module.function_meta(RngRef::float__meta)?;
+        module.function_meta(RngRef::range__meta)?;
+        Ok(())
+    }
+}
+
+impl RngRef {
+    pub fn new(rng: Rng) -> Self {
+        Self(Rc::new(RefCell::new(rng)))
+    }
+
+    /// A float in `[0, 1)`.
+    #[rune::function(instance, keep)]
+    pub fn float(&self) -> f64 {
+        self.0.borrow_mut().float()
+    }
+
+    /// An integer in `[min, max)`.
+    #[rune::function(instance, keep)]
+    pub fn range(&self, min: i64, max: i64) -> i64 {
+        self.0.borrow_mut().range(min, max)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_same_seed_same_sequence() {
+        let mut a = Rng::new(42);
+        let mut b = Rng::new(42);
+        for _ in 0..16 {
+            assert_eq!(a.next_u64(), b.next_u64());
+        }
+        let mut c = Rng::new(43);
+        assert_ne!(Rng::new(42).next_u64(), c.next_u64());
+    }
+
+    #[test]
+    fn test_ranges() {
+        let mut rng = Rng::new(7);
+        for _ in 0..1000 {
+            let f = rng.float();
+            assert!((0.0..1.0).contains(&f));
+            let n = rng.range(-3, 4);
+            assert!((-3..4).contains(&n));
+        }
+        assert_eq!(rng.range(5, 5), 5);
+        assert_ne!(derive_seed(1, "level1"), derive_seed(1, "level2"));
+        assert_ne!(derive_seed(1, "level1"), derive_seed(2, "level1"));
+    }
+}
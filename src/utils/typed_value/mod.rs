@@ -1,6 +1,6 @@
 pub mod schema;
 
-use std::collections::HashMap;
+use std::{collections::HashMap, fmt};
 
 use crate::{
     engine::physics::vec2::Vec2,
@@ -19,6 +19,45 @@ pub enum TypedValue {
     Vector(Vec2),
 }
 
+/// Compact, JSON-like rendering for logs and debugging. Object keys are
+/// sorted so the output is stable.
+impl fmt::Display for TypedValue {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            TypedValue::String(s) => write!(f, "{s:?}"),
+            TypedValue::Number(n) => write!(f, "{n}"),
+            TypedValue::Bool(b) => write!(f, "{b}"),
+            TypedValue::Vector(v) => write!(f, "({}, {})", v.x, v.y),
+            TypedValue::Array(items) => {
+                write!(f, "[")?;
+                for (i, item) in items.iter().enumerate() {
+                    if i > 0 {
+                        write!(f, ", ")?;
+                    }
+                    write!(f, "{item}")?;
+                }
+                write!(f, "]")
+            }
+            TypedValue::Object(map) => {
+                let mut keys: Vec<&String> = map.keys().collect();
+                keys.sort();
+                write!(f, "{{")?;
+                for (i, key) in keys.into_iter().enumerate() {
+                    if i > 0 {
+                        write!(f, ",")?;
+                    }
+                    write!(f, " {key}: {}", map[key])?;
+                }
+                if map.is_empty() {
+                    write!(f, "}}")
+                } else {
+                    write!(f, " }}")
+                }
+            }
+        }
+    }
+}
+
 impl TryFrom<&Value> for TypedValue {
     type Error = TetronError;
 
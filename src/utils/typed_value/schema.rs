@@ -1,6 +1,7 @@
 use super::TypedValue;
-use crate::utils::Registrable;
+use crate::{engine::physics::vec2::Vec2, error::TetronError, utils::Registrable};
 use rune::{ContextError, Module, Value};
+use serde_json::{Map, Value as Json, json};
 use std::{
     collections::HashMap,
     fmt::{self, Display},
@@ -21,7 +22,12 @@ pub enum Schema {
     Object {
         fields: HashMap<String, SchemaField>,
     },
-    Optional(Box<Schema>),
+    /// May be left out of an object, in which case `default` (if any) is
+    /// filled in.
+    Optional {
+        schema: Box<Schema>,
+        default: Option<TypedValue>,
+    },
     Default {
         schema: Box<Schema>,
         default: TypedValue,
@@ -31,8 +37,26 @@ pub enum Schema {
 #[derive(Debug, Clone, rune::Any)]
 pub struct SchemaField {
     pub schema: Schema,
-    pub optional: bool,
-    pub default: Option<TypedValue>,
+}
+
+impl SchemaField {
+    /// The value to fill in when this field is missing from an object, or
+    /// `MissingField` if it is required. A `Schema::Default` field is
+    /// filled in whether it's optional or not.
+    fn missing(&self, name: &str) -> Result<Option<TypedValue>, SchemaError> {
+        match &self.schema {
+            Schema::Optional {
+                default: Some(default),
+                ..
+            } => Ok(Some(default.clone())),
+            Schema::Optional { schema, .. } => match schema.as_ref() {
+                Schema::Default { default, .. } => Ok(Some(default.clone())),
+                _ => Ok(None),
+            },
+            Schema::Default { default, .. } => Ok(Some(default.clone())),
+            _ => Err(SchemaError::MissingField(name.to_owned())),
+        }
+    }
 }
 
 #[derive(Debug, Clone, rune::Any)]
@@ -87,7 +111,27 @@ impl Schema {
     }
     #[rune::function(instance, keep)]
     pub fn optional(&self) -> Self {
-        Schema::Optional(Box::new(self.clone()))
+        Schema::Optional {
+            schema: Box::new(self.clone()),
+            default: None,
+        }
+    }
+
+    /// Like `optional`, but fills in `default` when the field is missing.
+    pub fn optional_with_default(&self, default: TypedValue) -> Self {
+        Schema::Optional {
+            schema: Box::new(self.clone()),
+            default: Some(default),
+        }
+    }
+
+    /// Fails if `default` can't be stored in a schema, e.g. a function.
+    #[rune::function(instance, keep, path = Self::optional_with_default)]
+    pub fn optional_with_default_rune(&self, default: Value) -> Result<Self, SchemaError> {
+        let default = default
+            .try_into()
+            .map_err(|e| SchemaError::Validation(format!("{e}")))?;
+        Ok(self.optional_with_default(default))
     }
 
     pub fn default(&self, default: TypedValue) -> Self {
@@ -105,6 +149,31 @@ impl Schema {
         };
         self.default(def)
     }
+    /// An object schema with the fields of both object schemas `self` and
+    /// `other`. Fields of `other` replace fields of `self` with the same name.
+    pub fn merge(&self, other: &Schema) -> Result<Schema, SchemaError> {
+        match (self, other) {
+            (Schema::Object { fields: base }, Schema::Object { fields: extra }) => {
+                let mut fields = base.clone();
+                for (name, field) in extra {
+                    fields.insert(name.clone(), field.clone());
+                }
+                Ok(Schema::Object { fields })
+            }
+            (Schema::Object { .. }, found) | (found, _) => Err(SchemaError::TypeMismatch {
+                expected: "Object".into(),
+                found: format!("{:?}", found),
+            }),
+        }
+    }
+    /// The value filled in for the object field `name` when it's left out,
+    /// or `None` if it has no default or isn't a field of this schema.
+    pub fn field_default(&self, name: &str) -> Option<TypedValue> {
+        match self {
+            Schema::Object { fields } => fields.get(name)?.missing(name).ok().flatten(),
+            _ => None,
+        }
+    }
     // Internal validation
     pub fn validate(&self, value: &TypedValue) -> Result<TypedValue, SchemaError> {
         match (self, value) {
@@ -117,49 +186,50 @@ impl Schema {
                 found: "Array".into(),
             }),
             (Schema::Array { item, min, max }, TypedValue::Array(items)) => {
-                if let Some(min) = min {
-                    if items.len() < *min {
-                        return Err(SchemaError::ArrayMinViolation {
-                            min: *min,
-                            found: items.len(),
-                        });
-                    }
+                if let Some(min) = min
+                    && items.len() < *min
+                {
+                    return Err(SchemaError::ArrayMinViolation {
+                        min: *min,
+                        found: items.len(),
+                    });
                 }
-                if let Some(max) = max {
-                    if items.len() > *max {
-                        return Err(SchemaError::ArrayMaxViolation {
-                            max: *max,
-                            found: items.len(),
-                        });
-                    }
+                if let Some(max) = max
+                    && items.len() > *max
+                {
+                    return Err(SchemaError::ArrayMaxViolation {
+                        max: *max,
+                        found: items.len(),
+                    });
                 }
                 let mut validated = Vec::with_capacity(items.len());
-                for item_val in items {
-                    validated.push(item.validate(item_val)?);
+                for (i, item_val) in items.iter().enumerate() {
+                    validated.push(
+                        item.validate(item_val)
+                            .map_err(|e| e.at_path(&i.to_string()))?,
+                    );
                 }
                 Ok(TypedValue::Array(validated))
             }
             (Schema::Object { fields }, TypedValue::Object(obj)) => {
                 let mut out = HashMap::new();
                 for (key, field_schema) in fields {
-                    match obj.get(key) {
-                        Some(v) => {
-                            out.insert(key.clone(), field_schema.schema.validate(v)?);
-                        }
-                        None => {
-                            if field_schema.optional {
-                                if let Some(default) = &field_schema.default {
-                                    out.insert(key.clone(), default.clone());
-                                }
-                            } else {
-                                return Err(SchemaError::MissingField(key.clone()));
-                            }
-                        }
+                    let value = match obj.get(key) {
+                        Some(v) => Some(
+                            field_schema
+                                .schema
+                                .validate(v)
+                                .map_err(|e| e.at_path(key))?,
+                        ),
+                        None => field_schema.missing(key)?,
+                    };
+                    if let Some(value) = value {
+                        out.insert(key.clone(), value);
                     }
                 }
                 Ok(TypedValue::Object(out))
             }
-            (Schema::Optional(sub), v) => sub.validate(v),
+            (Schema::Optional { schema, .. }, v) => schema.validate(v),
             (Schema::Default { schema, default }, v) => match schema.validate(v) {
                 Ok(valid) => Ok(valid),
                 Err(_) => Ok(default.clone()),
@@ -185,28 +255,18 @@ impl Schema {
 impl ObjectBuilder {
     pub fn field(&self, name: &str, schema: Schema) -> Self {
         let mut clone = self.clone();
-        clone.fields.push((
-            name.into(),
-            SchemaField {
-                schema,
-                optional: false,
-                default: None,
-            },
-        ));
+        clone.fields.push((name.into(), SchemaField { schema }));
         clone
     }
 
+    /// Shorthand for `field(name, schema.optional_with_default(default))`,
+    /// or `schema.optional()` without a default.
     pub fn optional_field(&self, name: &str, schema: Schema, default: Option<TypedValue>) -> Self {
-        let mut clone = self.clone();
-        clone.fields.push((
-            name.into(),
-            SchemaField {
-                schema,
-                optional: true,
-                default,
-            },
-        ));
-        clone
+        let schema = match default {
+            Some(default) => schema.optional_with_default(default),
+            None => schema.optional(),
+        };
+        self.field(name, schema)
     }
 
     #[rune::function(instance, path = ObjectBuilder::field)]
@@ -243,6 +303,7 @@ impl Registrable for Schema {
         module.function_meta(Schema::min__meta)?;
         module.function_meta(Schema::max__meta)?;
         module.function_meta(Schema::optional__meta)?;
+        module.function_meta(Schema::optional_with_default_rune__meta)?;
         module.function_meta(Schema::default_rune__meta)?;
         module.function_meta(Schema::validate_rune__meta)?;
         Ok(())
@@ -281,11 +342,46 @@ pub fn module() -> Result<Module, ContextError> {
 
 #[derive(Debug, Clone, PartialEq, rune::Any)]
 pub enum SchemaError {
-    TypeMismatch { expected: String, found: String },
+    TypeMismatch {
+        expected: String,
+        found: String,
+    },
     MissingField(String),
-    ArrayMinViolation { min: usize, found: usize },
-    ArrayMaxViolation { max: usize, found: usize },
+    ArrayMinViolation {
+        min: usize,
+        found: usize,
+    },
+    ArrayMaxViolation {
+        max: usize,
+        found: usize,
+    },
     Validation(String),
+    /// `inner` happened at `path`, a dotted chain of object fields and
+    /// array indices like `physics.vel` or `points.2`.
+    AtPath {
+        path: String,
+        inner: Box<SchemaError>,
+    },
+}
+
+impl SchemaError {
+    /// Record that this error happened inside the field or index `path`.
+    /// Nested paths are joined, outermost first.
+    pub fn at_path(self, path: &str) -> SchemaError {
+        match self {
+            SchemaError::AtPath {
+                path: inner_path,
+                inner,
+            } => SchemaError::AtPath {
+                path: format!("{path}.{inner_path}"),
+                inner,
+            },
+            inner => SchemaError::AtPath {
+                path: path.to_owned(),
+                inner: Box::new(inner),
+            },
+        }
+    }
 }
 
 fn format_typed_value_for_display(value: &TypedValue) -> String {
@@ -315,7 +411,11 @@ impl fmt::Debug for Schema {
                     .field("keys", &keys)
                     .finish()
             }
-            Schema::Optional(schema) => f.debug_tuple("Schema::Optional").field(schema).finish(),
+            Schema::Optional { schema, default } => f
+                .debug_struct("Schema::Optional")
+                .field("schema", schema)
+                .field("default", default)
+                .finish(),
             Schema::Default { schema, default } => {
                 f.debug_struct("Schema::Default")
                     .field("schema", schema)
@@ -384,11 +484,18 @@ impl Schema {
                         let field_indent_level = indent_level + 1;
                         let field_leading_spaces = "  ".repeat(field_indent_level);
 
+                        // Optional fields are shown as attributes of the inner schema.
+                        let (schema, optional, default) = match &field_schema.schema {
+                            Schema::Optional { schema, default } => {
+                                (schema.as_ref(), true, default.as_ref())
+                            }
+                            schema => (schema, false, None),
+                        };
                         let mut field_attributes = Vec::new();
-                        if field_schema.optional {
+                        if optional {
                             field_attributes.push("Optional".into());
                         }
-                        if let Some(def_val) = &field_schema.default {
+                        if let Some(def_val) = default {
                             field_attributes.push(format!(
                                 "Default: {}",
                                 format_typed_value_for_display(def_val)
@@ -404,29 +511,27 @@ impl Schema {
                         write!(f, "{}\"{}\": ", field_leading_spaces, key)?;
                         // The schema for the field, potentially with attributes suffix.
                         // It will decide if it needs a newline (e.g. if it's an Array or Object).
-                        field_schema
-                            .schema
-                            .fmt_recursive(f, 0, &attributes_suffix)?; // Start with 0 relative indent for the type itself
+                        schema.fmt_recursive(f, 0, &attributes_suffix)?; // Start with 0 relative indent for the type itself
 
                         // Add a newline after each field, unless it's the last one and the parent won't add one.
                         // The typical structure is that writeln! is used by containers, so simple types don't need to add their own.
                         // The loop for object fields should ensure each field ends on its own line.
                         if i < sorted_fields.len() - 1
                             || matches!(
-                                field_schema.schema,
+                                schema,
                                 Schema::Object { .. }
                                     | Schema::Array { .. }
-                                    | Schema::Optional(_)
+                                    | Schema::Optional { .. }
                                     | Schema::Default { .. }
                             )
                         {
                             // If the field schema itself was a container, it already added a newline.
                             // If it was a simple type, we add the newline here.
                             if !matches!(
-                                field_schema.schema,
+                                schema,
                                 Schema::Object { .. }
                                     | Schema::Array { .. }
-                                    | Schema::Optional(_)
+                                    | Schema::Optional { .. }
                                     | Schema::Default { .. }
                             ) {
                                 writeln!(f)?;
@@ -440,8 +545,20 @@ impl Schema {
                 }
                 Ok(())
             }
-            Schema::Optional(sub_schema) => {
-                writeln!(f, "{}Optional:{}", leading_spaces, suffix)?;
+            Schema::Optional {
+                schema: sub_schema,
+                default,
+            } => {
+                match default {
+                    Some(default) => writeln!(
+                        f,
+                        "{}Optional (default: {}):{}",
+                        leading_spaces,
+                        format_typed_value_for_display(default),
+                        suffix
+                    )?,
+                    None => writeln!(f, "{}Optional:{}", leading_spaces, suffix)?,
+                }
                 sub_schema.fmt_recursive(f, indent_level + 1, "")
             }
             Schema::Default {
@@ -461,6 +578,248 @@ impl Schema {
     }
 }
 
+// JSON representation, for schemas defined in `.schema.json` files. Every
+// schema is an object with a "type" key:
+//
+// * {"type": "null" | "bool" | "number" | "string" | "vec2"}
+// * {"type": "array", "item": <schema>, "min": n, "max": n}, min/max optional
+// * {"type": "object", "fields": {"name": <schema>}}, where each field schema
+//   may also have "optional": true and a "default" value. This is how
+//   optional fields are written, rather than as a nested "optional" schema.
+//   A "default" without "optional" makes the field a "default" schema.
+// * {"type": "optional", "schema": <schema>, "default": <value>}, default
+//   optional
+// * {"type": "default", "schema": <schema>, "default": <value>}
+//
+// Vec2 values are written as [x, y], and anything else is an error for a
+// vec2 schema. Defaults are read back using the schema they belong to, so
+// they can't be confused with arrays of numbers.
+impl Schema {
+    pub fn to_json(&self) -> Result<String, TetronError> {
+        serde_json::to_string(&self.to_json_value())
+            .map_err(|e| TetronError::Conversion(format!("Could not serialize schema: {e}")))
+    }
+
+    /// Parse a schema written in the JSON format described above, e.g. the
+    /// contents of a `.schema.json` file.
+    pub fn from_json(json: &str) -> Result<Schema, TetronError> {
+        let value: Json = serde_json::from_str(json)
+            .map_err(|e| TetronError::Conversion(format!("Invalid schema JSON: {e}")))?;
+        Schema::from_json_value(&value)
+    }
+
+    fn to_json_value(&self) -> Json {
+        match self {
+            Schema::Null => json!({ "type": "null" }),
+            Schema::Bool => json!({ "type": "bool" }),
+            Schema::Number => json!({ "type": "number" }),
+            Schema::String => json!({ "type": "string" }),
+            Schema::Vec2 => json!({ "type": "vec2" }),
+            Schema::Array { item, min, max } => {
+                let mut out = json!({ "type": "array", "item": item.to_json_value() });
+                if let Some(min) = min {
+                    out["min"] = json!(min);
+                }
+                if let Some(max) = max {
+                    out["max"] = json!(max);
+                }
+                out
+            }
+            Schema::Object { fields } => {
+                let fields: Map<String, Json> = fields
+                    .iter()
+                    .map(|(name, field)| {
+                        let out = match &field.schema {
+                            Schema::Optional { schema, default } => {
+                                let mut out = schema.to_json_value();
+                                out["optional"] = json!(true);
+                                if let Some(default) = default {
+                                    out["default"] = typed_value_to_json(default);
+                                }
+                                out
+                            }
+                            schema => schema.to_json_value(),
+                        };
+                        (name.clone(), out)
+                    })
+                    .collect();
+                json!({ "type": "object", "fields": fields })
+            }
+            Schema::Optional { schema, default } => {
+                let mut out = json!({ "type": "optional", "schema": schema.to_json_value() });
+                if let Some(default) = default {
+                    out["default"] = typed_value_to_json(default);
+                }
+                out
+            }
+            Schema::Default { schema, default } => json!({
+                "type": "default",
+                "schema": schema.to_json_value(),
+                "default": typed_value_to_json(default),
+            }),
+        }
+    }
+
+    fn from_json_value(value: &Json) -> Result<Schema, TetronError> {
+        let invalid = |msg: &str| TetronError::Conversion(format!("Invalid schema: {msg}"));
+        let sub_schema = |key: &str| -> Result<Box<Schema>, TetronError> {
+            let sub = value
+                .get(key)
+                .ok_or_else(|| invalid(&format!("missing \"{key}\"")))?;
+            Ok(Box::new(Schema::from_json_value(sub)?))
+        };
+        let count = |key: &str| -> Result<Option<usize>, TetronError> {
+            value
+                .get(key)
+                .map(|n| {
+                    n.as_u64().map(|n| n as usize).ok_or_else(|| {
+                        invalid(&format!("\"{key}\" must be a non-negative integer"))
+                    })
+                })
+                .transpose()
+        };
+
+        let ty = value
+            .get("type")
+            .and_then(Json::as_str)
+            .ok_or_else(|| invalid("missing \"type\""))?;
+        Ok(match ty {
+            "null" => Schema::Null,
+            "bool" => Schema::Bool,
+            "number" => Schema::Number,
+            "string" => Schema::String,
+            "vec2" => Schema::Vec2,
+            "array" => Schema::Array {
+                item: sub_schema("item")?,
+                min: count("min")?,
+                max: count("max")?,
+            },
+            "object" => {
+                let fields = value
+                    .get("fields")
+                    .and_then(Json::as_object)
+                    .ok_or_else(|| invalid("object schemas need a \"fields\" object"))?;
+                let mut out = HashMap::new();
+                for (name, field) in fields {
+                    let schema = Schema::from_json_value(field)?;
+                    let default = field
+                        .get("default")
+                        .map(|default| json_to_typed_value(default, &schema))
+                        .transpose()?;
+                    let optional = field
+                        .get("optional")
+                        .and_then(Json::as_bool)
+                        .unwrap_or(false);
+                    // A default without "optional" is still filled in for a
+                    // missing field, like `Schema::default`.
+                    let schema = match (optional, default) {
+                        (true, default) => Schema::Optional {
+                            schema: Box::new(schema),
+                            default,
+                        },
+                        (false, Some(default)) => Schema::Default {
+                            schema: Box::new(schema),
+                            default,
+                        },
+                        (false, None) => schema,
+                    };
+                    out.insert(name.clone(), SchemaField { schema });
+                }
+                Schema::Object { fields: out }
+            }
+            "optional" => {
+                let schema = sub_schema("schema")?;
+                let default = value
+                    .get("default")
+                    .map(|default| json_to_typed_value(default, &schema))
+                    .transpose()?;
+                Schema::Optional { schema, default }
+            }
+            "default" => {
+                let schema = sub_schema("schema")?;
+                let default = value
+                    .get("default")
+                    .ok_or_else(|| invalid("missing \"default\""))?;
+                let default = json_to_typed_value(default, &schema)?;
+                Schema::Default { schema, default }
+            }
+            other => return Err(invalid(&format!("unknown type \"{other}\""))),
+        })
+    }
+}
+
+fn typed_value_to_json(value: &TypedValue) -> Json {
+    match value {
+        TypedValue::String(s) => json!(s),
+        TypedValue::Number(n) => json!(n),
+        TypedValue::Bool(b) => json!(b),
+        TypedValue::Array(items) => Json::Array(items.iter().map(typed_value_to_json).collect()),
+        TypedValue::Object(map) => Json::Object(
+            map.iter()
+                .map(|(k, v)| (k.clone(), typed_value_to_json(v)))
+                .collect(),
+        ),
+        TypedValue::Vector(v) => json!([v.x, v.y]),
+    }
+}
+
+/// Convert a JSON value into a TypedValue, using `schema` to tell vectors
+/// apart from arrays.
+fn json_to_typed_value(value: &Json, schema: &Schema) -> Result<TypedValue, TetronError> {
+    let schema = match schema {
+        Schema::Optional { schema, .. } | Schema::Default { schema, .. } => {
+            return json_to_typed_value(value, schema);
+        }
+        schema => schema,
+    };
+    Ok(match (schema, value) {
+        (Schema::Vec2, value) => match value.as_array().map(Vec::as_slice) {
+            Some([x, y]) if x.is_number() && y.is_number() => TypedValue::Vector(Vec2::new(
+                x.as_f64().unwrap_or_default(),
+                y.as_f64().unwrap_or_default(),
+            )),
+            _ => {
+                return Err(TetronError::Conversion(format!(
+                    "Expected [x, y] for a vec2, found {value}"
+                )));
+            }
+        },
+        (_, Json::Null) => {
+            return Err(TetronError::Conversion(
+                "null is not a valid value".to_string(),
+            ));
+        }
+        (_, Json::Bool(b)) => TypedValue::Bool(*b),
+        (_, Json::Number(n)) => TypedValue::Number(n.as_f64().unwrap_or_default()),
+        (_, Json::String(s)) => TypedValue::String(s.clone()),
+        (Schema::Array { item, .. }, Json::Array(items)) => TypedValue::Array(
+            items
+                .iter()
+                .map(|v| json_to_typed_value(v, item))
+                .collect::<Result<_, _>>()?,
+        ),
+        (_, Json::Array(items)) => TypedValue::Array(
+            items
+                .iter()
+                .map(|v| json_to_typed_value(v, &Schema::Null))
+                .collect::<Result<_, _>>()?,
+        ),
+        (Schema::Object { fields }, Json::Object(map)) => TypedValue::Object(
+            map.iter()
+                .map(|(k, v)| {
+                    let schema = fields.get(k).map_or(&Schema::Null, |f| &f.schema);
+                    Ok((k.clone(), json_to_typed_value(v, schema)?))
+                })
+                .collect::<Result<_, TetronError>>()?,
+        ),
+        (_, Json::Object(map)) => TypedValue::Object(
+            map.iter()
+                .map(|(k, v)| Ok((k.clone(), json_to_typed_value(v, &Schema::Null)?)))
+                .collect::<Result<_, TetronError>>()?,
+        ),
+    })
+}
+
 impl Display for SchemaError {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         match self {
@@ -487,6 +846,7 @@ impl Display for SchemaError {
                 )
             }
             SchemaError::Validation(msg) => write!(f, "Validation error: {}", msg),
+            SchemaError::AtPath { path, inner } => write!(f, "Invalid field '{path}': {inner}"),
         }
     }
 }
@@ -506,6 +866,45 @@ mod tests {
         assert!(matches!(Schema::vec2(), Schema::Vec2));
     }
 
+    #[test]
+    fn test_nested_error_path() {
+        let field = |schema| SchemaField { schema };
+        let physics = Schema::Object {
+            fields: HashMap::from([("vel".to_string(), field(Schema::Vec2))]),
+        };
+        let schema = Schema::Object {
+            fields: HashMap::from([
+                ("physics".to_string(), field(physics)),
+                ("tags".to_string(), field(Schema::array(Schema::string()))),
+            ]),
+        };
+
+        let value = |vel, tags| {
+            TypedValue::Object(HashMap::from([
+                (
+                    "physics".to_string(),
+                    TypedValue::Object(HashMap::from([("vel".to_string(), vel)])),
+                ),
+                ("tags".to_string(), TypedValue::Array(tags)),
+            ]))
+        };
+
+        let err = schema
+            .validate(&value(TypedValue::String("fast".into()), vec![]))
+            .unwrap_err();
+        assert!(matches!(&err, SchemaError::AtPath { path, .. } if path == "physics.vel"));
+        assert!(
+            err.to_string()
+                .starts_with("Invalid field 'physics.vel': Type mismatch")
+        );
+
+        let tags = vec![TypedValue::String("a".into()), TypedValue::Number(1.0)];
+        let err = schema
+            .validate(&value(TypedValue::Vector(Default::default()), tags))
+            .unwrap_err();
+        assert!(err.to_string().starts_with("Invalid field 'tags.1':"));
+    }
+
     #[test]
     fn test_array_schema_min_max() {
         let arr = Schema::array(Schema::number()).min(2).max(5);
@@ -569,4 +968,122 @@ mod tests {
         let got = schema.validate(&TypedValue::String("bad".into())).unwrap();
         assert_eq!(got, TypedValue::Number(7.0));
     }
+
+    #[test]
+    fn test_json_round_trip() -> Result<(), TetronError> {
+        use crate::engine::{drawable, physics};
+
+        let object = |fields: Vec<(&str, TypedValue)>| {
+            TypedValue::Object(
+                fields
+                    .into_iter()
+                    .map(|(k, v)| (k.to_string(), v))
+                    .collect(),
+            )
+        };
+        let cases = [
+            (
+                physics::schema(),
+                vec![
+                    object(vec![("collision", "simulate".into()), ("mass", 2.0.into())]),
+                    object(vec![("vel", Vec2::new(1.0, 2.0).into())]),
+                    object(vec![("collision", 3.0.into())]),
+                ],
+            ),
+            (
+                drawable::schema(),
+                vec![
+                    object(vec![("color", "#fff".into())]),
+                    object(vec![("font", object(vec![("size", 12.0.into())]))]),
+                    object(vec![("font", object(vec![("face", "mono".into())]))]),
+                ],
+            ),
+            (
+                Schema::array(Schema::vec2().optional())
+                    .min(1)
+                    .max(2)
+                    .default(TypedValue::Array(vec![Vec2::zero().into()])),
+                vec![
+                    TypedValue::Array(vec![Vec2::new(1.0, 1.0).into()]),
+                    TypedValue::Array(vec![]),
+                    TypedValue::Bool(true),
+                ],
+            ),
+        ];
+
+        for (schema, values) in cases {
+            let json = schema.to_json()?;
+            let parsed = Schema::from_json(&json)?;
+            assert_eq!(parsed.to_json()?, json);
+            for value in values {
+                assert_eq!(schema.validate(&value), parsed.validate(&value));
+            }
+        }
+
+        // The physics velocity default is a vector, not an array.
+        let parsed = Schema::from_json(&physics::schema().to_json()?)?;
+        let validated = parsed.validate(&object(vec![("collision", "none".into())]));
+        assert_eq!(
+            validated,
+            Ok(object(vec![
+                ("collision", "none".into()),
+                ("vel", Vec2::zero().into()),
+            ]))
+        );
+
+        // A field default without "optional" is still filled in.
+        let parsed = Schema::from_json(
+            r#"{ "type": "object", "fields": { "hp": { "type": "number", "default": 3 } } }"#,
+        )?;
+        assert_eq!(
+            parsed.validate(&object(vec![])),
+            Ok(object(vec![("hp", TypedValue::Number(3.0))]))
+        );
+
+        assert!(Schema::from_json(r#"{ "type": "union" }"#).is_err());
+        assert!(Schema::from_json(r#"{ "type": "array" }"#).is_err());
+        for default in ["[1, 2, 3]", "[1]", "5", "\"up\""] {
+            let json = format!(
+                r#"{{ "type": "default", "schema": {{ "type": "vec2" }}, "default": {default} }}"#
+            );
+            assert!(Schema::from_json(&json).is_err(), "{default}");
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn test_optional_field_paths_match() {
+        let object = |fields: Vec<(&str, TypedValue)>| {
+            TypedValue::Object(fields.into_iter().map(|(k, v)| (k.into(), v)).collect())
+        };
+        let builder = Schema::object()
+            .optional_field("x", Schema::number(), Some(TypedValue::Number(0.0)))
+            .optional_field("label", Schema::string(), None)
+            .build();
+        let wrapped = Schema::object()
+            .field(
+                "x",
+                Schema::number().optional_with_default(TypedValue::Number(0.0)),
+            )
+            .field("label", Schema::string().optional())
+            .build();
+
+        for value in [
+            object(vec![]),
+            object(vec![("x", TypedValue::Number(3.0))]),
+            object(vec![("label", "hi".into())]),
+            object(vec![("x", "oops".into())]),
+        ] {
+            assert_eq!(builder.validate(&value), wrapped.validate(&value));
+        }
+        assert_eq!(
+            wrapped.validate(&object(vec![])),
+            Ok(object(vec![("x", TypedValue::Number(0.0))]))
+        );
+        assert_eq!(builder.to_json().unwrap(), wrapped.to_json().unwrap());
+
+        let number = Schema::number();
+        assert!(number.optional_with_default_rune(Value::from(2.0)).is_ok());
+        assert!(number.optional_with_default_rune(Value::empty()).is_err());
+    }
 }